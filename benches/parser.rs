@@ -0,0 +1,77 @@
+//! Parsing throughput for pipelines and large replies. Time per byte should
+//! stay flat as the input grows; it grew with the input back when every
+//! value was parsed from a copy of the rest of the buffer.
+//!
+//! Run with `cargo bench --bench parser`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+// The server is a binary, so the parser is compiled in directly. Parts of
+// it, and its tests under `--all-targets`, go unused here.
+#[allow(dead_code, unused_imports)]
+#[path = "../src/resp/mod.rs"]
+mod resp;
+
+use resp::parser::{self, Limits};
+use resp::value::Value;
+
+const SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+
+fn pipeline(commands: usize) -> Vec<u8> {
+    (0..commands)
+        .flat_map(|i| {
+            Value::Array(vec![
+                Value::BulkString(b"SET".to_vec()),
+                Value::BulkString(format!("key:{}", i).into_bytes()),
+                Value::BulkString(b"value".to_vec()),
+            ])
+            .to_resp()
+        })
+        .collect()
+}
+
+fn bench_pipeline(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pipeline");
+    let limits = Limits::default();
+    for commands in SIZES {
+        let input = pipeline(commands);
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(commands), &input, |b, input| {
+            b.iter(|| {
+                let mut offset = 0;
+                while offset < input.len() {
+                    let (consumed, command) =
+                        parser::parse_request(&input[offset..], &limits).unwrap();
+                    black_box(command);
+                    offset += consumed;
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_array_reply(c: &mut Criterion) {
+    let mut group = c.benchmark_group("array_reply");
+    for elements in SIZES {
+        let reply = Value::Array(
+            (0..elements as i64)
+                .map(|i| {
+                    Value::Array(vec![
+                        Value::Integer(i),
+                        Value::SimpleString("OK".to_string()),
+                    ])
+                })
+                .collect(),
+        )
+        .to_resp();
+        group.throughput(Throughput::Bytes(reply.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(elements), &reply, |b, reply| {
+            b.iter(|| black_box(parser::parse(reply).unwrap()))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_pipeline, bench_array_reply);
+criterion_main!(benches);
@@ -1,48 +1,815 @@
-use std::collections::HashMap;
-use std::time::Instant;
+use crate::notify::{self, Notification};
+use crate::random;
+use crate::stream::Stream;
+use indexmap::IndexSet;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+/// Rough per-allocation costs used to estimate memory usage: a key with
+/// its slot and index entry, and an element of a collection.
+const ENTRY_OVERHEAD: usize = 96;
+pub const ELEMENT_OVERHEAD: usize = 32;
+
+/// The access counter new keys start with, so they aren't evicted by LFU
+/// before they have had a chance to be read.
+const LFU_INIT_VAL: u8 = 5;
+const LFU_LOG_FACTOR: f64 = 10.0;
+const LFU_DECAY_MS: u64 = 60_000;
+
+/// A set's members. Removing one moves the last into its place, so
+/// `SSCAN` can page through them the way `scan` pages through keys.
+pub type Members = IndexSet<Vec<u8>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Data {
+    String(Vec<u8>),
+    Set(Members),
+    Stream(Stream),
+}
+
+impl Data {
+    fn is_empty(&self) -> bool {
+        match self {
+            Data::String(_) => false,
+            Data::Set(set) => set.is_empty(),
+            // Streams, like in Redis, may legitimately exist with no entries.
+            Data::Stream(_) => false,
+        }
+    }
+
+    /// The name `TYPE` and `SCAN ... TYPE` use for the value's type.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Data::String(_) => "string",
+            Data::Set(_) => "set",
+            Data::Stream(_) => "stream",
+        }
+    }
+
+    /// An estimate of the memory the value takes, in bytes.
+    fn memory_usage(&self) -> usize {
+        match self {
+            Data::String(value) => ELEMENT_OVERHEAD + value.len(),
+            Data::Set(set) => set
+                .iter()
+                .map(|member| ELEMENT_OVERHEAD + member.len())
+                .sum::<usize>(),
+            Data::Stream(stream) => stream.memory_usage(),
+        }
+    }
+}
+
+/// Current Unix time in milliseconds.
+///
+/// This is the wall clock, so expiry times stay comparable with other
+/// processes' (RDB files, `PXAT` sent to replicas, `RESTORE ... ABSTTL`)
+/// however long the server runs. If the host's clock is set back, the time
+/// stands still until it catches up rather than going backwards.
+pub fn now_ms() -> u64 {
+    static LATEST: AtomicU64 = AtomicU64::new(0);
+    let wall = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    LATEST.fetch_max(wall, Ordering::Relaxed).max(wall)
+}
+
+impl From<Vec<u8>> for Data {
+    fn from(value: Vec<u8>) -> Self {
+        Data::String(value)
+    }
+}
+
+/// Returned when a key exists but holds a different type than the command expects.
+#[derive(Debug, PartialEq)]
+pub struct WrongType;
+
+/// What to evict once `maxmemory` is reached, named as in `redis.conf`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EvictionPolicy {
+    NoEviction,
+    AllKeysLru,
+    VolatileLru,
+    AllKeysLfu,
+    VolatileLfu,
+    AllKeysRandom,
+    VolatileRandom,
+    VolatileTtl,
+}
+
+impl EvictionPolicy {
+    const NAMES: [(&'static str, EvictionPolicy); 8] = [
+        ("noeviction", EvictionPolicy::NoEviction),
+        ("allkeys-lru", EvictionPolicy::AllKeysLru),
+        ("volatile-lru", EvictionPolicy::VolatileLru),
+        ("allkeys-lfu", EvictionPolicy::AllKeysLfu),
+        ("volatile-lfu", EvictionPolicy::VolatileLfu),
+        ("allkeys-random", EvictionPolicy::AllKeysRandom),
+        ("volatile-random", EvictionPolicy::VolatileRandom),
+        ("volatile-ttl", EvictionPolicy::VolatileTtl),
+    ];
+
+    fn volatile(self) -> bool {
+        matches!(
+            self,
+            EvictionPolicy::VolatileLru
+                | EvictionPolicy::VolatileLfu
+                | EvictionPolicy::VolatileRandom
+                | EvictionPolicy::VolatileTtl
+        )
+    }
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::NAMES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|(_, policy)| *policy)
+            .ok_or_else(|| format!("invalid eviction policy '{}'", s))
+    }
+}
+
+impl fmt::Display for EvictionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (name, _) = Self::NAMES
+            .iter()
+            .find(|(_, policy)| policy == self)
+            .expect("every policy has a name");
+        f.write_str(name)
+    }
+}
+
+struct Entry {
+    value: Data,
+    expiry: Option<u64>,
+    /// The estimated size of key and value, as counted in `used_memory`.
+    size: usize,
+    /// When the key was last read or written, for LRU and LFU decay.
+    accessed: u64,
+    /// Redis's logarithmic access counter, for LFU.
+    frequency: u8,
+}
+
+impl Entry {
+    fn new(key: &[u8], value: Data, expiry: Option<u64>) -> Self {
+        let size = ENTRY_OVERHEAD + key.len() + value.memory_usage();
+        Entry {
+            value,
+            expiry,
+            size,
+            accessed: now_ms(),
+            frequency: LFU_INIT_VAL,
+        }
+    }
+
+    /// The access counter after losing one for every decay period the key
+    /// went unused.
+    fn decayed_frequency(&self, now: u64) -> u8 {
+        let periods = now.saturating_sub(self.accessed) / LFU_DECAY_MS;
+        self.frequency.saturating_sub(periods.min(255) as u8)
+    }
+
+    fn touch(&mut self) {
+        let now = now_ms();
+        let mut frequency = self.decayed_frequency(now);
+        // Incremented with a probability that falls as the counter grows, so
+        // it takes about a million hits to saturate it.
+        if frequency < u8::MAX {
+            let base = frequency.saturating_sub(LFU_INIT_VAL) as f64;
+            let chance = 1.0 / (base * LFU_LOG_FACTOR + 1.0);
+            if (random::next_u64() as f64 / u64::MAX as f64) < chance {
+                frequency += 1;
+            }
+        }
+        self.frequency = frequency;
+        self.accessed = now;
+    }
+}
+
+/// A watched key's version and how many `WATCH`es are on it.
+struct Watch {
+    version: u64,
+    watchers: usize,
+}
+
+/// The slots a scan over `len` of them visits next: up to `count` going
+/// down from `cursor`, where 0 starts at the top. The start of the range is
+/// the cursor to continue from, 0 once done.
+pub fn scan_slots(len: usize, cursor: u64, count: usize) -> Range<usize> {
+    let top = match cursor {
+        0 => len,
+        cursor => len.min(cursor as usize),
+    };
+    top.saturating_sub(count)..top
+}
+
+/// Versions are never reused, so a key can't look unchanged after being
+/// changed and changed back. 0 is never handed out; it's the version of keys nobody watches.
+fn next_version() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Expiries are absolute Unix times in milliseconds, so they can be saved
+/// and sent to replicas as they are.
 pub struct Cache {
-    data: HashMap<Vec<u8>, (Vec<u8>, Option<Instant>)>,
+    /// Entries in no particular order. `index` maps each key to its slot,
+    /// so picking a random key means picking a random slot.
+    slots: Vec<(Vec<u8>, Entry)>,
+    index: HashMap<Vec<u8>, usize>,
+    /// Keys with an expiry, ordered by it, for the active expire cycle.
+    expires: BTreeSet<(u64, Vec<u8>)>,
+    /// Keys handed out mutably since their size was last estimated.
+    dirty: HashSet<Vec<u8>>,
+    used_memory: usize,
+    /// Keys under `WATCH`, whose version changes whenever they are written,
+    /// deleted, expired or evicted.
+    watched: HashMap<Vec<u8>, Watch>,
+    /// Keyspace events waiting to be published.
+    notifications: Vec<Notification>,
+    /// Keys read and keys changed since the handler last collected them,
+    /// kept only while some client has tracking on.
+    reads: Vec<Vec<u8>>,
+    modified: Vec<Vec<u8>>,
+    /// The `notify-keyspace-events` classes to record events for.
+    notify_flags: u16,
+    /// Whether some client has tracking on.
+    tracking: bool,
 }
 
 impl Cache {
     pub fn new() -> Self {
-        let data = HashMap::new();
-        Cache { data }
+        Cache {
+            slots: Vec::new(),
+            index: HashMap::new(),
+            expires: BTreeSet::new(),
+            dirty: HashSet::new(),
+            used_memory: 0,
+            watched: HashMap::new(),
+            notifications: Vec::new(),
+            reads: Vec::new(),
+            modified: Vec::new(),
+            notify_flags: 0,
+            tracking: false,
+        }
+    }
+
+    /// Sets which keyspace events to record, and whether to record the
+    /// keys read and changed for tracking. Both are server-wide; the
+    /// handler passes them on to every database whenever they change.
+    pub fn configure(&mut self, notify_flags: u16, tracking: bool) {
+        self.notify_flags = notify_flags;
+        self.tracking = tracking;
     }
 
     pub fn insert(
         &mut self,
         key: Vec<u8>,
-        value: Vec<u8>,
-        expiry_time: Option<Instant>,
-    ) -> Option<(Vec<u8>, Option<Instant>)> {
-        self.data.insert(key, (value, expiry_time))
-    }
-
-    pub fn get(&mut self, key: &Vec<u8>) -> Option<Vec<u8>> {
-        if let Some((value, expiry)) = self.data.get(key) {
-            if let Some(exp_time) = expiry {
-                if Instant::now() > *exp_time {
-                    self.data.remove(key);
-                    return None;
-                }
+        value: impl Into<Data>,
+        expiry_time: Option<u64>,
+    ) -> Option<(Data, Option<u64>)> {
+        let entry = Entry::new(&key, value.into(), expiry_time);
+        self.used_memory += entry.size;
+        self.signal_modified(&key);
+        if let Some(at) = expiry_time {
+            self.expires.insert((at, key.clone()));
+        }
+        // An existing key keeps its slot, so overwriting it can't make a
+        // `SCAN` in progress miss it.
+        let slot = match self.index.get(&key) {
+            Some(&slot) => slot,
+            None => {
+                self.notify(notify::NEW, "new", &key);
+                self.index.insert(key.clone(), self.slots.len());
+                self.slots.push((key, entry));
+                return None;
+            }
+        };
+        let old = std::mem::replace(&mut self.slots[slot].1, entry);
+        if let Some(at) = old.expiry.filter(|&at| Some(at) != expiry_time) {
+            self.expires.remove(&(at, key.clone()));
+        }
+        self.dirty.remove(&key);
+        self.used_memory -= old.size;
+        Some((old.value, old.expiry))
+    }
+
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, WrongType> {
+        match self.get_data(key) {
+            Some(Data::String(value)) => Ok(Some(value.clone())),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Mutable access to a string value, leaving its expiry untouched.
+    pub fn get_string_mut(&mut self, key: &[u8]) -> Result<Option<&mut Vec<u8>>, WrongType> {
+        match self.lookup_mut(key).map(|entry| &mut entry.value) {
+            Some(Data::String(value)) => Ok(Some(value)),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    pub fn expiry(&mut self, key: &[u8]) -> Option<u64> {
+        self.expire_if_needed(key);
+        self.entry(key).and_then(|entry| entry.expiry)
+    }
+
+    /// Replaces the expiry of an existing key; returns false if it doesn't exist.
+    pub fn set_expiry(&mut self, key: &[u8], expiry_time: Option<u64>) -> bool {
+        self.expire_if_needed(key);
+        let old = match self.entry_mut(key) {
+            Some(entry) => std::mem::replace(&mut entry.expiry, expiry_time),
+            None => return false,
+        };
+        self.signal_modified(key);
+        if let Some(at) = old {
+            self.expires.remove(&(at, key.to_vec()));
+        }
+        if let Some(at) = expiry_time {
+            self.expires.insert((at, key.to_vec()));
+        }
+        true
+    }
+
+    pub fn get_data(&mut self, key: &[u8]) -> Option<&Data> {
+        self.lookup(key).map(|entry| &entry.value)
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<Data> {
+        self.expire_if_needed(key);
+        self.take(key).map(|entry| entry.value)
+    }
+
+    pub fn get_set(&mut self, key: &[u8]) -> Result<Option<&Members>, WrongType> {
+        match self.get_data(key) {
+            Some(Data::Set(set)) => Ok(Some(set)),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_set_mut(&mut self, key: &[u8]) -> Result<Option<&mut Members>, WrongType> {
+        match self.lookup_mut(key).map(|entry| &mut entry.value) {
+            Some(Data::Set(set)) => Ok(Some(set)),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Like `get_set_mut`, but creates an empty set when the key is missing.
+    pub fn get_or_create_set(&mut self, key: &[u8]) -> Result<&mut Members, WrongType> {
+        self.expire_if_needed(key);
+        if self.entry(key).is_none() {
+            self.insert(key.to_vec(), Data::Set(Members::new()), None);
+        }
+        match self.lookup_mut(key).map(|entry| &mut entry.value) {
+            Some(Data::Set(set)) => Ok(set),
+            _ => Err(WrongType),
+        }
+    }
+
+    pub fn get_stream(&mut self, key: &[u8]) -> Result<Option<&Stream>, WrongType> {
+        match self.get_data(key) {
+            Some(Data::Stream(stream)) => Ok(Some(stream)),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_stream_mut(&mut self, key: &[u8]) -> Result<Option<&mut Stream>, WrongType> {
+        match self.lookup_mut(key).map(|entry| &mut entry.value) {
+            Some(Data::Stream(stream)) => Ok(Some(stream)),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_or_create_stream(&mut self, key: &[u8]) -> Result<&mut Stream, WrongType> {
+        self.expire_if_needed(key);
+        if self.entry(key).is_none() {
+            self.insert(key.to_vec(), Data::Stream(Stream::new()), None);
+        }
+        match self.lookup_mut(key).map(|entry| &mut entry.value) {
+            Some(Data::Stream(stream)) => Ok(stream),
+            _ => Err(WrongType),
+        }
+    }
+
+    /// Drops `key` if it holds an empty collection, mirroring Redis which never
+    /// keeps empty aggregates around.
+    pub fn remove_if_empty(&mut self, key: &[u8]) {
+        if self.entry(key).is_some_and(|entry| entry.value.is_empty()) {
+            self.take(key);
+            self.notify(notify::GENERIC, "del", key);
+        }
+    }
+
+    /// Records a keyspace event for `key` if its class is enabled.
+    pub fn notify(&mut self, class: u16, event: &'static str, key: &[u8]) {
+        if notify::enabled(self.notify_flags, class) {
+            self.notifications.push(Notification {
+                event,
+                key: key.to_vec(),
+            });
+        }
+    }
+
+    pub fn take_notifications(&mut self) -> Vec<Notification> {
+        std::mem::take(&mut self.notifications)
+    }
+
+    /// The keys looked up since the last call, hit or miss, for
+    /// `CLIENT TRACKING`.
+    pub fn take_reads(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.reads)
+    }
+
+    /// The keys written, deleted, expired or evicted since the last call.
+    pub fn take_modified(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.modified)
+    }
+
+    /// Every entry with its expiry, including ones that have expired but
+    /// not been evicted yet.
+    pub fn iter(&self) -> impl Iterator<Item = (&Vec<u8>, &Data, Option<u64>)> {
+        self.slots
+            .iter()
+            .map(|(key, entry)| (key, &entry.value, entry.expiry))
+    }
+
+    /// Visits up to `count` slots, going down from `cursor` (0 starts at the
+    /// top), and returns the next cursor, 0 once done, with the live keys
+    /// found that `keep` accepts.
+    ///
+    /// Removing a key moves the last slot into its place, so slots still to
+    /// be visited only ever move to other unvisited slots, and keys added
+    /// during a scan land above the cursor. Every key present for the whole
+    /// scan is thus returned at least once, as `SCAN` promises.
+    pub fn scan(
+        &self,
+        cursor: u64,
+        count: usize,
+        mut keep: impl FnMut(&[u8], &Data) -> bool,
+    ) -> (u64, Vec<Vec<u8>>) {
+        let slots = scan_slots(self.slots.len(), cursor, count);
+        let bottom = slots.start;
+        let now = now_ms();
+        let keys = self.slots[slots]
+            .iter()
+            .rev()
+            .filter(|(_, entry)| entry.expiry.is_none_or(|at| at > now))
+            .filter(|(key, entry)| keep(key, &entry.value))
+            .map(|(key, _)| key.clone())
+            .collect();
+        (bottom as u64, keys)
+    }
+
+    /// Starts a `WATCH` on `key`, returning its current version.
+    pub fn watch(&mut self, key: &[u8]) -> u64 {
+        self.expire_if_needed(key);
+        let watch = self.watched.entry(key.to_vec()).or_insert_with(|| Watch {
+            version: next_version(),
+            watchers: 0,
+        });
+        watch.watchers += 1;
+        watch.version
+    }
+
+    pub fn unwatch(&mut self, key: &[u8]) {
+        if let Some(watch) = self.watched.get_mut(key) {
+            watch.watchers -= 1;
+            if watch.watchers == 0 {
+                self.watched.remove(key);
+            }
+        }
+    }
+
+    /// The version of a watched key, counting one that has expired since
+    /// as changed.
+    pub fn version(&mut self, key: &[u8]) -> u64 {
+        self.expire_if_needed(key);
+        self.watched.get(key).map_or(0, |watch| watch.version)
+    }
+
+    /// Bumps the version of a watched key and, while some client has
+    /// tracking on, records the change for invalidation. `insert`,
+    /// `set_expiry` and deletions do this themselves; commands that change
+    /// a value in place through a `get_*_mut` call it once they actually
+    /// have, like Redis's `signalModifiedKey`.
+    pub fn signal_modified(&mut self, key: &[u8]) {
+        if let Some(watch) = self.watched.get_mut(key) {
+            watch.version = next_version();
+        }
+        if self.tracking {
+            self.modified.push(key.to_vec());
+        }
+    }
+
+    /// Empties the keyspace, as `FLUSHDB` does, and returns the old keys
+    /// for freeing. `WATCH`es stay, and the watched keys that existed
+    /// count as changed.
+    pub fn flush(&mut self) -> Cache {
+        let existed = self.watched_where(|key| self.index.contains_key(key));
+        self.touch_watched(&existed);
+        Cache {
+            slots: std::mem::take(&mut self.slots),
+            index: std::mem::take(&mut self.index),
+            expires: std::mem::take(&mut self.expires),
+            dirty: std::mem::take(&mut self.dirty),
+            used_memory: std::mem::take(&mut self.used_memory),
+            ..Cache::new()
+        }
+    }
+
+    /// Swaps the keyspaces of two databases, as `SWAPDB` does, leaving
+    /// each one's `WATCH`es where they were. A watched key that exists on
+    /// either side counts as changed.
+    pub fn swap_keyspace(&mut self, other: &mut Cache) {
+        let either = |key: &[u8]| self.index.contains_key(key) || other.index.contains_key(key);
+        let (ours, theirs) = (self.watched_where(either), other.watched_where(either));
+        self.touch_watched(&ours);
+        other.touch_watched(&theirs);
+        std::mem::swap(&mut self.slots, &mut other.slots);
+        std::mem::swap(&mut self.index, &mut other.index);
+        std::mem::swap(&mut self.expires, &mut other.expires);
+        std::mem::swap(&mut self.dirty, &mut other.dirty);
+        std::mem::swap(&mut self.used_memory, &mut other.used_memory);
+    }
+
+    fn watched_where(&self, keep: impl Fn(&[u8]) -> bool) -> HashSet<Vec<u8>> {
+        self.watched
+            .keys()
+            .filter(|key| keep(key))
+            .cloned()
+            .collect()
+    }
+
+    /// Bumps the versions of `keys`, like Redis's `touchAllWatchedKeysInDb`.
+    fn touch_watched(&mut self, keys: &HashSet<Vec<u8>>) {
+        for key in keys {
+            self.signal_modified(key);
+        }
+    }
+
+    /// Backdates the last access of `key` by `idle_ms` and sets its LFU
+    /// counter, as `RESTORE ... IDLETIME`/`FREQ` do.
+    pub fn set_access(&mut self, key: &[u8], idle_ms: Option<u64>, frequency: Option<u8>) {
+        if let Some(entry) = self.entry_mut(key) {
+            if let Some(idle_ms) = idle_ms {
+                entry.accessed = now_ms().saturating_sub(idle_ms);
+            }
+            if let Some(frequency) = frequency {
+                entry.frequency = frequency;
+            }
+        }
+    }
+
+    /// A random live key, deleting expired ones it comes across.
+    pub fn random_key(&mut self) -> Option<Vec<u8>> {
+        let now = now_ms();
+        while !self.slots.is_empty() {
+            let (key, entry) = &self.slots[random::below(self.slots.len())];
+            if entry.expiry.is_none_or(|at| at > now) {
+                return Some(key.clone());
             }
-            return Some(value.clone());
+            let key = key.clone();
+            self.take(&key);
         }
         None
     }
 
+    /// Every key that hasn't expired.
     pub fn keys(&self) -> Vec<Vec<u8>> {
-        self.data.keys().cloned().collect()
+        let now = now_ms();
+        self.slots
+            .iter()
+            .filter(|(_, entry)| entry.expiry.is_none_or(|at| at > now))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// The number of keys that haven't expired.
+    pub fn len(&self) -> usize {
+        let now = now_ms();
+        let expired = self.expires.range(..(now + 1, Vec::new())).count();
+        self.slots.len() - expired
+    }
+
+    /// The estimated memory taken by all keys and values, in bytes.
+    pub fn used_memory(&mut self) -> usize {
+        for key in std::mem::take(&mut self.dirty) {
+            if let Some(&slot) = self.index.get(&key) {
+                let entry = &mut self.slots[slot].1;
+                let size = ENTRY_OVERHEAD + key.len() + entry.value.memory_usage();
+                self.used_memory = self.used_memory - entry.size + size;
+                entry.size = size;
+            }
+        }
+        self.used_memory
+    }
+
+    /// The key `policy` would evict next from this database, with a rank
+    /// to compare it against other databases' candidates: lowest goes first.
+    fn eviction_candidate(
+        &self,
+        policy: EvictionPolicy,
+        samples: usize,
+    ) -> Option<(Vec<u8>, (u64, u64))> {
+        let now = now_ms();
+        if policy == EvictionPolicy::NoEviction {
+            return None;
+        }
+        if policy == EvictionPolicy::VolatileTtl {
+            return self
+                .expires
+                .first()
+                .map(|(at, key)| (key.clone(), (*at, 0)));
+        }
+        let rank = |slot: usize| {
+            let entry = &self.slots[slot].1;
+            match policy {
+                EvictionPolicy::AllKeysLfu | EvictionPolicy::VolatileLfu => {
+                    (entry.decayed_frequency(now) as u64, entry.accessed)
+                }
+                EvictionPolicy::AllKeysRandom | EvictionPolicy::VolatileRandom => {
+                    (random::next_u64(), 0)
+                }
+                _ => (entry.accessed, 0),
+            }
+        };
+        self.sample(samples, policy.volatile())
+            .into_iter()
+            .map(|slot| (slot, rank(slot)))
+            .min_by_key(|(_, rank)| *rank)
+            .map(|(slot, rank)| (self.slots[slot].0.clone(), rank))
+    }
+
+    /// Picks up to `count` random slots, only ones with an expiry if
+    /// `volatile`. When keys with an expiry are too rare to find by chance,
+    /// the ones expiring soonest stand in.
+    fn sample(&self, count: usize, volatile: bool) -> Vec<usize> {
+        if self.slots.is_empty() || (volatile && self.expires.is_empty()) {
+            return Vec::new();
+        }
+        if !volatile {
+            return (0..count)
+                .map(|_| random::below(self.slots.len()))
+                .collect();
+        }
+        let mut sampled: Vec<usize> = (0..count * 10)
+            .map(|_| random::below(self.slots.len()))
+            .filter(|&slot| self.slots[slot].1.expiry.is_some())
+            .take(count)
+            .collect();
+        if sampled.is_empty() {
+            sampled = self
+                .expires
+                .iter()
+                .take(count)
+                .map(|(_, key)| self.index[key])
+                .collect();
+        }
+        sampled
+    }
+
+    /// Deletes expired keys, soonest first, until there are none left or
+    /// `budget` runs out. Redis samples random keys with a TTL because its
+    /// expires dict is unordered; ours is ordered, so every key visited is
+    /// one to delete. Returns how many were deleted.
+    pub fn active_expire_cycle(&mut self, budget: Duration) -> usize {
+        let start = Instant::now();
+        let now = now_ms();
+        let mut deleted = 0;
+        while let Some((at, key)) = self.expires.first() {
+            if *at > now {
+                break;
+            }
+            // Checking the clock is not free, so only do it every few keys.
+            if deleted % 16 == 15 && start.elapsed() >= budget {
+                break;
+            }
+            let key = key.clone();
+            self.take(&key);
+            self.notify(notify::EXPIRED, "expired", &key);
+            deleted += 1;
+        }
+        deleted
+    }
+
+    fn entry(&self, key: &[u8]) -> Option<&Entry> {
+        self.index.get(key).map(|&slot| &self.slots[slot].1)
+    }
+
+    fn entry_mut(&mut self, key: &[u8]) -> Option<&mut Entry> {
+        self.index.get(key).map(|&slot| &mut self.slots[slot].1)
+    }
+
+    /// The live entry for `key`, marked as just accessed.
+    fn lookup(&mut self, key: &[u8]) -> Option<&mut Entry> {
+        self.expire_if_needed(key);
+        if self.tracking {
+            self.reads.push(key.to_vec());
+        }
+        let entry = self.entry_mut(key)?;
+        entry.touch();
+        Some(entry)
+    }
+
+    /// Like `lookup`, for callers that may change the value's size. Whether
+    /// they change it at all is theirs to signal.
+    fn lookup_mut(&mut self, key: &[u8]) -> Option<&mut Entry> {
+        self.expire_if_needed(key);
+        if self.index.contains_key(key) {
+            self.dirty.insert(key.to_vec());
+        }
+        self.lookup(key)
+    }
+
+    fn expire_if_needed(&mut self, key: &[u8]) {
+        if let Some(Entry {
+            expiry: Some(exp_time),
+            ..
+        }) = self.entry(key)
+        {
+            if now_ms() >= *exp_time {
+                self.take(key);
+                self.notify(notify::EXPIRED, "expired", key);
+            }
+        }
     }
+
+    /// Removes `key` from every index, moving the last slot into its place.
+    fn take(&mut self, key: &[u8]) -> Option<Entry> {
+        let slot = self.index.remove(key)?;
+        let (_, entry) = self.slots.swap_remove(slot);
+        if let Some((moved, _)) = self.slots.get(slot) {
+            if let Some(index) = self.index.get_mut(moved) {
+                *index = slot;
+            }
+        }
+        if let Some(at) = entry.expiry {
+            self.expires.remove(&(at, key.to_vec()));
+        }
+        self.dirty.remove(key);
+        self.used_memory -= entry.size;
+        self.signal_modified(key);
+        Some(entry)
+    }
+}
+
+/// Evicts keys chosen by `policy` from any database until memory use is
+/// back within `maxmemory` or there is nothing left it may evict, and
+/// returns them with their database.
+///
+/// Like Redis, LRU and LFU compare a handful of randomly sampled keys
+/// rather than tracking the exact order; `volatile-ttl` needs no sampling
+/// since keys with a TTL are already ordered by it.
+pub fn evict(
+    dbs: &mut [Cache],
+    maxmemory: usize,
+    policy: EvictionPolicy,
+    samples: usize,
+) -> Vec<(usize, Vec<u8>)> {
+    let mut evicted = Vec::new();
+    while used_memory(dbs) > maxmemory {
+        let best = dbs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, db)| {
+                let (key, rank) = db.eviction_candidate(policy, samples.max(1))?;
+                Some((rank, i, key))
+            })
+            .min();
+        let (_, i, key) = match best {
+            Some(best) => best,
+            None => break,
+        };
+        dbs[i].take(&key);
+        dbs[i].notify(notify::EVICTED, "evicted", &key);
+        evicted.push((i, key));
+    }
+    evicted
+}
+
+/// The estimated memory taken by all databases, in bytes.
+pub fn used_memory(dbs: &mut [Cache]) -> usize {
+    dbs.iter_mut().map(Cache::used_memory).sum()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::thread;
-    use std::time::{Duration, Instant};
+    use std::time::Duration;
 
     #[test]
     fn test_insert_expirable() {
@@ -50,12 +817,224 @@ mod tests {
         let key = b"key".to_vec();
         let value = b"value".to_vec();
         let duration = Duration::from_secs(1);
-        let expiry_time = Instant::now() + duration;
+        let expiry_time = now_ms() + duration.as_millis() as u64;
         cache.insert(key.clone(), value.clone(), Some(expiry_time));
         thread::sleep(duration / 2);
-        assert_eq!(cache.get(&key), Some(value));
+        assert_eq!(cache.get(&key), Ok(Some(value)));
 
         thread::sleep(duration / 2);
-        assert_eq!(cache.get(&key), None);
+        assert_eq!(cache.get(&key), Ok(None));
+    }
+
+    #[test]
+    fn test_active_expire_cycle() {
+        let mut cache = Cache::new();
+        let past = now_ms() - 1;
+        for i in 0..100 {
+            let key = format!("old{}", i).into_bytes();
+            cache.insert(key, b"v".to_vec(), Some(past));
+        }
+        cache.insert(b"later".to_vec(), b"v".to_vec(), Some(now_ms() + 60_000));
+        cache.insert(b"forever".to_vec(), b"v".to_vec(), None);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.keys().len(), 2);
+
+        assert_eq!(cache.active_expire_cycle(Duration::from_millis(25)), 100);
+        assert_eq!(cache.slots.len(), 2);
+        assert_eq!(cache.active_expire_cycle(Duration::from_millis(25)), 0);
+
+        cache.set_expiry(b"later", Some(past));
+        cache.insert(b"forever".to_vec(), b"w".to_vec(), Some(past));
+        cache.insert(b"forever".to_vec(), b"w".to_vec(), None);
+        assert_eq!(cache.active_expire_cycle(Duration::from_millis(25)), 1);
+        assert_eq!(cache.expires.len(), 0);
+        assert_eq!(cache.keys(), vec![b"forever".to_vec()]);
+    }
+
+    #[test]
+    fn test_memory_accounting() {
+        let mut cache = Cache::new();
+        assert_eq!(cache.used_memory(), 0);
+        cache.insert(b"key".to_vec(), vec![b'x'; 1000], None);
+        let one = cache.used_memory();
+        assert!(one > 1000);
+        cache.insert(b"key".to_vec(), vec![b'x'; 1000], None);
+        assert_eq!(cache.used_memory(), one);
+
+        let set = cache.get_or_create_set(b"set").unwrap();
+        set.insert(vec![b'm'; 500]);
+        assert!(cache.used_memory() > one + 500);
+        cache.get_set_mut(b"set").unwrap().unwrap().clear();
+        cache.remove_if_empty(b"set");
+        assert_eq!(cache.used_memory(), one);
+
+        cache.get_string_mut(b"key").unwrap().unwrap().truncate(10);
+        assert!(cache.used_memory() < one - 900);
+        cache.remove(b"key");
+        assert_eq!(cache.used_memory(), 0);
+    }
+
+    #[test]
+    fn test_eviction_policies() {
+        // "old" and "hot" live in different databases, so every policy
+        // has to compare candidates across them.
+        let fill = || {
+            let mut dbs = vec![Cache::new(), Cache::new()];
+            let later = now_ms() + 60_000;
+            dbs[0].insert(b"old".to_vec(), b"v".to_vec(), Some(later + 1000));
+            dbs[1].insert(b"hot".to_vec(), b"v".to_vec(), Some(later));
+            dbs[1].insert(b"kept".to_vec(), b"v".to_vec(), None);
+            dbs[0].slots[0].1.accessed -= 1000;
+            dbs[1].slots[0].1.frequency = 100;
+            dbs
+        };
+        let old = (0, b"old".to_vec());
+        let hot = (1, b"hot".to_vec());
+
+        let mut dbs = fill();
+        let limit = used_memory(&mut dbs) - 1;
+        assert!(evict(&mut dbs, limit, EvictionPolicy::NoEviction, 5).is_empty());
+        assert_eq!(
+            evict(&mut dbs, limit, EvictionPolicy::AllKeysLru, 64),
+            vec![old.clone()]
+        );
+        assert_eq!(
+            evict(&mut dbs, 0, EvictionPolicy::VolatileTtl, 64),
+            vec![hot.clone()]
+        );
+        assert!(evict(&mut dbs, 0, EvictionPolicy::VolatileRandom, 64).is_empty());
+        assert_eq!(dbs[1].keys(), vec![b"kept".to_vec()]);
+
+        let mut dbs = fill();
+        let evicted = evict(&mut dbs, 0, EvictionPolicy::VolatileLfu, 64);
+        assert_eq!(evicted, vec![old, hot]);
+        assert_eq!(used_memory(&mut dbs), dbs[1].entry(b"kept").unwrap().size);
+
+        let mut dbs = fill();
+        assert_eq!(
+            evict(&mut dbs, 0, EvictionPolicy::AllKeysRandom, 64).len(),
+            3
+        );
+
+        assert_eq!(
+            "allkeys-LRU".parse::<EvictionPolicy>(),
+            Ok(EvictionPolicy::AllKeysLru)
+        );
+        assert_eq!(EvictionPolicy::VolatileTtl.to_string(), "volatile-ttl");
+        assert!("lru".parse::<EvictionPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_watched_versions() {
+        let mut cache = Cache::new();
+        cache.insert(b"k".to_vec(), b"v".to_vec(), None);
+        let version = cache.watch(b"k");
+        assert_eq!(cache.watch(b"k"), version);
+        cache.get(b"k").unwrap();
+        cache.get_data(b"other");
+        assert_eq!(cache.version(b"k"), version);
+
+        // Mutable access alone isn't a change.
+        cache.get_string_mut(b"k").unwrap();
+        assert_eq!(cache.version(b"k"), version);
+        cache.signal_modified(b"k");
+        let modified = cache.version(b"k");
+        assert_ne!(modified, version);
+        cache.set_expiry(b"k", Some(now_ms() - 1));
+        let expired = cache.version(b"k");
+        assert_ne!(expired, modified);
+        assert_eq!(cache.get(b"k"), Ok(None));
+        assert_eq!(cache.version(b"k"), expired);
+
+        let missing = cache.watch(b"new");
+        cache.insert(b"new".to_vec(), b"v".to_vec(), None);
+        cache.remove(b"new");
+        assert_ne!(cache.version(b"new"), missing);
+
+        cache.unwatch(b"k");
+        assert_ne!(cache.version(b"k"), 0);
+        cache.unwatch(b"k");
+        assert_eq!(cache.version(b"k"), 0);
+        assert_eq!(Cache::new().version(b"new"), 0);
+    }
+
+    #[test]
+    fn test_notifications() {
+        let events = |cache: &mut Cache| -> Vec<(&str, Vec<u8>)> {
+            let notifications = cache.take_notifications();
+            notifications
+                .into_iter()
+                .map(|n| (n.event, n.key))
+                .collect()
+        };
+        let flags = notify::parse("KEA").unwrap();
+        let mut cache = Cache::new();
+        cache.insert(b"quiet".to_vec(), b"v".to_vec(), None);
+        assert!(events(&mut cache).is_empty());
+        cache.configure(flags, false);
+        cache.insert(b"k".to_vec(), b"v".to_vec(), Some(now_ms() - 1));
+        cache.get_or_create_set(b"s").unwrap();
+        cache.remove_if_empty(b"s");
+        assert_eq!(cache.get(b"k"), Ok(None));
+        assert_eq!(
+            events(&mut cache),
+            vec![("del", b"s".to_vec()), ("expired", b"k".to_vec())]
+        );
+        assert!(events(&mut cache).is_empty());
+
+        let mut dbs = [Cache::new()];
+        dbs[0].configure(flags, false);
+        dbs[0].insert(b"big".to_vec(), vec![0; 1024], None);
+        evict(&mut dbs, 0, EvictionPolicy::AllKeysRandom, 5);
+        assert_eq!(events(&mut dbs[0]), vec![("evicted", b"big".to_vec())]);
+    }
+
+    #[test]
+    fn test_random_key() {
+        let mut cache = Cache::new();
+        assert_eq!(cache.random_key(), None);
+        for i in 0..10 {
+            let key = format!("old{}", i).into_bytes();
+            cache.insert(key, b"v".to_vec(), Some(now_ms() - 1));
+        }
+        assert_eq!(cache.random_key(), None);
+        assert_eq!(cache.slots.len(), 0);
+        cache.insert(b"key".to_vec(), b"v".to_vec(), None);
+        assert_eq!(cache.random_key(), Some(b"key".to_vec()));
+    }
+
+    #[test]
+    fn test_set_wrong_type() {
+        let mut cache = Cache::new();
+        cache.insert(b"key".to_vec(), b"value".to_vec(), None);
+        assert_eq!(cache.get_set(b"key"), Err(WrongType));
+        cache.get_or_create_set(b"set").unwrap();
+        assert_eq!(cache.get(b"set"), Err(WrongType));
+        assert!(cache.get_or_create_set(b"key").is_err());
+    }
+
+    #[test]
+    fn test_now_ms_is_monotonic() {
+        let wall = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let mut last = now_ms();
+        assert!(last.abs_diff(wall) < 1000);
+        for _ in 0..1000 {
+            let now = now_ms();
+            assert!(now >= last);
+            last = now;
+        }
+    }
+
+    #[test]
+    fn test_empty_set_is_removed() {
+        let mut cache = Cache::new();
+        let set = cache.get_or_create_set(b"key").unwrap();
+        set.insert(b"member".to_vec());
+        set.swap_remove(b"member".as_slice());
+        cache.remove_if_empty(b"key");
+        assert_eq!(cache.get_data(b"key"), None);
     }
 }
@@ -0,0 +1,340 @@
+//! `CLIENT` subcommands for the connection itself: its id and name, and
+//! `CLIENT TRACKING` for client-side caching. A client's id is its token.
+
+use super::table::{Command, Keys, LOADING, NOSCRIPT, STALE};
+use super::{arg_eq, bulk, check_arity, parse_int, CommandError};
+use crate::resp::value::Value;
+use crate::resp::value::Value::*;
+use crate::tracking::{self, Options, Tracking};
+use mio::Token;
+
+pub const COMMANDS: &[Command] = &[Command {
+    name: "CLIENT",
+    arity: -2,
+    flags: NOSCRIPT | LOADING | STALE,
+    keys: Keys::None,
+    group: "connection",
+    summary: "Manages client connections and their settings.",
+}];
+
+/// Runs a `CLIENT` subcommand for `client`, whose name is `name`. `exists`
+/// tells whether a client id names an open connection, for `REDIRECT`.
+pub fn execute(
+    arr: &[Value],
+    tracking: &mut Tracking,
+    client: Token,
+    name: &mut Option<Vec<u8>>,
+    exists: impl Fn(Token) -> bool,
+) -> Value {
+    let result = match arr.get(1) {
+        None => Err(CommandError::WrongArity(super::command_name(arr))),
+        Some(sub) if arg_eq(sub, "ID") && arr.len() == 2 => Ok(Integer(client.0 as i64)),
+        Some(sub) if arg_eq(sub, "SETNAME") && arr.len() == 3 => {
+            check_name(&arr[2]).map(|new_name| {
+                *name = new_name;
+                SimpleString("OK".to_string())
+            })
+        }
+        Some(sub) if arg_eq(sub, "GETNAME") && arr.len() == 2 => {
+            Ok(name.as_deref().map_or(Null, bulk))
+        }
+        Some(sub) if arg_eq(sub, "TRACKING") => execute_tracking(arr, tracking, client, exists),
+        Some(sub) if arg_eq(sub, "CACHING") => execute_caching(arr, tracking, client),
+        Some(sub) if arg_eq(sub, "GETREDIR") && arr.len() == 2 => {
+            Ok(Integer(redirect_id(tracking, client)))
+        }
+        Some(sub) if arg_eq(sub, "TRACKINGINFO") && arr.len() == 2 => {
+            Ok(tracking_info(tracking, client))
+        }
+        Some(sub) => Err(CommandError::Other(format!(
+            "unknown subcommand '{}'. Try CLIENT HELP.",
+            String::from_utf8_lossy(&sub.to_bytes())
+        ))),
+    };
+    result.unwrap_or_else(Into::into)
+}
+
+/// Checks a name for `CLIENT SETNAME` or `HELLO ... SETNAME`, where an
+/// empty one clears the name.
+pub fn check_name(name: &Value) -> Result<Option<Vec<u8>>, CommandError> {
+    let name = name.to_bytes();
+    if name.iter().any(|&c| !(b'!'..=b'~').contains(&c)) {
+        return Err(CommandError::Other(
+            "Client names cannot contain spaces, newlines or special characters.".to_string(),
+        ));
+    }
+    Ok(Some(name).filter(|name| !name.is_empty()))
+}
+
+/// Whether `arr` is `CLIENT CACHING`, which sets up the next command
+/// rather than using up the setting itself.
+pub fn is_caching(arr: &[Value]) -> bool {
+    arr.len() > 1 && arg_eq(&arr[0], "CLIENT") && arg_eq(&arr[1], "CACHING")
+}
+
+/// `CLIENT TRACKING ON|OFF [REDIRECT id] [PREFIX prefix ...] [BCAST]
+/// [OPTIN] [OPTOUT] [NOLOOP]`
+fn execute_tracking(
+    arr: &[Value],
+    tracking: &mut Tracking,
+    client: Token,
+    exists: impl Fn(Token) -> bool,
+) -> Result<Value, CommandError> {
+    check_arity(arr, -3)?;
+    let mut options = Options::default();
+    let mut i = 3;
+    while i < arr.len() {
+        let arg = &arr[i];
+        if arg_eq(arg, "REDIRECT") && i + 1 < arr.len() {
+            i += 1;
+            let id = parse_int(&arr[i])?;
+            let target = Token(id.max(0) as usize);
+            if id <= 0 || !exists(target) {
+                return Err(CommandError::Other(
+                    "The client ID you want redirect to does not exist".to_string(),
+                ));
+            }
+            options.redirect = Some(target);
+        } else if arg_eq(arg, "PREFIX") && i + 1 < arr.len() {
+            i += 1;
+            options.prefixes.push(arr[i].to_bytes());
+        } else if arg_eq(arg, "BCAST") {
+            options.bcast = true;
+        } else if arg_eq(arg, "OPTIN") {
+            options.optin = true;
+        } else if arg_eq(arg, "OPTOUT") {
+            options.optout = true;
+        } else if arg_eq(arg, "NOLOOP") {
+            options.noloop = true;
+        } else {
+            return Err(CommandError::Syntax);
+        }
+        i += 1;
+    }
+
+    if arg_eq(&arr[2], "OFF") {
+        tracking.disable(client);
+        return Ok(SimpleString("OK".to_string()));
+    }
+    if !arg_eq(&arr[2], "ON") {
+        return Err(CommandError::Syntax);
+    }
+    if !options.bcast && !options.prefixes.is_empty() {
+        return Err(CommandError::Other(
+            "PREFIX option requires BCAST mode to be enabled".to_string(),
+        ));
+    }
+    if let Some(current) = tracking.options(client) {
+        if current.bcast != options.bcast {
+            return Err(CommandError::Other(
+                "You can't switch BCAST mode on/off before disabling tracking for this client, and then re-enabling it with a different mode.".to_string(),
+            ));
+        }
+        // Enabling again adds to the prefixes already broadcast.
+        let mut prefixes = current.prefixes.clone();
+        prefixes.append(&mut options.prefixes);
+        options.prefixes = prefixes;
+    }
+    if options.optin && options.optout {
+        return Err(CommandError::Other(
+            "You can't use both OPTIN and OPTOUT".to_string(),
+        ));
+    }
+    if options.bcast && (options.optin || options.optout) {
+        return Err(CommandError::Other(
+            "OPTIN and OPTOUT are not compatible with BCAST".to_string(),
+        ));
+    }
+    tracking::check_prefixes(&options.prefixes).map_err(CommandError::Other)?;
+    tracking.enable(client, options);
+    Ok(SimpleString("OK".to_string()))
+}
+
+/// `CLIENT CACHING YES|NO`, which decides whether the next command's reads
+/// are tracked in `OPTIN` or `OPTOUT` mode.
+fn execute_caching(
+    arr: &[Value],
+    tracking: &mut Tracking,
+    client: Token,
+) -> Result<Value, CommandError> {
+    check_arity(arr, 3)?;
+    let Some(options) = tracking.options(client).filter(|o| o.optin || o.optout) else {
+        return Err(CommandError::Other(
+            "CLIENT CACHING can be called only when the client is in tracking mode with OPTIN or OPTOUT mode enabled".to_string(),
+        ));
+    };
+    let caching = if arg_eq(&arr[2], "YES") {
+        if !options.optin {
+            return Err(CommandError::Other(
+                "CLIENT CACHING YES is only valid when tracking is enabled in OPTIN mode."
+                    .to_string(),
+            ));
+        }
+        true
+    } else if arg_eq(&arr[2], "NO") {
+        if !options.optout {
+            return Err(CommandError::Other(
+                "CLIENT CACHING NO is only valid when tracking is enabled in OPTOUT mode."
+                    .to_string(),
+            ));
+        }
+        false
+    } else {
+        return Err(CommandError::Syntax);
+    };
+    tracking.set_caching(client, caching);
+    Ok(SimpleString("OK".to_string()))
+}
+
+/// The id invalidations are redirected to: 0 if they aren't, -1 if
+/// tracking is off.
+fn redirect_id(tracking: &Tracking, client: Token) -> i64 {
+    match tracking.options(client) {
+        Some(options) => options.redirect.map_or(0, |target| target.0 as i64),
+        None => -1,
+    }
+}
+
+fn tracking_info(tracking: &Tracking, client: Token) -> Value {
+    let mut flags = Vec::new();
+    let mut prefixes = Vec::new();
+    match tracking.options(client) {
+        None => flags.push(bulk(b"off")),
+        Some(options) => {
+            flags.push(bulk(b"on"));
+            let modes = [
+                (options.bcast, b"bcast".as_slice()),
+                (options.optin, b"optin"),
+                (options.optout, b"optout"),
+                (options.noloop, b"noloop"),
+            ];
+            flags.extend(modes.iter().filter(|(set, _)| *set).map(|(_, f)| bulk(f)));
+            prefixes.extend(options.prefixes.iter().map(|prefix| bulk(prefix)));
+        }
+    }
+    Map(vec![
+        (bulk(b"flags"), Set(flags)),
+        (bulk(b"redirect"), Integer(redirect_id(tracking, client))),
+        (bulk(b"prefixes"), Array(prefixes)),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(tracking: &mut Tracking, client: Token, args: &[&str]) -> Value {
+        let arr: Vec<Value> = args.iter().map(|arg| bulk(arg.as_bytes())).collect();
+        execute(&arr, tracking, client, &mut None, |token| token == Token(3))
+    }
+
+    fn error(message: &str) -> Value {
+        SimpleError(message.to_string())
+    }
+
+    #[test]
+    fn test_client_tracking() {
+        let mut tracking = Tracking::default();
+        let client = Token(2);
+        let ok = SimpleString("OK".to_string());
+        assert_eq!(run(&mut tracking, client, &["CLIENT", "ID"]), Integer(2));
+        assert_eq!(
+            run(&mut tracking, client, &["CLIENT", "GETREDIR"]),
+            Integer(-1)
+        );
+        assert_eq!(
+            run(
+                &mut tracking,
+                client,
+                &["CLIENT", "TRACKING", "on", "REDIRECT", "4"]
+            ),
+            error("ERR The client ID you want redirect to does not exist")
+        );
+        assert_eq!(
+            run(
+                &mut tracking,
+                client,
+                &["CLIENT", "TRACKING", "on", "PREFIX", "a"]
+            ),
+            error("ERR PREFIX option requires BCAST mode to be enabled")
+        );
+        assert_eq!(
+            run(
+                &mut tracking,
+                client,
+                &["CLIENT", "TRACKING", "on", "BCAST", "OPTIN"]
+            ),
+            error("ERR OPTIN and OPTOUT are not compatible with BCAST")
+        );
+        assert_eq!(
+            run(&mut tracking, client, &["CLIENT", "CACHING", "yes"]),
+            error("ERR CLIENT CACHING can be called only when the client is in tracking mode with OPTIN or OPTOUT mode enabled")
+        );
+        assert_eq!(
+            run(
+                &mut tracking,
+                client,
+                &["CLIENT", "TRACKING", "on", "REDIRECT", "3", "OPTIN"]
+            ),
+            ok
+        );
+        assert_eq!(
+            run(&mut tracking, client, &["CLIENT", "GETREDIR"]),
+            Integer(3)
+        );
+        assert_eq!(
+            run(&mut tracking, client, &["CLIENT", "CACHING", "yes"]),
+            ok
+        );
+        assert_eq!(
+            run(&mut tracking, client, &["CLIENT", "CACHING", "no"]),
+            error("ERR CLIENT CACHING NO is only valid when tracking is enabled in OPTOUT mode.")
+        );
+        assert_eq!(
+            run(&mut tracking, client, &["CLIENT", "TRACKING", "on", "BCAST"]),
+            error("ERR You can't switch BCAST mode on/off before disabling tracking for this client, and then re-enabling it with a different mode.")
+        );
+        assert_eq!(
+            run(&mut tracking, client, &["CLIENT", "TRACKINGINFO"]),
+            Map(vec![
+                (bulk(b"flags"), Set(vec![bulk(b"on"), bulk(b"optin")])),
+                (bulk(b"redirect"), Integer(3)),
+                (bulk(b"prefixes"), Array(vec![])),
+            ])
+        );
+        assert_eq!(
+            run(&mut tracking, client, &["CLIENT", "TRACKING", "off"]),
+            ok
+        );
+        assert_eq!(
+            run(&mut tracking, client, &["CLIENT", "TRACKING", "on", "BCAST", "PREFIX", "a", "PREFIX", "ab"]),
+            error("ERR Prefix 'ab' overlaps with another provided prefix 'a'. Prefixes for a single client must not overlap.")
+        );
+        assert_eq!(
+            run(&mut tracking, client, &["CLIENT", "NOPE"]),
+            error("ERR unknown subcommand 'NOPE'. Try CLIENT HELP.")
+        );
+    }
+
+    #[test]
+    fn test_client_name() {
+        let mut tracking = Tracking::default();
+        let mut name = None;
+        let mut run = |args: &[&str]| {
+            let arr: Vec<Value> = args.iter().map(|arg| bulk(arg.as_bytes())).collect();
+            execute(&arr, &mut tracking, Token(2), &mut name, |_| false)
+        };
+        assert_eq!(run(&["CLIENT", "GETNAME"]), Null);
+        assert_eq!(
+            run(&["CLIENT", "SETNAME", "worker-1"]),
+            SimpleString("OK".to_string())
+        );
+        assert_eq!(run(&["CLIENT", "GETNAME"]), bulk(b"worker-1"));
+        assert_eq!(
+            run(&["CLIENT", "SETNAME", "a b"]),
+            error("ERR Client names cannot contain spaces, newlines or special characters.")
+        );
+        run(&["CLIENT", "SETNAME", ""]);
+        assert_eq!(run(&["CLIENT", "GETNAME"]), Null);
+    }
+}
@@ -0,0 +1,359 @@
+use super::table::{Command, Keys, DENYOOM, FAST, LOADING, STALE, WRITE};
+use super::{arg_eq, check_arity, parse_int, CommandError, Propagation};
+use crate::cache::Cache;
+use crate::lazyfree;
+use crate::notify;
+use crate::resp::value::Value;
+use crate::resp::value::Value::*;
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "SELECT",
+        arity: 2,
+        flags: LOADING | STALE | FAST,
+        keys: Keys::None,
+        group: "connection",
+        summary: "Changes the selected database.",
+    },
+    Command {
+        name: "MOVE",
+        arity: 3,
+        flags: WRITE | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "generic",
+        summary: "Moves a key to another database.",
+    },
+    Command {
+        name: "COPY",
+        arity: -3,
+        flags: WRITE | DENYOOM,
+        keys: Keys::Range(1, 2, 1),
+        group: "generic",
+        summary: "Copies the value of a key to a new key.",
+    },
+    Command {
+        name: "SWAPDB",
+        arity: 3,
+        flags: WRITE | FAST,
+        keys: Keys::None,
+        group: "server",
+        summary: "Swaps two Redis databases.",
+    },
+    Command {
+        name: "FLUSHDB",
+        arity: -1,
+        flags: WRITE,
+        keys: Keys::None,
+        group: "server",
+        summary: "Removes all keys from the current database.",
+    },
+    Command {
+        name: "FLUSHALL",
+        arity: -1,
+        flags: WRITE,
+        keys: Keys::None,
+        group: "server",
+        summary: "Removes all keys from all databases.",
+    },
+];
+
+/// Runs a command that works across databases; `selected` is the calling
+/// client's database, which `SELECT` changes.
+pub fn execute(
+    name: &str,
+    arr: &[Value],
+    dbs: &mut [Cache],
+    selected: &mut usize,
+) -> (Value, Propagation) {
+    let ok = |_| (SimpleString("OK".to_string()), Propagation::Verbatim);
+
+    let result = match name {
+        // Replicas are sent `SELECT` when the replication stream needs it,
+        // whoever selected what.
+        "SELECT" => execute_select(arr, dbs, selected)
+            .map(|_| (SimpleString("OK".to_string()), Propagation::None)),
+        "MOVE" => execute_move(arr, dbs, *selected),
+        "COPY" => execute_copy(arr, dbs, *selected),
+        "SWAPDB" => execute_swapdb(arr, dbs).map(ok),
+        "FLUSHDB" => execute_flush(arr, &mut dbs[*selected..=*selected]).map(ok),
+        "FLUSHALL" => execute_flush(arr, dbs).map(ok),
+        _ => Err(CommandError::Other(format!("unknown command '{}'", name))),
+    };
+
+    match result {
+        Ok(response) => response,
+        Err(err) => (err.into(), Propagation::None),
+    }
+}
+
+fn index(value: &Value, dbs: &[Cache], not_integer: CommandError) -> Result<usize, CommandError> {
+    let index = parse_int(value).map_err(|_| not_integer)?;
+    match usize::try_from(index) {
+        Ok(index) if index < dbs.len() => Ok(index),
+        _ => Err(CommandError::Other("DB index is out of range".to_string())),
+    }
+}
+
+fn execute_select(arr: &[Value], dbs: &[Cache], selected: &mut usize) -> Result<(), CommandError> {
+    check_arity(arr, 2)?;
+    *selected = index(&arr[1], dbs, CommandError::NotInteger)?;
+    Ok(())
+}
+
+/// Moves a key, with its TTL, unless the target database already has it.
+fn execute_move(
+    arr: &[Value],
+    dbs: &mut [Cache],
+    selected: usize,
+) -> Result<(Value, Propagation), CommandError> {
+    check_arity(arr, 3)?;
+    let key = arr[1].to_bytes();
+    let target = index(&arr[2], dbs, CommandError::NotInteger)?;
+    if target == selected {
+        return Err(CommandError::Other(
+            "source and destination objects are the same".to_string(),
+        ));
+    }
+    if dbs[selected].get_data(&key).is_none() || dbs[target].get_data(&key).is_some() {
+        return Ok((Integer(0), Propagation::None));
+    }
+    let expiry = dbs[selected].expiry(&key);
+    if let Some(value) = dbs[selected].remove(&key) {
+        dbs[selected].notify(notify::GENERIC, "move_from", &key);
+        dbs[target].insert(key.clone(), value, expiry);
+        dbs[target].notify(notify::GENERIC, "move_to", &key);
+    }
+    Ok((Integer(1), Propagation::Verbatim))
+}
+
+/// `COPY source destination [DB destination-db] [REPLACE]`, keeping the TTL.
+fn execute_copy(
+    arr: &[Value],
+    dbs: &mut [Cache],
+    selected: usize,
+) -> Result<(Value, Propagation), CommandError> {
+    check_arity(arr, -3)?;
+    let source = arr[1].to_bytes();
+    let destination = arr[2].to_bytes();
+    let mut target = selected;
+    let mut replace = false;
+    let mut i = 3;
+    while i < arr.len() {
+        if arg_eq(&arr[i], "REPLACE") {
+            replace = true;
+            i += 1;
+        } else if arg_eq(&arr[i], "DB") && i + 1 < arr.len() {
+            target = index(&arr[i + 1], dbs, CommandError::NotInteger)?;
+            i += 2;
+        } else {
+            return Err(CommandError::Syntax);
+        }
+    }
+    if target == selected && source == destination {
+        return Err(CommandError::Other(
+            "source and destination objects are the same".to_string(),
+        ));
+    }
+
+    let value = match dbs[selected].get_data(&source) {
+        Some(value) => value.clone(),
+        None => return Ok((Integer(0), Propagation::None)),
+    };
+    if !replace && dbs[target].get_data(&destination).is_some() {
+        return Ok((Integer(0), Propagation::None));
+    }
+    let expiry = dbs[selected].expiry(&source);
+    dbs[target].insert(destination.clone(), value, expiry);
+    dbs[target].notify(notify::GENERIC, "copy_to", &destination);
+    Ok((Integer(1), Propagation::Verbatim))
+}
+
+fn execute_swapdb(arr: &[Value], dbs: &mut [Cache]) -> Result<(), CommandError> {
+    check_arity(arr, 3)?;
+    let first = index(
+        &arr[1],
+        dbs,
+        CommandError::Other("invalid first DB index".to_string()),
+    )?;
+    let second = index(
+        &arr[2],
+        dbs,
+        CommandError::Other("invalid second DB index".to_string()),
+    )?;
+    if first != second {
+        let (low, high) = dbs.split_at_mut(first.max(second));
+        low[first.min(second)].swap_keyspace(&mut high[0]);
+    }
+    Ok(())
+}
+
+/// `FLUSHDB` and `FLUSHALL`; with `ASYNC` the old keys are freed in the
+/// background.
+fn execute_flush(arr: &[Value], dbs: &mut [Cache]) -> Result<(), CommandError> {
+    check_arity(arr, -1)?;
+    let lazy = match &arr[1..] {
+        [] => false,
+        [mode] if arg_eq(mode, "ASYNC") => true,
+        [mode] if arg_eq(mode, "SYNC") => false,
+        _ => return Err(CommandError::Syntax),
+    };
+    for db in dbs {
+        let old = db.flush();
+        if lazy {
+            lazyfree::drop_later(old);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(dbs: &mut [Cache], selected: &mut usize, args: &[&str]) -> Value {
+        let arr: Vec<Value> = args
+            .iter()
+            .map(|arg| BulkString(arg.to_string().into_bytes()))
+            .collect();
+        execute(args[0], &arr, dbs, selected).0
+    }
+
+    #[test]
+    fn test_select_and_move() {
+        let mut dbs = [Cache::new(), Cache::new(), Cache::new()];
+        let mut selected = 0;
+        let ok = SimpleString("OK".to_string());
+        assert_eq!(run(&mut dbs, &mut selected, &["SELECT", "2"]), ok);
+        assert_eq!(selected, 2);
+        assert_eq!(
+            run(&mut dbs, &mut selected, &["SELECT", "3"]),
+            SimpleError("ERR DB index is out of range".to_string())
+        );
+        assert_eq!(
+            run(&mut dbs, &mut selected, &["SELECT", "one"]),
+            SimpleError("ERR value is not an integer or out of range".to_string())
+        );
+        assert_eq!(selected, 2);
+
+        dbs[2].insert(b"k".to_vec(), b"v".to_vec(), Some(u64::MAX));
+        assert_eq!(
+            run(&mut dbs, &mut selected, &["MOVE", "k", "0"]),
+            Integer(1)
+        );
+        assert_eq!(dbs[0].get(b"k"), Ok(Some(b"v".to_vec())));
+        assert_eq!(dbs[0].expiry(b"k"), Some(u64::MAX));
+        assert_eq!(
+            run(&mut dbs, &mut selected, &["MOVE", "k", "0"]),
+            Integer(0)
+        );
+
+        dbs[2].insert(b"k".to_vec(), b"other".to_vec(), None);
+        assert_eq!(
+            run(&mut dbs, &mut selected, &["MOVE", "k", "0"]),
+            Integer(0)
+        );
+        assert_eq!(dbs[2].get(b"k"), Ok(Some(b"other".to_vec())));
+        assert_eq!(
+            run(&mut dbs, &mut selected, &["MOVE", "k", "2"]),
+            SimpleError("ERR source and destination objects are the same".to_string())
+        );
+    }
+
+    #[test]
+    fn test_copy() {
+        let mut dbs = [Cache::new(), Cache::new()];
+        let mut selected = 0;
+        dbs[0].insert(b"src".to_vec(), b"v".to_vec(), Some(u64::MAX));
+        assert_eq!(
+            run(&mut dbs, &mut selected, &["COPY", "src", "dst"]),
+            Integer(1)
+        );
+        assert_eq!(dbs[0].get(b"dst"), Ok(Some(b"v".to_vec())));
+        assert_eq!(dbs[0].expiry(b"dst"), Some(u64::MAX));
+        assert_eq!(
+            run(&mut dbs, &mut selected, &["COPY", "src", "dst"]),
+            Integer(0)
+        );
+        dbs[0].insert(b"src".to_vec(), b"w".to_vec(), None);
+        assert_eq!(
+            run(&mut dbs, &mut selected, &["COPY", "src", "dst", "REPLACE"]),
+            Integer(1)
+        );
+        assert_eq!(dbs[0].expiry(b"dst"), None);
+        assert_eq!(
+            run(&mut dbs, &mut selected, &["COPY", "src", "src", "DB", "1"]),
+            Integer(1)
+        );
+        assert_eq!(dbs[1].get(b"src"), Ok(Some(b"w".to_vec())));
+        assert_eq!(
+            run(&mut dbs, &mut selected, &["COPY", "missing", "dst"]),
+            Integer(0)
+        );
+        assert_eq!(
+            run(&mut dbs, &mut selected, &["COPY", "src", "src"]),
+            SimpleError("ERR source and destination objects are the same".to_string())
+        );
+        assert_eq!(
+            run(&mut dbs, &mut selected, &["COPY", "src", "dst", "DB"]),
+            SimpleError("ERR syntax error".to_string())
+        );
+    }
+
+    #[test]
+    fn test_swapdb_and_flush() {
+        let mut dbs = [Cache::new(), Cache::new()];
+        let mut selected = 0;
+        let ok = SimpleString("OK".to_string());
+        dbs[0].insert(b"a".to_vec(), b"1".to_vec(), None);
+        assert_eq!(run(&mut dbs, &mut selected, &["SWAPDB", "0", "1"]), ok);
+        assert_eq!(dbs[0].len(), 0);
+        assert_eq!(dbs[1].len(), 1);
+        assert_eq!(
+            run(&mut dbs, &mut selected, &["SWAPDB", "x", "1"]),
+            SimpleError("ERR invalid first DB index".to_string())
+        );
+        assert_eq!(
+            run(&mut dbs, &mut selected, &["SWAPDB", "0", "2"]),
+            SimpleError("ERR DB index is out of range".to_string())
+        );
+
+        dbs[0].insert(b"b".to_vec(), b"2".to_vec(), None);
+        assert_eq!(run(&mut dbs, &mut selected, &["FLUSHDB"]), ok);
+        assert_eq!((dbs[0].len(), dbs[1].len()), (0, 1));
+        assert_eq!(
+            run(&mut dbs, &mut selected, &["FLUSHALL", "LATER"]),
+            SimpleError("ERR syntax error".to_string())
+        );
+        assert_eq!(run(&mut dbs, &mut selected, &["FLUSHALL", "async"]), ok);
+        assert_eq!(dbs[1].len(), 0);
+    }
+
+    #[test]
+    fn test_swapdb_and_flush_keep_watches() {
+        let mut dbs = [Cache::new(), Cache::new()];
+        let mut selected = 0;
+        dbs[0].insert(b"a".to_vec(), b"1".to_vec(), None);
+        let a = dbs[0].watch(b"a");
+        let missing = dbs[0].watch(b"missing");
+        let b = dbs[1].watch(b"b");
+
+        run(&mut dbs, &mut selected, &["SWAPDB", "0", "1"]);
+        assert_ne!(dbs[0].version(b"a"), a);
+        assert_eq!(dbs[0].version(b"missing"), missing);
+        assert_eq!(dbs[1].version(b"b"), b);
+        assert_eq!(dbs[1].get(b"a"), Ok(Some(b"1".to_vec())));
+
+        dbs[0].insert(b"missing".to_vec(), b"2".to_vec(), None);
+        let missing = dbs[0].version(b"missing");
+        let a = dbs[0].version(b"a");
+        run(&mut dbs, &mut selected, &["FLUSHDB"]);
+        assert_ne!(dbs[0].version(b"missing"), missing);
+        assert_eq!(dbs[0].version(b"a"), a);
+
+        // The watch counts survive, so unwatching still finds them.
+        dbs[0].unwatch(b"a");
+        dbs[0].unwatch(b"missing");
+        assert_eq!(dbs[0].version(b"a"), 0);
+        assert_eq!(dbs[0].version(b"missing"), 0);
+    }
+}
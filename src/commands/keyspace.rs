@@ -0,0 +1,1062 @@
+use super::table::{Command, Keys, DENYOOM, FAST, READONLY, WRITE};
+use super::{arg_eq, bulk, check_arity, command_name, parse_int, CommandError, Propagation};
+use crate::cache::{now_ms, scan_slots, Cache};
+use crate::glob;
+use crate::lazyfree;
+use crate::notify;
+use crate::persistence::rdb;
+use crate::resp::value::Value;
+use crate::resp::value::Value::*;
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "DEL",
+        arity: -2,
+        flags: WRITE,
+        keys: Keys::Range(1, -1, 1),
+        group: "generic",
+        summary: "Deletes one or more keys.",
+    },
+    Command {
+        name: "UNLINK",
+        arity: -2,
+        flags: WRITE | FAST,
+        keys: Keys::Range(1, -1, 1),
+        group: "generic",
+        summary: "Asynchronously deletes one or more keys.",
+    },
+    Command {
+        name: "EXISTS",
+        arity: -2,
+        flags: READONLY | FAST,
+        keys: Keys::Range(1, -1, 1),
+        group: "generic",
+        summary: "Determines whether one or more keys exist.",
+    },
+    Command {
+        name: "TOUCH",
+        arity: -2,
+        flags: READONLY | FAST,
+        keys: Keys::Range(1, -1, 1),
+        group: "generic",
+        summary: "Returns the number of existing keys out of those specified after updating the time they were last accessed.",
+    },
+    Command {
+        name: "TYPE",
+        arity: 2,
+        flags: READONLY | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "generic",
+        summary: "Determines the type of value stored at a key.",
+    },
+    Command {
+        name: "RENAME",
+        arity: 3,
+        flags: WRITE,
+        keys: Keys::Range(1, 2, 1),
+        group: "generic",
+        summary: "Renames a key and overwrites the destination.",
+    },
+    Command {
+        name: "RENAMENX",
+        arity: 3,
+        flags: WRITE | FAST,
+        keys: Keys::Range(1, 2, 1),
+        group: "generic",
+        summary: "Renames a key only when the target key name doesn't exist.",
+    },
+    Command {
+        name: "RANDOMKEY",
+        arity: 1,
+        flags: READONLY,
+        keys: Keys::None,
+        group: "generic",
+        summary: "Returns a random key name from the database.",
+    },
+    Command {
+        name: "DUMP",
+        arity: 2,
+        flags: READONLY,
+        keys: Keys::Range(1, 1, 1),
+        group: "generic",
+        summary: "Returns a serialized representation of the value stored at a key.",
+    },
+    Command {
+        name: "RESTORE",
+        arity: -4,
+        flags: WRITE | DENYOOM,
+        keys: Keys::Range(1, 1, 1),
+        group: "generic",
+        summary: "Creates a key from the serialized representation of a value.",
+    },
+    Command {
+        name: "EXPIRE",
+        arity: -3,
+        flags: WRITE | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "generic",
+        summary: "Sets the expiration time of a key in seconds.",
+    },
+    Command {
+        name: "PEXPIRE",
+        arity: -3,
+        flags: WRITE | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "generic",
+        summary: "Sets the expiration time of a key in milliseconds.",
+    },
+    Command {
+        name: "EXPIREAT",
+        arity: -3,
+        flags: WRITE | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "generic",
+        summary: "Sets the expiration time of a key to a Unix timestamp.",
+    },
+    Command {
+        name: "PEXPIREAT",
+        arity: -3,
+        flags: WRITE | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "generic",
+        summary: "Sets the expiration time of a key to a Unix milliseconds timestamp.",
+    },
+    Command {
+        name: "TTL",
+        arity: 2,
+        flags: READONLY | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "generic",
+        summary: "Returns the expiration time in seconds of a key.",
+    },
+    Command {
+        name: "PTTL",
+        arity: 2,
+        flags: READONLY | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "generic",
+        summary: "Returns the expiration time in milliseconds of a key.",
+    },
+    Command {
+        name: "EXPIRETIME",
+        arity: 2,
+        flags: READONLY | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "generic",
+        summary: "Returns the expiration time of a key as a Unix timestamp.",
+    },
+    Command {
+        name: "PEXPIRETIME",
+        arity: 2,
+        flags: READONLY | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "generic",
+        summary: "Returns the expiration time of a key as a Unix milliseconds timestamp.",
+    },
+    Command {
+        name: "PERSIST",
+        arity: 2,
+        flags: WRITE | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "generic",
+        summary: "Removes the expiration time of a key.",
+    },
+    Command {
+        name: "DBSIZE",
+        arity: 1,
+        flags: READONLY | FAST,
+        keys: Keys::None,
+        group: "generic",
+        summary: "Returns the number of keys in the database.",
+    },
+    Command {
+        name: "KEYS",
+        arity: 2,
+        flags: READONLY,
+        keys: Keys::None,
+        group: "generic",
+        summary: "Returns all key names that match a pattern.",
+    },
+    Command {
+        name: "SCAN",
+        arity: -2,
+        flags: READONLY,
+        keys: Keys::None,
+        group: "generic",
+        summary: "Iterates over the key names in the database.",
+    },
+    Command {
+        name: "SSCAN",
+        arity: -3,
+        flags: READONLY,
+        keys: Keys::Range(1, 1, 1),
+        group: "generic",
+        summary: "Iterates over members of a set.",
+    },
+    Command {
+        name: "HSCAN",
+        arity: -3,
+        flags: READONLY,
+        keys: Keys::Range(1, 1, 1),
+        group: "generic",
+        summary: "Iterates over fields and values of a hash.",
+    },
+    Command {
+        name: "ZSCAN",
+        arity: -3,
+        flags: READONLY,
+        keys: Keys::Range(1, 1, 1),
+        group: "generic",
+        summary: "Iterates over members and scores of a sorted set.",
+    },
+];
+
+#[derive(Clone, Copy)]
+enum Unit {
+    Seconds,
+    Millis,
+}
+
+pub fn execute(name: &str, arr: &[Value], db: &mut Cache) -> (Value, Propagation) {
+    let read = |reply| (reply, Propagation::None);
+
+    let result = match name {
+        "DEL" => execute_del(arr, db, false),
+        "UNLINK" => execute_del(arr, db, true),
+        "EXISTS" => execute_exists(arr, db).map(read),
+        "TOUCH" => execute_exists(arr, db).map(read),
+        "TYPE" => execute_type(arr, db).map(read),
+        "RENAME" => execute_rename(arr, db, false),
+        "RENAMENX" => execute_rename(arr, db, true),
+        "RANDOMKEY" => execute_randomkey(arr, db).map(read),
+        "DUMP" => execute_dump(arr, db).map(read),
+        "RESTORE" => execute_restore(arr, db),
+        "EXPIRE" => execute_expire(arr, db, Unit::Seconds, false),
+        "PEXPIRE" => execute_expire(arr, db, Unit::Millis, false),
+        "EXPIREAT" => execute_expire(arr, db, Unit::Seconds, true),
+        "PEXPIREAT" => execute_expire(arr, db, Unit::Millis, true),
+        "TTL" => execute_ttl(arr, db, Unit::Seconds).map(read),
+        "PTTL" => execute_ttl(arr, db, Unit::Millis).map(read),
+        "EXPIRETIME" => execute_expiretime(arr, db, Unit::Seconds).map(read),
+        "PEXPIRETIME" => execute_expiretime(arr, db, Unit::Millis).map(read),
+        "PERSIST" => execute_persist(arr, db),
+        "DBSIZE" => execute_dbsize(arr, db).map(read),
+        "KEYS" => execute_keys(arr, db).map(read),
+        "SCAN" => execute_scan(arr, db).map(read),
+        "SSCAN" | "HSCAN" | "ZSCAN" => execute_scan_collection(arr, db).map(read),
+        _ => Err(CommandError::Other(format!("unknown command '{}'", name))),
+    };
+
+    match result {
+        Ok(response) => response,
+        Err(err) => (err.into(), Propagation::None),
+    }
+}
+
+/// `DEL` and `UNLINK`, the latter leaving large values to the lazyfree
+/// thread.
+fn execute_del(
+    arr: &[Value],
+    db: &mut Cache,
+    lazy: bool,
+) -> Result<(Value, Propagation), CommandError> {
+    check_arity(arr, -2)?;
+    let mut deleted = 0;
+    for key in &arr[1..] {
+        let key = key.to_bytes();
+        let Some(value) = db.remove(&key) else {
+            continue;
+        };
+        db.notify(notify::GENERIC, "del", &key);
+        deleted += 1;
+        if lazy {
+            lazyfree::free(value);
+        }
+    }
+    let propagation = match deleted {
+        0 => Propagation::None,
+        _ => Propagation::Verbatim,
+    };
+    Ok((Integer(deleted), propagation))
+}
+
+/// `EXISTS` and `TOUCH`: counts the keys that exist, repeats included,
+/// bumping their last access.
+fn execute_exists(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, -2)?;
+    let count = arr[1..]
+        .iter()
+        .filter(|key| db.get_data(&key.to_bytes()).is_some())
+        .count();
+    Ok(Integer(count as i64))
+}
+
+fn execute_type(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, 2)?;
+    let name = match db.get_data(&arr[1].to_bytes()) {
+        Some(data) => data.type_name(),
+        None => "none",
+    };
+    Ok(SimpleString(name.to_string()))
+}
+
+/// `RENAME` and `RENAMENX`; the key keeps its TTL.
+fn execute_rename(
+    arr: &[Value],
+    db: &mut Cache,
+    nx: bool,
+) -> Result<(Value, Propagation), CommandError> {
+    check_arity(arr, 3)?;
+    let source = arr[1].to_bytes();
+    let destination = arr[2].to_bytes();
+    if db.get_data(&source).is_none() {
+        return Err(CommandError::Other("no such key".to_string()));
+    }
+    let renamed = |done: bool| match nx {
+        true => Integer(done as i64),
+        false => SimpleString("OK".to_string()),
+    };
+    if source == destination {
+        return Ok((renamed(!nx), Propagation::None));
+    }
+    if nx && db.get_data(&destination).is_some() {
+        return Ok((renamed(false), Propagation::None));
+    }
+    let expiry = db.expiry(&source);
+    if let Some(value) = db.remove(&source) {
+        db.notify(notify::GENERIC, "rename_from", &source);
+        db.insert(destination.clone(), value, expiry);
+        db.notify(notify::GENERIC, "rename_to", &destination);
+    }
+    Ok((renamed(true), Propagation::Verbatim))
+}
+
+fn execute_randomkey(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, 1)?;
+    Ok(match db.random_key() {
+        Some(key) => bulk(&key),
+        None => Null,
+    })
+}
+
+fn execute_dump(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, 2)?;
+    Ok(match db.get_data(&arr[1].to_bytes()) {
+        Some(data) => bulk(&rdb::dump(data)),
+        None => Null,
+    })
+}
+
+/// `RESTORE key ttl payload [REPLACE] [ABSTTL] [IDLETIME seconds] [FREQ n]`.
+/// Replicas are sent the expiry as an absolute time.
+fn execute_restore(arr: &[Value], db: &mut Cache) -> Result<(Value, Propagation), CommandError> {
+    check_arity(arr, -4)?;
+    let key = arr[1].to_bytes();
+    let ttl = parse_int(&arr[2])?;
+    let payload = arr[3].to_bytes();
+    let (mut replace, mut absttl) = (false, false);
+    let (mut idle_ms, mut frequency) = (None, None);
+    let mut i = 4;
+    // IDLETIME is for LRU and FREQ for LFU; like Redis, only one of them
+    // may be given.
+    while i < arr.len() {
+        if arg_eq(&arr[i], "REPLACE") {
+            replace = true;
+            i += 1;
+        } else if arg_eq(&arr[i], "ABSTTL") {
+            absttl = true;
+            i += 1;
+        } else if arg_eq(&arr[i], "IDLETIME") && i + 1 < arr.len() && frequency.is_none() {
+            let secs = u64::try_from(parse_int(&arr[i + 1])?).map_err(|_| {
+                CommandError::Other("Invalid IDLETIME value, must be >= 0".to_string())
+            })?;
+            idle_ms = Some(secs.saturating_mul(1000));
+            i += 2;
+        } else if arg_eq(&arr[i], "FREQ") && i + 1 < arr.len() && idle_ms.is_none() {
+            let freq = u8::try_from(parse_int(&arr[i + 1])?).map_err(|_| {
+                CommandError::Other("Invalid FREQ value, must be >= 0 and <= 255".to_string())
+            })?;
+            frequency = Some(freq);
+            i += 2;
+        } else {
+            return Err(CommandError::Syntax);
+        }
+    }
+    if ttl < 0 {
+        return Err(CommandError::Other(
+            "Invalid TTL value, must be >= 0".to_string(),
+        ));
+    }
+    if !replace && db.get_data(&key).is_some() {
+        return Err(CommandError::Raw(
+            "BUSYKEY Target key name already exists.".to_string(),
+        ));
+    }
+    if !rdb::verify_dump(&payload) {
+        return Err(CommandError::Other(
+            "DUMP payload version or checksum are wrong".to_string(),
+        ));
+    }
+    let data =
+        rdb::load_dump(&payload).map_err(|_| CommandError::Other("Bad data format".to_string()))?;
+
+    let ok = SimpleString("OK".to_string());
+    let expiry = match ttl as u64 {
+        0 => None,
+        at if absttl => Some(at),
+        ttl => Some(now_ms().saturating_add(ttl)),
+    };
+    if expiry.is_some_and(|at| at <= now_ms()) {
+        // Restoring an already expired key only deletes the one it replaces.
+        return Ok(match db.remove(&key) {
+            Some(_) => {
+                db.notify(notify::GENERIC, "del", &key);
+                let delete = Array(vec![bulk(b"DEL"), bulk(&key)]);
+                (ok, Propagation::Rewrite(vec![delete]))
+            }
+            None => (ok, Propagation::None),
+        });
+    }
+    db.insert(key.clone(), data, expiry);
+    db.set_access(&key, idle_ms, frequency);
+    db.notify(notify::GENERIC, "restore", &key);
+
+    let mut command = arr.to_vec();
+    if let (Some(at), false) = (expiry, absttl) {
+        command[2] = bulk(at.to_string().as_bytes());
+        command.push(bulk(b"ABSTTL"));
+    }
+    Ok((ok, Propagation::Rewrite(vec![Array(command)])))
+}
+
+/// The `PEXPIREAT` replicas receive for any change of expiry, so they
+/// don't depend on when they apply it.
+pub(crate) fn pexpireat(key: &[u8], unix_ms: u64) -> Value {
+    Array(vec![
+        BulkString(b"PEXPIREAT".to_vec()),
+        bulk(key),
+        BulkString(unix_ms.to_string().into_bytes()),
+    ])
+}
+
+/// `EXPIRE`, `PEXPIRE`, `EXPIREAT` and `PEXPIREAT` with `NX|XX|GT|LT`.
+fn execute_expire(
+    arr: &[Value],
+    db: &mut Cache,
+    unit: Unit,
+    absolute: bool,
+) -> Result<(Value, Propagation), CommandError> {
+    check_arity(arr, -3)?;
+    let key = arr[1].to_bytes();
+    let invalid = || {
+        CommandError::Other(format!(
+            "invalid expire time in '{}' command",
+            command_name(arr).to_lowercase()
+        ))
+    };
+
+    let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+    for option in &arr[3..] {
+        if arg_eq(option, "NX") {
+            nx = true;
+        } else if arg_eq(option, "XX") {
+            xx = true;
+        } else if arg_eq(option, "GT") {
+            gt = true;
+        } else if arg_eq(option, "LT") {
+            lt = true;
+        } else {
+            return Err(CommandError::Other(format!(
+                "Unsupported option {}",
+                String::from_utf8_lossy(&option.to_bytes())
+            )));
+        }
+    }
+    if nx && (xx || gt || lt) {
+        return Err(CommandError::Other(
+            "NX and XX, GT or LT options at the same time are not compatible".to_string(),
+        ));
+    }
+    if gt && lt {
+        return Err(CommandError::Other(
+            "GT and LT options at the same time are not compatible".to_string(),
+        ));
+    }
+
+    let n = parse_int(&arr[2])?;
+    let ms = match unit {
+        Unit::Seconds => n.checked_mul(1000).ok_or_else(invalid)?,
+        Unit::Millis => n,
+    };
+    let at = match absolute {
+        true => ms,
+        false => ms.checked_add(now_ms() as i64).ok_or_else(invalid)?,
+    };
+
+    if db.get_data(&key).is_none() {
+        return Ok((Integer(0), Propagation::None));
+    }
+    let current = db.expiry(&key).map(|expiry| expiry as i64);
+    let skip = match current {
+        Some(current) => nx || (gt && at <= current) || (lt && at >= current),
+        None => xx || gt,
+    };
+    if skip {
+        return Ok((Integer(0), Propagation::None));
+    }
+
+    let at = at.max(0) as u64;
+    db.set_expiry(&key, Some(at));
+    if at <= now_ms() {
+        db.remove(&key);
+        db.notify(notify::GENERIC, "del", &key);
+    } else {
+        db.notify(notify::GENERIC, "expire", &key);
+    }
+    Ok((Integer(1), Propagation::Rewrite(vec![pexpireat(&key, at)])))
+}
+
+fn execute_ttl(arr: &[Value], db: &mut Cache, unit: Unit) -> Result<Value, CommandError> {
+    check_arity(arr, 2)?;
+    let key = arr[1].to_bytes();
+    if db.get_data(&key).is_none() {
+        return Ok(Integer(-2));
+    }
+    let left = match db.expiry(&key) {
+        Some(expiry) => expiry.saturating_sub(now_ms()),
+        None => return Ok(Integer(-1)),
+    };
+    match unit {
+        Unit::Seconds => Ok(Integer(((left + 500) / 1000) as i64)),
+        Unit::Millis => Ok(Integer(left as i64)),
+    }
+}
+
+fn execute_expiretime(arr: &[Value], db: &mut Cache, unit: Unit) -> Result<Value, CommandError> {
+    check_arity(arr, 2)?;
+    let key = arr[1].to_bytes();
+    if db.get_data(&key).is_none() {
+        return Ok(Integer(-2));
+    }
+    let at = match db.expiry(&key) {
+        Some(expiry) => expiry,
+        None => return Ok(Integer(-1)),
+    };
+    match unit {
+        Unit::Seconds => Ok(Integer((at / 1000) as i64)),
+        Unit::Millis => Ok(Integer(at as i64)),
+    }
+}
+
+fn execute_persist(arr: &[Value], db: &mut Cache) -> Result<(Value, Propagation), CommandError> {
+    check_arity(arr, 2)?;
+    let key = arr[1].to_bytes();
+    if db.expiry(&key).is_none() {
+        return Ok((Integer(0), Propagation::None));
+    }
+    db.set_expiry(&key, None);
+    db.notify(notify::GENERIC, "persist", &key);
+    Ok((Integer(1), Propagation::Verbatim))
+}
+
+fn execute_dbsize(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, 1)?;
+    Ok(Integer(db.len() as i64))
+}
+
+fn execute_keys(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, 2)?;
+    let pattern = arr[1].to_bytes();
+    let keys = db
+        .keys()
+        .iter()
+        .filter(|key| glob::matches(&pattern, key))
+        .map(|key| bulk(key))
+        .collect();
+    Ok(Array(keys))
+}
+
+struct ScanOptions {
+    pattern: Option<Vec<u8>>,
+    count: usize,
+    type_name: Option<String>,
+}
+
+/// Parses `[MATCH pattern] [COUNT count]`, plus `[TYPE type]` for `SCAN`.
+fn parse_scan_options(args: &[Value], with_type: bool) -> Result<ScanOptions, CommandError> {
+    let mut options = ScanOptions {
+        pattern: None,
+        count: 10,
+        type_name: None,
+    };
+    for pair in args.chunks(2) {
+        let (option, value) = match pair {
+            [option, value] => (option, value),
+            _ => return Err(CommandError::Syntax),
+        };
+        if arg_eq(option, "MATCH") {
+            options.pattern = Some(value.to_bytes());
+        } else if arg_eq(option, "COUNT") {
+            let count = parse_int(value)?;
+            if count < 1 {
+                return Err(CommandError::Syntax);
+            }
+            options.count = count as usize;
+        } else if with_type && arg_eq(option, "TYPE") {
+            options.type_name = Some(String::from_utf8_lossy(&value.to_bytes()).to_lowercase());
+        } else {
+            return Err(CommandError::Syntax);
+        }
+    }
+    Ok(options)
+}
+
+fn parse_cursor(value: &Value) -> Result<u64, CommandError> {
+    String::from_utf8_lossy(&value.to_bytes())
+        .parse()
+        .map_err(|_| CommandError::Other("invalid cursor".to_string()))
+}
+
+fn scan_reply(cursor: u64, items: Vec<Value>) -> Value {
+    Array(vec![
+        BulkString(cursor.to_string().into_bytes()),
+        Array(items),
+    ])
+}
+
+fn execute_scan(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, -2)?;
+    let cursor = parse_cursor(&arr[1])?;
+    let options = parse_scan_options(&arr[2..], true)?;
+    let (cursor, keys) = db.scan(cursor, options.count, |key, data| {
+        options
+            .pattern
+            .as_ref()
+            .is_none_or(|pattern| glob::matches(pattern, key))
+            && options
+                .type_name
+                .as_ref()
+                .is_none_or(|name| name == data.type_name())
+    });
+    Ok(scan_reply(
+        cursor,
+        keys.iter().map(|key| bulk(key)).collect(),
+    ))
+}
+
+/// `SSCAN`, `HSCAN` and `ZSCAN`. Sets are paged through with the same
+/// cursor scheme as `SCAN`, their members standing in for slots. There are
+/// no hash or sorted set types, so `HSCAN` and `ZSCAN` only ever find
+/// missing keys or ones of the wrong type.
+fn execute_scan_collection(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, -3)?;
+    let key = arr[1].to_bytes();
+    let cursor = parse_cursor(&arr[2])?;
+    let options = parse_scan_options(&arr[3..], false)?;
+    let set = match command_name(arr).to_uppercase().as_str() {
+        "SSCAN" => db.get_set(&key)?,
+        _ if db.get_data(&key).is_some() => return Err(CommandError::WrongType),
+        _ => None,
+    };
+    let set = match set {
+        Some(set) => set,
+        None => return Ok(scan_reply(0, vec![])),
+    };
+    let slots = scan_slots(set.len(), cursor, options.count);
+    let cursor = slots.start as u64;
+    let members = slots
+        .rev()
+        .filter_map(|slot| set.get_index(slot))
+        .filter(|member| {
+            options
+                .pattern
+                .as_ref()
+                .is_none_or(|pattern| glob::matches(pattern, member))
+        })
+        .map(|member| bulk(member))
+        .collect();
+    Ok(scan_reply(cursor, members))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(db: &mut Cache, args: &[&str]) -> (Value, Propagation) {
+        let arr: Vec<Value> = args
+            .iter()
+            .map(|arg| BulkString(arg.to_string().into_bytes()))
+            .collect();
+        execute(args[0], &arr, db)
+    }
+
+    fn reply(db: &mut Cache, args: &[&str]) -> Value {
+        run(db, args).0
+    }
+
+    #[test]
+    fn test_del_exists_and_type() {
+        let mut db = Cache::new();
+        db.insert(b"a".to_vec(), b"1".to_vec(), None);
+        db.insert(b"b".to_vec(), b"2".to_vec(), None);
+        db.get_or_create_set(b"s").unwrap().insert(b"m".to_vec());
+        assert_eq!(reply(&mut db, &["EXISTS", "a", "a", "x"]), Integer(2));
+        assert_eq!(reply(&mut db, &["TOUCH", "a", "s", "x"]), Integer(2));
+        assert_eq!(
+            reply(&mut db, &["TYPE", "s"]),
+            SimpleString("set".to_string())
+        );
+        assert_eq!(
+            reply(&mut db, &["TYPE", "x"]),
+            SimpleString("none".to_string())
+        );
+
+        assert_eq!(
+            run(&mut db, &["DEL", "a", "x"]),
+            (Integer(1), Propagation::Verbatim)
+        );
+        assert_eq!(run(&mut db, &["DEL", "a"]), (Integer(0), Propagation::None));
+        assert_eq!(
+            run(&mut db, &["UNLINK", "b", "s"]),
+            (Integer(2), Propagation::Verbatim)
+        );
+        assert_eq!(db.len(), 0);
+        assert_eq!(reply(&mut db, &["RANDOMKEY"]), Null);
+        db.insert(b"c".to_vec(), b"3".to_vec(), None);
+        assert_eq!(reply(&mut db, &["RANDOMKEY"]), BulkString(b"c".to_vec()));
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut db = Cache::new();
+        let ok = SimpleString("OK".to_string());
+        db.insert(b"a".to_vec(), b"1".to_vec(), Some(u64::MAX));
+        assert_eq!(
+            run(&mut db, &["RENAME", "a", "b"]),
+            (ok.clone(), Propagation::Verbatim)
+        );
+        assert_eq!(db.get(b"b"), Ok(Some(b"1".to_vec())));
+        assert_eq!(db.expiry(b"b"), Some(u64::MAX));
+        assert_eq!(
+            reply(&mut db, &["RENAME", "a", "b"]),
+            SimpleError("ERR no such key".to_string())
+        );
+        assert_eq!(reply(&mut db, &["RENAME", "b", "b"]), ok);
+
+        db.insert(b"c".to_vec(), b"2".to_vec(), None);
+        assert_eq!(reply(&mut db, &["RENAMENX", "b", "c"]), Integer(0));
+        assert_eq!(reply(&mut db, &["RENAMENX", "b", "b"]), Integer(0));
+        assert_eq!(reply(&mut db, &["RENAMENX", "b", "d"]), Integer(1));
+        assert_eq!(reply(&mut db, &["RENAME", "d", "c"]), ok);
+        assert_eq!(db.get(b"c"), Ok(Some(b"1".to_vec())));
+        assert_eq!(db.len(), 1);
+    }
+
+    #[test]
+    fn test_dump_and_restore() {
+        let mut db = Cache::new();
+        let ok = SimpleString("OK".to_string());
+        db.get_or_create_set(b"s").unwrap().insert(b"m".to_vec());
+        assert_eq!(reply(&mut db, &["DUMP", "missing"]), Null);
+        let payload = match reply(&mut db, &["DUMP", "s"]) {
+            BulkString(payload) => payload,
+            other => panic!("Wrong type. got {:?}", other),
+        };
+        let restore = |db: &mut Cache, args: &[&str]| {
+            let mut arr: Vec<Value> = args.iter().map(|arg| bulk(arg.as_bytes())).collect();
+            arr.insert(3, BulkString(payload.clone()));
+            execute("RESTORE", &arr, db)
+        };
+
+        assert_eq!(
+            restore(&mut db, &["RESTORE", "s", "0"]).0,
+            SimpleError("BUSYKEY Target key name already exists.".to_string())
+        );
+        assert_eq!(restore(&mut db, &["RESTORE", "s", "0", "REPLACE"]).0, ok);
+        let (response, propagation) = restore(&mut db, &["RESTORE", "t", "10000", "IDLETIME", "5"]);
+        assert_eq!(response, ok);
+        assert_eq!(db.get_set(b"t").unwrap().unwrap().len(), 1);
+        let at = db.expiry(b"t").unwrap();
+        assert!(at > now_ms() + 9_000);
+        match propagation {
+            Propagation::Rewrite(commands) => match &commands[..] {
+                [Array(args)] => {
+                    assert_eq!(args[2], bulk(at.to_string().as_bytes()));
+                    assert_eq!(args.last(), Some(&bulk(b"ABSTTL")));
+                }
+                other => panic!("unexpected propagation {:?}", other),
+            },
+            other => panic!("unexpected propagation {:?}", other),
+        }
+
+        assert_eq!(
+            restore(&mut db, &["RESTORE", "t", "1", "REPLACE", "ABSTTL"]),
+            (
+                ok,
+                Propagation::Rewrite(vec![Array(vec![bulk(b"DEL"), bulk(b"t")])])
+            )
+        );
+        assert_eq!(db.get_data(b"t"), None);
+        assert_eq!(
+            restore(&mut db, &["RESTORE", "u", "-1"]).0,
+            SimpleError("ERR Invalid TTL value, must be >= 0".to_string())
+        );
+        assert_eq!(
+            restore(&mut db, &["RESTORE", "u", "0", "FREQ", "256"]).0,
+            SimpleError("ERR Invalid FREQ value, must be >= 0 and <= 255".to_string())
+        );
+        assert_eq!(
+            restore(
+                &mut db,
+                &["RESTORE", "u", "0", "IDLETIME", "5", "FREQ", "3"]
+            )
+            .0,
+            SimpleError("ERR syntax error".to_string())
+        );
+        assert_eq!(
+            restore(
+                &mut db,
+                &["RESTORE", "u", "0", "FREQ", "3", "IDLETIME", "5"]
+            )
+            .0,
+            SimpleError("ERR syntax error".to_string())
+        );
+        assert_eq!(
+            reply(&mut db, &["RESTORE", "u", "0", "garbage"]),
+            SimpleError("ERR DUMP payload version or checksum are wrong".to_string())
+        );
+    }
+
+    #[test]
+    fn test_expire_and_ttl() {
+        let mut db = Cache::new();
+        assert_eq!(reply(&mut db, &["EXPIRE", "k", "10"]), Integer(0));
+        assert_eq!(reply(&mut db, &["TTL", "k"]), Integer(-2));
+
+        db.insert(b"k".to_vec(), b"v".to_vec(), None);
+        assert_eq!(reply(&mut db, &["TTL", "k"]), Integer(-1));
+        assert_eq!(reply(&mut db, &["EXPIRETIME", "k"]), Integer(-1));
+        assert_eq!(reply(&mut db, &["EXPIRE", "k", "100"]), Integer(1));
+        assert_eq!(reply(&mut db, &["TTL", "k"]), Integer(100));
+        match reply(&mut db, &["PTTL", "k"]) {
+            Integer(ms) => assert!(ms > 99_000 && ms <= 100_000),
+            other => panic!("Wrong type. got {:?}", other),
+        }
+
+        let at = now_ms() + 50_000;
+        assert_eq!(
+            reply(&mut db, &["PEXPIREAT", "k", &at.to_string()]),
+            Integer(1)
+        );
+        assert_eq!(reply(&mut db, &["PEXPIRETIME", "k"]), Integer(at as i64));
+        assert_eq!(
+            reply(&mut db, &["EXPIRETIME", "k"]),
+            Integer((at / 1000) as i64)
+        );
+        assert_eq!(reply(&mut db, &["PERSIST", "k"]), Integer(1));
+        assert_eq!(reply(&mut db, &["PERSIST", "k"]), Integer(0));
+        assert_eq!(reply(&mut db, &["TTL", "k"]), Integer(-1));
+
+        assert_eq!(reply(&mut db, &["DBSIZE"]), Integer(1));
+        assert_eq!(reply(&mut db, &["EXPIRE", "k", "-1"]), Integer(1));
+        assert_eq!(db.get_data(b"k"), None);
+        assert_eq!(reply(&mut db, &["DBSIZE"]), Integer(0));
+    }
+
+    #[test]
+    fn test_expire_options() {
+        let mut db = Cache::new();
+        db.insert(b"k".to_vec(), b"v".to_vec(), None);
+        assert_eq!(reply(&mut db, &["EXPIRE", "k", "100", "XX"]), Integer(0));
+        assert_eq!(reply(&mut db, &["EXPIRE", "k", "100", "GT"]), Integer(0));
+        assert_eq!(reply(&mut db, &["EXPIRE", "k", "100", "NX"]), Integer(1));
+        assert_eq!(reply(&mut db, &["EXPIRE", "k", "200", "NX"]), Integer(0));
+        assert_eq!(reply(&mut db, &["EXPIRE", "k", "50", "GT"]), Integer(0));
+        assert_eq!(reply(&mut db, &["EXPIRE", "k", "200", "GT"]), Integer(1));
+        assert_eq!(reply(&mut db, &["EXPIRE", "k", "300", "LT"]), Integer(0));
+        assert_eq!(
+            reply(&mut db, &["EXPIRE", "k", "10", "LT", "XX"]),
+            Integer(1)
+        );
+        assert_eq!(reply(&mut db, &["TTL", "k"]), Integer(10));
+
+        assert_eq!(
+            reply(&mut db, &["EXPIRE", "k", "10", "NX", "GT"]),
+            SimpleError(
+                "ERR NX and XX, GT or LT options at the same time are not compatible".to_string()
+            )
+        );
+        assert_eq!(
+            reply(&mut db, &["EXPIRE", "k", "10", "GT", "LT"]),
+            SimpleError("ERR GT and LT options at the same time are not compatible".to_string())
+        );
+        assert_eq!(
+            reply(&mut db, &["EXPIRE", "k", "10", "SOON"]),
+            SimpleError("ERR Unsupported option SOON".to_string())
+        );
+        assert_eq!(
+            reply(&mut db, &["EXPIRE", "k", &i64::MAX.to_string()]),
+            SimpleError("ERR invalid expire time in 'expire' command".to_string())
+        );
+    }
+
+    #[test]
+    fn test_expire_propagates_absolute_time() {
+        let mut db = Cache::new();
+        db.insert(b"k".to_vec(), b"v".to_vec(), None);
+        let before = now_ms();
+        let (_, propagation) = run(&mut db, &["EXPIRE", "k", "10"]);
+        let at = match propagation {
+            Propagation::Rewrite(commands) => match &commands[..] {
+                [Array(args)] => {
+                    assert_eq!(args[0], BulkString(b"PEXPIREAT".to_vec()));
+                    match &args[2] {
+                        BulkString(at) => String::from_utf8_lossy(at).parse::<u64>().unwrap(),
+                        other => panic!("Wrong type. got {:?}", other),
+                    }
+                }
+                other => panic!("unexpected propagation {:?}", other),
+            },
+            other => panic!("unexpected propagation {:?}", other),
+        };
+        assert!(at >= before + 10_000 && at <= now_ms() + 10_000);
+    }
+
+    #[test]
+    fn test_keys_and_scan() {
+        let mut db = Cache::new();
+        for i in 0..100 {
+            db.insert(format!("user:{}", i).into_bytes(), b"v".to_vec(), None);
+        }
+        db.get_or_create_set(b"users")
+            .unwrap()
+            .insert(b"1".to_vec());
+        match reply(&mut db, &["KEYS", "user:?"]) {
+            Array(keys) => assert_eq!(keys.len(), 10),
+            other => panic!("Wrong type. got {:?}", other),
+        }
+
+        // Keys deleted or added halfway must not hide the ones that stay.
+        let mut cursor = "0".to_string();
+        let mut seen = Vec::new();
+        for round in 0.. {
+            if round == 3 {
+                for i in 0..20 {
+                    db.remove(format!("user:{}", i).as_bytes());
+                }
+                db.insert(b"late".to_vec(), b"v".to_vec(), None);
+            }
+            let (next, keys) = match reply(&mut db, &["SCAN", &cursor, "COUNT", "7"]) {
+                Array(mut reply) => match (reply.remove(0), reply.remove(0)) {
+                    (BulkString(next), Array(keys)) => (String::from_utf8(next).unwrap(), keys),
+                    other => panic!("unexpected reply {:?}", other),
+                },
+                other => panic!("Wrong type. got {:?}", other),
+            };
+            assert!(keys.len() <= 7);
+            seen.extend(keys);
+            if next == "0" {
+                break;
+            }
+            cursor = next;
+        }
+        for i in 20..100 {
+            assert!(seen.contains(&BulkString(format!("user:{}", i).into_bytes())));
+        }
+        assert!(seen.contains(&BulkString(b"users".to_vec())));
+
+        assert_eq!(
+            reply(&mut db, &["SCAN", "0", "COUNT", "1000", "TYPE", "set"]),
+            Array(vec![
+                BulkString(b"0".to_vec()),
+                Array(vec![BulkString(b"users".to_vec())])
+            ])
+        );
+        assert_eq!(
+            reply(&mut db, &["SCAN", "0", "MATCH", "user:9?", "COUNT", "1000"]),
+            reply(&mut db, &["SCAN", "0", "COUNT", "1000", "MATCH", "user:9?"])
+        );
+        assert_eq!(
+            reply(&mut db, &["SCAN", "x"]),
+            SimpleError("ERR invalid cursor".to_string())
+        );
+        assert_eq!(
+            reply(&mut db, &["SCAN", "0", "COUNT", "0"]),
+            SimpleError("ERR syntax error".to_string())
+        );
+    }
+
+    #[test]
+    fn test_collection_scans() {
+        let mut db = Cache::new();
+        let set = db.get_or_create_set(b"s").unwrap();
+        set.insert(b"apple".to_vec());
+        set.insert(b"avocado".to_vec());
+        set.insert(b"banana".to_vec());
+        db.insert(b"str".to_vec(), b"v".to_vec(), None);
+
+        match reply(&mut db, &["SSCAN", "s", "0", "MATCH", "a*"]) {
+            Array(reply) => {
+                assert_eq!(reply[0], BulkString(b"0".to_vec()));
+                match &reply[1] {
+                    Array(members) => assert_eq!(members.len(), 2),
+                    other => panic!("Wrong type. got {:?}", other),
+                }
+            }
+            other => panic!("Wrong type. got {:?}", other),
+        }
+        let empty = Array(vec![BulkString(b"0".to_vec()), Array(vec![])]);
+        assert_eq!(reply(&mut db, &["SSCAN", "missing", "0"]), empty);
+        assert_eq!(reply(&mut db, &["HSCAN", "missing", "0"]), empty);
+        assert_eq!(
+            reply(&mut db, &["ZSCAN", "str", "0"]),
+            SimpleError(
+                "WRONGTYPE Operation against a key holding the wrong kind of value".to_string()
+            )
+        );
+        assert_eq!(
+            reply(&mut db, &["SSCAN", "s", "0", "TYPE", "set"]),
+            SimpleError("ERR syntax error".to_string())
+        );
+    }
+
+    #[test]
+    fn test_sscan_pages() {
+        let mut db = Cache::new();
+        let set = db.get_or_create_set(b"s").unwrap();
+        for i in 0..100 {
+            set.insert(format!("m{}", i).into_bytes());
+        }
+
+        // Members removed or added halfway must not hide the ones that stay.
+        let mut cursor = "0".to_string();
+        let mut seen = Vec::new();
+        for round in 0.. {
+            if round == 3 {
+                let set = db.get_set_mut(b"s").unwrap().unwrap();
+                for i in 0..20 {
+                    set.swap_remove(format!("m{}", i).as_bytes());
+                }
+                set.insert(b"late".to_vec());
+            }
+            let (next, members) = match reply(&mut db, &["SSCAN", "s", &cursor, "COUNT", "7"]) {
+                Array(mut reply) => match (reply.remove(0), reply.remove(0)) {
+                    (BulkString(next), Array(members)) => {
+                        (String::from_utf8(next).unwrap(), members)
+                    }
+                    other => panic!("unexpected reply {:?}", other),
+                },
+                other => panic!("Wrong type. got {:?}", other),
+            };
+            assert!(members.len() <= 7);
+            seen.extend(members);
+            if next == "0" {
+                break;
+            }
+            cursor = next;
+        }
+        for i in 20..100 {
+            assert!(seen.contains(&BulkString(format!("m{}", i).into_bytes())));
+        }
+    }
+}
@@ -0,0 +1,435 @@
+//! `MIGRATE`, which moves keys to another instance by sending it `DUMP`
+//! payloads in `RESTORE`s. Like Redis, it blocks the event loop on the
+//! target for at most the given timeout.
+
+use super::{arg_eq, bulk, check_arity, parse_int, CommandError, Propagation};
+use crate::cache::{now_ms, Cache};
+use crate::persistence::rdb;
+use crate::resp::value::Value;
+use crate::resp::value::Value::*;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(1000);
+
+struct Migrate {
+    host: String,
+    port: u16,
+    db: i64,
+    timeout: Duration,
+    copy: bool,
+    replace: bool,
+    keys: Vec<Vec<u8>>,
+}
+
+/// A key as it is sent: its `DUMP` payload and remaining TTL, 0 for none.
+struct Outgoing {
+    key: Vec<u8>,
+    payload: Vec<u8>,
+    ttl: u64,
+}
+
+pub fn execute(arr: &[Value], db: &mut Cache) -> (Value, Propagation) {
+    match execute_migrate(arr, db) {
+        Ok(response) => response,
+        Err(err) => (err.into(), Propagation::None),
+    }
+}
+
+/// `MIGRATE host port key|"" destination-db timeout [COPY] [REPLACE]
+/// [KEYS key ...]`
+fn parse_migrate(arr: &[Value]) -> Result<Migrate, CommandError> {
+    check_arity(arr, -6)?;
+    let port = u16::try_from(parse_int(&arr[2])?).map_err(|_| CommandError::NotInteger)?;
+    let timeout = match parse_int(&arr[5])? {
+        ms if ms <= 0 => DEFAULT_TIMEOUT,
+        ms => Duration::from_millis(ms as u64),
+    };
+    let mut migrate = Migrate {
+        host: String::from_utf8_lossy(&arr[1].to_bytes()).into_owned(),
+        port,
+        db: parse_int(&arr[4])?,
+        timeout,
+        copy: false,
+        replace: false,
+        keys: vec![arr[3].to_bytes()],
+    };
+
+    let mut i = 6;
+    while i < arr.len() {
+        if arg_eq(&arr[i], "COPY") {
+            migrate.copy = true;
+        } else if arg_eq(&arr[i], "REPLACE") {
+            migrate.replace = true;
+        } else if arg_eq(&arr[i], "KEYS") {
+            if !migrate.keys[0].is_empty() {
+                return Err(CommandError::Other(
+                    "When using MIGRATE KEYS option, the key argument must be set to the empty string"
+                        .to_string(),
+                ));
+            }
+            migrate.keys = arr[i + 1..].iter().map(Value::to_bytes).collect();
+            break;
+        } else {
+            return Err(CommandError::Syntax);
+        }
+        i += 1;
+    }
+    Ok(migrate)
+}
+
+/// The keys of a `MIGRATE`: its `key` argument, or those after `KEYS` when
+/// that is empty.
+pub fn key_positions(arr: &[Value]) -> Vec<usize> {
+    if !arr[3].to_bytes().is_empty() {
+        return vec![3];
+    }
+    match arr.iter().skip(6).position(|arg| arg_eq(arg, "KEYS")) {
+        Some(i) => (i + 7..arr.len()).collect(),
+        None => Vec::new(),
+    }
+}
+
+fn execute_migrate(arr: &[Value], db: &mut Cache) -> Result<(Value, Propagation), CommandError> {
+    let migrate = parse_migrate(arr)?;
+
+    // Keys that don't exist are skipped; if none do there's nothing to send.
+    let mut outgoing = Vec::new();
+    for key in migrate.keys {
+        let Some(data) = db.get_data(&key) else {
+            continue;
+        };
+        let payload = rdb::dump(data);
+        let ttl = match db.expiry(&key) {
+            Some(at) => at.saturating_sub(now_ms()).max(1),
+            None => 0,
+        };
+        outgoing.push(Outgoing { key, payload, ttl });
+    }
+    if outgoing.is_empty() {
+        return Ok((SimpleString("NOKEY".to_string()), Propagation::None));
+    }
+
+    let ioerr = |action: &str| {
+        CommandError::Raw(format!("IOERR error or timeout {} target instance", action))
+    };
+    let mut stream = connect(&migrate.host, migrate.port, migrate.timeout)
+        .map_err(|_| ioerr("connecting to"))?;
+    send(&mut stream, migrate.db, &outgoing, migrate.replace).map_err(|_| ioerr("writing to"))?;
+    let replies =
+        read_replies(&mut stream, outgoing.len() + 1).map_err(|_| ioerr("reading from"))?;
+
+    let target_error =
+        |err: &str| CommandError::Other(format!("Target instance replied with error: {}", err));
+    if let Err(err) = &replies[0] {
+        return Err(target_error(err));
+    }
+    // Keys the target took are gone from here even if others failed.
+    let mut first_error = None;
+    let mut moved = Vec::new();
+    for (item, reply) in outgoing.into_iter().zip(&replies[1..]) {
+        match reply {
+            Ok(()) if !migrate.copy => {
+                db.remove(&item.key);
+                moved.push(bulk(&item.key));
+            }
+            Ok(()) => {}
+            Err(err) => {
+                first_error.get_or_insert_with(|| err.clone());
+            }
+        }
+    }
+
+    let propagation = match moved.is_empty() {
+        true => Propagation::None,
+        false => {
+            moved.insert(0, bulk(b"DEL"));
+            Propagation::Rewrite(vec![Array(moved)])
+        }
+    };
+    let reply = match first_error {
+        Some(err) => target_error(&err).into(),
+        None => SimpleString("OK".to_string()),
+    };
+    Ok((reply, propagation))
+}
+
+fn connect(host: &str, port: u16, timeout: Duration) -> io::Result<TcpStream> {
+    let address = (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::other("no address for host"))?;
+    let stream = TcpStream::connect_timeout(&address, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+/// Pipelines a `SELECT` and one `RESTORE` per key.
+fn send<S: Write>(stream: &mut S, db: i64, outgoing: &[Outgoing], replace: bool) -> io::Result<()> {
+    let select = Array(vec![bulk(b"SELECT"), bulk(db.to_string().as_bytes())]);
+    let mut buffer = select.to_resp();
+    for item in outgoing {
+        let mut restore = vec![
+            bulk(b"RESTORE"),
+            bulk(&item.key),
+            bulk(item.ttl.to_string().as_bytes()),
+            bulk(&item.payload),
+        ];
+        if replace {
+            restore.push(bulk(b"REPLACE"));
+        }
+        buffer.extend(Array(restore).to_resp());
+    }
+    stream.write_all(&buffer)?;
+    stream.flush()
+}
+
+/// Reads `count` replies, each `+OK` or an error, which are all one line.
+fn read_replies<S: Read>(stream: &mut S, count: usize) -> io::Result<Vec<Result<(), String>>> {
+    let mut replies = Vec::with_capacity(count);
+    let mut buffer = Vec::new();
+    let mut chunk = [0; 512];
+    while replies.len() < count {
+        let Some(end) = buffer.windows(2).position(|w| w == b"\r\n") else {
+            let n = stream.read(&mut chunk)?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            buffer.extend_from_slice(&chunk[..n]);
+            continue;
+        };
+        let line: Vec<u8> = buffer.drain(..end + 2).collect();
+        replies.push(match line.first() {
+            Some(b'-') => Err(String::from_utf8_lossy(&line[1..end]).into_owned()),
+            _ => Ok(()),
+        });
+    }
+    Ok(replies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::keyspace;
+    use crate::resp::parser;
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+
+    fn call(args: &[&str]) -> Vec<Value> {
+        args.iter().map(|arg| bulk(arg.as_bytes())).collect()
+    }
+
+    /// A target instance on loopback serving `connections` connections one
+    /// after the other, applying the `RESTORE`s sent to `target`, which it
+    /// hands back at the end.
+    fn serve(mut target: Cache, connections: usize) -> (String, JoinHandle<Cache>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port().to_string();
+        let handle = thread::spawn(move || {
+            for _ in 0..connections {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut buffer = Vec::new();
+                let mut chunk = [0; 4096];
+                loop {
+                    let n = stream.read(&mut chunk).unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    buffer.extend_from_slice(&chunk[..n]);
+                    while let Ok((consumed, Array(arr))) = parser::parse(&buffer) {
+                        buffer.drain(..consumed);
+                        let name = String::from_utf8(arr[0].to_bytes()).unwrap();
+                        let reply = match name.as_str() {
+                            "SELECT" => SimpleString("OK".to_string()),
+                            _ => keyspace::execute(&name, &arr, &mut target).0,
+                        };
+                        stream.write_all(&reply.to_resp()).unwrap();
+                    }
+                }
+            }
+            target
+        });
+        (port, handle)
+    }
+
+    #[test]
+    fn test_migrate_to_a_listening_target() {
+        let mut target = Cache::new();
+        target.insert(b"busy".to_vec(), b"theirs".to_vec(), None);
+        let (port, handle) = serve(target, 3);
+
+        let mut db = Cache::new();
+        for key in ["a", "c", "busy", "d"] {
+            db.insert(key.as_bytes().to_vec(), b"1".to_vec(), None);
+        }
+        db.get_or_create_set(b"s").unwrap().insert(b"m".to_vec());
+        let migrate = |db: &mut Cache, options: &[&str]| {
+            let mut args = vec!["MIGRATE", "127.0.0.1", &port, "", "0", "1000"];
+            args.extend_from_slice(options);
+            execute(&call(&args), db)
+        };
+
+        assert_eq!(
+            migrate(&mut db, &["KEYS", "a", "s", "missing"]),
+            (
+                SimpleString("OK".to_string()),
+                Propagation::Rewrite(vec![Array(call(&["DEL", "a", "s"]))])
+            )
+        );
+        assert_eq!(db.get(b"a"), Ok(None));
+        assert_eq!(db.get_set(b"s"), Ok(None));
+
+        assert_eq!(
+            migrate(&mut db, &["COPY", "KEYS", "c"]),
+            (SimpleString("OK".to_string()), Propagation::None)
+        );
+        assert_eq!(db.get(b"c"), Ok(Some(b"1".to_vec())));
+
+        // The key the target refused stays; the one it took is moved.
+        assert_eq!(
+            migrate(&mut db, &["KEYS", "busy", "d"]),
+            (
+                SimpleError(
+                    "ERR Target instance replied with error: BUSYKEY Target key name already \
+                     exists."
+                        .to_string()
+                ),
+                Propagation::Rewrite(vec![Array(call(&["DEL", "d"]))])
+            )
+        );
+        assert_eq!(db.get(b"busy"), Ok(Some(b"1".to_vec())));
+        assert_eq!(db.get(b"d"), Ok(None));
+
+        let mut target = handle.join().unwrap();
+        for key in [&b"a"[..], b"c", b"d"] {
+            assert_eq!(target.get(key), Ok(Some(b"1".to_vec())));
+        }
+        assert_eq!(target.get(b"busy"), Ok(Some(b"theirs".to_vec())));
+        assert!(target
+            .get_set(b"s")
+            .unwrap()
+            .unwrap()
+            .contains(&b"m".to_vec()));
+    }
+
+    fn outgoing(db: &mut Cache, key: &[u8]) -> Outgoing {
+        Outgoing {
+            key: key.to_vec(),
+            payload: rdb::dump(db.get_data(key).unwrap()),
+            ttl: 0,
+        }
+    }
+
+    #[test]
+    fn test_restores_on_target() {
+        let mut source = Cache::new();
+        source.insert(b"a".to_vec(), b"1".to_vec(), None);
+        source
+            .get_or_create_set(b"s")
+            .unwrap()
+            .insert(b"m".to_vec());
+        let items = [outgoing(&mut source, b"a"), outgoing(&mut source, b"s")];
+
+        let mut sent = Vec::new();
+        send(&mut sent, 3, &items, true).unwrap();
+
+        // Play the pipeline against a target keyspace.
+        let mut target = Cache::new();
+        let mut offset = 0;
+        let mut replies = Vec::new();
+        while offset < sent.len() {
+            let (consumed, command) = parser::parse(&sent[offset..]).unwrap();
+            offset += consumed;
+            let arr = match command {
+                Array(arr) => arr,
+                other => panic!("Wrong type. got {:?}", other),
+            };
+            let name = String::from_utf8(arr[0].to_bytes()).unwrap();
+            match name.as_str() {
+                "SELECT" => {
+                    assert_eq!(arr[1], bulk(b"3"));
+                    replies.extend(b"+OK\r\n");
+                }
+                _ => replies.extend(keyspace::execute(&name, &arr, &mut target).0.to_resp()),
+            }
+        }
+        assert_eq!(target.get(b"a"), Ok(Some(b"1".to_vec())));
+        assert_eq!(target.get_set(b"s").unwrap().unwrap().len(), 1);
+        assert_eq!(
+            read_replies(&mut &replies[..], 3).unwrap(),
+            vec![Ok(()), Ok(()), Ok(())]
+        );
+    }
+
+    #[test]
+    fn test_read_replies() {
+        let replies = b"+OK\r\n-BUSYKEY Target key name already exists.\r\n+OK\r\n";
+        assert_eq!(
+            read_replies(&mut &replies[..], 3).unwrap(),
+            vec![
+                Ok(()),
+                Err("BUSYKEY Target key name already exists.".to_string()),
+                Ok(())
+            ]
+        );
+        assert!(read_replies(&mut &replies[..20], 3).is_err());
+    }
+
+    #[test]
+    fn test_unreachable_target_keeps_keys() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+            .to_string();
+        let mut db = Cache::new();
+        db.insert(b"k".to_vec(), b"v".to_vec(), None);
+        let arr: Vec<Value> = ["MIGRATE", "127.0.0.1", &port, "k", "0", "100"]
+            .iter()
+            .map(|arg| bulk(arg.as_bytes()))
+            .collect();
+        assert_eq!(
+            execute(&arr, &mut db),
+            (
+                SimpleError("IOERR error or timeout connecting to target instance".to_string()),
+                Propagation::None
+            )
+        );
+        assert_eq!(db.get(b"k"), Ok(Some(b"v".to_vec())));
+    }
+
+    #[test]
+    fn test_arguments() {
+        let mut db = Cache::new();
+        let run = |db: &mut Cache, args: &[&str]| {
+            let arr: Vec<Value> = args.iter().map(|arg| bulk(arg.as_bytes())).collect();
+            execute(&arr, db).0
+        };
+        assert_eq!(
+            run(&mut db, &["MIGRATE", "localhost", "1", "k", "0", "10"]),
+            SimpleString("NOKEY".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["MIGRATE", "localhost", "1", "k", "0", "10", "KEYS", "a"]),
+            SimpleError(
+                "ERR When using MIGRATE KEYS option, the key argument must be set to the empty string"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            run(
+                &mut db,
+                &["MIGRATE", "localhost", "1", "k", "0", "10", "MOVE"]
+            ),
+            SimpleError("ERR syntax error".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["MIGRATE", "localhost", "port", "k", "0", "10"]),
+            SimpleError("ERR value is not an integer or out of range".to_string())
+        );
+    }
+}
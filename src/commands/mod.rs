@@ -0,0 +1,107 @@
+pub mod client;
+pub mod database;
+pub mod keyspace;
+pub mod migrate;
+pub mod pubsub;
+pub mod set;
+pub mod stream;
+pub mod string;
+pub mod table;
+
+use crate::cache::WrongType;
+use crate::resp::value::Value;
+use std::fmt;
+
+/// What should be sent to replicas once a command has executed.
+#[derive(Debug, PartialEq)]
+pub enum Propagation {
+    None,
+    /// Forward the command exactly as the client sent it.
+    Verbatim,
+    /// Forward these commands instead, e.g. when the original is not
+    /// deterministic (`SPOP`) and replicas must apply its effects.
+    Rewrite(Vec<Value>),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CommandError {
+    WrongArity(String),
+    WrongType,
+    NotInteger,
+    Syntax,
+    Other(String),
+    /// An error reply that carries its own code instead of `ERR`,
+    /// e.g. `NOGROUP ...`.
+    Raw(String),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::WrongArity(name) => write!(
+                f,
+                "ERR wrong number of arguments for '{}' command",
+                name.to_lowercase()
+            ),
+            CommandError::WrongType => write!(
+                f,
+                "WRONGTYPE Operation against a key holding the wrong kind of value"
+            ),
+            CommandError::NotInteger => write!(f, "ERR value is not an integer or out of range"),
+            CommandError::Syntax => write!(f, "ERR syntax error"),
+            CommandError::Other(msg) => write!(f, "ERR {}", msg),
+            CommandError::Raw(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl From<WrongType> for CommandError {
+    fn from(_: WrongType) -> Self {
+        CommandError::WrongType
+    }
+}
+
+impl From<CommandError> for Value {
+    fn from(err: CommandError) -> Self {
+        Value::SimpleError(err.to_string())
+    }
+}
+
+pub(crate) fn bulk(bytes: &[u8]) -> Value {
+    Value::BulkString(bytes.to_vec())
+}
+
+/// Case-insensitive comparison of an argument against a keyword.
+pub(crate) fn arg_eq(value: &Value, keyword: &str) -> bool {
+    match value {
+        Value::BulkString(s) => s.eq_ignore_ascii_case(keyword.as_bytes()),
+        _ => false,
+    }
+}
+
+pub(crate) fn parse_int(value: &Value) -> Result<i64, CommandError> {
+    match value {
+        Value::BulkString(s) => std::str::from_utf8(s)
+            .ok()
+            .and_then(|s| s.parse::<i64>().ok())
+            .ok_or(CommandError::NotInteger),
+        _ => Err(CommandError::NotInteger),
+    }
+}
+
+/// Checks the argument count the way Redis describes arity: a positive value
+/// is an exact count, a negative one a minimum. Both include the command name.
+pub(crate) fn check_arity(arr: &[Value], arity: i64) -> Result<(), CommandError> {
+    let len = arr.len() as i64;
+    if (arity > 0 && len != arity) || (arity < 0 && len < -arity) {
+        return Err(CommandError::WrongArity(command_name(arr)));
+    }
+    Ok(())
+}
+
+pub(crate) fn command_name(arr: &[Value]) -> String {
+    match arr.first() {
+        Some(Value::BulkString(name)) => String::from_utf8_lossy(name).into_owned(),
+        _ => String::new(),
+    }
+}
@@ -0,0 +1,293 @@
+//! Subscribing to channels and patterns, and `PUBSUB` introspection.
+//! `PUBLISH` is run by the handler, which owns the connections it writes to.
+
+use super::table::{Command, Keys, LOADING, NOSCRIPT, NO_MULTI, PUBSUB, STALE};
+use super::{arg_eq, bulk, check_arity, CommandError};
+use crate::pubsub::{Kind, PubSub};
+use crate::resp::value::Value;
+use crate::resp::value::Value::*;
+use mio::Token;
+
+/// The subscription commands send a reply per channel, which `EXEC` could
+/// not fit into one reply per command, so they are refused inside `MULTI`.
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "SUBSCRIBE",
+        arity: -2,
+        flags: PUBSUB | NOSCRIPT | LOADING | STALE | NO_MULTI,
+        keys: Keys::None,
+        group: "pubsub",
+        summary: "Listens for messages published to channels.",
+    },
+    Command {
+        name: "UNSUBSCRIBE",
+        arity: -1,
+        flags: PUBSUB | NOSCRIPT | LOADING | STALE | NO_MULTI,
+        keys: Keys::None,
+        group: "pubsub",
+        summary: "Stops listening to messages posted to channels.",
+    },
+    Command {
+        name: "PSUBSCRIBE",
+        arity: -2,
+        flags: PUBSUB | NOSCRIPT | LOADING | STALE | NO_MULTI,
+        keys: Keys::None,
+        group: "pubsub",
+        summary: "Listens for messages published to channels that match one or more patterns.",
+    },
+    Command {
+        name: "PUNSUBSCRIBE",
+        arity: -1,
+        flags: PUBSUB | NOSCRIPT | LOADING | STALE | NO_MULTI,
+        keys: Keys::None,
+        group: "pubsub",
+        summary:
+            "Stops listening to messages published to channels that match one or more patterns.",
+    },
+    Command {
+        name: "SSUBSCRIBE",
+        arity: -2,
+        flags: PUBSUB | NOSCRIPT | LOADING | STALE | NO_MULTI,
+        keys: Keys::None,
+        group: "pubsub",
+        summary: "Listens for messages published to shard channels.",
+    },
+    Command {
+        name: "SUNSUBSCRIBE",
+        arity: -1,
+        flags: PUBSUB | NOSCRIPT | LOADING | STALE | NO_MULTI,
+        keys: Keys::None,
+        group: "pubsub",
+        summary: "Stops listening to messages posted to shard channels.",
+    },
+    Command {
+        name: "PUBSUB",
+        arity: -2,
+        flags: PUBSUB | LOADING | STALE,
+        keys: Keys::None,
+        group: "pubsub",
+        summary: "Inspects the state of the Pub/Sub subsystem.",
+    },
+];
+
+/// Runs a subscription command for `client`, returning its replies: one
+/// confirmation per channel or pattern for the (un)subscribe commands.
+pub fn execute(name: &str, arr: &[Value], pubsub: &mut PubSub, client: Token) -> Vec<Value> {
+    let result = match name {
+        "SUBSCRIBE" => execute_subscribe(arr, pubsub, client, Kind::Channel),
+        "PSUBSCRIBE" => execute_subscribe(arr, pubsub, client, Kind::Pattern),
+        "SSUBSCRIBE" => execute_subscribe(arr, pubsub, client, Kind::Shard),
+        "UNSUBSCRIBE" => Ok(execute_unsubscribe(arr, pubsub, client, Kind::Channel)),
+        "PUNSUBSCRIBE" => Ok(execute_unsubscribe(arr, pubsub, client, Kind::Pattern)),
+        "SUNSUBSCRIBE" => Ok(execute_unsubscribe(arr, pubsub, client, Kind::Shard)),
+        "PUBSUB" => execute_pubsub(arr, pubsub).map(|reply| vec![reply]),
+        _ => Err(CommandError::Other(format!("unknown command '{}'", name))),
+    };
+
+    match result {
+        Ok(replies) => replies,
+        Err(err) => vec![err.into()],
+    }
+}
+
+fn confirmation(kind: &[u8], name: Value, count: usize) -> Value {
+    Push(vec![bulk(kind), name, Integer(count as i64)])
+}
+
+fn execute_subscribe(
+    arr: &[Value],
+    pubsub: &mut PubSub,
+    client: Token,
+    kind: Kind,
+) -> Result<Vec<Value>, CommandError> {
+    check_arity(arr, -2)?;
+    let reply: &[u8] = match kind {
+        Kind::Channel => b"subscribe",
+        Kind::Pattern => b"psubscribe",
+        Kind::Shard => b"ssubscribe",
+    };
+    let mut replies = Vec::with_capacity(arr.len() - 1);
+    for name in &arr[1..] {
+        let name = name.to_bytes();
+        pubsub.subscribe(kind, client, &name);
+        replies.push(confirmation(reply, bulk(&name), pubsub.count(kind, client)));
+    }
+    Ok(replies)
+}
+
+/// Without arguments, unsubscribes from everything of `kind`; with nothing
+/// to unsubscribe from, still confirms once with a null name.
+fn execute_unsubscribe(
+    arr: &[Value],
+    pubsub: &mut PubSub,
+    client: Token,
+    kind: Kind,
+) -> Vec<Value> {
+    let reply: &[u8] = match kind {
+        Kind::Channel => b"unsubscribe",
+        Kind::Pattern => b"punsubscribe",
+        Kind::Shard => b"sunsubscribe",
+    };
+    let names = match arr.len() {
+        1 => pubsub.subscriptions(kind, client),
+        _ => arr[1..].iter().map(Value::to_bytes).collect(),
+    };
+    if names.is_empty() {
+        return vec![confirmation(reply, Null, pubsub.count(kind, client))];
+    }
+    names
+        .into_iter()
+        .map(|name| {
+            pubsub.unsubscribe(kind, client, &name);
+            confirmation(reply, bulk(&name), pubsub.count(kind, client))
+        })
+        .collect()
+}
+
+/// `PUBSUB CHANNELS|SHARDCHANNELS [pattern]`, `PUBSUB NUMSUB|SHARDNUMSUB
+/// [channel ...]` and `PUBSUB NUMPAT`.
+fn execute_pubsub(arr: &[Value], pubsub: &PubSub) -> Result<Value, CommandError> {
+    check_arity(arr, -2)?;
+    let subcommand = &arr[1];
+    let channels = |kind| {
+        let pattern = arr.get(2).map(Value::to_bytes);
+        let mut channels = pubsub.active_channels(kind, pattern.as_deref());
+        channels.sort();
+        Array(channels.iter().map(|c| bulk(c)).collect())
+    };
+    let numsub = |kind| {
+        let mut counts = Vec::with_capacity((arr.len() - 2) * 2);
+        for channel in &arr[2..] {
+            let channel = channel.to_bytes();
+            counts.push(bulk(&channel));
+            counts.push(Integer(pubsub.numsub(kind, &channel) as i64));
+        }
+        Array(counts)
+    };
+    if arg_eq(subcommand, "CHANNELS") && arr.len() <= 3 {
+        Ok(channels(Kind::Channel))
+    } else if arg_eq(subcommand, "SHARDCHANNELS") && arr.len() <= 3 {
+        Ok(channels(Kind::Shard))
+    } else if arg_eq(subcommand, "NUMSUB") {
+        Ok(numsub(Kind::Channel))
+    } else if arg_eq(subcommand, "SHARDNUMSUB") {
+        Ok(numsub(Kind::Shard))
+    } else if arg_eq(subcommand, "NUMPAT") && arr.len() == 2 {
+        Ok(Integer(pubsub.numpat() as i64))
+    } else {
+        Err(CommandError::Other(format!(
+            "unknown subcommand or wrong number of arguments for '{}'. Try PUBSUB HELP.",
+            String::from_utf8_lossy(&subcommand.to_bytes())
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(pubsub: &mut PubSub, client: Token, args: &[&str]) -> Vec<Value> {
+        let arr: Vec<Value> = args.iter().map(|arg| bulk(arg.as_bytes())).collect();
+        execute(&args[0].to_uppercase(), &arr, pubsub, client)
+    }
+
+    #[test]
+    fn test_subscribe_and_unsubscribe() {
+        let mut pubsub = PubSub::default();
+        let client = Token(2);
+        assert_eq!(
+            run(&mut pubsub, client, &["SUBSCRIBE", "a", "b"]),
+            vec![
+                confirmation(b"subscribe", bulk(b"a"), 1),
+                confirmation(b"subscribe", bulk(b"b"), 2),
+            ]
+        );
+        assert_eq!(
+            run(&mut pubsub, client, &["PSUBSCRIBE", "c*"]),
+            vec![confirmation(b"psubscribe", bulk(b"c*"), 3)]
+        );
+        assert_eq!(
+            run(&mut pubsub, client, &["UNSUBSCRIBE"]),
+            vec![
+                confirmation(b"unsubscribe", bulk(b"a"), 2),
+                confirmation(b"unsubscribe", bulk(b"b"), 1),
+            ]
+        );
+        assert_eq!(
+            run(&mut pubsub, client, &["UNSUBSCRIBE"]),
+            vec![confirmation(b"unsubscribe", Null, 1)]
+        );
+        assert_eq!(
+            run(&mut pubsub, client, &["PUNSUBSCRIBE", "c*"]),
+            vec![confirmation(b"punsubscribe", bulk(b"c*"), 0)]
+        );
+        assert_eq!(
+            run(&mut pubsub, client, &["SSUBSCRIBE", "a"]),
+            vec![confirmation(b"ssubscribe", bulk(b"a"), 1)]
+        );
+        assert_eq!(
+            run(&mut pubsub, client, &["SUNSUBSCRIBE"]),
+            vec![confirmation(b"sunsubscribe", bulk(b"a"), 0)]
+        );
+        assert_eq!(
+            run(&mut pubsub, client, &["SUBSCRIBE"]),
+            vec![SimpleError(
+                "ERR wrong number of arguments for 'subscribe' command".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_pubsub_introspection() {
+        let mut pubsub = PubSub::default();
+        run(&mut pubsub, Token(2), &["SUBSCRIBE", "news", "sport"]);
+        run(&mut pubsub, Token(3), &["SUBSCRIBE", "news"]);
+        run(&mut pubsub, Token(3), &["PSUBSCRIBE", "n*"]);
+        assert_eq!(
+            run(&mut pubsub, Token(2), &["PUBSUB", "CHANNELS"]),
+            vec![Array(vec![bulk(b"news"), bulk(b"sport")])]
+        );
+        assert_eq!(
+            run(&mut pubsub, Token(2), &["PUBSUB", "channels", "s*"]),
+            vec![Array(vec![bulk(b"sport")])]
+        );
+        assert_eq!(
+            run(&mut pubsub, Token(2), &["PUBSUB", "NUMSUB", "news", "none"]),
+            vec![Array(vec![
+                bulk(b"news"),
+                Integer(2),
+                bulk(b"none"),
+                Integer(0)
+            ])]
+        );
+        assert_eq!(
+            run(&mut pubsub, Token(2), &["PUBSUB", "NUMPAT"]),
+            vec![Integer(1)]
+        );
+        run(&mut pubsub, Token(3), &["SSUBSCRIBE", "orders"]);
+        assert_eq!(
+            run(&mut pubsub, Token(2), &["PUBSUB", "SHARDCHANNELS"]),
+            vec![Array(vec![bulk(b"orders")])]
+        );
+        assert_eq!(
+            run(
+                &mut pubsub,
+                Token(2),
+                &["PUBSUB", "SHARDNUMSUB", "orders", "news"]
+            ),
+            vec![Array(vec![
+                bulk(b"orders"),
+                Integer(1),
+                bulk(b"news"),
+                Integer(0)
+            ])]
+        );
+        assert_eq!(
+            run(&mut pubsub, Token(2), &["PUBSUB", "NUMPAT", "x"]),
+            vec![SimpleError(
+                "ERR unknown subcommand or wrong number of arguments for 'NUMPAT'. Try PUBSUB HELP."
+                    .to_string()
+            )]
+        );
+    }
+}
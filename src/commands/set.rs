@@ -0,0 +1,606 @@
+use super::table::{Command, Keys, DENYOOM, FAST, READONLY, WRITE};
+use super::{arg_eq, bulk, check_arity, parse_int, CommandError, Propagation};
+use crate::cache::{Cache, Data, Members};
+use crate::notify;
+use crate::random;
+use crate::resp::value::Value;
+use crate::resp::value::Value::*;
+
+/// Most members a negative `SRANDMEMBER` count may ask for, matching Redis'
+/// default `proto-max-multibulk-len`.
+const MAX_RANDOM_MEMBERS: u64 = 1024 * 1024;
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "SADD",
+        arity: -3,
+        flags: WRITE | DENYOOM | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "set",
+        summary: "Adds one or more members to a set. Creates the key if it doesn't exist.",
+    },
+    Command {
+        name: "SREM",
+        arity: -3,
+        flags: WRITE | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "set",
+        summary: "Removes one or more members from a set. Deletes the set if the last member was removed.",
+    },
+    Command {
+        name: "SMEMBERS",
+        arity: 2,
+        flags: READONLY,
+        keys: Keys::Range(1, 1, 1),
+        group: "set",
+        summary: "Returns all members of a set.",
+    },
+    Command {
+        name: "SISMEMBER",
+        arity: 3,
+        flags: READONLY | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "set",
+        summary: "Determines whether a member belongs to a set.",
+    },
+    Command {
+        name: "SMISMEMBER",
+        arity: -3,
+        flags: READONLY | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "set",
+        summary: "Determines whether multiple members belong to a set.",
+    },
+    Command {
+        name: "SCARD",
+        arity: 2,
+        flags: READONLY | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "set",
+        summary: "Returns the number of members in a set.",
+    },
+    Command {
+        name: "SPOP",
+        arity: -2,
+        flags: WRITE | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "set",
+        summary: "Returns one or more random members from a set after removing them. Deletes the set if the last member was popped.",
+    },
+    Command {
+        name: "SRANDMEMBER",
+        arity: -2,
+        flags: READONLY,
+        keys: Keys::Range(1, 1, 1),
+        group: "set",
+        summary: "Get one or multiple random members from a set.",
+    },
+    Command {
+        name: "SMOVE",
+        arity: 4,
+        flags: WRITE | FAST,
+        keys: Keys::Range(1, 2, 1),
+        group: "set",
+        summary: "Moves a member from one set to another.",
+    },
+    Command {
+        name: "SINTER",
+        arity: -2,
+        flags: READONLY,
+        keys: Keys::Range(1, -1, 1),
+        group: "set",
+        summary: "Returns the intersect of multiple sets.",
+    },
+    Command {
+        name: "SINTERSTORE",
+        arity: -3,
+        flags: WRITE | DENYOOM,
+        keys: Keys::Range(1, -1, 1),
+        group: "set",
+        summary: "Stores the intersect of multiple sets in a key.",
+    },
+    Command {
+        name: "SINTERCARD",
+        arity: -3,
+        flags: READONLY,
+        keys: Keys::Movable(sintercard_key_positions),
+        group: "set",
+        summary: "Returns the number of members of the intersect of multiple sets.",
+    },
+    Command {
+        name: "SUNION",
+        arity: -2,
+        flags: READONLY,
+        keys: Keys::Range(1, -1, 1),
+        group: "set",
+        summary: "Returns the union of multiple sets.",
+    },
+    Command {
+        name: "SUNIONSTORE",
+        arity: -3,
+        flags: WRITE | DENYOOM,
+        keys: Keys::Range(1, -1, 1),
+        group: "set",
+        summary: "Stores the union of multiple sets in a key.",
+    },
+    Command {
+        name: "SDIFF",
+        arity: -2,
+        flags: READONLY,
+        keys: Keys::Range(1, -1, 1),
+        group: "set",
+        summary: "Returns the difference of multiple sets.",
+    },
+    Command {
+        name: "SDIFFSTORE",
+        arity: -3,
+        flags: WRITE | DENYOOM,
+        keys: Keys::Range(1, -1, 1),
+        group: "set",
+        summary: "Stores the difference of multiple sets in a key.",
+    },
+];
+
+/// `SINTERCARD numkeys key [key ...] [LIMIT limit]`: the `numkeys` keys
+/// after `numkeys`.
+fn sintercard_key_positions(arr: &[Value]) -> Vec<usize> {
+    let numkeys = arr.get(1).and_then(|n| parse_int(n).ok()).unwrap_or(0);
+    (2..arr.len()).take(numkeys.max(0) as usize).collect()
+}
+
+#[derive(Clone, Copy)]
+enum SetOp {
+    Inter,
+    Union,
+    Diff,
+}
+
+pub fn execute(name: &str, arr: &[Value], db: &mut Cache) -> (Value, Propagation) {
+    let read = |reply| (reply, Propagation::None);
+    let write = |reply| (reply, Propagation::Verbatim);
+
+    let result = match name {
+        "SADD" => execute_sadd(arr, db).map(write),
+        "SREM" => execute_srem(arr, db).map(write),
+        "SMEMBERS" => execute_smembers(arr, db).map(read),
+        "SISMEMBER" => execute_sismember(arr, db).map(read),
+        "SMISMEMBER" => execute_smismember(arr, db).map(read),
+        "SCARD" => execute_scard(arr, db).map(read),
+        "SPOP" => execute_spop(arr, db),
+        "SRANDMEMBER" => execute_srandmember(arr, db).map(read),
+        "SMOVE" => execute_smove(arr, db).map(write),
+        "SINTER" => execute_setop(arr, db, SetOp::Inter).map(read),
+        "SUNION" => execute_setop(arr, db, SetOp::Union).map(read),
+        "SDIFF" => execute_setop(arr, db, SetOp::Diff).map(read),
+        "SINTERSTORE" => execute_setop_store(arr, db, SetOp::Inter).map(write),
+        "SUNIONSTORE" => execute_setop_store(arr, db, SetOp::Union).map(write),
+        "SDIFFSTORE" => execute_setop_store(arr, db, SetOp::Diff).map(write),
+        "SINTERCARD" => execute_sintercard(arr, db).map(read),
+        _ => Err(CommandError::Other(format!("unknown command '{}'", name))),
+    };
+
+    match result {
+        Ok(response) => response,
+        Err(err) => (err.into(), Propagation::None),
+    }
+}
+
+fn members_reply<'a>(members: impl Iterator<Item = &'a Vec<u8>>) -> Value {
+    Set(members.map(|m| bulk(m)).collect())
+}
+
+fn execute_sadd(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, -3)?;
+    let key = arr[1].to_bytes();
+    let set = db.get_or_create_set(&key)?;
+    let added = arr[2..]
+        .iter()
+        .filter(|member| set.insert(member.to_bytes()))
+        .count();
+    if added > 0 {
+        db.signal_modified(&key);
+        db.notify(notify::SET, "sadd", &key);
+    }
+    Ok(Integer(added as i64))
+}
+
+fn execute_srem(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, -3)?;
+    let key = arr[1].to_bytes();
+    let removed = match db.get_set_mut(&key)? {
+        Some(set) => arr[2..]
+            .iter()
+            .filter(|member| set.swap_remove(&member.to_bytes()))
+            .count(),
+        None => 0,
+    };
+    if removed > 0 {
+        db.signal_modified(&key);
+        db.notify(notify::SET, "srem", &key);
+    }
+    db.remove_if_empty(&key);
+    Ok(Integer(removed as i64))
+}
+
+fn execute_smembers(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, 2)?;
+    match db.get_set(&arr[1].to_bytes())? {
+        Some(set) => Ok(members_reply(set.iter())),
+        None => Ok(Set(vec![])),
+    }
+}
+
+fn execute_sismember(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, 3)?;
+    let found = match db.get_set(&arr[1].to_bytes())? {
+        Some(set) => set.contains(&arr[2].to_bytes()),
+        None => false,
+    };
+    Ok(Integer(found as i64))
+}
+
+fn execute_smismember(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, -3)?;
+    let set = db.get_set(&arr[1].to_bytes())?;
+    let replies = arr[2..]
+        .iter()
+        .map(|member| {
+            let found = set.is_some_and(|set| set.contains(&member.to_bytes()));
+            Integer(found as i64)
+        })
+        .collect();
+    Ok(Array(replies))
+}
+
+fn execute_scard(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, 2)?;
+    let len = db.get_set(&arr[1].to_bytes())?.map_or(0, |set| set.len());
+    Ok(Integer(len as i64))
+}
+
+/// Picks `count` distinct members at random with a partial Fisher-Yates shuffle.
+fn random_members(set: &Members, count: usize) -> Vec<Vec<u8>> {
+    let mut members: Vec<&Vec<u8>> = set.iter().collect();
+    let count = count.min(members.len());
+    for i in 0..count {
+        let j = i + random::below(members.len() - i);
+        members.swap(i, j);
+    }
+    members[..count].iter().map(|m| m.to_vec()).collect()
+}
+
+fn parse_count(value: &Value) -> Result<usize, CommandError> {
+    let count = parse_int(value)?;
+    if count < 0 {
+        return Err(CommandError::Other(
+            "value is out of range, must be positive".to_string(),
+        ));
+    }
+    Ok(count as usize)
+}
+
+fn execute_spop(arr: &[Value], db: &mut Cache) -> Result<(Value, Propagation), CommandError> {
+    check_arity(arr, -2)?;
+    if arr.len() > 3 {
+        return Err(CommandError::Syntax);
+    }
+    let key = arr[1].to_bytes();
+    let count = match arr.get(2) {
+        Some(value) => Some(parse_count(value)?),
+        None => None,
+    };
+
+    let set = match db.get_set_mut(&key)? {
+        Some(set) => set,
+        None => {
+            let reply = if count.is_some() { Set(vec![]) } else { Null };
+            return Ok((reply, Propagation::None));
+        }
+    };
+
+    let popped = random_members(set, count.unwrap_or(1));
+    for member in &popped {
+        set.swap_remove(member);
+    }
+    if !popped.is_empty() {
+        db.signal_modified(&key);
+        db.notify(notify::SET, "spop", &key);
+    }
+    db.remove_if_empty(&key);
+
+    // SPOP is not deterministic, so replicas are told which members went away.
+    let propagation = if popped.is_empty() {
+        Propagation::None
+    } else {
+        let mut srem = vec![BulkString(b"SREM".to_vec()), bulk(&key)];
+        srem.extend(popped.iter().map(|m| bulk(m)));
+        Propagation::Rewrite(vec![Array(srem)])
+    };
+
+    let reply = match count {
+        Some(_) => members_reply(popped.iter()),
+        None => bulk(&popped[0]),
+    };
+    Ok((reply, propagation))
+}
+
+fn execute_srandmember(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, -2)?;
+    if arr.len() > 3 {
+        return Err(CommandError::Syntax);
+    }
+    let count = match arr.get(2) {
+        // A negative count repeats members, so nothing else bounds the
+        // reply; it is refused before a single member is picked.
+        Some(value) => match parse_int(value)? {
+            count if count.unsigned_abs() > MAX_RANDOM_MEMBERS => {
+                return Err(CommandError::Other("value is out of range".to_string()))
+            }
+            count => Some(count),
+        },
+        None => None,
+    };
+
+    let set = match db.get_set(&arr[1].to_bytes())? {
+        Some(set) => set,
+        None => return Ok(if count.is_some() { Array(vec![]) } else { Null }),
+    };
+
+    match count {
+        None => Ok(bulk(&random_members(set, 1)[0])),
+        Some(count) if count >= 0 => {
+            let members = random_members(set, count as usize);
+            Ok(Array(members.iter().map(|m| bulk(m)).collect()))
+        }
+        // A negative count allows the same member to be returned several
+        // times.
+        Some(count) => {
+            let members: Vec<&Vec<u8>> = set.iter().collect();
+            let replies = (0..count.unsigned_abs())
+                .map(|_| bulk(members[random::below(members.len())]))
+                .collect();
+            Ok(Array(replies))
+        }
+    }
+}
+
+fn execute_smove(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, 4)?;
+    let source = arr[1].to_bytes();
+    let destination = arr[2].to_bytes();
+    let member = arr[3].to_bytes();
+
+    db.get_set(&destination)?;
+    let moved = match db.get_set_mut(&source)? {
+        Some(set) if source == destination => set.contains(&member),
+        Some(set) => set.swap_remove(&member),
+        None => false,
+    };
+    if moved && source != destination {
+        db.signal_modified(&source);
+        db.notify(notify::SET, "srem", &source);
+        db.remove_if_empty(&source);
+        db.get_or_create_set(&destination)?.insert(member);
+        db.signal_modified(&destination);
+        db.notify(notify::SET, "sadd", &destination);
+    }
+    Ok(Integer(moved as i64))
+}
+
+fn load_sets(keys: &[Value], db: &mut Cache) -> Result<Vec<Option<Members>>, CommandError> {
+    keys.iter()
+        .map(|key| Ok(db.get_set(&key.to_bytes())?.cloned()))
+        .collect()
+}
+
+fn apply_setop(sets: Vec<Option<Members>>, op: SetOp) -> Members {
+    let mut sets = sets.into_iter();
+    let first = sets.next().flatten().unwrap_or_default();
+    match op {
+        SetOp::Union => sets.flatten().fold(first, |mut acc, set| {
+            acc.extend(set);
+            acc
+        }),
+        SetOp::Diff => sets.flatten().fold(first, |mut acc, set| {
+            acc.retain(|member| !set.contains(member));
+            acc
+        }),
+        SetOp::Inter => sets.fold(first, |mut acc, set| match set {
+            Some(set) => {
+                acc.retain(|member| set.contains(member));
+                acc
+            }
+            None => Members::new(),
+        }),
+    }
+}
+
+fn execute_setop(arr: &[Value], db: &mut Cache, op: SetOp) -> Result<Value, CommandError> {
+    check_arity(arr, -2)?;
+    let sets = load_sets(&arr[1..], db)?;
+    let result = apply_setop(sets, op);
+    Ok(members_reply(result.iter()))
+}
+
+fn execute_setop_store(arr: &[Value], db: &mut Cache, op: SetOp) -> Result<Value, CommandError> {
+    check_arity(arr, -3)?;
+    let destination = arr[1].to_bytes();
+    let sets = load_sets(&arr[2..], db)?;
+    let result = apply_setop(sets, op);
+    let len = result.len();
+
+    let existed = db.remove(&destination).is_some();
+    if len > 0 {
+        let event = match op {
+            SetOp::Inter => "sinterstore",
+            SetOp::Union => "sunionstore",
+            SetOp::Diff => "sdiffstore",
+        };
+        db.insert(destination.clone(), Data::Set(result), None);
+        db.notify(notify::SET, event, &destination);
+    } else if existed {
+        db.notify(notify::GENERIC, "del", &destination);
+    }
+    Ok(Integer(len as i64))
+}
+
+fn execute_sintercard(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, -3)?;
+    let numkeys = parse_int(&arr[1])?;
+    if numkeys <= 0 {
+        return Err(CommandError::Other(
+            "numkeys should be greater than 0".to_string(),
+        ));
+    }
+    let numkeys = numkeys as usize;
+    if numkeys > arr.len() - 2 {
+        return Err(CommandError::Other(
+            "Number of keys can't be greater than number of args".to_string(),
+        ));
+    }
+
+    let options = &arr[2 + numkeys..];
+    let limit = match options {
+        [] => 0,
+        [option, limit] if arg_eq(option, "LIMIT") => {
+            let limit = parse_int(limit)?;
+            if limit < 0 {
+                return Err(CommandError::Other("LIMIT can't be negative".to_string()));
+            }
+            limit as usize
+        }
+        _ => return Err(CommandError::Syntax),
+    };
+
+    let sets = load_sets(&arr[2..2 + numkeys], db)?;
+    let len = apply_setop(sets, SetOp::Inter).len();
+    match limit {
+        0 => Ok(Integer(len as i64)),
+        limit => Ok(Integer(len.min(limit) as i64)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(args: &[&str]) -> Vec<Value> {
+        args.iter()
+            .map(|arg| BulkString(arg.to_string().into_bytes()))
+            .collect()
+    }
+
+    fn run(db: &mut Cache, args: &[&str]) -> Value {
+        let arr = command(args);
+        execute(args[0], &arr, db).0
+    }
+
+    fn sorted(value: Value) -> Vec<Value> {
+        match value {
+            Set(mut members) | Array(mut members) => {
+                members.sort_by_key(|m| m.to_bytes());
+                members
+            }
+            other => panic!("Wrong type. got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_sadd_srem() {
+        let mut db = Cache::new();
+        assert_eq!(run(&mut db, &["SADD", "s", "a", "b", "a"]), Integer(2));
+        assert_eq!(run(&mut db, &["SCARD", "s"]), Integer(2));
+        assert_eq!(run(&mut db, &["SREM", "s", "a", "c"]), Integer(1));
+        assert_eq!(run(&mut db, &["SISMEMBER", "s", "b"]), Integer(1));
+        assert_eq!(run(&mut db, &["SREM", "s", "b"]), Integer(1));
+        assert_eq!(db.get_data(b"s"), None);
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        let mut db = Cache::new();
+        db.insert(b"k".to_vec(), b"v".to_vec(), None);
+        assert_eq!(
+            run(&mut db, &["SADD", "k", "a"]),
+            CommandError::WrongType.into()
+        );
+    }
+
+    #[test]
+    fn test_set_algebra() {
+        let mut db = Cache::new();
+        run(&mut db, &["SADD", "a", "1", "2", "3"]);
+        run(&mut db, &["SADD", "b", "2", "3", "4"]);
+        let values = |members: &[&str]| command(members);
+
+        assert_eq!(
+            sorted(run(&mut db, &["SINTER", "a", "b"])),
+            values(&["2", "3"])
+        );
+        assert_eq!(
+            sorted(run(&mut db, &["SUNION", "a", "b"])),
+            values(&["1", "2", "3", "4"])
+        );
+        assert_eq!(sorted(run(&mut db, &["SDIFF", "a", "b"])), values(&["1"]));
+        assert_eq!(sorted(run(&mut db, &["SINTER", "a", "missing"])), vec![]);
+        assert_eq!(
+            run(&mut db, &["SINTERCARD", "2", "a", "b", "LIMIT", "1"]),
+            Integer(1)
+        );
+        assert_eq!(run(&mut db, &["SUNIONSTORE", "c", "a", "b"]), Integer(4));
+        assert_eq!(
+            run(&mut db, &["SINTERSTORE", "c", "a", "missing"]),
+            Integer(0)
+        );
+        assert_eq!(db.get_data(b"c"), None);
+    }
+
+    #[test]
+    fn test_spop_propagates_srem() {
+        let mut db = Cache::new();
+        run(&mut db, &["SADD", "s", "only"]);
+        let (reply, propagation) = execute("SPOP", &command(&["SPOP", "s"]), &mut db);
+        assert_eq!(reply, BulkString(b"only".to_vec()));
+        assert_eq!(
+            propagation,
+            Propagation::Rewrite(vec![Array(command(&["SREM", "s", "only"]))])
+        );
+        assert_eq!(db.get_data(b"s"), None);
+    }
+
+    #[test]
+    fn test_srandmember_count() {
+        let mut db = Cache::new();
+        run(&mut db, &["SADD", "s", "a"]);
+        assert_eq!(
+            run(&mut db, &["SRANDMEMBER", "s", "-3"]),
+            Array(command(&["a", "a", "a"]))
+        );
+        let out_of_range = CommandError::Other("value is out of range".to_string()).into();
+        assert_eq!(
+            run(&mut db, &["SRANDMEMBER", "s", "-9223372036854775808"]),
+            out_of_range
+        );
+        assert_eq!(
+            run(&mut db, &["SRANDMEMBER", "s", "-9223372036854775807"]),
+            out_of_range
+        );
+        assert_eq!(
+            run(&mut db, &["SRANDMEMBER", "missing", "-9223372036854775808"]),
+            out_of_range
+        );
+        let Array(members) = run(&mut db, &["SRANDMEMBER", "s", "-1048576"]) else {
+            panic!("SRANDMEMBER with a count should reply with an array");
+        };
+        assert_eq!(members.len(), 1024 * 1024);
+    }
+
+    #[test]
+    fn test_smove() {
+        let mut db = Cache::new();
+        run(&mut db, &["SADD", "src", "a"]);
+        assert_eq!(run(&mut db, &["SMOVE", "src", "dst", "a"]), Integer(1));
+        assert_eq!(run(&mut db, &["SMOVE", "src", "dst", "a"]), Integer(0));
+        assert_eq!(run(&mut db, &["SMEMBERS", "dst"]), Set(command(&["a"])));
+    }
+}
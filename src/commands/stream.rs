@@ -0,0 +1,1718 @@
+use super::table::{Command, Keys, DENYOOM, FAST, READONLY, WRITE};
+use super::{arg_eq, bulk, check_arity, parse_int, CommandError, Propagation};
+use crate::cache::{now_ms, Cache};
+use crate::notify;
+use crate::resp::value::Value;
+use crate::resp::value::Value::*;
+use crate::stream::{ConsumerGroup, Fields, Stream, StreamId, Trim};
+use std::ops::Bound;
+use std::time::{Duration, Instant};
+
+/// Stream commands that never block. `XREAD` and `XREADGROUP` go through
+/// `execute_read` instead.
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "XADD",
+        arity: -5,
+        flags: WRITE | DENYOOM | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "stream",
+        summary: "Appends a new message to a stream. Creates the key if it doesn't exist.",
+    },
+    Command {
+        name: "XRANGE",
+        arity: -4,
+        flags: READONLY,
+        keys: Keys::Range(1, 1, 1),
+        group: "stream",
+        summary: "Returns the messages from a stream within a range of IDs.",
+    },
+    Command {
+        name: "XREVRANGE",
+        arity: -4,
+        flags: READONLY,
+        keys: Keys::Range(1, 1, 1),
+        group: "stream",
+        summary: "Returns the messages from a stream within a range of IDs in reverse order.",
+    },
+    Command {
+        name: "XLEN",
+        arity: 2,
+        flags: READONLY | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "stream",
+        summary: "Return the number of messages in a stream.",
+    },
+    Command {
+        name: "XTRIM",
+        arity: -4,
+        flags: WRITE,
+        keys: Keys::Range(1, 1, 1),
+        group: "stream",
+        summary: "Deletes messages from the beginning of a stream.",
+    },
+    Command {
+        name: "XDEL",
+        arity: -3,
+        flags: WRITE | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "stream",
+        summary: "Returns the number of messages after removing them from a stream.",
+    },
+    Command {
+        name: "XGROUP",
+        arity: -2,
+        flags: WRITE | DENYOOM,
+        keys: Keys::Range(2, 2, 1),
+        group: "stream",
+        summary: "Creates, destroys and manages consumer groups and their consumers.",
+    },
+    Command {
+        name: "XACK",
+        arity: -4,
+        flags: WRITE | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "stream",
+        summary: "Returns the number of messages that were successfully acknowledged by the consumer group member of a stream.",
+    },
+    Command {
+        name: "XPENDING",
+        arity: -3,
+        flags: READONLY,
+        keys: Keys::Range(1, 1, 1),
+        group: "stream",
+        summary: "Returns the information and entries from a stream consumer group's pending entries list.",
+    },
+    Command {
+        name: "XCLAIM",
+        arity: -6,
+        flags: WRITE | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "stream",
+        summary: "Changes, or acquires, ownership of a message in a consumer group, as if the message was delivered a consumer group member.",
+    },
+    Command {
+        name: "XAUTOCLAIM",
+        arity: -6,
+        flags: WRITE | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "stream",
+        summary: "Changes, or acquires, ownership of messages in a consumer group, as if the messages were delivered to as consumer group member.",
+    },
+    Command {
+        name: "XINFO",
+        arity: -2,
+        flags: READONLY,
+        keys: Keys::Range(2, 2, 1),
+        group: "stream",
+        summary: "Returns information about a stream, its consumer groups or their consumers.",
+    },
+];
+
+pub fn execute(name: &str, arr: &[Value], db: &mut Cache) -> (Value, Propagation) {
+    let read = |reply| (reply, Propagation::None);
+    let write = |reply| (reply, Propagation::Verbatim);
+
+    let result = match name {
+        "XADD" => execute_xadd(arr, db),
+        "XRANGE" => execute_xrange(arr, db, false).map(read),
+        "XREVRANGE" => execute_xrange(arr, db, true).map(read),
+        "XLEN" => execute_xlen(arr, db).map(read),
+        "XTRIM" => execute_xtrim(arr, db).map(write),
+        "XDEL" => execute_xdel(arr, db).map(write),
+        "XGROUP" => execute_xgroup(arr, db).map(write),
+        "XACK" => execute_xack(arr, db).map(write),
+        "XPENDING" => execute_xpending(arr, db).map(read),
+        "XCLAIM" => execute_xclaim(arr, db),
+        "XAUTOCLAIM" => execute_xautoclaim(arr, db),
+        "XINFO" => execute_xinfo(arr, db).map(read),
+        _ => Err(CommandError::Other(format!("unknown command '{}'", name))),
+    };
+
+    match result {
+        Ok(response) => response,
+        Err(err) => (err.into(), Propagation::None),
+    }
+}
+
+fn text(value: &Value) -> String {
+    String::from_utf8_lossy(&value.to_bytes()).into_owned()
+}
+
+fn id_reply(id: StreamId) -> Value {
+    BulkString(id.to_string().into_bytes())
+}
+
+fn entry_reply(id: StreamId, fields: &Fields) -> Value {
+    let mut flat = Vec::with_capacity(fields.len() * 2);
+    for (field, value) in fields {
+        flat.push(bulk(field));
+        flat.push(bulk(value));
+    }
+    Array(vec![id_reply(id), Array(flat)])
+}
+
+fn command(args: Vec<Value>) -> Value {
+    Array(args)
+}
+
+fn invalid_id() -> CommandError {
+    CommandError::Other("Invalid stream ID specified as stream command argument".to_string())
+}
+
+fn no_group(key: &[u8], group: &[u8]) -> CommandError {
+    CommandError::Raw(format!(
+        "NOGROUP No such key '{}' or consumer group '{}'",
+        String::from_utf8_lossy(key),
+        String::from_utf8_lossy(group)
+    ))
+}
+
+fn parse_id(value: &Value, missing_seq: u64) -> Result<StreamId, CommandError> {
+    StreamId::parse(&text(value), missing_seq).ok_or_else(invalid_id)
+}
+
+fn parse_range_start(value: &Value) -> Result<Bound<StreamId>, CommandError> {
+    let s = text(value);
+    match s.as_str() {
+        "-" => Ok(Bound::Unbounded),
+        "+" => Ok(Bound::Included(StreamId::MAX)),
+        _ => match s.strip_prefix('(') {
+            Some(id) => Ok(Bound::Excluded(
+                StreamId::parse(id, 0).ok_or_else(invalid_id)?,
+            )),
+            None => Ok(Bound::Included(
+                StreamId::parse(&s, 0).ok_or_else(invalid_id)?,
+            )),
+        },
+    }
+}
+
+fn parse_range_end(value: &Value) -> Result<Bound<StreamId>, CommandError> {
+    let s = text(value);
+    match s.as_str() {
+        "+" => Ok(Bound::Unbounded),
+        "-" => Ok(Bound::Included(StreamId::MIN)),
+        _ => match s.strip_prefix('(') {
+            Some(id) => Ok(Bound::Excluded(
+                StreamId::parse(id, u64::MAX).ok_or_else(invalid_id)?,
+            )),
+            None => Ok(Bound::Included(
+                StreamId::parse(&s, u64::MAX).ok_or_else(invalid_id)?,
+            )),
+        },
+    }
+}
+
+/// Parses `MAXLEN|MINID [=|~] threshold [LIMIT count]` starting at `*i`.
+fn parse_trim(arr: &[Value], i: &mut usize) -> Result<(Trim, usize), CommandError> {
+    let maxlen = arg_eq(&arr[*i], "MAXLEN");
+    *i += 1;
+
+    let mut approx = false;
+    match arr.get(*i) {
+        Some(arg) if arg_eq(arg, "=") => *i += 1,
+        Some(arg) if arg_eq(arg, "~") => {
+            approx = true;
+            *i += 1;
+        }
+        _ => {}
+    }
+
+    let threshold = arr.get(*i).ok_or(CommandError::Syntax)?;
+    *i += 1;
+    let strategy = if maxlen {
+        let len = parse_int(threshold)?;
+        if len < 0 {
+            return Err(CommandError::Other(
+                "The MAXLEN argument must be >= 0.".to_string(),
+            ));
+        }
+        Trim::MaxLen(len as u64)
+    } else {
+        Trim::MinId(parse_id(threshold, 0)?)
+    };
+
+    // Approximate trimming is carried out exactly; LIMIT still caps the
+    // number of evicted entries.
+    let mut limit = 0;
+    if arr.get(*i).is_some_and(|arg| arg_eq(arg, "LIMIT")) {
+        let count = parse_int(arr.get(*i + 1).ok_or(CommandError::Syntax)?)?;
+        if count < 0 {
+            return Err(CommandError::Other(
+                "The LIMIT argument must be >= 0.".to_string(),
+            ));
+        }
+        if !approx {
+            return Err(CommandError::Other(
+                "syntax error, LIMIT cannot be used without the special ~ option".to_string(),
+            ));
+        }
+        limit = count as usize;
+        *i += 2;
+    }
+    Ok((strategy, limit))
+}
+
+fn execute_xadd(arr: &[Value], db: &mut Cache) -> Result<(Value, Propagation), CommandError> {
+    check_arity(arr, -5)?;
+    let key = arr[1].to_bytes();
+
+    let mut nomkstream = false;
+    let mut trim = None;
+    let mut i = 2;
+    while let Some(arg) = arr.get(i) {
+        if arg_eq(arg, "NOMKSTREAM") {
+            nomkstream = true;
+            i += 1;
+        } else if arg_eq(arg, "MAXLEN") || arg_eq(arg, "MINID") {
+            trim = Some(parse_trim(arr, &mut i)?);
+        } else {
+            break;
+        }
+    }
+
+    let id_index = i;
+    let pairs = arr.get(id_index + 1..).unwrap_or(&[]);
+    if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+        return Err(CommandError::WrongArity("xadd".to_string()));
+    }
+    let fields: Fields = pairs
+        .chunks(2)
+        .map(|pair| (pair[0].to_bytes(), pair[1].to_bytes()))
+        .collect();
+
+    let requested = text(&arr[id_index]);
+    let explicit = match requested.as_str() {
+        "*" => None,
+        s if s.ends_with("-*") => None,
+        s => Some(StreamId::parse(s, 0).ok_or_else(invalid_id)?),
+    };
+    if explicit == Some(StreamId::MIN) {
+        return Err(CommandError::Other(
+            "The ID specified in XADD must be greater than 0-0".to_string(),
+        ));
+    }
+
+    let stream = match nomkstream {
+        true => match db.get_stream_mut(&key)? {
+            Some(stream) => stream,
+            None => return Ok((Null, Propagation::None)),
+        },
+        false => db.get_or_create_stream(&key)?,
+    };
+    if stream.last_id == StreamId::MAX {
+        return Err(CommandError::Other(
+            "The stream has exhausted the last possible ID, unable to add more items".to_string(),
+        ));
+    }
+
+    let id = match (requested.strip_suffix("-*"), explicit) {
+        (_, Some(id)) => (id > stream.last_id).then_some(id),
+        (Some(ms), None) => stream.next_id_for(ms.parse().map_err(|_| invalid_id())?),
+        (None, None) => stream.next_id(now_ms()),
+    };
+    let id = id.ok_or_else(|| {
+        CommandError::Other(
+            "The ID specified in XADD is equal or smaller than the target stream top item"
+                .to_string(),
+        )
+    })?;
+
+    stream.add(id, fields);
+    let trimmed = match trim {
+        Some((strategy, limit)) => stream.trim(strategy, limit),
+        None => 0,
+    };
+    db.signal_modified(&key);
+    db.notify(notify::STREAM, "xadd", &key);
+    if trimmed > 0 {
+        db.notify(notify::STREAM, "xtrim", &key);
+    }
+
+    // Replicas must store the exact ID we generated.
+    let mut rewritten = arr.to_vec();
+    rewritten[id_index] = id_reply(id);
+    Ok((id_reply(id), Propagation::Rewrite(vec![command(rewritten)])))
+}
+
+fn execute_xrange(arr: &[Value], db: &mut Cache, rev: bool) -> Result<Value, CommandError> {
+    check_arity(arr, -4)?;
+    let (start, end) = match rev {
+        true => (parse_range_start(&arr[3])?, parse_range_end(&arr[2])?),
+        false => (parse_range_start(&arr[2])?, parse_range_end(&arr[3])?),
+    };
+    let count = match &arr[4..] {
+        [] => None,
+        [option, count] if arg_eq(option, "COUNT") => Some(parse_int(count)?.max(0) as usize),
+        _ => return Err(CommandError::Syntax),
+    };
+
+    let stream = match db.get_stream(&arr[1].to_bytes())? {
+        Some(stream) => stream,
+        None => return Ok(Array(vec![])),
+    };
+    let entries = stream.range(start, end, count, rev);
+    Ok(Array(
+        entries
+            .into_iter()
+            .map(|(id, fields)| entry_reply(id, fields))
+            .collect(),
+    ))
+}
+
+fn execute_xlen(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, 2)?;
+    let len = db.get_stream(&arr[1].to_bytes())?.map_or(0, |s| s.len());
+    Ok(Integer(len as i64))
+}
+
+fn execute_xtrim(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, -4)?;
+    if !arg_eq(&arr[2], "MAXLEN") && !arg_eq(&arr[2], "MINID") {
+        return Err(CommandError::Syntax);
+    }
+    let mut i = 2;
+    let (strategy, limit) = parse_trim(arr, &mut i)?;
+    if i != arr.len() {
+        return Err(CommandError::Syntax);
+    }
+
+    let key = arr[1].to_bytes();
+    let removed = match db.get_stream_mut(&key)? {
+        Some(stream) => stream.trim(strategy, limit),
+        None => 0,
+    };
+    if removed > 0 {
+        db.signal_modified(&key);
+        db.notify(notify::STREAM, "xtrim", &key);
+    }
+    Ok(Integer(removed as i64))
+}
+
+fn execute_xdel(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, -3)?;
+    let ids = arr[2..]
+        .iter()
+        .map(|id| parse_id(id, 0))
+        .collect::<Result<Vec<StreamId>, CommandError>>()?;
+
+    let key = arr[1].to_bytes();
+    let deleted = match db.get_stream_mut(&key)? {
+        Some(stream) => ids.iter().filter(|id| stream.delete(id)).count(),
+        None => 0,
+    };
+    if deleted > 0 {
+        db.signal_modified(&key);
+        db.notify(notify::STREAM, "xdel", &key);
+    }
+    Ok(Integer(deleted as i64))
+}
+
+fn parse_entries_read(value: &Value) -> Result<Option<u64>, CommandError> {
+    match parse_int(value)? {
+        -1 => Ok(None),
+        n if n >= 0 => Ok(Some(n as u64)),
+        _ => Err(CommandError::Other(
+            "value for ENTRIESREAD must be positive or -1".to_string(),
+        )),
+    }
+}
+
+fn group_id(value: &Value, stream: Option<&Stream>) -> Result<StreamId, CommandError> {
+    match text(value).as_str() {
+        "$" => Ok(stream.map_or(StreamId::MIN, |s| s.last_id)),
+        s => StreamId::parse(s, 0).ok_or_else(invalid_id),
+    }
+}
+
+fn execute_xgroup(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, -2)?;
+    let subcommand = text(&arr[1]).to_uppercase();
+    let arity: i64 = match subcommand.as_str() {
+        "CREATE" | "SETID" => -5,
+        "DESTROY" => 4,
+        "CREATECONSUMER" | "DELCONSUMER" => 5,
+        _ => {
+            return Err(CommandError::Other(format!(
+                "unknown subcommand '{}'. Try XGROUP HELP.",
+                text(&arr[1])
+            )))
+        }
+    };
+    let len = arr.len() as i64;
+    if (arity > 0 && len != arity) || (arity < 0 && len < -arity) {
+        return Err(CommandError::WrongArity(format!(
+            "xgroup|{}",
+            subcommand.to_lowercase()
+        )));
+    }
+
+    let key = arr[2].to_bytes();
+    let name = arr[3].to_bytes();
+    let missing_key = || {
+        CommandError::Other(
+            "The XGROUP subcommand requires the key to exist. Note that for CREATE you may want \
+             to use the MKSTREAM option to create an empty stream automatically."
+                .to_string(),
+        )
+    };
+    let missing_group = || {
+        CommandError::Raw(format!(
+            "NOGROUP No such consumer group '{}' for key name '{}'",
+            String::from_utf8_lossy(&name),
+            String::from_utf8_lossy(&key)
+        ))
+    };
+
+    match subcommand.as_str() {
+        "CREATE" => {
+            let mut mkstream = false;
+            let mut entries_read = None;
+            let mut i = 5;
+            while i < arr.len() {
+                if arg_eq(&arr[i], "MKSTREAM") {
+                    mkstream = true;
+                    i += 1;
+                } else if arg_eq(&arr[i], "ENTRIESREAD") && i + 1 < arr.len() {
+                    entries_read = parse_entries_read(&arr[i + 1])?;
+                    i += 2;
+                } else {
+                    return Err(CommandError::Syntax);
+                }
+            }
+
+            let id = group_id(&arr[4], db.get_stream(&key)?)?;
+            let stream = match mkstream {
+                true => db.get_or_create_stream(&key)?,
+                false => db.get_stream_mut(&key)?.ok_or_else(missing_key)?,
+            };
+            if stream.groups.contains_key(&name) {
+                return Err(CommandError::Raw(
+                    "BUSYGROUP Consumer Group name already exists".to_string(),
+                ));
+            }
+            stream
+                .groups
+                .insert(name, ConsumerGroup::new(id, entries_read));
+            db.signal_modified(&key);
+            db.notify(notify::STREAM, "xgroup-create", &key);
+            Ok(SimpleString("OK".to_string()))
+        }
+        "SETID" => {
+            let entries_read = match &arr[5..] {
+                [] => None,
+                [option, n] if arg_eq(option, "ENTRIESREAD") => parse_entries_read(n)?,
+                _ => return Err(CommandError::Syntax),
+            };
+            let id = group_id(&arr[4], db.get_stream(&key)?)?;
+            let stream = db.get_stream_mut(&key)?.ok_or_else(missing_key)?;
+            let group = stream.groups.get_mut(&name).ok_or_else(missing_group)?;
+            group.last_id = id;
+            group.entries_read = entries_read;
+            db.signal_modified(&key);
+            db.notify(notify::STREAM, "xgroup-setid", &key);
+            Ok(SimpleString("OK".to_string()))
+        }
+        "DESTROY" => {
+            let stream = db.get_stream_mut(&key)?.ok_or_else(missing_key)?;
+            let destroyed = stream.groups.remove(&name).is_some();
+            if destroyed {
+                db.signal_modified(&key);
+                db.notify(notify::STREAM, "xgroup-destroy", &key);
+            }
+            Ok(Integer(destroyed as i64))
+        }
+        "CREATECONSUMER" => {
+            let stream = db.get_stream_mut(&key)?.ok_or_else(missing_key)?;
+            let group = stream.groups.get_mut(&name).ok_or_else(missing_group)?;
+            let consumer = arr[4].to_bytes();
+            if group.consumers.contains_key(&consumer) {
+                return Ok(Integer(0));
+            }
+            group.consumer(&consumer, now_ms());
+            db.signal_modified(&key);
+            db.notify(notify::STREAM, "xgroup-createconsumer", &key);
+            Ok(Integer(1))
+        }
+        _ => {
+            let stream = db.get_stream_mut(&key)?.ok_or_else(missing_key)?;
+            let group = stream.groups.get_mut(&name).ok_or_else(missing_group)?;
+            let pending = match group.consumers.remove(&arr[4].to_bytes()) {
+                Some(consumer) => consumer.pending,
+                None => return Ok(Integer(0)),
+            };
+            for id in &pending {
+                group.pending.remove(id);
+            }
+            db.signal_modified(&key);
+            db.notify(notify::STREAM, "xgroup-delconsumer", &key);
+            Ok(Integer(pending.len() as i64))
+        }
+    }
+}
+
+fn execute_xack(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, -4)?;
+    let ids = arr[3..]
+        .iter()
+        .map(|id| parse_id(id, 0))
+        .collect::<Result<Vec<StreamId>, CommandError>>()?;
+
+    let key = arr[1].to_bytes();
+    let group = match db.get_stream_mut(&key)? {
+        Some(stream) => stream.groups.get_mut(&arr[2].to_bytes()),
+        None => None,
+    };
+    let acked = match group {
+        Some(group) => ids.iter().filter(|id| group.ack(id)).count(),
+        None => 0,
+    };
+    if acked > 0 {
+        db.signal_modified(&key);
+    }
+    Ok(Integer(acked as i64))
+}
+
+fn execute_xpending(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, -3)?;
+    let key = arr[1].to_bytes();
+    let name = arr[2].to_bytes();
+    let group = match db.get_stream(&key)? {
+        Some(stream) => stream.groups.get(&name),
+        None => None,
+    }
+    .ok_or_else(|| no_group(&key, &name))?;
+
+    if arr.len() == 3 {
+        let (first, last) = match (group.pending.keys().next(), group.pending.keys().last()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => return Ok(Array(vec![Integer(0), Null, Null, Null])),
+        };
+        let consumers = group
+            .consumers
+            .iter()
+            .filter(|(_, consumer)| !consumer.pending.is_empty())
+            .map(|(name, consumer)| {
+                Array(vec![
+                    bulk(name),
+                    BulkString(consumer.pending.len().to_string().into_bytes()),
+                ])
+            })
+            .collect();
+        return Ok(Array(vec![
+            Integer(group.pending.len() as i64),
+            id_reply(first),
+            id_reply(last),
+            Array(consumers),
+        ]));
+    }
+
+    let mut i = 3;
+    let mut min_idle = 0;
+    if arg_eq(&arr[i], "IDLE") {
+        min_idle = parse_int(arr.get(i + 1).ok_or(CommandError::Syntax)?)?.max(0) as u64;
+        i += 2;
+    }
+    let (start, end, count, consumer) = match &arr[i..] {
+        [start, end, count] => (start, end, count, None),
+        [start, end, count, consumer] => (start, end, count, Some(consumer.to_bytes())),
+        _ => return Err(CommandError::Syntax),
+    };
+    let start = parse_range_start(start)?;
+    let end = parse_range_end(end)?;
+    let count = parse_int(count)?.max(0) as usize;
+
+    let now = now_ms();
+    let empty = matches!((start, end), (Bound::Included(s), Bound::Included(e)) if s > e)
+        || matches!((start, end), (Bound::Excluded(s), Bound::Excluded(e)) if s >= e);
+    if empty {
+        return Ok(Array(vec![]));
+    }
+    let entries = group
+        .pending
+        .range((start, end))
+        .filter(|(_, entry)| consumer.as_ref().is_none_or(|c| &entry.consumer == c))
+        .filter(|(_, entry)| now.saturating_sub(entry.delivery_time) >= min_idle)
+        .take(count)
+        .map(|(id, entry)| {
+            Array(vec![
+                id_reply(*id),
+                bulk(&entry.consumer),
+                Integer(now.saturating_sub(entry.delivery_time) as i64),
+                Integer(entry.delivery_count as i64),
+            ])
+        })
+        .collect();
+    Ok(Array(entries))
+}
+
+/// The `XCLAIM` replicas receive for every entry whose ownership changed,
+/// carrying the exact delivery time and count so they don't depend on
+/// their own clocks.
+fn xclaim_propagation(
+    key: &[u8],
+    group_name: &[u8],
+    group: &ConsumerGroup,
+    consumer: &[u8],
+    id: StreamId,
+) -> Value {
+    let (time, count) = match group.pending.get(&id) {
+        Some(entry) => (entry.delivery_time, entry.delivery_count),
+        None => (now_ms(), 0),
+    };
+    command(vec![
+        BulkString(b"XCLAIM".to_vec()),
+        bulk(key),
+        bulk(group_name),
+        bulk(consumer),
+        BulkString(b"0".to_vec()),
+        id_reply(id),
+        BulkString(b"TIME".to_vec()),
+        BulkString(time.to_string().into_bytes()),
+        BulkString(b"RETRYCOUNT".to_vec()),
+        BulkString(count.to_string().into_bytes()),
+        BulkString(b"FORCE".to_vec()),
+        BulkString(b"JUSTID".to_vec()),
+        BulkString(b"LASTID".to_vec()),
+        id_reply(group.last_id),
+    ])
+}
+
+fn create_consumer_propagation(key: &[u8], group: &[u8], consumer: &[u8]) -> Value {
+    command(vec![
+        BulkString(b"XGROUP".to_vec()),
+        BulkString(b"CREATECONSUMER".to_vec()),
+        bulk(key),
+        bulk(group),
+        bulk(consumer),
+    ])
+}
+
+fn setid_propagation(key: &[u8], group_name: &[u8], group: &ConsumerGroup) -> Value {
+    let entries_read = group.entries_read.map_or(-1, |n| n as i64);
+    command(vec![
+        BulkString(b"XGROUP".to_vec()),
+        BulkString(b"SETID".to_vec()),
+        bulk(key),
+        bulk(group_name),
+        id_reply(group.last_id),
+        BulkString(b"ENTRIESREAD".to_vec()),
+        BulkString(entries_read.to_string().into_bytes()),
+    ])
+}
+
+fn propagation(commands: Vec<Value>) -> Propagation {
+    match commands.is_empty() {
+        true => Propagation::None,
+        false => Propagation::Rewrite(commands),
+    }
+}
+
+/// Looks up a stream and detaches one of its groups so both can be
+/// mutated independently; `reattach` must be called when done.
+fn detach_group<'a>(
+    db: &'a mut Cache,
+    key: &[u8],
+    name: &[u8],
+) -> Result<(&'a mut Stream, ConsumerGroup), CommandError> {
+    let stream = db.get_stream_mut(key)?.ok_or_else(|| no_group(key, name))?;
+    let group = stream
+        .groups
+        .remove(name)
+        .ok_or_else(|| no_group(key, name))?;
+    Ok((stream, group))
+}
+
+fn execute_xclaim(arr: &[Value], db: &mut Cache) -> Result<(Value, Propagation), CommandError> {
+    check_arity(arr, -6)?;
+    let key = arr[1].to_bytes();
+    let group_name = arr[2].to_bytes();
+    let consumer = arr[3].to_bytes();
+    let min_idle = parse_int(&arr[4])
+        .map_err(|_| CommandError::Other("Invalid min-idle-time argument for XCLAIM".to_string()))?
+        .max(0) as u64;
+
+    let mut ids = Vec::new();
+    let mut i = 5;
+    while let Some(id) = arr.get(i).and_then(|id| StreamId::parse(&text(id), 0)) {
+        ids.push(id);
+        i += 1;
+    }
+    if ids.is_empty() {
+        return Err(invalid_id());
+    }
+
+    let now = now_ms();
+    let mut delivery_time = now;
+    let mut retry_count = None;
+    let mut force = false;
+    let mut justid = false;
+    let mut last_id = None;
+    while i < arr.len() {
+        let option = text(&arr[i]).to_uppercase();
+        let value = arr.get(i + 1);
+        match (option.as_str(), value) {
+            ("FORCE", _) => force = true,
+            ("JUSTID", _) => justid = true,
+            ("IDLE", Some(value)) => {
+                delivery_time = now.saturating_sub(parse_int(value)?.max(0) as u64);
+                i += 1;
+            }
+            ("TIME", Some(value)) => {
+                delivery_time = parse_int(value)?.max(0) as u64;
+                i += 1;
+            }
+            ("RETRYCOUNT", Some(value)) => {
+                retry_count = Some(parse_int(value)?.max(0) as u64);
+                i += 1;
+            }
+            ("LASTID", Some(value)) => {
+                last_id = Some(parse_id(value, 0)?);
+                i += 1;
+            }
+            _ => {
+                return Err(CommandError::Other(format!(
+                    "Unrecognized XCLAIM option '{}'",
+                    text(&arr[i])
+                )))
+            }
+        }
+        i += 1;
+    }
+
+    let (stream, mut group) = detach_group(db, &key, &group_name)?;
+    let mut propagate = Vec::new();
+    if !group.consumers.contains_key(&consumer) {
+        propagate.push(create_consumer_propagation(&key, &group_name, &consumer));
+    }
+    group.consumer(&consumer, now);
+
+    let mut last_id_changed = false;
+    if let Some(last_id) = last_id {
+        if last_id > group.last_id {
+            group.last_id = last_id;
+            last_id_changed = true;
+        }
+    }
+
+    let mut claimed = Vec::new();
+    for id in ids {
+        let count = match group.pending.get(&id) {
+            Some(entry) => {
+                if now.saturating_sub(entry.delivery_time) < min_idle {
+                    continue;
+                }
+                if !stream.entries.contains_key(&id) {
+                    // The entry was deleted from the stream, drop it from the PEL.
+                    group.ack(&id);
+                    propagate.push(xclaim_propagation(&key, &group_name, &group, &consumer, id));
+                    continue;
+                }
+                entry.delivery_count
+            }
+            None if force && stream.entries.contains_key(&id) => 0,
+            None => continue,
+        };
+        let count = match (retry_count, justid) {
+            (Some(retry_count), _) => retry_count,
+            (None, true) => count,
+            (None, false) => count + 1,
+        };
+        group.assign(id, &consumer, delivery_time, count);
+        group.consumer(&consumer, now).active_time = Some(now);
+        propagate.push(xclaim_propagation(&key, &group_name, &group, &consumer, id));
+        claimed.push(match justid {
+            true => id_reply(id),
+            false => entry_reply(id, &stream.entries[&id]),
+        });
+    }
+    if last_id_changed && claimed.is_empty() {
+        propagate.push(setid_propagation(&key, &group_name, &group));
+    }
+
+    stream.groups.insert(group_name, group);
+    if !propagate.is_empty() {
+        db.signal_modified(&key);
+    }
+    Ok((Array(claimed), propagation(propagate)))
+}
+
+fn execute_xautoclaim(arr: &[Value], db: &mut Cache) -> Result<(Value, Propagation), CommandError> {
+    check_arity(arr, -6)?;
+    let key = arr[1].to_bytes();
+    let group_name = arr[2].to_bytes();
+    let consumer = arr[3].to_bytes();
+    let min_idle = parse_int(&arr[4])
+        .map_err(|_| {
+            CommandError::Other("Invalid min-idle-time argument for XAUTOCLAIM".to_string())
+        })?
+        .max(0) as u64;
+    let start = parse_range_start(&arr[5])?;
+
+    let mut count = 100;
+    let mut justid = false;
+    let mut i = 6;
+    while i < arr.len() {
+        if arg_eq(&arr[i], "JUSTID") {
+            justid = true;
+            i += 1;
+        } else if arg_eq(&arr[i], "COUNT") && i + 1 < arr.len() {
+            count = parse_int(&arr[i + 1])?;
+            if !(1..=i64::MAX / 10).contains(&count) {
+                return Err(CommandError::Other("COUNT must be > 0".to_string()));
+            }
+            i += 2;
+        } else {
+            return Err(CommandError::Syntax);
+        }
+    }
+    let mut count = count as usize;
+
+    let (stream, mut group) = detach_group(db, &key, &group_name)?;
+    let now = now_ms();
+    let mut propagate = Vec::new();
+    if !group.consumers.contains_key(&consumer) {
+        propagate.push(create_consumer_propagation(&key, &group_name, &consumer));
+    }
+    group.consumer(&consumer, now);
+
+    let candidates: Vec<StreamId> = group
+        .pending
+        .range((start, Bound::Unbounded))
+        .map(|(id, _)| *id)
+        .take(count * 10)
+        .collect();
+    let mut claimed = Vec::new();
+    let mut deleted = Vec::new();
+    let mut scanned = 0;
+    for id in &candidates {
+        if count == 0 {
+            break;
+        }
+        scanned += 1;
+        let entry = &group.pending[id];
+        if now.saturating_sub(entry.delivery_time) < min_idle {
+            continue;
+        }
+        if !stream.entries.contains_key(id) {
+            group.ack(id);
+            propagate.push(xclaim_propagation(
+                &key,
+                &group_name,
+                &group,
+                &consumer,
+                *id,
+            ));
+            deleted.push(id_reply(*id));
+            count -= 1;
+            continue;
+        }
+        let delivery_count = match justid {
+            true => entry.delivery_count,
+            false => entry.delivery_count + 1,
+        };
+        group.assign(*id, &consumer, now, delivery_count);
+        group.consumer(&consumer, now).active_time = Some(now);
+        propagate.push(xclaim_propagation(
+            &key,
+            &group_name,
+            &group,
+            &consumer,
+            *id,
+        ));
+        claimed.push(match justid {
+            true => id_reply(*id),
+            false => entry_reply(*id, &stream.entries[id]),
+        });
+        count -= 1;
+    }
+
+    let cursor = match candidates.get(scanned) {
+        Some(next) => *next,
+        None => candidates
+            .last()
+            .and_then(|last| {
+                group
+                    .pending
+                    .range((Bound::Excluded(*last), Bound::Unbounded))
+                    .next()
+                    .map(|(id, _)| *id)
+            })
+            .unwrap_or(StreamId::MIN),
+    };
+
+    stream.groups.insert(group_name, group);
+    if !propagate.is_empty() {
+        db.signal_modified(&key);
+    }
+    Ok((
+        Array(vec![id_reply(cursor), Array(claimed), Array(deleted)]),
+        propagation(propagate),
+    ))
+}
+
+fn execute_xinfo(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, -2)?;
+    let subcommand = text(&arr[1]).to_uppercase();
+    let arity_ok = match subcommand.as_str() {
+        "STREAM" => arr.len() >= 3,
+        "GROUPS" => arr.len() == 3,
+        "CONSUMERS" => arr.len() == 4,
+        _ => {
+            return Err(CommandError::Other(format!(
+                "unknown subcommand '{}'. Try XINFO HELP.",
+                text(&arr[1])
+            )))
+        }
+    };
+    if !arity_ok {
+        return Err(CommandError::WrongArity(format!(
+            "xinfo|{}",
+            subcommand.to_lowercase()
+        )));
+    }
+
+    let key = arr[2].to_bytes();
+    let stream = db
+        .get_stream(&key)?
+        .ok_or_else(|| CommandError::Other("no such key".to_string()))?;
+    let now = now_ms();
+    let field = |name: &str| BulkString(name.to_string().into_bytes());
+    let lag = |group: &ConsumerGroup| stream.lag(group).map_or(Null, |lag| Integer(lag as i64));
+    let entries_read =
+        |group: &ConsumerGroup| group.entries_read.map_or(Null, |read| Integer(read as i64));
+
+    match subcommand.as_str() {
+        "STREAM" => {
+            let full = match &arr[3..] {
+                [] => None,
+                [option] if arg_eq(option, "FULL") => Some(10),
+                [option, count_option, count]
+                    if arg_eq(option, "FULL") && arg_eq(count_option, "COUNT") =>
+                {
+                    Some(parse_int(count)?.max(0) as usize)
+                }
+                _ => return Err(CommandError::Syntax),
+            };
+
+            let mut reply = vec![
+                field("length"),
+                Integer(stream.len() as i64),
+                field("radix-tree-keys"),
+                Integer(stream.nodes() as i64),
+                field("radix-tree-nodes"),
+                Integer(stream.nodes() as i64),
+                field("last-generated-id"),
+                id_reply(stream.last_id),
+                field("max-deleted-entry-id"),
+                id_reply(stream.max_deleted_entry_id),
+                field("entries-added"),
+                Integer(stream.entries_added as i64),
+                field("recorded-first-entry-id"),
+                id_reply(stream.first_id),
+            ];
+            let count = match full {
+                None => {
+                    let edge = |entry: Option<(&StreamId, &Fields)>| {
+                        entry.map_or(Null, |(id, fields)| entry_reply(*id, fields))
+                    };
+                    reply.extend([
+                        field("groups"),
+                        Integer(stream.groups.len() as i64),
+                        field("first-entry"),
+                        edge(stream.entries.iter().next()),
+                        field("last-entry"),
+                        edge(stream.entries.iter().next_back()),
+                    ]);
+                    return Ok(Array(reply));
+                }
+                Some(0) => usize::MAX,
+                Some(count) => count,
+            };
+
+            let entries = stream
+                .entries
+                .iter()
+                .take(count)
+                .map(|(id, fields)| entry_reply(*id, fields))
+                .collect();
+            let groups = stream
+                .groups
+                .iter()
+                .map(|(name, group)| {
+                    let pending = group
+                        .pending
+                        .iter()
+                        .take(count)
+                        .map(|(id, entry)| {
+                            Array(vec![
+                                id_reply(*id),
+                                bulk(&entry.consumer),
+                                Integer(entry.delivery_time as i64),
+                                Integer(entry.delivery_count as i64),
+                            ])
+                        })
+                        .collect();
+                    let consumers = group
+                        .consumers
+                        .iter()
+                        .map(|(name, consumer)| {
+                            let pending = consumer
+                                .pending
+                                .iter()
+                                .take(count)
+                                .map(|id| {
+                                    let entry = &group.pending[id];
+                                    Array(vec![
+                                        id_reply(*id),
+                                        Integer(entry.delivery_time as i64),
+                                        Integer(entry.delivery_count as i64),
+                                    ])
+                                })
+                                .collect();
+                            Array(vec![
+                                field("name"),
+                                bulk(name),
+                                field("seen-time"),
+                                Integer(consumer.seen_time as i64),
+                                field("active-time"),
+                                Integer(consumer.active_time.map_or(-1, |t| t as i64)),
+                                field("pel-count"),
+                                Integer(consumer.pending.len() as i64),
+                                field("pending"),
+                                Array(pending),
+                            ])
+                        })
+                        .collect();
+                    Array(vec![
+                        field("name"),
+                        bulk(name),
+                        field("last-delivered-id"),
+                        id_reply(group.last_id),
+                        field("entries-read"),
+                        entries_read(group),
+                        field("lag"),
+                        lag(group),
+                        field("pel-count"),
+                        Integer(group.pending.len() as i64),
+                        field("pending"),
+                        Array(pending),
+                        field("consumers"),
+                        Array(consumers),
+                    ])
+                })
+                .collect();
+            reply.extend([
+                field("entries"),
+                Array(entries),
+                field("groups"),
+                Array(groups),
+            ]);
+            Ok(Array(reply))
+        }
+        "GROUPS" => {
+            let groups = stream
+                .groups
+                .iter()
+                .map(|(name, group)| {
+                    Array(vec![
+                        field("name"),
+                        bulk(name),
+                        field("consumers"),
+                        Integer(group.consumers.len() as i64),
+                        field("pending"),
+                        Integer(group.pending.len() as i64),
+                        field("last-delivered-id"),
+                        id_reply(group.last_id),
+                        field("entries-read"),
+                        entries_read(group),
+                        field("lag"),
+                        lag(group),
+                    ])
+                })
+                .collect();
+            Ok(Array(groups))
+        }
+        _ => {
+            let name = arr[3].to_bytes();
+            let group = stream.groups.get(&name).ok_or_else(|| {
+                CommandError::Raw(format!(
+                    "NOGROUP No such consumer group '{}' for key name '{}'",
+                    String::from_utf8_lossy(&name),
+                    String::from_utf8_lossy(&key)
+                ))
+            })?;
+            let consumers = group
+                .consumers
+                .iter()
+                .map(|(name, consumer)| {
+                    let inactive = consumer
+                        .active_time
+                        .map_or(-1, |t| now.saturating_sub(t) as i64);
+                    Array(vec![
+                        field("name"),
+                        bulk(name),
+                        field("pending"),
+                        Integer(consumer.pending.len() as i64),
+                        field("idle"),
+                        Integer(now.saturating_sub(consumer.seen_time) as i64),
+                        field("inactive"),
+                        Integer(inactive),
+                    ])
+                })
+                .collect();
+            Ok(Array(consumers))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ReadFrom {
+    After(StreamId),
+    /// `$`: only entries added after the command was issued.
+    Last,
+    /// `+`: the last entry currently in the stream, then anything newer.
+    LastEntry,
+    /// `>`: entries never delivered to the group.
+    Undelivered,
+}
+
+#[derive(Debug)]
+struct ReadRequest {
+    group: Option<(Vec<u8>, Vec<u8>)>,
+    count: Option<usize>,
+    block: Option<u64>,
+    noack: bool,
+    keys: Vec<Vec<u8>>,
+    ids: Vec<ReadFrom>,
+}
+
+/// An `XREAD`/`XREADGROUP ... BLOCK` waiting for entries.
+#[derive(Debug)]
+pub struct BlockedRead {
+    request: ReadRequest,
+    /// The database the read was issued in, which it keeps reading from
+    /// whatever the client selects later.
+    pub db: usize,
+    pub since: Instant,
+    pub deadline: Option<Instant>,
+}
+
+pub enum Read {
+    Ready(Value, Propagation),
+    Block(BlockedRead),
+}
+
+impl BlockedRead {
+    /// Retries the read, returning the reply once there is something to
+    /// deliver (or the read can no longer succeed).
+    pub fn retry(&self, db: &mut Cache) -> Option<(Value, Propagation)> {
+        match read_streams(&self.request, db) {
+            Ok((Null, _)) => None,
+            Ok(reply) => Some(reply),
+            Err(err) => Some((err.into(), Propagation::None)),
+        }
+    }
+}
+
+/// The keys of an `XREAD` or `XREADGROUP`: the first half of the arguments
+/// after `STREAMS`, the second half being their ids.
+pub fn read_key_positions(arr: &[Value]) -> Vec<usize> {
+    let Some(streams_at) = arr.iter().position(|arg| arg_eq(arg, "STREAMS")) else {
+        return Vec::new();
+    };
+    let first = streams_at + 1;
+    (first..first + (arr.len() - first) / 2).collect()
+}
+
+fn parse_read(arr: &[Value]) -> Result<ReadRequest, CommandError> {
+    let xreadgroup = arg_eq(&arr[0], "XREADGROUP");
+    let name = match xreadgroup {
+        true => "xreadgroup",
+        false => "xread",
+    };
+    check_arity(arr, if xreadgroup { -7 } else { -4 })?;
+
+    let mut request = ReadRequest {
+        group: None,
+        count: None,
+        block: None,
+        noack: false,
+        keys: Vec::new(),
+        ids: Vec::new(),
+    };
+    let mut i = 1;
+    let streams_at = loop {
+        let option = text(arr.get(i).ok_or(CommandError::Syntax)?).to_uppercase();
+        match (option.as_str(), arr.get(i + 1)) {
+            ("STREAMS", _) => break i + 1,
+            ("COUNT", Some(value)) => {
+                let count = parse_int(value)?;
+                request.count = (count > 0).then_some(count as usize);
+                i += 2;
+            }
+            ("BLOCK", Some(value)) => {
+                let timeout = parse_int(value).map_err(|_| {
+                    CommandError::Other("timeout is not an integer or out of range".to_string())
+                })?;
+                if timeout < 0 {
+                    return Err(CommandError::Other("timeout is negative".to_string()));
+                }
+                request.block = Some(timeout as u64);
+                i += 2;
+            }
+            ("GROUP", Some(group)) if xreadgroup => {
+                let consumer = arr.get(i + 2).ok_or(CommandError::Syntax)?;
+                request.group = Some((group.to_bytes(), consumer.to_bytes()));
+                i += 3;
+            }
+            ("NOACK", _) if xreadgroup => {
+                request.noack = true;
+                i += 1;
+            }
+            _ => return Err(CommandError::Syntax),
+        }
+    };
+    if xreadgroup && request.group.is_none() {
+        return Err(CommandError::Other(
+            "Missing GROUP option for XREADGROUP".to_string(),
+        ));
+    }
+
+    let streams = &arr[streams_at..];
+    if streams.is_empty() || !streams.len().is_multiple_of(2) {
+        return Err(CommandError::Other(format!(
+            "Unbalanced '{}' list of streams: for each stream key an ID or '$' must be specified.",
+            name
+        )));
+    }
+    let (keys, ids) = streams.split_at(streams.len() / 2);
+    request.keys = keys.iter().map(Value::to_bytes).collect();
+    for id in ids {
+        let from =
+            match (text(id).as_str(), xreadgroup) {
+                (">", true) => ReadFrom::Undelivered,
+                (">", false) => {
+                    return Err(CommandError::Other(
+                        "The > ID can be specified only when calling XREADGROUP using the GROUP \
+                     <group> <consumer> option."
+                            .to_string(),
+                    ))
+                }
+                ("$", false) => ReadFrom::Last,
+                ("+", false) => ReadFrom::LastEntry,
+                ("$", true) => return Err(CommandError::Other(
+                    "The $ ID is meaningless in the context of XREADGROUP: you want to read the \
+                     history of this consumer by specifying a proper ID, or use the > ID to get \
+                     new messages. The $ ID would just return an empty result set."
+                        .to_string(),
+                )),
+                (s, _) => ReadFrom::After(StreamId::parse(s, 0).ok_or_else(invalid_id)?),
+            };
+        request.ids.push(from);
+    }
+    Ok(request)
+}
+
+/// Pins `$` and `+` to concrete IDs so a blocked read keeps waiting for
+/// the same entries no matter how often it is retried.
+fn resolve_ids(request: &mut ReadRequest, db: &mut Cache) -> Result<(), CommandError> {
+    for (key, from) in request.keys.iter().zip(request.ids.iter_mut()) {
+        let stream = db.get_stream(key)?;
+        *from = match *from {
+            ReadFrom::Last => ReadFrom::After(stream.map_or(StreamId::MIN, |s| s.last_id)),
+            ReadFrom::LastEntry => {
+                let last = stream.and_then(|s| s.entries.keys().next_back().copied());
+                match last {
+                    Some(last) => ReadFrom::After(last.prev().unwrap_or(StreamId::MIN)),
+                    None => ReadFrom::After(stream.map_or(StreamId::MIN, |s| s.last_id)),
+                }
+            }
+            other => other,
+        };
+    }
+    Ok(())
+}
+
+fn read_streams(
+    request: &ReadRequest,
+    db: &mut Cache,
+) -> Result<(Value, Propagation), CommandError> {
+    let (group_name, consumer) = match &request.group {
+        Some((group, consumer)) => (group, consumer),
+        None => return read_plain(request, db).map(|reply| (reply, Propagation::None)),
+    };
+
+    // All groups have to exist before anything is delivered.
+    for key in &request.keys {
+        let exists = db
+            .get_stream(key)?
+            .is_some_and(|stream| stream.groups.contains_key(group_name));
+        if !exists {
+            return Err(CommandError::Raw(format!(
+                "NOGROUP No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+                String::from_utf8_lossy(key),
+                String::from_utf8_lossy(group_name)
+            )));
+        }
+    }
+
+    let now = now_ms();
+    let mut results = Vec::new();
+    let mut propagate = Vec::new();
+    for (key, from) in request.keys.iter().zip(&request.ids) {
+        // The group changed if replicas have to be told about it.
+        let before = propagate.len();
+        let (stream, mut group) = detach_group(db, key, group_name)?;
+        if !group.consumers.contains_key(consumer) {
+            propagate.push(create_consumer_propagation(key, group_name, consumer));
+        }
+        group.consumer(consumer, now);
+
+        let entries = match from {
+            ReadFrom::After(after) => {
+                // Reading the consumer's history: its pending entries, with
+                // deleted ones reported as nil.
+                let ids: Vec<StreamId> = group.consumers[consumer]
+                    .pending
+                    .range((Bound::Excluded(*after), Bound::Unbounded))
+                    .take(request.count.unwrap_or(usize::MAX))
+                    .copied()
+                    .collect();
+                let mut entries = Vec::new();
+                for id in ids {
+                    match stream.entries.get(&id) {
+                        Some(fields) => {
+                            let count = group.pending[&id].delivery_count + 1;
+                            group.assign(id, consumer, now, count);
+                            propagate
+                                .push(xclaim_propagation(key, group_name, &group, consumer, id));
+                            entries.push(entry_reply(id, fields));
+                        }
+                        None => entries.push(Array(vec![id_reply(id), Null])),
+                    }
+                }
+                results.push(Array(vec![bulk(key), Array(entries)]));
+                stream.groups.insert(group_name.clone(), group);
+                if propagate.len() > before {
+                    db.signal_modified(key);
+                }
+                continue;
+            }
+            _ => stream.range(
+                Bound::Excluded(group.last_id),
+                Bound::Unbounded,
+                request.count,
+                false,
+            ),
+        };
+
+        if !entries.is_empty() {
+            group.consumer(consumer, now).active_time = Some(now);
+            for (id, _) in &entries {
+                stream.advance_group(&mut group, *id);
+                if !request.noack {
+                    group.assign(*id, consumer, now, 1);
+                    propagate.push(xclaim_propagation(key, group_name, &group, consumer, *id));
+                }
+            }
+            propagate.push(setid_propagation(key, group_name, &group));
+            let entries = entries
+                .into_iter()
+                .map(|(id, fields)| entry_reply(id, fields))
+                .collect();
+            results.push(Array(vec![bulk(key), Array(entries)]));
+        }
+        stream.groups.insert(group_name.clone(), group);
+        if propagate.len() > before {
+            db.signal_modified(key);
+        }
+    }
+
+    let reply = match results.is_empty() {
+        true => Null,
+        false => Array(results),
+    };
+    Ok((reply, propagation(propagate)))
+}
+
+fn read_plain(request: &ReadRequest, db: &mut Cache) -> Result<Value, CommandError> {
+    let mut results = Vec::new();
+    for (key, from) in request.keys.iter().zip(&request.ids) {
+        let after = match from {
+            ReadFrom::After(after) => *after,
+            _ => continue,
+        };
+        let stream = match db.get_stream(key)? {
+            Some(stream) => stream,
+            None => continue,
+        };
+        let entries = stream.range(
+            Bound::Excluded(after),
+            Bound::Unbounded,
+            request.count,
+            false,
+        );
+        if !entries.is_empty() {
+            let entries = entries
+                .into_iter()
+                .map(|(id, fields)| entry_reply(id, fields))
+                .collect();
+            results.push(Array(vec![bulk(key), Array(entries)]));
+        }
+    }
+    match results.is_empty() {
+        true => Ok(Null),
+        false => Ok(Array(results)),
+    }
+}
+
+/// Runs `XREAD` or `XREADGROUP` against `db`, database number `selected`,
+/// which may ask the caller to park the client until entries arrive.
+pub fn execute_read(arr: &[Value], db: &mut Cache, selected: usize) -> Read {
+    let result = parse_read(arr).and_then(|mut request| {
+        resolve_ids(&mut request, db)?;
+        let (reply, propagation) = read_streams(&request, db)?;
+        let waiting = request.ids.iter().all(|from| match from {
+            ReadFrom::After(_) => request.group.is_none(),
+            _ => true,
+        });
+        match (&reply, request.block) {
+            (Null, Some(timeout)) if waiting => {
+                let since = Instant::now();
+                let deadline = match timeout {
+                    0 => None,
+                    ms => Some(since + Duration::from_millis(ms)),
+                };
+                Ok(Read::Block(BlockedRead {
+                    request,
+                    db: selected,
+                    since,
+                    deadline,
+                }))
+            }
+            _ => Ok(Read::Ready(reply, propagation)),
+        }
+    });
+    match result {
+        Ok(read) => read,
+        Err(err) => Read::Ready(err.into(), Propagation::None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(args: &[&str]) -> Vec<Value> {
+        args.iter()
+            .map(|arg| BulkString(arg.to_string().into_bytes()))
+            .collect()
+    }
+
+    fn run(db: &mut Cache, args: &[&str]) -> Value {
+        execute(args[0], &command(args), db).0
+    }
+
+    fn read(db: &mut Cache, args: &[&str]) -> Read {
+        execute_read(&command(args), db, 0)
+    }
+
+    fn ids(value: &Value) -> Vec<String> {
+        match value {
+            Array(entries) => entries
+                .iter()
+                .map(|entry| match entry {
+                    Array(entry) => match &entry[0] {
+                        BulkString(id) => String::from_utf8(id.clone()).unwrap(),
+                        other => panic!("Wrong type. got {:?}", other),
+                    },
+                    BulkString(id) => String::from_utf8(id.clone()).unwrap(),
+                    other => panic!("Wrong type. got {:?}", other),
+                })
+                .collect(),
+            other => panic!("Wrong type. got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_xadd_ids() {
+        let mut db = Cache::new();
+        assert_eq!(
+            run(&mut db, &["XADD", "s", "1-1", "f", "v"]),
+            BulkString(b"1-1".to_vec())
+        );
+        assert_eq!(
+            run(&mut db, &["XADD", "s", "1-*", "f", "v"]),
+            BulkString(b"1-2".to_vec())
+        );
+        assert_eq!(
+            run(&mut db, &["XADD", "s", "1-1", "f", "v"]),
+            SimpleError(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            run(&mut db, &["XADD", "s", "NOMKSTREAM", "0-0", "f", "v"]),
+            SimpleError("ERR The ID specified in XADD must be greater than 0-0".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["XADD", "missing", "NOMKSTREAM", "*", "f", "v"]),
+            Null
+        );
+        assert_eq!(run(&mut db, &["XLEN", "s"]), Integer(2));
+    }
+
+    #[test]
+    fn test_xadd_propagates_generated_id() {
+        let mut db = Cache::new();
+        let (reply, propagation) =
+            execute("XADD", &command(&["XADD", "s", "*", "f", "v"]), &mut db);
+        let id = match reply {
+            BulkString(id) => String::from_utf8(id).unwrap(),
+            other => panic!("Wrong type. got {:?}", other),
+        };
+        let expected = Array(command(&["XADD", "s", &id, "f", "v"]));
+        assert_eq!(propagation, Propagation::Rewrite(vec![expected]));
+    }
+
+    #[test]
+    fn test_xrange_and_trim() {
+        let mut db = Cache::new();
+        for id in ["1-0", "2-0", "3-0", "4-0"] {
+            run(&mut db, &["XADD", "s", id, "f", "v"]);
+        }
+        assert_eq!(
+            ids(&run(&mut db, &["XRANGE", "s", "(1", "+", "COUNT", "2"])),
+            ["2-0", "3-0"]
+        );
+        assert_eq!(
+            ids(&run(&mut db, &["XREVRANGE", "s", "+", "-", "COUNT", "1"])),
+            ["4-0"]
+        );
+        assert_eq!(
+            run(&mut db, &["XTRIM", "s", "MAXLEN", "=", "2"]),
+            Integer(2)
+        );
+        assert_eq!(run(&mut db, &["XDEL", "s", "3-0", "9-0"]), Integer(1));
+        assert_eq!(ids(&run(&mut db, &["XRANGE", "s", "-", "+"])), ["4-0"]);
+        assert_eq!(
+            run(&mut db, &["XTRIM", "s", "MAXLEN", "2", "LIMIT", "1"]),
+            SimpleError(
+                "ERR syntax error, LIMIT cannot be used without the special ~ option".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_xread() {
+        let mut db = Cache::new();
+        run(&mut db, &["XADD", "s", "1-0", "f", "v"]);
+        match read(&mut db, &["XREAD", "STREAMS", "s", "0"]) {
+            Read::Ready(Array(streams), Propagation::None) => assert_eq!(streams.len(), 1),
+            _ => panic!("expected a reply"),
+        }
+
+        let blocked = match read(&mut db, &["XREAD", "BLOCK", "0", "STREAMS", "s", "$"]) {
+            Read::Block(blocked) => blocked,
+            _ => panic!("expected the read to block"),
+        };
+        assert!(blocked.deadline.is_none());
+        assert!(blocked.retry(&mut db).is_none());
+        run(&mut db, &["XADD", "s", "2-0", "f", "v"]);
+        let (reply, _) = blocked.retry(&mut db).unwrap();
+        let expected = Array(vec![Array(vec![
+            BulkString(b"s".to_vec()),
+            Array(vec![entry_reply(
+                StreamId::new(2, 0),
+                &vec![(b"f".to_vec(), b"v".to_vec())],
+            )]),
+        ])]);
+        assert_eq!(reply, expected);
+    }
+
+    #[test]
+    fn test_consumer_group_flow() {
+        let mut db = Cache::new();
+        assert_eq!(
+            run(&mut db, &["XGROUP", "CREATE", "s", "g", "$"]),
+            SimpleError(
+                "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you \
+                 may want to use the MKSTREAM option to create an empty stream automatically."
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            run(&mut db, &["XGROUP", "CREATE", "s", "g", "$", "MKSTREAM"]),
+            SimpleString("OK".to_string())
+        );
+        run(&mut db, &["XADD", "s", "1-0", "f", "v"]);
+        run(&mut db, &["XADD", "s", "2-0", "f", "v"]);
+
+        match read(
+            &mut db,
+            &["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", ">"],
+        ) {
+            Read::Ready(Array(streams), Propagation::Rewrite(_)) => {
+                assert_eq!(streams.len(), 1)
+            }
+            _ => panic!("expected entries"),
+        }
+        let summary = run(&mut db, &["XPENDING", "s", "g"]);
+        assert_eq!(
+            summary,
+            Array(vec![
+                Integer(2),
+                BulkString(b"1-0".to_vec()),
+                BulkString(b"2-0".to_vec()),
+                Array(vec![Array(command(&["alice", "2"]))]),
+            ])
+        );
+
+        assert_eq!(run(&mut db, &["XACK", "s", "g", "1-0"]), Integer(1));
+        let claimed = run(&mut db, &["XCLAIM", "s", "g", "bob", "0", "2-0", "JUSTID"]);
+        assert_eq!(ids(&claimed), ["2-0"]);
+        let pending = run(&mut db, &["XPENDING", "s", "g", "-", "+", "10", "bob"]);
+        match pending {
+            Array(entries) => {
+                assert_eq!(entries.len(), 1);
+                match &entries[0] {
+                    Array(entry) => assert_eq!(entry[3], Integer(1)),
+                    other => panic!("Wrong type. got {:?}", other),
+                }
+            }
+            other => panic!("Wrong type. got {:?}", other),
+        }
+
+        run(&mut db, &["XDEL", "s", "2-0"]);
+        let autoclaim = run(&mut db, &["XAUTOCLAIM", "s", "g", "alice", "0", "0"]);
+        assert_eq!(
+            autoclaim,
+            Array(vec![
+                BulkString(b"0-0".to_vec()),
+                Array(vec![]),
+                Array(vec![BulkString(b"2-0".to_vec())]),
+            ])
+        );
+        assert_eq!(
+            run(&mut db, &["XGROUP", "DELCONSUMER", "s", "g", "bob"]),
+            Integer(0)
+        );
+        assert_eq!(run(&mut db, &["XGROUP", "DESTROY", "s", "g"]), Integer(1));
+    }
+
+    #[test]
+    fn test_xreadgroup_requires_group() {
+        let mut db = Cache::new();
+        run(&mut db, &["XADD", "s", "1-0", "f", "v"]);
+        match read(
+            &mut db,
+            &["XREADGROUP", "GROUP", "g", "c", "STREAMS", "s", ">"],
+        ) {
+            Read::Ready(SimpleError(msg), _) => assert!(msg.starts_with("NOGROUP")),
+            _ => panic!("expected NOGROUP"),
+        }
+    }
+}
@@ -0,0 +1,1067 @@
+use super::keyspace::pexpireat;
+use super::table::{Command, Keys, DENYOOM, FAST, READONLY, WRITE};
+use super::{arg_eq, bulk, check_arity, command_name, parse_int, CommandError, Propagation};
+use crate::cache::{now_ms, Cache, Data};
+use crate::notify;
+use crate::resp::value::Value;
+use crate::resp::value::Value::*;
+
+/// Largest string a `SETRANGE` may produce, matching Redis' default
+/// `proto-max-bulk-len`.
+const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+/// The most memory `LCS` may use for its table, like Redis's cap at
+/// `proto-max-bulk-len`.
+const MAX_LCS_TABLE: usize = 512 * 1024 * 1024;
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "SET",
+        arity: -3,
+        flags: WRITE | DENYOOM,
+        keys: Keys::Range(1, 1, 1),
+        group: "string",
+        summary: "Sets the string value of a key, ignoring its type. The key is created if it doesn't exist.",
+    },
+    Command {
+        name: "GET",
+        arity: 2,
+        flags: READONLY | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "string",
+        summary: "Returns the string value of a key.",
+    },
+    Command {
+        name: "INCR",
+        arity: 2,
+        flags: WRITE | DENYOOM | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "string",
+        summary: "Increments the integer value of a key by one. Uses 0 as initial value if the key doesn't exist.",
+    },
+    Command {
+        name: "DECR",
+        arity: 2,
+        flags: WRITE | DENYOOM | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "string",
+        summary: "Decrements the integer value of a key by one. Uses 0 as initial value if the key doesn't exist.",
+    },
+    Command {
+        name: "INCRBY",
+        arity: 3,
+        flags: WRITE | DENYOOM | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "string",
+        summary: "Increments the integer value of a key by a number. Uses 0 as initial value if the key doesn't exist.",
+    },
+    Command {
+        name: "DECRBY",
+        arity: 3,
+        flags: WRITE | DENYOOM | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "string",
+        summary: "Decrements a number from the integer value of a key. Uses 0 as initial value if the key doesn't exist.",
+    },
+    Command {
+        name: "INCRBYFLOAT",
+        arity: 3,
+        flags: WRITE | DENYOOM | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "string",
+        summary: "Increment the floating point value of a key by a number. Uses 0 as initial value if the key doesn't exist.",
+    },
+    Command {
+        name: "APPEND",
+        arity: 3,
+        flags: WRITE | DENYOOM | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "string",
+        summary: "Appends a string to the value of a key. Creates the key if it doesn't exist.",
+    },
+    Command {
+        name: "STRLEN",
+        arity: 2,
+        flags: READONLY | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "string",
+        summary: "Returns the length of a string value.",
+    },
+    Command {
+        name: "GETRANGE",
+        arity: 4,
+        flags: READONLY,
+        keys: Keys::Range(1, 1, 1),
+        group: "string",
+        summary: "Returns a substring of the string stored at a key.",
+    },
+    Command {
+        name: "SETRANGE",
+        arity: 4,
+        flags: WRITE | DENYOOM,
+        keys: Keys::Range(1, 1, 1),
+        group: "string",
+        summary: "Overwrites a part of a string value with another by an offset. Creates the key if it doesn't exist.",
+    },
+    Command {
+        name: "MGET",
+        arity: -2,
+        flags: READONLY | FAST,
+        keys: Keys::Range(1, -1, 1),
+        group: "string",
+        summary: "Atomically returns the string values of one or more keys.",
+    },
+    Command {
+        name: "MSET",
+        arity: -3,
+        flags: WRITE | DENYOOM,
+        keys: Keys::Range(1, -1, 2),
+        group: "string",
+        summary: "Atomically creates or modifies the string values of one or more keys.",
+    },
+    Command {
+        name: "MSETNX",
+        arity: -3,
+        flags: WRITE | DENYOOM,
+        keys: Keys::Range(1, -1, 2),
+        group: "string",
+        summary: "Atomically modifies the string values of one or more keys only when all keys don't exist.",
+    },
+    Command {
+        name: "GETDEL",
+        arity: 2,
+        flags: WRITE | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "string",
+        summary: "Returns the string value of a key after deleting the key.",
+    },
+    Command {
+        name: "GETEX",
+        arity: -2,
+        flags: WRITE | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "string",
+        summary: "Returns the string value of a key after setting its expiration time.",
+    },
+    Command {
+        name: "GETSET",
+        arity: 3,
+        flags: WRITE | DENYOOM | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "string",
+        summary: "Returns the previous string value of a key after setting it to a new value.",
+    },
+    Command {
+        name: "SETNX",
+        arity: 3,
+        flags: WRITE | DENYOOM | FAST,
+        keys: Keys::Range(1, 1, 1),
+        group: "string",
+        summary: "Set the string value of a key only when the key doesn't exist.",
+    },
+    Command {
+        name: "SETEX",
+        arity: 4,
+        flags: WRITE | DENYOOM,
+        keys: Keys::Range(1, 1, 1),
+        group: "string",
+        summary: "Sets the string value and expiration time of a key. Creates the key if it doesn't exist.",
+    },
+    Command {
+        name: "PSETEX",
+        arity: 4,
+        flags: WRITE | DENYOOM,
+        keys: Keys::Range(1, 1, 1),
+        group: "string",
+        summary: "Sets both string value and expiration time in milliseconds of a key. The key is created if it doesn't exist.",
+    },
+    Command {
+        name: "LCS",
+        arity: -3,
+        flags: READONLY,
+        keys: Keys::Range(1, 2, 1),
+        group: "string",
+        summary: "Finds the longest common substring.",
+    },
+];
+
+pub fn execute(name: &str, arr: &[Value], db: &mut Cache) -> (Value, Propagation) {
+    let read = |reply| (reply, Propagation::None);
+    let write = |reply| (reply, Propagation::Verbatim);
+
+    let result = match name {
+        "SET" => execute_set(arr, db),
+        "GET" => execute_get(arr, db).map(read),
+        "INCR" => execute_incrby(arr, db, 2, Some(1)).map(write),
+        "DECR" => execute_incrby(arr, db, 2, Some(-1)).map(write),
+        "INCRBY" => execute_incrby(arr, db, 3, None).map(write),
+        "DECRBY" => execute_decrby(arr, db).map(write),
+        "INCRBYFLOAT" => execute_incrbyfloat(arr, db),
+        "APPEND" => execute_append(arr, db).map(write),
+        "STRLEN" => execute_strlen(arr, db).map(read),
+        "GETRANGE" => execute_getrange(arr, db).map(read),
+        "SETRANGE" => execute_setrange(arr, db).map(write),
+        "MGET" => execute_mget(arr, db).map(read),
+        "MSET" => execute_mset(arr, db).map(write),
+        "MSETNX" => execute_msetnx(arr, db),
+        "GETDEL" => execute_getdel(arr, db),
+        "GETEX" => execute_getex(arr, db),
+        "GETSET" => execute_getset(arr, db).map(write),
+        "SETNX" => execute_setnx(arr, db),
+        "SETEX" => execute_setex(arr, db, 1000),
+        "PSETEX" => execute_setex(arr, db, 1),
+        "LCS" => execute_lcs(arr, db).map(read),
+        _ => Err(CommandError::Other(format!("unknown command '{}'", name))),
+    };
+
+    match result {
+        Ok(response) => response,
+        Err(err) => (err.into(), Propagation::None),
+    }
+}
+
+fn command(args: &[&[u8]]) -> Value {
+    Array(args.iter().map(|arg| bulk(arg)).collect())
+}
+
+/// Parses an integer the way Redis' `string2ll` does: no sign other than a
+/// leading `-`, no leading zeros, no surrounding spaces.
+fn parse_stored_int(value: &[u8]) -> Result<i64, CommandError> {
+    let s = std::str::from_utf8(value).map_err(|_| CommandError::NotInteger)?;
+    match s.parse::<i64>() {
+        Ok(n) if n.to_string() == s => Ok(n),
+        _ => Err(CommandError::NotInteger),
+    }
+}
+
+fn parse_float(value: &[u8]) -> Option<f64> {
+    let n = std::str::from_utf8(value).ok()?.parse::<f64>().ok()?;
+    (!n.is_nan()).then_some(n)
+}
+
+/// Turns a relative (`EX`/`PX`) or absolute (`EXAT`/`PXAT`) expiry argument
+/// into a Unix time in milliseconds.
+fn parse_expiry(option: &Value, value: &Value, command: &str) -> Result<u64, CommandError> {
+    let invalid = || CommandError::Other(format!("invalid expire time in '{}' command", command));
+    let n = parse_int(value)?;
+    if n <= 0 {
+        return Err(invalid());
+    }
+    let n = n as u64;
+    let ms = if arg_eq(option, "EX") || arg_eq(option, "EXAT") {
+        n.checked_mul(1000).ok_or_else(invalid)?
+    } else {
+        n
+    };
+    match arg_eq(option, "EXAT") || arg_eq(option, "PXAT") {
+        true => Ok(ms),
+        false => ms.checked_add(now_ms()).ok_or_else(invalid),
+    }
+}
+
+/// The `SET` replicas receive in place of one with a relative expiry, so
+/// the key expires at the same moment everywhere.
+fn set_pxat(key: &[u8], value: &[u8], at: u64) -> Propagation {
+    let at = at.to_string();
+    let set = command(&[b"SET", key, value, b"PXAT", at.as_bytes()]);
+    Propagation::Rewrite(vec![set])
+}
+
+#[derive(PartialEq)]
+enum Condition {
+    Always,
+    IfMissing,
+    IfExists,
+}
+
+enum Ttl {
+    Clear,
+    Keep,
+    At(u64),
+}
+
+/// `SET key value [NX | XX] [GET] [EX s | PX ms | EXAT ts | PXAT ts | KEEPTTL]`,
+/// with the options in any order.
+fn execute_set(arr: &[Value], db: &mut Cache) -> Result<(Value, Propagation), CommandError> {
+    check_arity(arr, -3)?;
+    let key = arr[1].to_bytes();
+
+    let mut condition = Condition::Always;
+    let mut get = false;
+    let mut ttl = Ttl::Clear;
+    let mut ttl_given = false;
+    let mut i = 3;
+    while i < arr.len() {
+        let option = &arr[i];
+        if arg_eq(option, "NX") && condition != Condition::IfExists {
+            condition = Condition::IfMissing;
+        } else if arg_eq(option, "XX") && condition != Condition::IfMissing {
+            condition = Condition::IfExists;
+        } else if arg_eq(option, "GET") {
+            get = true;
+        } else if arg_eq(option, "KEEPTTL") && !ttl_given {
+            ttl = Ttl::Keep;
+            ttl_given = true;
+        } else if ["EX", "PX", "EXAT", "PXAT"]
+            .iter()
+            .any(|name| arg_eq(option, name))
+            && !ttl_given
+            && i + 1 < arr.len()
+        {
+            ttl = Ttl::At(parse_expiry(option, &arr[i + 1], "set")?);
+            ttl_given = true;
+            i += 1;
+        } else {
+            return Err(CommandError::Syntax);
+        }
+        i += 1;
+    }
+
+    let old = match get {
+        true => db.get(&key)?,
+        false => None,
+    };
+    let exists = db.get_data(&key).is_some();
+    let reply = |old: Option<Vec<u8>>, set: bool| match (get, set) {
+        (true, _) => old.map_or(Null, |value| bulk(&value)),
+        (false, true) => SimpleString("OK".to_string()),
+        (false, false) => Null,
+    };
+    let skip = match condition {
+        Condition::Always => false,
+        Condition::IfMissing => exists,
+        Condition::IfExists => !exists,
+    };
+    if skip {
+        return Ok((reply(old, false), Propagation::None));
+    }
+
+    let value = arr[2].to_bytes();
+    let (expiry, propagation) = match ttl {
+        Ttl::Clear => (None, Propagation::Verbatim),
+        Ttl::Keep => (db.expiry(&key), Propagation::Verbatim),
+        Ttl::At(deadline) => (Some(deadline), set_pxat(&key, &value, deadline)),
+    };
+    db.insert(key.clone(), value, expiry);
+    db.notify(notify::STRING, "set", &key);
+    if let Ttl::At(_) = ttl {
+        db.notify(notify::GENERIC, "expire", &key);
+    }
+    Ok((reply(old, true), propagation))
+}
+
+fn execute_get(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, 2)?;
+    match db.get(&arr[1].to_bytes())? {
+        Some(value) => Ok(bulk(&value)),
+        None => Ok(Null),
+    }
+}
+
+fn execute_decrby(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, 3)?;
+    let by = parse_int(&arr[2])?;
+    let by = by
+        .checked_neg()
+        .ok_or_else(|| CommandError::Other("decrement would overflow".to_string()))?;
+    execute_incrby(arr, db, 3, Some(by))
+}
+
+/// `INCR`, `DECR` and `INCRBY`; `by` is taken from the third argument when
+/// not given.
+fn execute_incrby(
+    arr: &[Value],
+    db: &mut Cache,
+    arity: i64,
+    by: Option<i64>,
+) -> Result<Value, CommandError> {
+    check_arity(arr, arity)?;
+    let by = match by {
+        Some(by) => by,
+        None => parse_int(&arr[2])?,
+    };
+    let key = arr[1].to_bytes();
+
+    let current = match db.get(&key)? {
+        Some(value) => parse_stored_int(&value)?,
+        None => 0,
+    };
+    let value = current
+        .checked_add(by)
+        .ok_or_else(|| CommandError::Other("increment or decrement would overflow".to_string()))?;
+    let bytes = value.to_string().into_bytes();
+    match db.get_string_mut(&key)? {
+        Some(stored) => {
+            *stored = bytes;
+            db.signal_modified(&key);
+        }
+        None => {
+            db.insert(key.clone(), bytes, None);
+        }
+    }
+    db.notify(notify::STRING, "incrby", &key);
+    Ok(Integer(value))
+}
+
+fn execute_incrbyfloat(
+    arr: &[Value],
+    db: &mut Cache,
+) -> Result<(Value, Propagation), CommandError> {
+    check_arity(arr, 3)?;
+    let key = arr[1].to_bytes();
+    let not_float = || CommandError::Other("value is not a valid float".to_string());
+
+    let by = parse_float(&arr[2].to_bytes()).ok_or_else(not_float)?;
+    let current = match db.get(&key)? {
+        Some(value) => parse_float(&value).ok_or_else(not_float)?,
+        None => 0.0,
+    };
+    let value = current + by;
+    if !value.is_finite() {
+        return Err(CommandError::Other(
+            "increment would produce NaN or Infinity".to_string(),
+        ));
+    }
+
+    let bytes = value.to_string().into_bytes();
+    match db.get_string_mut(&key)? {
+        Some(stored) => {
+            *stored = bytes.clone();
+            db.signal_modified(&key);
+        }
+        None => {
+            db.insert(key.clone(), bytes.clone(), None);
+        }
+    }
+    db.notify(notify::STRING, "incrbyfloat", &key);
+    // Float formatting may differ between instances, so replicas get the
+    // resulting value rather than the increment.
+    let set = command(&[b"SET", &key, &bytes, b"KEEPTTL"]);
+    Ok((bulk(&bytes), Propagation::Rewrite(vec![set])))
+}
+
+fn execute_append(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, 3)?;
+    let key = arr[1].to_bytes();
+    let suffix = arr[2].to_bytes();
+    let len = match db.get_string_mut(&key)? {
+        Some(value) => {
+            value.extend_from_slice(&suffix);
+            let len = value.len();
+            db.signal_modified(&key);
+            len
+        }
+        None => {
+            let len = suffix.len();
+            db.insert(key.clone(), suffix, None);
+            len
+        }
+    };
+    db.notify(notify::STRING, "append", &key);
+    Ok(Integer(len as i64))
+}
+
+fn execute_strlen(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, 2)?;
+    let len = db.get(&arr[1].to_bytes())?.map_or(0, |value| value.len());
+    Ok(Integer(len as i64))
+}
+
+fn execute_getrange(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, 4)?;
+    let start = parse_int(&arr[2])?;
+    let end = parse_int(&arr[3])?;
+    let value = db.get(&arr[1].to_bytes())?.unwrap_or_default();
+
+    let len = value.len() as i64;
+    if (start < 0 && end < 0 && start > end) || len == 0 {
+        return Ok(BulkString(Vec::new()));
+    }
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let end = if end < 0 {
+        (len + end).max(0)
+    } else {
+        end.min(len - 1)
+    };
+    if start > end {
+        return Ok(BulkString(Vec::new()));
+    }
+    Ok(bulk(&value[start as usize..=end as usize]))
+}
+
+fn execute_setrange(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, 4)?;
+    let key = arr[1].to_bytes();
+    let offset = parse_int(&arr[2])?;
+    let patch = arr[3].to_bytes();
+    if offset < 0 {
+        return Err(CommandError::Other("offset is out of range".to_string()));
+    }
+    let offset = offset as usize;
+
+    let current_len = db.get(&key)?.map(|value| value.len());
+    if patch.is_empty() {
+        return Ok(Integer(current_len.unwrap_or(0) as i64));
+    }
+    if offset.saturating_add(patch.len()) > MAX_STRING_LEN {
+        return Err(CommandError::Other(
+            "string exceeds maximum allowed size (proto-max-bulk-len)".to_string(),
+        ));
+    }
+
+    if current_len.is_none() {
+        db.insert(key.clone(), Vec::new(), None);
+    }
+    let value = db.get_string_mut(&key)?.expect("key was just created");
+    if value.len() < offset + patch.len() {
+        value.resize(offset + patch.len(), 0);
+    }
+    value[offset..offset + patch.len()].copy_from_slice(&patch);
+    let len = value.len();
+    db.signal_modified(&key);
+    db.notify(notify::STRING, "setrange", &key);
+    Ok(Integer(len as i64))
+}
+
+fn execute_mget(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, -2)?;
+    let values = arr[1..]
+        .iter()
+        .map(|key| match db.get(&key.to_bytes()) {
+            Ok(Some(value)) => bulk(&value),
+            _ => Null,
+        })
+        .collect();
+    Ok(Array(values))
+}
+
+fn check_pairs(arr: &[Value]) -> Result<(), CommandError> {
+    check_arity(arr, -3)?;
+    if arr.len().is_multiple_of(2) {
+        return Err(CommandError::WrongArity(command_name(arr)));
+    }
+    Ok(())
+}
+
+fn execute_mset(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_pairs(arr)?;
+    for pair in arr[1..].chunks(2) {
+        let key = pair[0].to_bytes();
+        db.insert(key.clone(), pair[1].to_bytes(), None);
+        db.notify(notify::STRING, "set", &key);
+    }
+    Ok(SimpleString("OK".to_string()))
+}
+
+fn execute_msetnx(arr: &[Value], db: &mut Cache) -> Result<(Value, Propagation), CommandError> {
+    check_pairs(arr)?;
+    let exists = arr[1..]
+        .iter()
+        .step_by(2)
+        .any(|key| db.get_data(&key.to_bytes()).is_some());
+    if exists {
+        return Ok((Integer(0), Propagation::None));
+    }
+    execute_mset(arr, db)?;
+    Ok((Integer(1), Propagation::Verbatim))
+}
+
+fn execute_getdel(arr: &[Value], db: &mut Cache) -> Result<(Value, Propagation), CommandError> {
+    check_arity(arr, 2)?;
+    let key = arr[1].to_bytes();
+    match db.get(&key)? {
+        Some(value) => {
+            db.remove(&key);
+            db.notify(notify::GENERIC, "del", &key);
+            Ok((bulk(&value), Propagation::Verbatim))
+        }
+        None => Ok((Null, Propagation::None)),
+    }
+}
+
+fn execute_getex(arr: &[Value], db: &mut Cache) -> Result<(Value, Propagation), CommandError> {
+    check_arity(arr, -2)?;
+    let key = arr[1].to_bytes();
+
+    // `None` leaves the TTL alone, `Some(None)` persists the key.
+    let expiry = match &arr[2..] {
+        [] => None,
+        [option] if arg_eq(option, "PERSIST") => Some(None),
+        [option, value]
+            if ["EX", "PX", "EXAT", "PXAT"]
+                .iter()
+                .any(|name| arg_eq(option, name)) =>
+        {
+            Some(Some(parse_expiry(option, value, "getex")?))
+        }
+        _ => return Err(CommandError::Syntax),
+    };
+
+    let value = match db.get(&key)? {
+        Some(value) => value,
+        None => return Ok((Null, Propagation::None)),
+    };
+    let propagation = match expiry {
+        None => Propagation::None,
+        Some(None) => {
+            let had_ttl = db.expiry(&key).is_some();
+            db.set_expiry(&key, None);
+            if had_ttl {
+                db.notify(notify::GENERIC, "persist", &key);
+            }
+            Propagation::Rewrite(vec![command(&[b"PERSIST", &key])])
+        }
+        Some(Some(deadline)) => {
+            db.set_expiry(&key, Some(deadline));
+            if deadline <= now_ms() {
+                db.remove(&key);
+                db.notify(notify::GENERIC, "del", &key);
+            } else {
+                db.notify(notify::GENERIC, "expire", &key);
+            }
+            Propagation::Rewrite(vec![pexpireat(&key, deadline)])
+        }
+    };
+    Ok((bulk(&value), propagation))
+}
+
+fn execute_getset(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, 3)?;
+    let key = arr[1].to_bytes();
+    let old = db.get(&key)?;
+    db.insert(key.clone(), arr[2].to_bytes(), None);
+    db.notify(notify::STRING, "set", &key);
+    Ok(old.map_or(Null, |value| bulk(&value)))
+}
+
+fn execute_setnx(arr: &[Value], db: &mut Cache) -> Result<(Value, Propagation), CommandError> {
+    check_arity(arr, 3)?;
+    let key = arr[1].to_bytes();
+    if db.get_data(&key).is_some() {
+        return Ok((Integer(0), Propagation::None));
+    }
+    db.insert(key.clone(), arr[2].to_bytes(), None);
+    db.notify(notify::STRING, "set", &key);
+    Ok((Integer(1), Propagation::Verbatim))
+}
+
+fn execute_setex(
+    arr: &[Value],
+    db: &mut Cache,
+    unit_ms: u64,
+) -> Result<(Value, Propagation), CommandError> {
+    check_arity(arr, 4)?;
+    let name = command_name(arr).to_lowercase();
+    let ttl = parse_int(&arr[2])?;
+    if ttl <= 0 {
+        return Err(CommandError::Other(format!(
+            "invalid expire time in '{}' command",
+            name
+        )));
+    }
+    let expiry = (ttl as u64)
+        .checked_mul(unit_ms)
+        .and_then(|ms| ms.checked_add(now_ms()))
+        .ok_or_else(|| CommandError::Other(format!("invalid expire time in '{}' command", name)))?;
+    let key = arr[1].to_bytes();
+    let value = arr[3].to_bytes();
+    let propagation = set_pxat(&key, &value, expiry);
+    db.insert(key.clone(), value, Some(expiry));
+    db.notify(notify::STRING, "set", &key);
+    db.notify(notify::GENERIC, "expire", &key);
+    Ok((SimpleString("OK".to_string()), propagation))
+}
+
+fn execute_lcs(arr: &[Value], db: &mut Cache) -> Result<Value, CommandError> {
+    check_arity(arr, -3)?;
+    let mut len_only = false;
+    let mut idx = false;
+    let mut with_match_len = false;
+    let mut min_match_len = 0;
+    let mut i = 3;
+    while i < arr.len() {
+        if arg_eq(&arr[i], "LEN") {
+            len_only = true;
+        } else if arg_eq(&arr[i], "IDX") {
+            idx = true;
+        } else if arg_eq(&arr[i], "WITHMATCHLEN") {
+            with_match_len = true;
+        } else if arg_eq(&arr[i], "MINMATCHLEN") && i + 1 < arr.len() {
+            min_match_len = parse_int(&arr[i + 1])?.max(0) as usize;
+            i += 1;
+        } else {
+            return Err(CommandError::Syntax);
+        }
+        i += 1;
+    }
+    if len_only && idx {
+        return Err(CommandError::Other(
+            "If you want both the length and indexes, please just use IDX.".to_string(),
+        ));
+    }
+
+    let mut load = |key: &Value| match db.get_data(&key.to_bytes()) {
+        Some(Data::String(value)) => Ok(value.clone()),
+        Some(_) => Err(CommandError::Other(
+            "The specified keys must contain string values".to_string(),
+        )),
+        None => Ok(Vec::new()),
+    };
+    let a = load(&arr[1])?;
+    let b = load(&arr[2])?;
+
+    // table[i][j] is the LCS length of a[..i] and b[..j].
+    let width = b.len() + 1;
+    let cells = (a.len() + 1)
+        .checked_mul(width)
+        .filter(|cells| cells.saturating_mul(4) <= MAX_LCS_TABLE)
+        .ok_or_else(|| {
+            CommandError::Other(
+                "Insufficient memory, transient memory for LCS exceeds proto-max-bulk-len"
+                    .to_string(),
+            )
+        })?;
+    let mut table = vec![0u32; cells];
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            table[i * width + j] = match a[i - 1] == b[j - 1] {
+                true => table[(i - 1) * width + j - 1] + 1,
+                false => table[(i - 1) * width + j].max(table[i * width + j - 1]),
+            };
+        }
+    }
+    let lcs_len = table[a.len() * width + b.len()] as usize;
+    if len_only {
+        return Ok(Integer(lcs_len as i64));
+    }
+
+    // Walk back from the end, collecting the common string and, for IDX,
+    // the matching ranges in both strings (last match first, like Redis).
+    let mut lcs = vec![0u8; lcs_len];
+    let mut matches = Vec::new();
+    let (mut i, mut j, mut pos) = (a.len(), b.len(), lcs_len);
+    let mut range: Option<(usize, usize, usize, usize)> = None;
+    while i > 0 && j > 0 {
+        let mut emit = false;
+        if a[i - 1] == b[j - 1] {
+            lcs[pos - 1] = a[i - 1];
+            range = match range {
+                None => Some((i - 1, i - 1, j - 1, j - 1)),
+                Some((a_start, a_end, b_start, b_end)) if a_start == i && b_start == j => {
+                    Some((a_start - 1, a_end, b_start - 1, b_end))
+                }
+                Some(range) => {
+                    emit = true;
+                    Some(range)
+                }
+            };
+            if range.is_some_and(|(a_start, _, b_start, _)| a_start == 0 || b_start == 0) {
+                emit = true;
+            }
+            pos -= 1;
+            i -= 1;
+            j -= 1;
+        } else {
+            if table[(i - 1) * width + j] > table[i * width + j - 1] {
+                i -= 1;
+            } else {
+                j -= 1;
+            }
+            emit = range.is_some();
+        }
+
+        if emit {
+            if let Some((a_start, a_end, b_start, b_end)) = range.take() {
+                let len = a_end - a_start + 1;
+                if idx && len >= min_match_len {
+                    let span = |start: usize, end: usize| {
+                        Array(vec![Integer(start as i64), Integer(end as i64)])
+                    };
+                    let mut entry = vec![span(a_start, a_end), span(b_start, b_end)];
+                    if with_match_len {
+                        entry.push(Integer(len as i64));
+                    }
+                    matches.push(Array(entry));
+                }
+            }
+        }
+    }
+
+    match idx {
+        true => Ok(Map(vec![
+            (BulkString(b"matches".to_vec()), Array(matches)),
+            (BulkString(b"len".to_vec()), Integer(lcs_len as i64)),
+        ])),
+        false => Ok(bulk(&lcs)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args_of(args: &[&str]) -> Vec<Value> {
+        args.iter()
+            .map(|arg| BulkString(arg.to_string().into_bytes()))
+            .collect()
+    }
+
+    fn run(db: &mut Cache, args: &[&str]) -> Value {
+        execute(args[0], &args_of(args), db).0
+    }
+
+    #[test]
+    fn test_incr_family() {
+        let mut db = Cache::new();
+        assert_eq!(run(&mut db, &["INCR", "n"]), Integer(1));
+        assert_eq!(run(&mut db, &["INCRBY", "n", "10"]), Integer(11));
+        assert_eq!(run(&mut db, &["DECRBY", "n", "20"]), Integer(-9));
+        assert_eq!(run(&mut db, &["DECR", "n"]), Integer(-10));
+        assert_eq!(
+            run(&mut db, &["INCRBYFLOAT", "n", "0.5"]),
+            BulkString(b"-9.5".to_vec())
+        );
+
+        let not_integer = SimpleError("ERR value is not an integer or out of range".to_string());
+        assert_eq!(run(&mut db, &["INCR", "n"]), not_integer);
+        assert_eq!(run(&mut db, &["INCRBY", "m", "ten"]), not_integer);
+        run(
+            &mut db,
+            &["MSET", "big", &i64::MAX.to_string(), "padded", " 1"],
+        );
+        assert_eq!(
+            run(&mut db, &["INCR", "big"]),
+            SimpleError("ERR increment or decrement would overflow".to_string())
+        );
+        assert_eq!(run(&mut db, &["INCR", "padded"]), not_integer);
+    }
+
+    #[test]
+    fn test_incr_keeps_ttl() {
+        let mut db = Cache::new();
+        let expiry = now_ms() + 60_000;
+        db.insert(b"n".to_vec(), b"1".to_vec(), Some(expiry));
+        run(&mut db, &["INCR", "n"]);
+        run(&mut db, &["APPEND", "n", "0"]);
+        assert_eq!(db.expiry(b"n"), Some(expiry));
+        assert_eq!(db.get(b"n"), Ok(Some(b"20".to_vec())));
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut db = Cache::new();
+        db.insert(b"k".to_vec(), b"Hello World".to_vec(), None);
+        assert_eq!(
+            run(&mut db, &["GETRANGE", "k", "0", "4"]),
+            BulkString(b"Hello".to_vec())
+        );
+        assert_eq!(
+            run(&mut db, &["GETRANGE", "k", "-5", "-1"]),
+            BulkString(b"World".to_vec())
+        );
+        assert_eq!(
+            run(&mut db, &["GETRANGE", "k", "5", "2"]),
+            BulkString(Vec::new())
+        );
+        assert_eq!(run(&mut db, &["SETRANGE", "k", "6", "Redis"]), Integer(11));
+        assert_eq!(
+            run(&mut db, &["GET", "k"]),
+            BulkString(b"Hello Redis".to_vec())
+        );
+        assert_eq!(run(&mut db, &["SETRANGE", "pad", "2", "x"]), Integer(3));
+        assert_eq!(db.get(b"pad"), Ok(Some(vec![0, 0, b'x'])));
+        assert_eq!(run(&mut db, &["STRLEN", "k"]), Integer(11));
+    }
+
+    #[test]
+    fn test_multi_and_conditional_sets() {
+        let mut db = Cache::new();
+        assert_eq!(
+            run(&mut db, &["MSET", "a", "1", "b", "2"]),
+            SimpleString("OK".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["MGET", "a", "missing", "b"]),
+            Array(vec![
+                BulkString(b"1".to_vec()),
+                Null,
+                BulkString(b"2".to_vec())
+            ])
+        );
+        assert_eq!(run(&mut db, &["MSETNX", "a", "x", "c", "3"]), Integer(0));
+        assert_eq!(run(&mut db, &["SETNX", "c", "3"]), Integer(1));
+        assert_eq!(run(&mut db, &["SETNX", "c", "4"]), Integer(0));
+        assert_eq!(
+            run(&mut db, &["GETSET", "c", "5"]),
+            BulkString(b"3".to_vec())
+        );
+        assert_eq!(run(&mut db, &["GETDEL", "c"]), BulkString(b"5".to_vec()));
+        assert_eq!(run(&mut db, &["GET", "c"]), Null);
+        assert_eq!(
+            run(&mut db, &["MSET", "a", "1", "b"]),
+            SimpleError("ERR wrong number of arguments for 'mset' command".to_string())
+        );
+    }
+
+    #[test]
+    fn test_expiring_sets() {
+        let mut db = Cache::new();
+        assert_eq!(
+            run(&mut db, &["SETEX", "k", "100", "v"]),
+            SimpleString("OK".to_string())
+        );
+        assert!(db.expiry(b"k").is_some());
+        assert_eq!(
+            run(&mut db, &["GETEX", "k", "PERSIST"]),
+            BulkString(b"v".to_vec())
+        );
+        assert_eq!(db.expiry(b"k"), None);
+        run(&mut db, &["GETEX", "k", "PX", "5000"]);
+        assert!(db.expiry(b"k").is_some());
+        assert_eq!(
+            run(&mut db, &["PSETEX", "k", "0", "v"]),
+            SimpleError("ERR invalid expire time in 'psetex' command".to_string())
+        );
+        run(&mut db, &["GETEX", "k", "EXAT", "1"]);
+        assert_eq!(run(&mut db, &["GET", "k"]), Null);
+    }
+
+    #[test]
+    fn test_set_options() {
+        let mut db = Cache::new();
+        let ok = SimpleString("OK".to_string());
+        assert_eq!(run(&mut db, &["SET", "k", "v1"]), ok);
+        assert_eq!(run(&mut db, &["SET", "k", "v2"]), ok);
+        assert_eq!(run(&mut db, &["SET", "k", "v3", "NX"]), Null);
+        assert_eq!(run(&mut db, &["SET", "new", "v", "XX"]), Null);
+        assert_eq!(
+            run(&mut db, &["SET", "k", "v3", "GET", "NX"]),
+            BulkString(b"v2".to_vec())
+        );
+        assert_eq!(run(&mut db, &["GET", "k"]), BulkString(b"v2".to_vec()));
+        assert_eq!(
+            run(&mut db, &["SET", "k", "v4", "get", "xx", "px", "60000"]),
+            BulkString(b"v2".to_vec())
+        );
+        let expiry = db.expiry(b"k");
+        assert!(expiry.is_some());
+        assert_eq!(run(&mut db, &["SET", "k", "v5", "KEEPTTL"]), ok);
+        assert_eq!(db.expiry(b"k"), expiry);
+        assert_eq!(run(&mut db, &["SET", "k", "v6"]), ok);
+        assert_eq!(db.expiry(b"k"), None);
+        assert_eq!(run(&mut db, &["SET", "k", "v", "EXAT", "1"]), ok);
+        assert_eq!(run(&mut db, &["GET", "k"]), Null);
+    }
+
+    #[test]
+    fn test_set_errors() {
+        let mut db = Cache::new();
+        let syntax = SimpleError("ERR syntax error".to_string());
+        assert_eq!(run(&mut db, &["SET", "k", "v", "NX", "XX"]), syntax);
+        assert_eq!(
+            run(&mut db, &["SET", "k", "v", "EX", "1", "PX", "1"]),
+            syntax
+        );
+        assert_eq!(
+            run(&mut db, &["SET", "k", "v", "KEEPTTL", "EX", "1"]),
+            syntax
+        );
+        assert_eq!(run(&mut db, &["SET", "k", "v", "EX"]), syntax);
+        assert_eq!(run(&mut db, &["SET", "k", "v", "FOO"]), syntax);
+        assert_eq!(
+            run(&mut db, &["SET", "k", "v", "EX", "0"]),
+            SimpleError("ERR invalid expire time in 'set' command".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["SET", "k", "v", "PX", "soon"]),
+            SimpleError("ERR value is not an integer or out of range".to_string())
+        );
+        db.get_or_create_set(b"set").unwrap().insert(b"m".to_vec());
+        assert_eq!(
+            run(&mut db, &["SET", "set", "v", "GET"]),
+            SimpleError(
+                "WRONGTYPE Operation against a key holding the wrong kind of value".to_string()
+            )
+        );
+        assert_eq!(
+            run(&mut db, &["SET", "set", "v"]),
+            SimpleString("OK".to_string())
+        );
+    }
+
+    #[test]
+    fn test_relative_expiry_propagates_as_pxat() {
+        let mut db = Cache::new();
+        let args = args_of(&["SETEX", "k", "10", "v"]);
+        let (_, propagation) = execute("SETEX", &args, &mut db);
+        let at = db.expiry(b"k").unwrap().to_string();
+        let expected = command(&[b"SET", b"k", b"v", b"PXAT", at.as_bytes()]);
+        assert_eq!(propagation, Propagation::Rewrite(vec![expected]));
+    }
+
+    #[test]
+    fn test_lcs() {
+        let mut db = Cache::new();
+        run(&mut db, &["MSET", "key1", "ohmytext", "key2", "mynewtext"]);
+        assert_eq!(
+            run(&mut db, &["LCS", "key1", "key2"]),
+            BulkString(b"mytext".to_vec())
+        );
+        assert_eq!(run(&mut db, &["LCS", "key1", "key2", "LEN"]), Integer(6));
+
+        let span = |a: i64, b: i64| Array(vec![Integer(a), Integer(b)]);
+        assert_eq!(
+            run(
+                &mut db,
+                &[
+                    "LCS",
+                    "key1",
+                    "key2",
+                    "IDX",
+                    "MINMATCHLEN",
+                    "4",
+                    "WITHMATCHLEN"
+                ]
+            ),
+            Map(vec![
+                (
+                    BulkString(b"matches".to_vec()),
+                    Array(vec![Array(vec![span(4, 7), span(5, 8), Integer(4)])])
+                ),
+                (BulkString(b"len".to_vec()), Integer(6)),
+            ])
+        );
+        assert_eq!(
+            run(&mut db, &["LCS", "key1", "key2", "IDX"]),
+            Map(vec![
+                (
+                    BulkString(b"matches".to_vec()),
+                    Array(vec![
+                        Array(vec![span(4, 7), span(5, 8)]),
+                        Array(vec![span(2, 3), span(0, 1)]),
+                    ])
+                ),
+                (BulkString(b"len".to_vec()), Integer(6)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_lcs_too_long() {
+        let mut db = Cache::new();
+        let value = vec![b'a'; 20_000];
+        db.insert(b"key1".to_vec(), value.clone(), None);
+        db.insert(b"key2".to_vec(), value, None);
+        assert_eq!(
+            run(&mut db, &["LCS", "key1", "key2"]),
+            CommandError::Other(
+                "Insufficient memory, transient memory for LCS exceeds proto-max-bulk-len"
+                    .to_string()
+            )
+            .into()
+        );
+    }
+}
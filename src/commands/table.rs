@@ -0,0 +1,595 @@
+//! The command table: every command the server knows, with the arity,
+//! flags and key positions `COMMAND` reports for it. Each `commands` module
+//! lists its own; the handler looks commands up here to dispatch them,
+//! check their arguments, decide whether they may run and which of them
+//! reach replicas.
+
+use super::{
+    bulk, check_arity, client, database, keyspace, migrate, pubsub, set, stream, string,
+    CommandError, Propagation,
+};
+use crate::resp::value::Value;
+use crate::resp::value::Value::*;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// The command may modify the dataset.
+pub const WRITE: u16 = 1 << 0;
+/// The command only reads keys.
+pub const READONLY: u16 = 1 << 1;
+/// The command may grow the dataset, so it is refused with `-OOM` when
+/// memory is over `maxmemory` and nothing can be evicted.
+pub const DENYOOM: u16 = 1 << 2;
+pub const ADMIN: u16 = 1 << 3;
+pub const PUBSUB: u16 = 1 << 4;
+pub const NOSCRIPT: u16 = 1 << 5;
+pub const BLOCKING: u16 = 1 << 6;
+pub const LOADING: u16 = 1 << 7;
+pub const STALE: u16 = 1 << 8;
+pub const FAST: u16 = 1 << 9;
+pub const NO_MULTI: u16 = 1 << 10;
+/// The command changes no keys but is still sent to replicas, like
+/// `PUBLISH`.
+pub const MAY_REPLICATE: u16 = 1 << 11;
+
+/// Each flag with the name `COMMAND INFO` gives it, in Redis's order.
+const FLAG_NAMES: [(u16, &str); 12] = [
+    (WRITE, "write"),
+    (READONLY, "readonly"),
+    (DENYOOM, "denyoom"),
+    (ADMIN, "admin"),
+    (PUBSUB, "pubsub"),
+    (NOSCRIPT, "noscript"),
+    (BLOCKING, "blocking"),
+    (LOADING, "loading"),
+    (STALE, "stale"),
+    (FAST, "fast"),
+    (MAY_REPLICATE, "may_replicate"),
+    (NO_MULTI, "no_multi"),
+];
+
+/// Where a command's keys are among its arguments.
+#[derive(Debug)]
+pub enum Keys {
+    None,
+    /// The first key's position, the last's (negative counts back from the
+    /// end) and the step between them.
+    Range(usize, i64, usize),
+    /// Keys that can only be found by reading the other arguments, like
+    /// those after `XREAD`'s `STREAMS`. Reported as `movablekeys`.
+    Movable(fn(&[Value]) -> Vec<usize>),
+}
+
+#[derive(Debug)]
+pub struct Command {
+    pub name: &'static str,
+    /// A positive arity is an exact argument count and a negative one a
+    /// minimum; both include the command name.
+    pub arity: i64,
+    pub flags: u16,
+    pub keys: Keys,
+    /// What `COMMAND DOCS` files the command under, e.g. `string`.
+    pub group: &'static str,
+    pub summary: &'static str,
+}
+
+impl Command {
+    pub fn has(&self, flag: u16) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// How a call of this command reaches replicas, given what the module
+    /// that ran it returned. The flags decide which commands replicate at
+    /// all: only `WRITE` and `MAY_REPLICATE` ones ever do. The module still
+    /// says whether this particular call did, because a write that failed
+    /// or changed nothing, like `SADD` to a string or `SETNX` of a key
+    /// already there, is not sent, and it supplies the rewrite when the
+    /// call can't be replayed as it is, like a relative `EXPIRE`.
+    pub fn propagation(&self, returned: Propagation) -> Propagation {
+        match self.has(WRITE | MAY_REPLICATE) {
+            true => returned,
+            false => Propagation::None,
+        }
+    }
+
+    /// The positions of the keys in `arr`, a call of this command.
+    pub fn key_positions(&self, arr: &[Value]) -> Vec<usize> {
+        match self.keys {
+            Keys::None => Vec::new(),
+            Keys::Range(first, last, step) => {
+                let last = match last {
+                    last if last < 0 => arr.len() as i64 + last,
+                    last => last,
+                };
+                (first..arr.len())
+                    .step_by(step)
+                    .take_while(|&i| i as i64 <= last)
+                    .collect()
+            }
+            Keys::Movable(positions) => positions(arr),
+        }
+    }
+
+    fn info(&self) -> Value {
+        let mut flags: Vec<Value> = FLAG_NAMES
+            .iter()
+            .filter(|(flag, _)| self.has(*flag))
+            .map(|(_, name)| SimpleString(name.to_string()))
+            .collect();
+        let (first, last, step) = match self.keys {
+            Keys::Range(first, last, step) => (first as i64, last, step as i64),
+            Keys::Movable(_) => {
+                flags.push(SimpleString("movablekeys".to_string()));
+                (0, 0, 0)
+            }
+            Keys::None => (0, 0, 0),
+        };
+        Array(vec![
+            bulk(self.name.to_lowercase().as_bytes()),
+            Integer(self.arity),
+            Set(flags),
+            Integer(first),
+            Integer(last),
+            Integer(step),
+            // ACL categories, tips, key specifications and subcommands.
+            Set(vec![]),
+            Set(vec![]),
+            Array(vec![]),
+            Array(vec![]),
+        ])
+    }
+
+    fn docs(&self) -> Value {
+        Map(vec![
+            (bulk(b"summary"), bulk(self.summary.as_bytes())),
+            (bulk(b"group"), bulk(self.group.as_bytes())),
+        ])
+    }
+}
+
+/// Which part of the server runs a command.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Module {
+    /// The handler itself, for commands that need more than a database.
+    Server,
+    String,
+    Database,
+    Keyspace,
+    Set,
+    Stream,
+    PubSub,
+    Client,
+}
+
+/// Commands the handler runs itself rather than handing them to one of
+/// the `commands` modules.
+pub const SERVER_COMMANDS: &[Command] = &[
+    Command {
+        name: "PING",
+        arity: -1,
+        flags: FAST,
+        keys: Keys::None,
+        group: "connection",
+        summary: "Returns the server's liveliness response.",
+    },
+    Command {
+        name: "QUIT",
+        arity: -1,
+        flags: NOSCRIPT | LOADING | STALE | FAST,
+        keys: Keys::None,
+        group: "connection",
+        summary: "Closes the connection.",
+    },
+    Command {
+        name: "RESET",
+        arity: 1,
+        flags: NOSCRIPT | LOADING | STALE | FAST,
+        keys: Keys::None,
+        group: "connection",
+        summary: "Resets the connection.",
+    },
+    Command {
+        name: "ECHO",
+        arity: 2,
+        flags: FAST,
+        keys: Keys::None,
+        group: "connection",
+        summary: "Returns the given string.",
+    },
+    Command {
+        name: "HELLO",
+        arity: -1,
+        flags: NOSCRIPT | LOADING | STALE | FAST,
+        keys: Keys::None,
+        group: "connection",
+        summary: "Handshakes with the Redis server.",
+    },
+    Command {
+        name: "MIGRATE",
+        arity: -6,
+        flags: WRITE,
+        keys: Keys::Movable(migrate::key_positions),
+        group: "generic",
+        summary: "Atomically transfers a key from one Redis instance to another.",
+    },
+    Command {
+        name: "XREAD",
+        arity: -4,
+        flags: READONLY | BLOCKING,
+        keys: Keys::Movable(stream::read_key_positions),
+        group: "stream",
+        summary: "Returns messages from multiple streams with IDs greater than the ones requested. Blocks until a message is available otherwise.",
+    },
+    Command {
+        name: "XREADGROUP",
+        arity: -7,
+        flags: WRITE | BLOCKING,
+        keys: Keys::Movable(stream::read_key_positions),
+        group: "stream",
+        summary: "Returns new or historical messages from a stream for a consumer in a group. Blocks until a message is available otherwise.",
+    },
+    Command {
+        name: "PUBLISH",
+        arity: 3,
+        flags: PUBSUB | LOADING | STALE | FAST | MAY_REPLICATE,
+        keys: Keys::None,
+        group: "pubsub",
+        summary: "Posts a message to a channel.",
+    },
+    Command {
+        name: "SPUBLISH",
+        arity: 3,
+        flags: PUBSUB | LOADING | STALE | FAST | MAY_REPLICATE,
+        keys: Keys::None,
+        group: "pubsub",
+        summary: "Posts a message to a shard channel.",
+    },
+    Command {
+        name: "MULTI",
+        arity: 1,
+        flags: NOSCRIPT | LOADING | STALE | FAST,
+        keys: Keys::None,
+        group: "transactions",
+        summary: "Starts a transaction.",
+    },
+    Command {
+        name: "EXEC",
+        arity: 1,
+        flags: NOSCRIPT | LOADING | STALE,
+        keys: Keys::None,
+        group: "transactions",
+        summary: "Executes all commands in a transaction.",
+    },
+    Command {
+        name: "DISCARD",
+        arity: 1,
+        flags: NOSCRIPT | LOADING | STALE | FAST,
+        keys: Keys::None,
+        group: "transactions",
+        summary: "Discards a transaction.",
+    },
+    Command {
+        name: "WATCH",
+        arity: -2,
+        flags: NOSCRIPT | LOADING | STALE | FAST,
+        keys: Keys::Range(1, -1, 1),
+        group: "transactions",
+        summary: "Monitors changes to keys to determine the execution of a transaction.",
+    },
+    Command {
+        name: "UNWATCH",
+        arity: 1,
+        flags: NOSCRIPT | LOADING | STALE | FAST,
+        keys: Keys::None,
+        group: "transactions",
+        summary: "Forgets about watched keys of a transaction.",
+    },
+    Command {
+        name: "INFO",
+        arity: -1,
+        flags: LOADING | STALE,
+        keys: Keys::None,
+        group: "server",
+        summary: "Returns information and statistics about the server.",
+    },
+    Command {
+        name: "CONFIG",
+        arity: -2,
+        flags: ADMIN | NOSCRIPT | LOADING | STALE,
+        keys: Keys::None,
+        group: "server",
+        summary: "Gets or sets configuration parameters.",
+    },
+    Command {
+        name: "SAVE",
+        arity: 1,
+        flags: ADMIN | NOSCRIPT | NO_MULTI,
+        keys: Keys::None,
+        group: "server",
+        summary: "Synchronously saves the database(s) to disk.",
+    },
+    Command {
+        name: "COMMAND",
+        arity: -1,
+        flags: LOADING | STALE,
+        keys: Keys::None,
+        group: "server",
+        summary: "Returns detailed information about all commands.",
+    },
+    Command {
+        name: "REPLCONF",
+        arity: -3,
+        flags: ADMIN | NOSCRIPT | LOADING | STALE,
+        keys: Keys::None,
+        group: "server",
+        summary: "An internal command for configuring the replication stream.",
+    },
+    Command {
+        name: "PSYNC",
+        arity: 3,
+        flags: ADMIN | NOSCRIPT | NO_MULTI,
+        keys: Keys::None,
+        group: "server",
+        summary: "An internal command used in replication.",
+    },
+    Command {
+        name: "WAIT",
+        arity: 3,
+        flags: NOSCRIPT | NO_MULTI,
+        keys: Keys::None,
+        group: "generic",
+        summary: "Blocks until the asynchronous replication of all preceding write commands sent by the connection is completed.",
+    },
+];
+
+/// Every module's commands, with the module that runs them.
+const TABLE: [(Module, &[Command]); 8] = [
+    (Module::Server, SERVER_COMMANDS),
+    (Module::String, string::COMMANDS),
+    (Module::Database, database::COMMANDS),
+    (Module::Keyspace, keyspace::COMMANDS),
+    (Module::Set, set::COMMANDS),
+    (Module::Stream, stream::COMMANDS),
+    (Module::PubSub, pubsub::COMMANDS),
+    (Module::Client, client::COMMANDS),
+];
+
+fn all() -> impl Iterator<Item = (Module, &'static Command)> {
+    TABLE
+        .iter()
+        .flat_map(|(module, commands)| commands.iter().map(|command| (*module, command)))
+}
+
+/// Every command by its upper-case name, built on first use.
+fn index() -> &'static HashMap<&'static [u8], (Module, &'static Command)> {
+    static INDEX: OnceLock<HashMap<&'static [u8], (Module, &'static Command)>> = OnceLock::new();
+    INDEX.get_or_init(|| {
+        all()
+            .map(|(module, command)| (command.name.as_bytes(), (module, command)))
+            .collect()
+    })
+}
+
+/// Finds a command by name, in any case.
+pub fn lookup(name: &[u8]) -> Option<(Module, &'static Command)> {
+    index().get(name.to_ascii_uppercase().as_slice()).copied()
+}
+
+/// Looks up the command `arr` calls and checks its argument count.
+pub fn resolve(arr: &[Value]) -> Result<(Module, &'static Command), CommandError> {
+    let name = arr.first().map(Value::to_bytes).unwrap_or_default();
+    let Some((module, command)) = lookup(&name) else {
+        let args: String = arr
+            .iter()
+            .skip(1)
+            .map(|arg| format!("'{}' ", String::from_utf8_lossy(&arg.to_bytes())))
+            .collect();
+        return Err(CommandError::Other(format!(
+            "unknown command '{}', with args beginning with: {}",
+            String::from_utf8_lossy(&name),
+            args
+        )));
+    };
+    check_arity(arr, command.arity)?;
+    Ok((module, command))
+}
+
+/// `COMMAND [COUNT | LIST | INFO [name ...] | DOCS [name ...] | GETKEYS
+/// command [arg ...]]`.
+pub fn execute(arr: &[Value]) -> Result<Value, CommandError> {
+    let Some(subcommand) = arr.get(1) else {
+        return Ok(Array(all().map(|(_, command)| command.info()).collect()));
+    };
+    let subcommand = String::from_utf8_lossy(&subcommand.to_bytes()).to_uppercase();
+    let wrong_arity = || CommandError::WrongArity(format!("command|{}", subcommand));
+    match subcommand.as_str() {
+        "COUNT" if arr.len() == 2 => Ok(Integer(all().count() as i64)),
+        "LIST" if arr.len() == 2 => Ok(Array(
+            all()
+                .map(|(_, command)| bulk(command.name.to_lowercase().as_bytes()))
+                .collect(),
+        )),
+        "INFO" => Ok(Array(match &arr[2..] {
+            [] => all().map(|(_, command)| command.info()).collect(),
+            names => names
+                .iter()
+                .map(|name| lookup(&name.to_bytes()).map_or(Null, |(_, command)| command.info()))
+                .collect(),
+        })),
+        "DOCS" => {
+            let docs =
+                |command: &Command| (bulk(command.name.to_lowercase().as_bytes()), command.docs());
+            Ok(Map(match &arr[2..] {
+                [] => all().map(|(_, command)| docs(command)).collect(),
+                names => names
+                    .iter()
+                    .filter_map(|name| lookup(&name.to_bytes()))
+                    .map(|(_, command)| docs(command))
+                    .collect(),
+            }))
+        }
+        "GETKEYS" if arr.len() >= 3 => {
+            let call = &arr[2..];
+            let Some((_, command)) = lookup(&call[0].to_bytes()) else {
+                return Err(CommandError::Other("Invalid command specified".to_string()));
+            };
+            if check_arity(call, command.arity).is_err() {
+                return Err(CommandError::Other(
+                    "Invalid number of arguments specified for command".to_string(),
+                ));
+            }
+            let keys = command.key_positions(call);
+            if keys.is_empty() {
+                return Err(CommandError::Other(
+                    "The command has no key arguments".to_string(),
+                ));
+            }
+            Ok(Array(keys.into_iter().map(|i| call[i].clone()).collect()))
+        }
+        "COUNT" | "LIST" | "GETKEYS" => Err(wrong_arity()),
+        _ => Err(CommandError::Other(format!(
+            "unknown subcommand '{}'. Try COMMAND HELP.",
+            String::from_utf8_lossy(&arr[1].to_bytes())
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(args: &[&str]) -> Vec<Value> {
+        args.iter().map(|arg| bulk(arg.as_bytes())).collect()
+    }
+
+    fn keys(args: &[&str]) -> Vec<usize> {
+        let arr = call(args);
+        let (_, command) = lookup(&arr[0].to_bytes()).unwrap();
+        command.key_positions(&arr)
+    }
+
+    #[test]
+    fn test_unique_names() {
+        let mut names: Vec<&str> = all().map(|(_, command)| command.name).collect();
+        names.sort();
+        let count = names.len();
+        names.dedup();
+        assert_eq!(names.len(), count);
+        for (module, command) in all() {
+            let found = lookup(command.name.to_lowercase().as_bytes()).unwrap();
+            assert_eq!(found.0, module);
+            assert!(std::ptr::eq(found.1, command));
+        }
+    }
+
+    #[test]
+    fn test_propagation() {
+        let spec = |name: &str| lookup(name.as_bytes()).unwrap().1;
+        assert_eq!(
+            spec("SET").propagation(Propagation::Verbatim),
+            Propagation::Verbatim
+        );
+        assert_eq!(
+            spec("SADD").propagation(Propagation::None),
+            Propagation::None
+        );
+        assert_eq!(
+            spec("PUBLISH").propagation(Propagation::Verbatim),
+            Propagation::Verbatim
+        );
+        assert_eq!(
+            spec("GET").propagation(Propagation::Verbatim),
+            Propagation::None
+        );
+        assert_eq!(
+            spec("SELECT").propagation(Propagation::Verbatim),
+            Propagation::None
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        let (module, command) = resolve(&call(&["get", "key"])).unwrap();
+        assert_eq!((module, command.name), (Module::String, "GET"));
+        assert!(command.has(READONLY));
+        assert_eq!(
+            resolve(&call(&["GET"])).unwrap_err(),
+            CommandError::WrongArity("GET".to_string())
+        );
+        assert_eq!(
+            resolve(&call(&["NOPE", "a", "b"])).unwrap_err(),
+            CommandError::Other(
+                "unknown command 'NOPE', with args beginning with: 'a' 'b' ".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_key_positions() {
+        assert_eq!(keys(&["GET", "a"]), vec![1]);
+        assert_eq!(keys(&["MSET", "a", "1", "b", "2"]), vec![1, 3]);
+        assert_eq!(keys(&["DEL", "a", "b", "c"]), vec![1, 2, 3]);
+        assert_eq!(keys(&["RENAME", "a", "b"]), vec![1, 2]);
+        assert_eq!(keys(&["PING"]), Vec::<usize>::new());
+        assert_eq!(
+            keys(&["SINTERCARD", "2", "a", "b", "LIMIT", "1"]),
+            vec![2, 3]
+        );
+        assert_eq!(
+            keys(&["XREAD", "COUNT", "1", "STREAMS", "a", "b", "0", "0"]),
+            vec![4, 5]
+        );
+        assert_eq!(keys(&["MIGRATE", "h", "1", "a", "0", "5"]), vec![3]);
+        assert_eq!(
+            keys(&["MIGRATE", "h", "1", "", "0", "5", "COPY", "KEYS", "a", "b"]),
+            vec![8, 9]
+        );
+    }
+
+    #[test]
+    fn test_command() {
+        let count = all().count() as i64;
+        assert_eq!(execute(&call(&["COMMAND", "COUNT"])), Ok(Integer(count)));
+        assert_eq!(
+            execute(&call(&["COMMAND", "INFO", "get", "nope"])),
+            Ok(Array(vec![
+                Array(vec![
+                    bulk(b"get"),
+                    Integer(2),
+                    Set(vec![
+                        SimpleString("readonly".to_string()),
+                        SimpleString("fast".to_string())
+                    ]),
+                    Integer(1),
+                    Integer(1),
+                    Integer(1),
+                    Set(vec![]),
+                    Set(vec![]),
+                    Array(vec![]),
+                    Array(vec![]),
+                ]),
+                Null,
+            ]))
+        );
+        assert_eq!(
+            execute(&call(&["COMMAND", "GETKEYS", "MSET", "a", "1", "b", "2"])),
+            Ok(Array(vec![bulk(b"a"), bulk(b"b")]))
+        );
+        assert_eq!(
+            execute(&call(&["COMMAND", "GETKEYS", "PING"])),
+            Err(CommandError::Other(
+                "The command has no key arguments".to_string()
+            ))
+        );
+        assert_eq!(
+            execute(&call(&["COMMAND", "GETKEYS", "GET"])),
+            Err(CommandError::Other(
+                "Invalid number of arguments specified for command".to_string()
+            ))
+        );
+        let Ok(Map(docs)) = execute(&call(&["COMMAND", "DOCS", "set"])) else {
+            panic!("COMMAND DOCS should reply with a map");
+        };
+        assert_eq!(docs[0].0, bulk(b"set"));
+    }
+}
@@ -1,4 +1,6 @@
 use crate::cache::Cache;
+use crate::handler;
+use crate::resp::value::null_array;
 use crate::resp::value::Value::Integer;
 use crate::{ServerInfo, WaitState};
 use mio::net::{TcpListener, TcpStream};
@@ -8,7 +10,12 @@ use std::io::ErrorKind::WouldBlock;
 use std::io::{Error, Write};
 use std::net::SocketAddr;
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+/// How often the event loop runs its periodic tasks, like Redis's `hz`.
+const HZ: u32 = 10;
+/// The share of each tick the active expire cycle may use, as in Redis.
+const ACTIVE_EXPIRE_BUDGET: Duration = Duration::from_millis(25);
 
 #[derive(Debug)]
 pub struct EventLoop {
@@ -17,6 +24,7 @@ pub struct EventLoop {
     events: Events,
     pub(crate) connections: HashMap<Token, TcpStream>,
     server_info: ServerInfo,
+    next_cron: Instant,
 }
 
 impl EventLoop {
@@ -45,12 +53,13 @@ impl EventLoop {
             events,
             connections,
             server_info,
+            next_cron: Instant::now(),
         }
     }
 
-    pub fn run<F>(&mut self, mut db: Cache, handler: F) -> Result<(), Error>
+    pub fn run<F>(&mut self, mut dbs: Vec<Cache>, handler: F) -> Result<(), Error>
     where
-        F: Fn(&mut Cache, &Token, &mut HashMap<Token, TcpStream>, &mut ServerInfo),
+        F: Fn(&mut [Cache], &Token, &mut HashMap<Token, TcpStream>, &mut ServerInfo),
     {
         const SERVER: Token = Token(0);
         let mut next_token = if self.connections.is_empty() { 1 } else { 2 };
@@ -62,11 +71,26 @@ impl EventLoop {
         loop {
             let timeout = self.get_timeout();
 
-            self.poll.poll(&mut self.events, timeout)?;
+            self.poll.poll(&mut self.events, Some(timeout))?;
 
             if let Some(x) = &self.server_info.waiting.clone() {
                 self.send_wait_timeout(x)
             }
+            self.send_blocked_timeouts(&mut dbs);
+            if Instant::now() >= self.next_cron {
+                let start = Instant::now();
+                for db in dbs.iter_mut() {
+                    db.active_expire_cycle(ACTIVE_EXPIRE_BUDGET.saturating_sub(start.elapsed()));
+                }
+                handler::publish_notifications(&mut dbs, &mut self.connections, &self.server_info);
+                handler::send_invalidations(
+                    &mut dbs,
+                    &mut self.connections,
+                    &mut self.server_info,
+                    None,
+                );
+                self.next_cron = Instant::now() + Duration::from_secs(1) / HZ;
+            }
 
             for event in self.events.iter() {
                 match event.token() {
@@ -94,7 +118,7 @@ impl EventLoop {
                             None => continue,
                         };
                         handler(
-                            &mut db,
+                            &mut dbs,
                             &client,
                             &mut self.connections,
                             &mut self.server_info,
@@ -105,28 +129,91 @@ impl EventLoop {
         }
     }
 
-    fn get_timeout(&self) -> Option<Duration> {
-        match &self.server_info.waiting {
-            Some(state) => {
-                let elapsed = state.start_time.elapsed();
-                if elapsed >= state.timeout {
-                    Some(Duration::from_millis(0))
-                } else {
-                    Some(state.timeout - elapsed)
+    fn get_timeout(&self) -> Duration {
+        let wait = self.server_info.waiting.as_ref().map(|state| {
+            let elapsed = state.start_time.elapsed();
+            if elapsed >= state.timeout {
+                Duration::from_millis(0)
+            } else {
+                state.timeout - elapsed
+            }
+        });
+        let now = Instant::now();
+        let blocked = self
+            .server_info
+            .blocked
+            .values()
+            .filter_map(|blocked| blocked.deadline)
+            .min()
+            .map(|deadline| deadline.saturating_duration_since(now));
+
+        let cron = self.next_cron.saturating_duration_since(now);
+
+        [wait, blocked]
+            .into_iter()
+            .flatten()
+            .fold(cron, Duration::min)
+    }
+
+    /// Answers the blocked reads whose timeout has passed, then runs what
+    /// their clients sent meanwhile.
+    fn send_blocked_timeouts(&mut self, dbs: &mut [Cache]) {
+        let now = Instant::now();
+        let expired: Vec<Token> = self
+            .server_info
+            .blocked
+            .iter()
+            .filter(|(_, blocked)| blocked.deadline.is_some_and(|deadline| deadline <= now))
+            .map(|(client, _)| *client)
+            .collect();
+
+        for client in expired {
+            self.server_info.blocked.remove(&client);
+            let protocol = self.server_info.clients.entry(client).or_default().protocol;
+            let response = null_array(protocol);
+            if let Some(conn) = self.connections.get_mut(&client) {
+                if let Err(e) = conn.write_all(&response) {
+                    println!("Error responding to blocked read after timeout: {}", e);
                 }
             }
-            None => None,
+            handler::resume(dbs, &client, &mut self.connections, &mut self.server_info);
         }
     }
 
     fn send_wait_timeout(&mut self, state: &WaitState) {
         if state.start_time.elapsed() > state.timeout {
             let response = Integer(state.acks_received as i64);
-            let client = &mut self.connections.get_mut(&state.client).unwrap();
-            client
-                .write_all(&response.to_resp().to_vec())
-                .expect("Error: could not respond to WAIT after timeout");
+            if let Some(client) = self.connections.get_mut(&state.client) {
+                if let Err(e) = client.write_all(&response.to_resp()) {
+                    println!("Error responding to WAIT after timeout: {}", e);
+                }
+            }
             self.server_info.waiting = None;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::tests::{received, Server};
+    use std::thread;
+
+    #[test]
+    fn test_blocked_read_timeout() {
+        let mut server = Server::new("master");
+        let mut client = server.connect(Token(2));
+        let mut resp3 = server.connect(Token(3));
+        server.send(Token(2), b"XREAD BLOCK 1 STREAMS s $\r\nPING\r\n");
+        server.send(Token(3), b"HELLO 3\r\n");
+        received(&mut resp3);
+        server.send(Token(3), b"XREAD BLOCK 1 STREAMS s $\r\n");
+        thread::sleep(Duration::from_millis(5));
+
+        let mut event_loop = EventLoop::new("127.0.0.1:0", server.server_info, server.connections);
+        event_loop.send_blocked_timeouts(&mut server.dbs);
+        assert_eq!(received(&mut client), b"*-1\r\n+PONG\r\n");
+        assert_eq!(received(&mut resp3), b"_\r\n");
+        assert!(event_loop.server_info.blocked.is_empty());
+    }
+}
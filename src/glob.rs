@@ -0,0 +1,110 @@
+//! Glob-style pattern matching with Redis's rules, as used by `KEYS` and
+//! `SCAN ... MATCH`: `*`, `?`, `[abc]`, `[^abc]`, `[a-z]` and `\` escapes.
+
+pub fn matches(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // Where to retry after a mismatch: just past the last `*`, with it
+    // swallowing one more byte of the string.
+    let mut backtrack = None;
+    while s < string.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            backtrack = Some((p, s));
+            continue;
+        }
+        if let Some(next) = match_one(pattern, p, string[s]) {
+            p = next;
+            s += 1;
+            continue;
+        }
+        match backtrack {
+            Some((star_p, star_s)) => {
+                p = star_p;
+                s = star_s + 1;
+                backtrack = Some((star_p, s));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Matches `c` against the single-byte token at `pattern[p]`, returning
+/// where the next token starts if it matches.
+fn match_one(pattern: &[u8], p: usize, c: u8) -> Option<usize> {
+    let (matched, next) = match *pattern.get(p)? {
+        b'?' => (true, p + 1),
+        b'\\' if p + 1 < pattern.len() => (pattern[p + 1] == c, p + 2),
+        b'[' => match_class(pattern, p + 1, c),
+        literal => (literal == c, p + 1),
+    };
+    matched.then_some(next)
+}
+
+/// Matches `c` against the class starting at `pattern[p]`, just after the
+/// `[`. Like Redis, a class left open runs to the end of the pattern.
+fn match_class(pattern: &[u8], mut p: usize, c: u8) -> (bool, usize) {
+    let negate = pattern.get(p) == Some(&b'^');
+    if negate {
+        p += 1;
+    }
+    let mut matched = false;
+    while p < pattern.len() {
+        match pattern[p] {
+            b']' => {
+                p += 1;
+                break;
+            }
+            b'\\' if p + 1 < pattern.len() => {
+                matched |= pattern[p + 1] == c;
+                p += 2;
+            }
+            start if p + 2 < pattern.len() && pattern[p + 1] == b'-' => {
+                let end = pattern[p + 2];
+                matched |= (start.min(end)..=start.max(end)).contains(&c);
+                p += 3;
+            }
+            member => {
+                matched |= member == c;
+                p += 1;
+            }
+        }
+    }
+    (matched != negate, p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcards() {
+        assert!(matches(b"*", b""));
+        assert!(matches(b"*", b"anything"));
+        assert!(matches(b"h?llo", b"hello"));
+        assert!(!matches(b"h?llo", b"hllo"));
+        assert!(matches(b"h*llo", b"heeeello"));
+        assert!(matches(b"h*llo", b"hllo"));
+        assert!(matches(b"*a*b*", b"xxaxxbxx"));
+        assert!(!matches(b"*a*b", b"xxaxxbxxc"));
+        assert!(matches(b"user:**:name", b"user:42:name"));
+        assert!(!matches(b"", b"a"));
+    }
+
+    #[test]
+    fn test_classes_and_escapes() {
+        assert!(matches(b"h[ae]llo", b"hello"));
+        assert!(matches(b"h[ae]llo", b"hallo"));
+        assert!(!matches(b"h[ae]llo", b"hillo"));
+        assert!(matches(b"h[^e]llo", b"hallo"));
+        assert!(!matches(b"h[^e]llo", b"hello"));
+        assert!(matches(b"h[a-b]llo", b"hbllo"));
+        assert!(matches(b"h[z-a]llo", b"hkllo"));
+        assert!(matches(b"[\\]]", b"]"));
+        assert!(matches(b"\\*", b"*"));
+        assert!(!matches(b"\\*", b"a"));
+        assert!(matches(b"a\\", b"a\\"));
+        assert!(matches(b"[abc", b"b"));
+        assert!(!matches(b"[]a", b"a"));
+    }
+}
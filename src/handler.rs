@@ -1,14 +1,28 @@
-use crate::cache::Cache;
-use crate::resp::value::Value;
-use crate::resp::{parser, value::Value::*};
-use crate::{ServerInfo, WaitState};
+use crate::cache::{self, Cache};
+use crate::commands::stream::Read as StreamRead;
+use crate::commands::table::{Module, DENYOOM, NO_MULTI, WRITE};
+use crate::commands::{
+    arg_eq, bulk, check_arity, client as client_command, command_name, database, keyspace,
+    migrate, parse_int, pubsub, set, stream, string, table, CommandError, Propagation,
+};
+use crate::resp::value::{null_array, Value};
+use crate::resp::parser::{self, Limits, RespError};
+use crate::resp::value::Value::*;
+use crate::{
+    parse_memory, ClientState, Config, ServerInfo, Transaction, WaitState, MASTER_TOKEN,
+};
 use mio::net::TcpStream;
 use mio::Token;
 use std::collections::HashMap;
-use std::io::ErrorKind::{NotFound, WouldBlock};
+use std::io::ErrorKind::{Interrupted, NotFound, WouldBlock};
 use std::io::{Error, Read, Write};
 use std::time::{Duration, Instant};
-use crate::persistence::rdb::RDB;
+use crate::glob;
+use crate::notify;
+use crate::pubsub::Kind;
+use crate::persistence::rdb;
+use std::fs;
+use std::path::Path;
 
 // TODO: Refactor - handler currently
 // owns too much responsibility.
@@ -18,83 +32,631 @@ use crate::persistence::rdb::RDB;
 // - persistence.rs (AOF/RDB)
 
 pub fn tcp_handler(
-    db: &mut Cache,
+    dbs: &mut [Cache],
     client: &Token,
     connections: &mut HashMap<Token, TcpStream>,
     server_info: &mut ServerInfo,
 ) {
-    let buffer = match read_buffer(client, connections) {
-        Some(v) => v,
-        None => return,
+    let state = server_info.clients.entry(*client).or_default();
+    let mut buffer = std::mem::take(&mut state.query_buffer);
+    loop {
+        let stream = match connections.get_mut(client) {
+            Some(conn) => conn,
+            None => {
+                println!("Error getting stream");
+                return;
+            }
+        };
+        let limit = server_info.config.client_query_buffer_limit;
+        let status = read_buffer(stream, &mut buffer, limit);
+
+        let Some(bytes_consumed) = process_buffer(dbs, client, &buffer, connections, server_info)
+        else {
+            return;
+        };
+        buffer.drain(..bytes_consumed);
+        if buffer.len() > limit {
+            println!("Closing client {:?} that reached max query buffer length", client);
+            disconnect(dbs, client, connections, server_info);
+            return;
+        }
+
+        match status {
+            ReadStatus::Drained => break,
+            ReadStatus::Full => continue,
+            ReadStatus::Closed => {
+                disconnect(dbs, client, connections, server_info);
+                return;
+            }
+        }
+    }
+    server_info.clients.entry(*client).or_default().query_buffer = buffer;
+}
+
+/// Runs the commands `client` sent while it was blocked, now that its
+/// blocking command has been answered.
+pub(crate) fn resume(
+    dbs: &mut [Cache],
+    client: &Token,
+    connections: &mut HashMap<Token, TcpStream>,
+    server_info: &mut ServerInfo,
+) {
+    let state = server_info.clients.entry(*client).or_default();
+    let mut buffer = std::mem::take(&mut state.query_buffer);
+    if let Some(bytes_consumed) = process_buffer(dbs, client, &buffer, connections, server_info) {
+        buffer.drain(..bytes_consumed);
+        server_info.clients.entry(*client).or_default().query_buffer = buffer;
+    }
+}
+
+/// Runs every whole command at the start of `buffer`, returning how many
+/// bytes they took up, or `None` if the client was disconnected. A client
+/// that blocks runs nothing more until `resume` is called for it, so its
+/// replies keep the order of its commands.
+fn process_buffer(
+    dbs: &mut [Cache],
+    client: &Token,
+    buffer: &[u8],
+    connections: &mut HashMap<Token, TcpStream>,
+    server_info: &mut ServerInfo,
+) -> Option<usize> {
+    let config = &server_info.config;
+    let limits = Limits {
+        max_bulk_len: config.proto_max_bulk_len,
+        max_multibulk_len: config.proto_max_multibulk_len,
     };
     let mut bytes_offset = 0usize;
-    while bytes_offset < buffer.len() {
-        let (bytes_consumed, parsed_command) = parser::parse(buffer[bytes_offset..].to_vec());
+    while bytes_offset < buffer.len() && !server_info.blocked.contains_key(client) {
+        let (bytes_consumed, parsed_command) =
+            match parser::parse_request(&buffer[bytes_offset..], &limits) {
+                Ok(parsed) => parsed,
+                // The rest of the command is still on its way.
+                Err(RespError::Incomplete) => break,
+                // There is no telling where the next command would start,
+                // so the connection can't be used any more.
+                Err(err) => {
+                    if let Some(stream) = connections.get_mut(client) {
+                        let reply = SimpleError(format!("ERR {}", err));
+                        if let Err(e) = write_buffer(stream, &reply.to_resp()) {
+                            println!("Error replying to client: {}", e);
+                        }
+                    }
+                    disconnect(dbs, client, connections, server_info);
+                    return None;
+                }
+            };
+        // Blank lines typed into telnet are skipped, as Redis does.
+        if matches!(&parsed_command, Array(arr) if arr.is_empty()) {
+            bytes_offset += bytes_consumed;
+            continue;
+        }
 
-        match process_command(db, &parsed_command, client, connections, server_info) {
-            Ok(true) => {
+        let admitted = admit_command(&parsed_command, client, connections, server_info);
+        let Some((module, spec)) = admitted else {
+            bytes_offset += bytes_consumed;
+            continue;
+        };
+        if !make_room(dbs, spec, connections, server_info) {
+            if let Some(transaction) = &mut server_info.clients.entry(*client).or_default().multi {
+                transaction.aborted = true;
+            }
+            if let Some(stream) = connections.get_mut(client) {
+                let oom = b"-OOM command not allowed when used memory > 'maxmemory'.\r\n";
+                if let Err(e) = write_buffer(stream, oom) {
+                    println!("Error replying to client: {}", e);
+                }
+            }
+            bytes_offset += bytes_consumed;
+            continue;
+        }
+        if refuse_in_subscriber_mode(spec, client, connections, server_info) {
+            bytes_offset += bytes_consumed;
+            continue;
+        }
+        if queue_command(&parsed_command, module, spec, client, connections, server_info) {
+            bytes_offset += bytes_consumed;
+            continue;
+        }
+
+        let mut reply = Vec::new();
+        let result = process_command(
+            dbs,
+            &parsed_command,
+            (module, spec),
+            client,
+            connections,
+            server_info,
+            &mut reply,
+        )
+        .and_then(|propagation| {
+            match connections.get_mut(client) {
+                Some(stream) if !reply.is_empty() => write_buffer(stream, &reply)?,
+                _ => {}
+            }
+            Ok(propagation)
+        });
+        if spec.name == "QUIT" {
+            disconnect(dbs, client, connections, server_info);
+            return None;
+        }
+        publish_notifications(dbs, connections, server_info);
+        send_invalidations(dbs, connections, server_info, Some(*client));
+        // `CLIENT CACHING` applies to the next command, or to the whole of
+        // a transaction that opens next.
+        let state = server_info.clients.entry(*client).or_default();
+        let caching = matches!(&parsed_command, Array(arr) if client_command::is_caching(arr))
+            || state.multi.is_some();
+        let db = state.db;
+        let read_only = matches!(result, Ok(Propagation::None));
+        track_reads(dbs, server_info, *client, read_only, caching);
+        match result {
+            Ok(Propagation::Verbatim) => {
                 if server_info.role == "master" {
+                    select_replica_db(server_info, connections, db);
                     propagate_command(
                         server_info,
                         connections,
-                        buffer[bytes_offset..bytes_consumed].to_vec(),
+                        buffer[bytes_offset..bytes_offset + bytes_consumed].to_vec(),
                     )
                     .expect("Error while propagating command");
                     server_info.master_repl_offset += bytes_consumed;
                 }
             }
-            Ok(false) => {
+            Ok(Propagation::Rewrite(commands)) => {
+                propagate_rewrite(server_info, connections, db, commands);
+            }
+            Ok(Propagation::None) => {
                 bytes_offset += bytes_consumed;
                 continue;
             }
             Err(e) => {
                 println!("Error processing command: {}", e);
-                return;
+                disconnect(dbs, client, connections, server_info);
+                return None;
             }
         };
 
         bytes_offset += bytes_consumed;
+        serve_blocked(dbs, connections, server_info);
+        publish_notifications(dbs, connections, server_info);
+        send_invalidations(dbs, connections, server_info, None);
     }
     if server_info.role == "slave" {
         server_info.master_repl_offset += bytes_offset;
     }
+    Some(bytes_offset)
 }
 
-fn read_buffer(client: &Token, connections: &mut HashMap<Token, TcpStream>) -> Option<Vec<u8>> {
-    let stream = match connections.get_mut(&client) {
-        Some(conn) => conn,
-        None => {
-            println!("Error getting stream");
-            return None;
+/// Forgets everything about `client` and closes its connection, after it
+/// hung up or broke the protocol.
+fn disconnect(
+    dbs: &mut [Cache],
+    client: &Token,
+    connections: &mut HashMap<Token, TcpStream>,
+    server_info: &mut ServerInfo,
+) {
+    connections.remove(client);
+    if let Some(mut state) = server_info.clients.remove(client) {
+        unwatch_all(dbs, &mut state);
+    }
+    server_info.pubsub.remove_client(*client);
+    server_info.tracking.disable(*client);
+    configure_dbs(dbs, server_info);
+    server_info.blocked.remove(client);
+    server_info.replicas.remove(client);
+    if server_info.waiting.as_ref().is_some_and(|state| state.client == *client) {
+        server_info.waiting = None;
+    }
+}
+
+/// Looks `command` up in the command table and refuses it with an error if
+/// it is unknown, has the wrong number of arguments, writes to a replica or
+/// may not be queued in the transaction `client` has open. Inside `MULTI`
+/// the refusal makes `EXEC` fail. Returns the command's table entry, which
+/// the rest of the request uses, unless it was refused.
+fn admit_command(
+    command: &Value,
+    client: &Token,
+    connections: &mut HashMap<Token, TcpStream>,
+    server_info: &mut ServerInfo,
+) -> Option<(Module, &'static table::Command)> {
+    let arr = match command {
+        Array(arr) => arr.as_slice(),
+        _ => &[],
+    };
+    let replica_write = |spec: &table::Command| {
+        spec.has(WRITE) && server_info.role == "slave" && *client != MASTER_TOKEN
+    };
+    let in_transaction =
+        server_info.clients.get(client).is_some_and(|state| state.multi.is_some());
+    let error = match table::resolve(arr) {
+        Ok((_, spec)) if replica_write(spec) => {
+            CommandError::Raw("READONLY You can't write against a read only replica.".to_string())
+        }
+        Ok((_, spec)) if in_transaction && spec.has(NO_MULTI) => {
+            CommandError::Other("Command not allowed inside a transaction".to_string())
         }
+        Ok(resolved) => return Some(resolved),
+        Err(err) => err,
     };
+    if let Some(transaction) = &mut server_info.clients.entry(*client).or_default().multi {
+        transaction.aborted = true;
+    }
+    // The master is never answered, even when a command fails here.
+    if server_info.role != "slave" || *client != MASTER_TOKEN {
+        if let Some(stream) = connections.get_mut(client) {
+            if let Err(e) = write_buffer(stream, &Value::from(error).to_resp()) {
+                println!("Error replying to client: {}", e);
+            }
+        }
+    }
+    None
+}
 
-    let mut buffer = [0; 512];
-    loop {
-        match stream.read(&mut buffer) {
-            // TODO: handle partial reads/failures of the byte stream
-            Ok(bytes) => {
-                if bytes == 0 {
-                    break;
-                }
+/// Queues `command`, which resolved to `module` and `spec`, if `client` is
+/// inside `MULTI`, replying `+QUEUED`. Returns false if the command should
+/// run now, as the ones that end the transaction do.
+fn queue_command(
+    command: &Value,
+    module: Module,
+    spec: &'static table::Command,
+    client: &Token,
+    connections: &mut HashMap<Token, TcpStream>,
+    server_info: &mut ServerInfo,
+) -> bool {
+    if matches!(spec.name, "MULTI" | "EXEC" | "DISCARD" | "WATCH" | "QUIT" | "RESET") {
+        return false;
+    }
+    let Some(transaction) = &mut server_info.clients.entry(*client).or_default().multi else {
+        return false;
+    };
 
-                return Some(buffer[..bytes].to_vec());
+    transaction.commands.push((command.clone(), module, spec));
+    let reply = SimpleString("QUEUED".to_string());
+    if server_info.role != "slave" || *client != MASTER_TOKEN {
+        if let Some(stream) = connections.get_mut(client) {
+            if let Err(e) = write_buffer(stream, &reply.to_resp()) {
+                println!("Error replying to client: {}", e);
             }
-            Err(ref err) if err.kind() == WouldBlock => break,
+        }
+    }
+    true
+}
+
+/// A RESP2 connection with subscriptions can only manage them, since its
+/// replies would be mixed up with the messages it is sent. Returns true if
+/// `spec`'s command was refused.
+fn refuse_in_subscriber_mode(
+    spec: &table::Command,
+    client: &Token,
+    connections: &mut HashMap<Token, TcpStream>,
+    server_info: &mut ServerInfo,
+) -> bool {
+    let protocol = server_info.clients.entry(*client).or_default().protocol;
+    if protocol != 2 || !server_info.pubsub.is_subscribed(*client) {
+        return false;
+    }
+    let allowed = [
+        "SUBSCRIBE",
+        "UNSUBSCRIBE",
+        "PSUBSCRIBE",
+        "PUNSUBSCRIBE",
+        "SSUBSCRIBE",
+        "SUNSUBSCRIBE",
+        "PING",
+        "QUIT",
+        "RESET",
+    ];
+    if allowed.contains(&spec.name) {
+        return false;
+    }
+    let error = CommandError::Other(format!(
+        "Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+        spec.name.to_lowercase()
+    ));
+    if let Some(stream) = connections.get_mut(client) {
+        if let Err(e) = write_buffer(stream, &Value::from(error).to_resp()) {
+            println!("Error replying to client: {}", e);
+        }
+    }
+    true
+}
+
+/// How far `read_buffer` got.
+enum ReadStatus {
+    /// Everything waiting on the socket has been read.
+    Drained,
+    /// `buffer` went over the limit first; there may be more to read once
+    /// the commands in it have run.
+    Full,
+    /// The peer closed the connection, or it failed.
+    Closed,
+}
+
+/// Appends what is waiting on the socket to `buffer`, stopping once it
+/// holds more than `limit` bytes.
+fn read_buffer(stream: &mut TcpStream, buffer: &mut Vec<u8>, limit: usize) -> ReadStatus {
+    let mut chunk = [0; 512];
+    while buffer.len() <= limit {
+        match stream.read(&mut chunk) {
+            Ok(0) => return ReadStatus::Closed,
+            Ok(bytes) => buffer.extend_from_slice(&chunk[..bytes]),
+            Err(ref err) if err.kind() == WouldBlock => return ReadStatus::Drained,
+            Err(ref err) if err.kind() == Interrupted => continue,
             Err(e) => {
                 println!("error: {}", e);
+                return ReadStatus::Closed;
             }
         }
     }
-    None
+    ReadStatus::Full
 }
 
-fn write_buffer(stream: &mut TcpStream, buffer: &[u8]) -> Result<(), Error> {
+fn write_buffer(stream: &mut impl Write, buffer: &[u8]) -> Result<(), Error> {
     stream.write_all(buffer)?;
     stream.flush()?;
     Ok(())
 }
 
+fn write_reply(stream: &mut impl Write, reply: &Value, protocol: u8) -> Result<(), Error> {
+    match protocol {
+        3 => write_buffer(stream, &reply.to_resp()),
+        _ => write_buffer(stream, &reply.to_resp2()),
+    }
+}
+
+/// Sends `SELECT` down the replication stream unless replicas already
+/// have `db` selected.
+fn select_replica_db(
+    server_info: &mut ServerInfo,
+    connections: &mut HashMap<Token, TcpStream>,
+    db: usize,
+) {
+    if server_info.repl_db == Some(db) {
+        return;
+    }
+    server_info.repl_db = Some(db);
+    let select = Array(vec![
+        BulkString(b"SELECT".to_vec()),
+        BulkString(db.to_string().into_bytes()),
+    ])
+    .to_resp();
+    let len = select.len();
+    propagate_command(server_info, connections, select).expect("Error while propagating command");
+    server_info.master_repl_offset += len;
+}
+
+fn propagate_rewrite(
+    server_info: &mut ServerInfo,
+    connections: &mut HashMap<Token, TcpStream>,
+    db: usize,
+    commands: Vec<Value>,
+) {
+    if server_info.role != "master" || commands.is_empty() {
+        return;
+    }
+    select_replica_db(server_info, connections, db);
+    for command in commands {
+        let command = command.to_resp();
+        let len = command.len();
+        propagate_command(server_info, connections, command)
+            .expect("Error while propagating command");
+        server_info.master_repl_offset += len;
+    }
+}
+
+/// Evicts keys to bring memory use back within `maxmemory` before a command
+/// runs, sending each eviction to replicas as a `DEL`. Returns false if
+/// memory is still over the limit and `spec`'s command could make it worse.
+/// Replicas leave eviction to their master.
+fn make_room(
+    dbs: &mut [Cache],
+    spec: &table::Command,
+    connections: &mut HashMap<Token, TcpStream>,
+    server_info: &mut ServerInfo,
+) -> bool {
+    let config = &server_info.config;
+    if config.maxmemory == 0 || server_info.role != "master" {
+        return true;
+    }
+    let evicted = cache::evict(
+        dbs,
+        config.maxmemory,
+        config.maxmemory_policy,
+        config.maxmemory_samples,
+    );
+    server_info.evicted_keys += evicted.len();
+    for (db, key) in evicted {
+        let delete = Array(vec![BulkString(b"DEL".to_vec()), bulk(&key)]);
+        propagate_rewrite(server_info, connections, db, vec![delete]);
+    }
+    publish_notifications(dbs, connections, server_info);
+    send_invalidations(dbs, connections, server_info, None);
+    if cache::used_memory(dbs) <= server_info.config.maxmemory {
+        return true;
+    }
+    !spec.has(DENYOOM)
+}
+
+/// Retries clients blocked on `XREAD`/`XREADGROUP`, oldest first, after a
+/// write may have made new entries available. Those served go on with the
+/// commands they sent while blocked.
+fn serve_blocked(
+    dbs: &mut [Cache],
+    connections: &mut HashMap<Token, TcpStream>,
+    server_info: &mut ServerInfo,
+) {
+    if server_info.blocked.is_empty() {
+        return;
+    }
+    let mut clients: Vec<Token> = server_info.blocked.keys().copied().collect();
+    clients.sort_by_key(|client| server_info.blocked[client].since);
+
+    let mut served = Vec::new();
+    for client in clients {
+        if !connections.contains_key(&client) {
+            server_info.blocked.remove(&client);
+            continue;
+        }
+        let protocol = server_info.clients.entry(client).or_default().protocol;
+        let blocked = &server_info.blocked[&client];
+        let db = blocked.db;
+        let (reply, propagation) = match blocked.retry(&mut dbs[db]) {
+            Some(ready) => ready,
+            None => continue,
+        };
+        server_info.blocked.remove(&client);
+        let read_only = propagation == Propagation::None;
+        track_reads(dbs, server_info, client, read_only, false);
+        if let Some(stream) = connections.get_mut(&client) {
+            if let Err(e) = write_reply(stream, &reply, protocol) {
+                println!("Error replying to blocked client: {}", e);
+            }
+        }
+        if let Propagation::Rewrite(commands) = propagation {
+            propagate_rewrite(server_info, connections, db, commands);
+        }
+        served.push(client);
+    }
+    for client in served {
+        resume(dbs, &client, connections, server_info);
+    }
+}
+
+/// Writes each message to its subscriber, returning how many were sent.
+fn deliver(
+    server_info: &ServerInfo,
+    connections: &mut HashMap<Token, TcpStream>,
+    messages: Vec<(Token, Value)>,
+) -> usize {
+    for (receiver, push) in &messages {
+        let protocol = server_info
+            .clients
+            .get(receiver)
+            .map_or(2, |state| state.protocol);
+        if let Some(stream) = connections.get_mut(receiver) {
+            if let Err(e) = write_reply(stream, push, protocol) {
+                println!("Error delivering message to {:?}: {}", receiver, e);
+            }
+        }
+    }
+    messages.len()
+}
+
+/// Publishes the keyspace events recorded since the last call, to
+/// `__keyspace@<db>__:<key>` and `__keyevent@<db>__:<event>` as enabled.
+pub(crate) fn publish_notifications(
+    dbs: &mut [Cache],
+    connections: &mut HashMap<Token, TcpStream>,
+    server_info: &ServerInfo,
+) {
+    let flags = server_info.config.notify_keyspace_events;
+    for (i, db) in dbs.iter_mut().enumerate() {
+        for notification in db.take_notifications() {
+            let event = notification.event.as_bytes();
+            let key = &notification.key;
+            if flags & notify::KEYSPACE != 0 {
+                let mut channel = format!("__keyspace@{}__:", i).into_bytes();
+                channel.extend_from_slice(key);
+                let messages = server_info.pubsub.messages(&channel, event);
+                deliver(server_info, connections, messages);
+            }
+            if flags & notify::KEYEVENT != 0 {
+                let mut channel = format!("__keyevent@{}__:", i).into_bytes();
+                channel.extend_from_slice(event);
+                let messages = server_info.pubsub.messages(&channel, key);
+                deliver(server_info, connections, messages);
+            }
+        }
+    }
+}
+
+/// Passes the settings each `Cache` acts on to every database, after they
+/// may have changed.
+pub(crate) fn configure_dbs(dbs: &mut [Cache], server_info: &ServerInfo) {
+    let tracking = server_info.tracking.is_enabled();
+    for db in dbs {
+        db.configure(server_info.config.notify_keyspace_events, tracking);
+    }
+}
+
+/// Records the keys `client`'s last command read, if it only read them,
+/// for `CLIENT TRACKING`.
+fn track_reads(
+    dbs: &mut [Cache],
+    server_info: &mut ServerInfo,
+    client: Token,
+    read_only: bool,
+    caching_command: bool,
+) {
+    let keys: Vec<Vec<u8>> = dbs.iter_mut().flat_map(Cache::take_reads).collect();
+    if !read_only || !server_info.tracking.is_enabled() {
+        return;
+    }
+    server_info.tracking.record_reads(client, keys, caching_command);
+}
+
+/// Tells tracking clients about the keys changed since the last call.
+/// `writer` is the client whose command changed them, for `NOLOOP`.
+pub(crate) fn send_invalidations(
+    dbs: &mut [Cache],
+    connections: &mut HashMap<Token, TcpStream>,
+    server_info: &mut ServerInfo,
+    writer: Option<Token>,
+) {
+    let keys: Vec<Vec<u8>> = dbs.iter_mut().flat_map(Cache::take_modified).collect();
+    if keys.is_empty() {
+        return;
+    }
+    let messages = server_info
+        .tracking
+        .invalidate(&keys, writer)
+        .into_iter()
+        .filter_map(|(target, keys)| {
+            let keys = Array(keys.iter().map(|key| bulk(key)).collect());
+            invalidation(server_info, target, keys)
+        })
+        .collect();
+    deliver(server_info, connections, messages);
+}
+
+/// Tells every tracking client to drop everything it cached, after a
+/// `FLUSHDB` or `FLUSHALL`.
+fn send_flush_invalidations(
+    connections: &mut HashMap<Token, TcpStream>,
+    server_info: &mut ServerInfo,
+) {
+    let messages = server_info
+        .tracking
+        .flush()
+        .into_iter()
+        .filter_map(|target| invalidation(server_info, target, Null))
+        .collect();
+    deliver(server_info, connections, messages);
+}
+
+/// The invalidation message for `target`: a push in RESP3, or a message on
+/// `__redis__:invalidate` in RESP2, which only gets through if `target` is
+/// subscribed to it.
+fn invalidation(server_info: &ServerInfo, target: Token, keys: Value) -> Option<(Token, Value)> {
+    let protocol = server_info
+        .clients
+        .get(&target)
+        .map_or(2, |state| state.protocol);
+    if protocol == 3 {
+        return Some((target, Push(vec![bulk(b"invalidate"), keys])));
+    }
+    let channel = b"__redis__:invalidate".to_vec();
+    if !server_info
+        .pubsub
+        .subscriptions(Kind::Channel, target)
+        .contains(&channel)
+    {
+        return None;
+    }
+    Some((target, Push(vec![bulk(b"message"), bulk(&channel), keys])))
+}
+
 fn propagate_command(
     server_info: &mut ServerInfo,
     connections: &mut HashMap<Token, TcpStream>,
@@ -145,288 +707,625 @@ fn get_connections<'a>(
     for (token, conn) in connections {
         if client == token {
             client_connection = Some(conn);
-        } else if server_info.replicas.contains_key(&token) {
+        } else if server_info.replicas.contains_key(token) {
             replica_connections.push(conn);
-        } else if server_info.waiting.is_some() {
-            if &server_info.waiting.clone().unwrap().client == token {
-                waiting_connection = Some(conn);
-            }
+        } else if server_info.waiting.is_some()
+            && &server_info.waiting.clone().unwrap().client == token
+        {
+            waiting_connection = Some(conn);
         }
     }
     (client_connection, waiting_connection, replica_connections)
 }
 
+/// Runs `command`, which resolved to `module` and `spec`, for `client`,
+/// writing its reply to `stream`, and returns how it should reach replicas.
+/// Every command writes exactly one reply, except to the master and while
+/// blocked.
 fn process_command(
-    db: &mut Cache,
+    dbs: &mut [Cache],
     command: &Value,
+    (module, spec): (Module, &'static table::Command),
     client: &Token,
     connections: &mut HashMap<Token, TcpStream>,
     server_info: &mut ServerInfo,
-) -> Result<bool, Error> {
+    stream: &mut Vec<u8>,
+) -> Result<Propagation, Error> {
     let (client_conn, waiting_conn, replicas) = get_connections(client, server_info, connections);
 
-    let stream = match client_conn {
-        Some(c) => c,
-        None => return Ok(false),
-    };
+    if client_conn.is_none() {
+        return Ok(Propagation::None);
+    }
+    let state = server_info.clients.entry(*client).or_default();
+    let (protocol, selected) = (state.protocol, state.db);
+    let in_transaction = state.multi.is_some();
+    let db = &mut dbs[selected];
+    let subscribed = server_info.pubsub.is_subscribed(*client);
 
-    match command {
-        Array(arr) => match &arr[0] {
-            BulkString(string) => match string.to_uppercase().as_ref() {
-                "PING" => {
-                    let response = b"+PONG\r\n";
-                    if server_info.role != "slave" {
-                        write_buffer(stream, response)?;
-                    }
-                    Ok(false)
+    let Array(arr) = command else {
+        write_buffer(stream, b"-Err an error occured\r\n")?;
+        return Ok(Propagation::None);
+    };
+    let propagation = match spec.name {
+        // Subscribers' replies must look like messages in RESP2.
+        "PING" if arr.len() > 2 => {
+            let error = CommandError::WrongArity(command_name(arr));
+            write_buffer(stream, &Value::from(error).to_resp())?;
+            Ok(Propagation::None)
+        }
+        "PING" if subscribed && protocol == 2 => {
+            let message = arr.get(1).map_or_else(Vec::new, Value::to_bytes);
+            let pong = Array(vec![bulk(b"pong"), bulk(&message)]);
+            write_buffer(stream, &pong.to_resp())?;
+            Ok(Propagation::None)
+        }
+        "PING" => {
+            let response = b"+PONG\r\n";
+            if server_info.role != "slave" || *client != MASTER_TOKEN {
+                write_buffer(stream, response)?;
+            }
+            Ok(Propagation::None)
+        }
+        "ECHO" => {
+            write_buffer(stream, &arr[1].to_resp())?;
+            Ok(Propagation::None)
+        }
+        name if module == Module::String => {
+            let (reply, propagation) = string::execute(name, arr, db);
+            if propagation == Propagation::None || server_info.role != "slave" {
+                write_reply(stream, &reply, protocol)?;
+            }
+            Ok(propagation)
+        }
+        name if module == Module::Database => {
+            let selected = &mut server_info.clients.entry(*client).or_default().db;
+            let (reply, propagation) = database::execute(name, arr, dbs, selected);
+            // The master's `SELECT`s must not be answered.
+            if server_info.role != "slave" || *client != MASTER_TOKEN {
+                write_reply(stream, &reply, protocol)?;
+            }
+            let flushed = matches!(name, "FLUSHDB" | "FLUSHALL");
+            if flushed && propagation != Propagation::None {
+                send_flush_invalidations(connections, server_info);
+            }
+            Ok(propagation)
+        }
+        name if module == Module::Keyspace => {
+            let (reply, propagation) = keyspace::execute(name, arr, db);
+            if propagation == Propagation::None || server_info.role != "slave" {
+                write_reply(stream, &reply, protocol)?;
+            }
+            Ok(propagation)
+        }
+        name if module == Module::PubSub => {
+            for reply in pubsub::execute(name, arr, &mut server_info.pubsub, *client) {
+                write_reply(stream, &reply, protocol)?;
+            }
+            Ok(Propagation::None)
+        }
+        name @ ("PUBLISH" | "SPUBLISH") => {
+            let reply = match check_arity(arr, 3) {
+                Ok(()) => {
+                    let (channel, message) = (arr[1].to_bytes(), arr[2].to_bytes());
+                    let messages = match name {
+                        "SPUBLISH" => server_info.pubsub.shard_messages(&channel, &message),
+                        _ => server_info.pubsub.messages(&channel, &message),
+                    };
+                    Integer(deliver(server_info, connections, messages) as i64)
                 }
-                "ECHO" => {
-                    write_buffer(stream, &arr[1].to_resp())?;
-                    Ok(false)
+                Err(err) => err.into(),
+            };
+            if server_info.role != "slave" || *client != MASTER_TOKEN {
+                write_reply(stream, &reply, protocol)?;
+            }
+            match reply {
+                Integer(_) => Ok(Propagation::Verbatim),
+                _ => Ok(Propagation::None),
+            }
+        }
+        _ if module == Module::Client => {
+            let name = &mut server_info.clients.entry(*client).or_default().name;
+            let reply = client_command::execute(
+                arr,
+                &mut server_info.tracking,
+                *client,
+                name,
+                |token| connections.contains_key(&token),
+            );
+            configure_dbs(dbs, server_info);
+            write_reply(stream, &reply, protocol)?;
+            Ok(Propagation::None)
+        }
+        "MIGRATE" => {
+            let (reply, propagation) = migrate::execute(arr, db);
+            if propagation == Propagation::None || server_info.role != "slave" {
+                write_reply(stream, &reply, protocol)?;
+            }
+            Ok(propagation)
+        }
+        name if module == Module::Set => {
+            let (reply, propagation) = set::execute(name, arr, db);
+            if propagation == Propagation::None || server_info.role != "slave" {
+                write_reply(stream, &reply, protocol)?;
+            }
+            Ok(propagation)
+        }
+        name if module == Module::Stream => {
+            let (reply, propagation) = stream::execute(name, arr, db);
+            if propagation == Propagation::None || server_info.role != "slave" {
+                write_reply(stream, &reply, protocol)?;
+            }
+            Ok(propagation)
+        }
+        "XREAD" | "XREADGROUP" => match stream::execute_read(arr, db, selected) {
+            StreamRead::Ready(Null, _) => {
+                write_buffer(stream, &null_array(protocol))?;
+                Ok(Propagation::None)
+            }
+            StreamRead::Ready(reply, propagation) => {
+                if propagation == Propagation::None || server_info.role != "slave" {
+                    write_reply(stream, &reply, protocol)?;
                 }
-                "SET" => {
-                    let response = execute_set(arr, db);
-                    if server_info.role != "slave" {
-                        write_buffer(stream, &response)?;
+                Ok(propagation)
+            }
+            // Transactions never block; they get the timeout reply.
+            StreamRead::Block(_) if in_transaction => {
+                write_buffer(stream, &null_array(protocol))?;
+                Ok(Propagation::None)
+            }
+            StreamRead::Block(blocked) => {
+                server_info.blocked.insert(*client, blocked);
+                Ok(Propagation::None)
+            }
+        },
+        "INFO" => {
+            execute_info(stream, arr, dbs, server_info)?;
+            Ok(Propagation::None)
+        }
+        "REPLCONF" => {
+            match execute_replconf(client, arr, server_info) {
+                // An ACK completing a WAIT answers the waiting client.
+                Ok(Some(reply)) if arg_eq(&arr[1], "ACK") => {
+                    if let Some(waiting) = waiting_conn {
+                        write_buffer(waiting, &reply.to_resp())?;
                     }
-                    Ok(true)
-                }
-                "GET" => {
-                    execute_get(stream, arr, db)?;
-                    Ok(false)
-                }
-                "INFO" => {
-                    execute_info(stream, arr, db, server_info)?;
-                    Ok(false)
-                }
-                "REPLCONF" => match execute_replconf(client, arr, server_info) {
-                    Ok(v) => match v {
-                        Some(msg) => {
-                            if arr[1] == BulkString("ACK".to_string()) {
-                                write_buffer(waiting_conn.unwrap(), &msg)?;
-                                server_info.waiting = None;
-                                Ok(false)
-                            } else {
-                                write_buffer(stream, &msg)?;
-                                Ok(false)
-                            }
-                        }
-                        None => Ok(false),
-                    },
-                    Err(err) => Err(err),
-                },
-                "PSYNC" => {
-                    execute_psync(stream, arr, client, server_info)?;
-                    Ok(false)
-                }
-                "WAIT" => {
-                    execute_wait(stream, client, arr, replicas, server_info)?;
-                    Ok(false)
-                }
-                "CONFIG" => {
-                    execute_config(stream, arr, server_info)?;
-                    Ok(false)
+                    server_info.waiting = None;
                 }
-                "KEYS" => {
-                    execute_keys(stream, arr, db)?;
-                    Ok(false)
-                }
-                _ => {
-                    write_buffer(stream, b"-ERR Unknown Command\r\n")?;
-                    Ok(false)
-                }
-            },
-            _ => {
-                write_buffer(stream, b"-Err Invalid Command\r\n")?;
-                Ok(false)
+                Ok(Some(reply)) => write_buffer(stream, &reply.to_resp())?,
+                Ok(None) => {}
+                Err(err) => write_buffer(stream, &Value::from(err).to_resp())?,
             }
-        },
+            Ok(Propagation::None)
+        }
+        "PSYNC" => {
+            execute_psync(stream, arr, dbs, client, server_info)?;
+            Ok(Propagation::None)
+        }
+        "WAIT" => {
+            execute_wait(stream, client, arr, replicas, server_info)?;
+            Ok(Propagation::None)
+        }
+        "CONFIG" => {
+            let reply = execute_config(arr, server_info).unwrap_or_else(Into::into);
+            configure_dbs(dbs, server_info);
+            write_reply(stream, &reply, protocol)?;
+            Ok(Propagation::None)
+        }
+        "SAVE" => {
+            execute_save(stream, dbs, server_info)?;
+            Ok(Propagation::None)
+        }
+        "RESET" => {
+            let reply = execute_reset(dbs, client, server_info);
+            write_buffer(stream, &reply.to_resp())?;
+            Ok(Propagation::None)
+        }
+        // The connection is closed once the reply is out.
+        "QUIT" => {
+            write_buffer(stream, b"+OK\r\n")?;
+            Ok(Propagation::None)
+        }
+        "HELLO" => {
+            let reply = execute_hello(arr, client, server_info).unwrap_or_else(Into::into);
+            // The reply is already in the protocol it switched to.
+            let protocol = server_info.clients.entry(*client).or_default().protocol;
+            write_reply(stream, &reply, protocol)?;
+            Ok(Propagation::None)
+        }
+        "COMMAND" => {
+            let reply = table::execute(arr).unwrap_or_else(Into::into);
+            write_reply(stream, &reply, protocol)?;
+            Ok(Propagation::None)
+        }
+        "MULTI" | "DISCARD" => {
+            let reply =
+                execute_multi(arr, dbs, client, server_info).unwrap_or_else(Into::into);
+            if server_info.role != "slave" || *client != MASTER_TOKEN {
+                write_buffer(stream, &reply.to_resp())?;
+            }
+            Ok(Propagation::None)
+        }
+        "EXEC" => {
+            execute_exec(dbs, arr, client, connections, server_info, stream)?;
+            Ok(Propagation::None)
+        }
+        "WATCH" | "UNWATCH" => {
+            let reply =
+                execute_watch(arr, dbs, client, server_info).unwrap_or_else(Into::into);
+            write_buffer(stream, &reply.to_resp())?;
+            Ok(Propagation::None)
+        }
         _ => {
-            write_buffer(stream, b"-Err an error occured\r\n")?;
-            Ok(false)
+            write_buffer(stream, b"-ERR Unknown Command\r\n")?;
+            Ok(Propagation::None)
+        }
+    };
+    propagation.map(|propagation| spec.propagation(propagation))
+}
+
+/// `HELLO [protover [AUTH username password] [SETNAME name]]` switches
+/// the connection's protocol and replies with details about the server.
+/// There are no users or passwords, so only `default` authenticates, with
+/// any password.
+fn execute_hello(
+    arr: &[Value],
+    client: &Token,
+    server_info: &mut ServerInfo,
+) -> Result<Value, CommandError> {
+    let state = server_info.clients.entry(*client).or_default();
+    let protocol = match arr.get(1) {
+        None => state.protocol,
+        Some(version) => match parse_int(version) {
+            Ok(version @ 2..=3) => version as u8,
+            Ok(_) => {
+                return Err(CommandError::Raw(
+                    "NOPROTO unsupported protocol version".to_string(),
+                ))
+            }
+            Err(_) => {
+                return Err(CommandError::Other(
+                    "Protocol version is not an integer or out of range".to_string(),
+                ))
+            }
+        },
+    };
+    let mut name = None;
+    let mut i = 2;
+    while i < arr.len() {
+        if arg_eq(&arr[i], "AUTH") && i + 2 < arr.len() {
+            if !arg_eq(&arr[i + 1], "default") {
+                return Err(CommandError::Raw(
+                    "WRONGPASS invalid username-password pair or user is disabled.".to_string(),
+                ));
+            }
+            i += 3;
+        } else if arg_eq(&arr[i], "SETNAME") && i + 1 < arr.len() {
+            name = Some(client_command::check_name(&arr[i + 1])?);
+            i += 2;
+        } else {
+            return Err(CommandError::Other(format!(
+                "Syntax error in HELLO option '{}'",
+                String::from_utf8_lossy(&arr[i].to_bytes())
+            )));
         }
     }
+
+    state.protocol = protocol;
+    if let Some(name) = name {
+        state.name = name;
+    }
+    let role = match server_info.role.as_str() {
+        "slave" => "replica",
+        _ => "master",
+    };
+    Ok(Map(vec![
+        (bulk(b"server"), bulk(b"redis")),
+        (bulk(b"version"), bulk(env!("CARGO_PKG_VERSION").as_bytes())),
+        (bulk(b"proto"), Integer(protocol as i64)),
+        (bulk(b"id"), Integer(client.0 as i64)),
+        (bulk(b"mode"), bulk(b"standalone")),
+        (bulk(b"role"), bulk(role.as_bytes())),
+        (bulk(b"modules"), Array(vec![])),
+    ]))
 }
 
-fn execute_set(arr: &Vec<Value>, db: &mut Cache) -> Vec<u8> {
-    let key = arr[1].to_bytes();
-    let value = arr[2].to_bytes();
+/// `RESET` puts the connection back the way it was when it connected: out
+/// of any transaction, `WATCH`, subscription and tracking, on RESP2 and
+/// database 0, without a name.
+fn execute_reset(dbs: &mut [Cache], client: &Token, server_info: &mut ServerInfo) -> Value {
+    let state = server_info.clients.entry(*client).or_default();
+    unwatch_all(dbs, state);
+    state.multi = None;
+    state.protocol = 2;
+    state.db = 0;
+    state.name = None;
+    server_info.pubsub.remove_client(*client);
+    server_info.tracking.disable(*client);
+    configure_dbs(dbs, server_info);
+    SimpleString("RESET".to_string())
+}
 
-    if arr.len() > 3 {
-        match &arr[3] {
-            BulkString(string) => match string.to_uppercase().as_ref() {
-                "PX" => match &arr[4] {
-                    BulkString(x) => {
-                        let time = x.parse::<u64>().unwrap();
-                        let duration = Duration::from_millis(time);
-                        let expiry_time = Instant::now() + duration;
-                        match db.insert(key, value, Some(expiry_time)) {
-                            Some(_) => b"+UPDATED\r\n".to_vec(),
-                            None => b"+OK\r\n".to_vec(),
-                        }
-                    }
-                    _ => panic!("INVALID VALUE TYPE {:?}", &arr[3]),
-                },
-                "EX" => match &arr[4] {
-                    BulkString(x) => {
-                        let time = x.parse::<u64>().unwrap();
-                        let duration = Duration::from_secs(time);
-                        let expiry_time = Instant::now() + duration;
-                        match db.insert(key, value, Some(expiry_time)) {
-                            Some(_) => b"+UPDATED\r\n".to_vec(),
-                            None => b"+OK\r\n".to_vec(),
-                        }
-                    }
-                    _ => panic!("INVALID VALUE {:?}", &arr[3]),
-                },
-                _ => panic!("INVALID SUBCOMMAND {:?}", &arr[3]),
-            },
-            _ => panic!("INVALID COMMAND STRUCTURE {:?}", &arr[2]),
+/// `MULTI` opens a transaction and `DISCARD` drops it, along with any
+/// `WATCH`es.
+fn execute_multi(
+    arr: &[Value],
+    dbs: &mut [Cache],
+    client: &Token,
+    server_info: &mut ServerInfo,
+) -> Result<Value, CommandError> {
+    check_arity(arr, 1)?;
+    let state = server_info.clients.entry(*client).or_default();
+    let multi = &mut state.multi;
+    let name = command_name(arr).to_uppercase();
+    match (name.as_str(), multi.is_some()) {
+        ("MULTI", true) => {
+            return Err(CommandError::Other(
+                "MULTI calls can not be nested".to_string(),
+            ))
         }
-    } else {
-        let res = db.insert(key, value, None);
+        ("MULTI", false) => *multi = Some(Transaction::default()),
+        (_, true) => {
+            *multi = None;
+            unwatch_all(dbs, state);
+        }
+        (_, false) => return Err(CommandError::Other("DISCARD without MULTI".to_string())),
+    }
+    Ok(SimpleString("OK".to_string()))
+}
 
-        match res {
-            Some(_) => b"+UPDATED\r\n".to_vec(),
-            None => b"+OK\r\n".to_vec(),
+/// `WATCH` records the version of each key for `EXEC` to check;
+/// `UNWATCH` forgets them all.
+fn execute_watch(
+    arr: &[Value],
+    dbs: &mut [Cache],
+    client: &Token,
+    server_info: &mut ServerInfo,
+) -> Result<Value, CommandError> {
+    let state = server_info.clients.entry(*client).or_default();
+    if command_name(arr).eq_ignore_ascii_case("UNWATCH") {
+        check_arity(arr, 1)?;
+        unwatch_all(dbs, state);
+        return Ok(SimpleString("OK".to_string()));
+    }
+    check_arity(arr, -2)?;
+    if state.multi.is_some() {
+        return Err(CommandError::Other(
+            "WATCH inside MULTI is not allowed".to_string(),
+        ));
+    }
+    for key in &arr[1..] {
+        let key = key.to_bytes();
+        let db = state.db;
+        if state.watched.iter().any(|(d, k, _)| *d == db && *k == key) {
+            continue;
         }
+        let version = dbs[db].watch(&key);
+        state.watched.push((db, key, version));
+    }
+    Ok(SimpleString("OK".to_string()))
+}
+
+fn unwatch_all(dbs: &mut [Cache], state: &mut ClientState) {
+    for (db, key, _) in state.watched.drain(..) {
+        dbs[db].unwatch(&key);
     }
 }
 
-fn execute_get(stream: &mut TcpStream, arr: &Vec<Value>, db: &mut Cache) -> Result<(), Error> {
-    let key = &arr[1];
-    let null_bulk_string = b"$-1\r\n".to_vec();
+/// Runs a transaction's commands back to back, unless a watched key has
+/// changed, and replies with an array of their replies. Replicas get the
+/// writes wrapped in `MULTI`/`EXEC`.
+fn execute_exec(
+    dbs: &mut [Cache],
+    arr: &[Value],
+    client: &Token,
+    connections: &mut HashMap<Token, TcpStream>,
+    server_info: &mut ServerInfo,
+    stream: &mut Vec<u8>,
+) -> Result<(), Error> {
+    let reply_to_client = server_info.role != "slave" || *client != MASTER_TOKEN;
+    let state = server_info.clients.entry(*client).or_default();
+    let commands = match &mut state.multi {
+        _ if arr.len() != 1 => Err(CommandError::WrongArity(command_name(arr))),
+        None => Err(CommandError::Other("EXEC without MULTI".to_string())),
+        Some(transaction) if transaction.aborted => {
+            state.multi = None;
+            unwatch_all(dbs, state);
+            Err(CommandError::Raw(
+                "EXECABORT Transaction discarded because of previous errors.".to_string(),
+            ))
+        }
+        Some(transaction) => Ok(std::mem::take(&mut transaction.commands)),
+    };
+    let commands = match commands {
+        Ok(commands) => commands,
+        Err(err) => {
+            if reply_to_client {
+                write_buffer(stream, &Value::from(err).to_resp())?;
+            }
+            return Ok(());
+        }
+    };
 
-    let value = match &db.get(&key.to_bytes()) {
-        Some(v) => BulkString(String::from_utf8(v.to_vec()).expect("Invalid UTF-8")).to_resp(),
-        None => null_bulk_string,
+    let state = server_info.clients.entry(*client).or_default();
+    let changed = state
+        .watched
+        .iter()
+        .any(|(db, key, version)| dbs[*db].version(key) != *version);
+    unwatch_all(dbs, state);
+    if changed {
+        state.multi = None;
+        if reply_to_client {
+            write_buffer(stream, &null_array(state.protocol))?;
+        }
+        return Ok(());
+    }
+
+    let mut replies = Vec::new();
+    // Each write with the database it was made in.
+    let mut writes = Vec::new();
+    for (command, module, spec) in &commands {
+        let mut reply = Vec::new();
+        let resolved = (*module, *spec);
+        let propagation =
+            process_command(dbs, command, resolved, client, connections, server_info, &mut reply)?;
+        let state = server_info.clients.entry(*client).or_default();
+        // A command that sends nothing, like `REPLCONF ACK`, still takes
+        // its place in the reply.
+        if reply.is_empty() {
+            reply = match state.protocol {
+                3 => Null.to_resp(),
+                _ => Null.to_resp2(),
+            };
+        }
+        replies.push(reply);
+        let db = state.db;
+        // Whether the transaction only read a key is decided per command.
+        let read_only = propagation == Propagation::None;
+        track_reads(dbs, server_info, *client, read_only, true);
+        match propagation {
+            Propagation::None => {}
+            Propagation::Verbatim => writes.push((db, vec![command.clone()])),
+            Propagation::Rewrite(commands) => writes.push((db, commands)),
+        }
+    }
+    server_info.clients.entry(*client).or_default().multi = None;
+    if reply_to_client {
+        write_buffer(stream, format!("*{}\r\n", replies.len()).as_bytes())?;
+        for reply in replies {
+            write_buffer(stream, &reply)?;
+        }
+    }
+
+    let (first_db, last_db) = match (writes.first(), writes.last()) {
+        (Some((first, _)), Some((last, _))) => (*first, *last),
+        _ => return Ok(()),
     };
-    write_buffer(stream, &value)
+    let multi = Array(vec![bulk(b"MULTI")]);
+    propagate_rewrite(server_info, connections, first_db, vec![multi]);
+    for (db, commands) in writes {
+        propagate_rewrite(server_info, connections, db, commands);
+    }
+    let exec = Array(vec![bulk(b"EXEC")]);
+    propagate_rewrite(server_info, connections, last_db, vec![exec]);
+    serve_blocked(dbs, connections, server_info);
+    Ok(())
 }
 
 fn execute_info(
-    stream: &mut TcpStream,
-    _arr: &Vec<Value>,
-    _db: &mut Cache,
+    stream: &mut impl Write,
+    _arr: &[Value],
+    dbs: &mut [Cache],
     server_info: &ServerInfo,
 ) -> Result<(), Error> {
     let server_info = BulkString(format!(
-        "role:{}\nmaster_replid:{}\nmaster_repl_offset:{}",
-        server_info.role, server_info.master_replid, server_info.master_repl_offset
-    ));
+        "role:{}\nmaster_replid:{}\nmaster_repl_offset:{}\nused_memory:{}\nmaxmemory:{}\nmaxmemory_policy:{}\nevicted_keys:{}",
+        server_info.role,
+        server_info.master_replid,
+        server_info.master_repl_offset,
+        cache::used_memory(dbs),
+        server_info.config.maxmemory,
+        server_info.config.maxmemory_policy,
+        server_info.evicted_keys
+    )
+    .into_bytes());
 
     write_buffer(stream, &server_info.to_resp())
 }
 
+/// `REPLCONF option value` carries replication details between a master
+/// and its replicas. An `ACK` is never answered, even when malformed.
 fn execute_replconf(
     client: &Token,
-    arr: &Vec<Value>,
+    arr: &[Value],
     server_info: &mut ServerInfo,
-) -> Result<Option<Vec<u8>>, Error> {
-    match &arr[1] {
-        BulkString(string) => match string.to_lowercase().as_ref() {
-            "listening-port" => match &arr[2] {
-                BulkString(_x) => Ok(Some(b"+OK\r\n".to_vec())),
-                _ => panic!("INVALID listening port {:?}", &arr[2]),
-            },
-            "capa" => match &arr[2] {
-                BulkString(x) => match x.to_lowercase().as_ref() {
-                    "psync2" => Ok(Some(b"+OK\r\n".to_vec())),
-                    _ => panic!("INVALID CAPA COMMAND {:?}", &arr[2]),
-                },
-                _ => panic!("INVALID VALUE {:?}", &arr[2]),
-            },
-            "getack" => match &arr[2] {
-                BulkString(x) => match x.to_lowercase().as_ref() {
-                    "*" => {
-                        let response = Array(vec![
-                            BulkString("REPLCONF".to_string()),
-                            BulkString("ACK".to_string()),
-                            BulkString(format!("{}", server_info.master_repl_offset as i64)),
-                        ]);
-                        Ok(Some(response.to_resp().to_vec()))
-                    }
-                    _ => panic!("INVALID GETACK COMMAND {:?}", &arr[2]),
-                },
-                _ => panic!("INVALID VALUE {:?}", &arr[2]),
-            },
-            "ack" => match &arr[2] {
-                BulkString(x) => {
-                    let offset = x.parse::<usize>().unwrap();
-
-                    match server_info.waiting {
-                        Some(ref mut state) => {
-                            server_info.replicas.insert(*client, offset);
-                            state.acks_received += 1;
-                            if state.acks_received >= state.min_replicas
-                                || state.start_time.elapsed() > state.timeout
-                            {
-                                let response = Integer(state.acks_received as i64);
-                                Ok(Some(response.to_resp().to_vec()))
-                            } else {
-                                Ok(None)
-                            }
-                        }
-                        None => {
-                            server_info.replicas.insert(*client, offset);
-                            Ok(None)
-                        }
+) -> Result<Option<Value>, CommandError> {
+    check_arity(arr, -3)?;
+    let option = String::from_utf8_lossy(&arr[1].to_bytes()).to_lowercase();
+    match option.as_ref() {
+        "listening-port" | "capa" => Ok(Some(SimpleString("OK".to_string()))),
+        "getack" => Ok(Some(Array(vec![
+            bulk(b"REPLCONF"),
+            bulk(b"ACK"),
+            bulk(server_info.master_repl_offset.to_string().as_bytes()),
+        ]))),
+        "ack" => {
+            let Some(offset) = parse_int(&arr[2]).ok().and_then(|n| usize::try_from(n).ok())
+            else {
+                return Ok(None);
+            };
+            server_info.replicas.insert(*client, offset);
+            match server_info.waiting {
+                Some(ref mut state) => {
+                    state.acks_received += 1;
+                    if state.acks_received >= state.min_replicas
+                        || state.start_time.elapsed() > state.timeout
+                    {
+                        Ok(Some(Integer(state.acks_received as i64)))
+                    } else {
+                        Ok(None)
                     }
                 }
-                _ => panic!("INVALID REPLICA OFFSET {:?}", &arr[2]),
-            },
-            _ => panic!("INVALID REPLCONF COMMAND {:?}", &arr[1]),
-        },
-        _ => panic!("INVALID COMMAND STRUCTURE {:?}", &arr[1]),
+                None => Ok(None),
+            }
+        }
+        _ => Err(CommandError::Other(format!(
+            "Unrecognized REPLCONF option: {}",
+            String::from_utf8_lossy(&arr[1].to_bytes())
+        ))),
     }
 }
 
+/// `PSYNC replicationid offset`: partial resynchronization isn't supported,
+/// so every replica gets a full copy of the dataset.
 fn execute_psync(
-    stream: &mut TcpStream,
-    arr: &Vec<Value>,
+    stream: &mut impl Write,
+    arr: &[Value],
+    dbs: &[Cache],
     client: &Token,
     server_info: &mut ServerInfo,
 ) -> Result<(), Error> {
-    match &arr[1] {
-        BulkString(string) => match string.to_lowercase().as_ref() {
-            "?" => match &arr[2] {
-                BulkString(_x) => {
-                    let response = SimpleString(format!(
-                        "FULLRESYNC {} {}",
-                        server_info.master_replid, server_info.master_repl_offset
-                    ));
-                    write_buffer(stream, &response.to_resp())?;
-
-                    let rdb_file = RDB::new().to_binary().unwrap();
-                    let response = format!("${}\r\n", &rdb_file.len());
-                    write_buffer(stream, &response.as_bytes())?;
-                    write_buffer(stream, &rdb_file)?;
-                    server_info.replicas.insert(*client, 0usize);
-                    Ok(())
-                }
-                _ => panic!("INVALID listening port {:?}", &arr[2]),
-            },
-            _ => panic!("INVALID PSYNC COMMAND {:?}", &arr[3]),
-        },
-        _ => panic!("INVALID COMMAND STRUCTURE {:?}", &arr[2]),
+    if let Err(err) = check_arity(arr, 3) {
+        return write_buffer(stream, &Value::from(err).to_resp());
+    }
+    let response = SimpleString(format!(
+        "FULLRESYNC {} {}",
+        server_info.master_replid, server_info.master_repl_offset
+    ));
+    write_buffer(stream, &response.to_resp())?;
+
+    let rdb_file = rdb::save(dbs);
+    let response = format!("${}\r\n", &rdb_file.len());
+    write_buffer(stream, response.as_bytes())?;
+    write_buffer(stream, &rdb_file)?;
+    server_info.replicas.insert(*client, 0usize);
+    // The new replica starts out on database 0, whatever the others have
+    // selected.
+    server_info.repl_db = None;
+    Ok(())
+}
+
+/// `WAIT numreplicas timeout` arguments: the replica count and how long to
+/// wait for it.
+fn wait_args(arr: &[Value]) -> Result<(usize, Duration), CommandError> {
+    check_arity(arr, 3)?;
+    let min_replicas = parse_int(&arr[1])?;
+    let timeout = parse_int(&arr[2])?;
+    if timeout < 0 {
+        return Err(CommandError::Other("timeout is negative".to_string()));
     }
+    Ok((min_replicas.max(0) as usize, Duration::from_millis(timeout as u64)))
 }
 
 fn execute_wait(
-    stream: &mut TcpStream,
+    stream: &mut impl Write,
     client: &Token,
-    arr: &Vec<Value>,
+    arr: &[Value],
     mut replicas: Vec<&mut TcpStream>,
     server_info: &mut ServerInfo,
 ) -> Result<(), Error> {
-    let min_replicas = match &arr[1] {
-        BulkString(x) => x.parse::<usize>().unwrap(),
-        _ => 0,
-    };
-
-    let timeout = match &arr[2] {
-        BulkString(x) => Duration::from_millis(x.parse::<u64>().unwrap()),
-        _ => Duration::from_millis(0),
+    let (min_replicas, timeout) = match wait_args(arr) {
+        Ok(args) => args,
+        Err(err) => return write_buffer(stream, &Value::from(err).to_resp()),
     };
 
     if server_info.master_repl_offset == 0 {
@@ -450,53 +1349,489 @@ fn execute_wait(
     Ok(())
 }
 
-fn execute_config(stream: &mut TcpStream, arr: &Vec<Value>, server_info: &ServerInfo) -> Result<(), Error> {
+/// The parameters `CONFIG GET` knows about.
+const CONFIG_PARAMS: &[&str] = &[
+    "dir",
+    "dbfilename",
+    "maxmemory",
+    "maxmemory-policy",
+    "maxmemory-samples",
+    "databases",
+    "notify-keyspace-events",
+    "proto-max-bulk-len",
+    "proto-max-multibulk-len",
+    "client-query-buffer-limit",
+];
 
-    match &arr[1] {
-        BulkString(string) => match string.to_uppercase().as_ref() {
-            "GET" => match &arr[2] {
-                BulkString(x) => match x.to_lowercase().as_ref() {
-                    "dir" => {
-                        let response = Array(vec![
-                            BulkString("dir".to_string()),
-                            BulkString(server_info.config.dir.clone()),
-                        ]);
-                        write_buffer(stream, &response.to_resp())
-                    }
-                    "dbfilename" => {
-                        let response = Array(vec![
-                            BulkString("dbfilename".to_string()),
-                            BulkString(server_info.config.dbfilename.clone()),
-                        ]);
-                        write_buffer(stream, &response.to_resp())
-                    }
-                    _ => panic!("Config not supported"),
-                }
-                _ => panic!("INVALID VALUE TYPE {:?}", &arr[3]),
+/// `CONFIG GET pattern [pattern ...]` replies with every parameter matching
+/// one of the glob-style patterns; `CONFIG SET parameter value` changes one.
+fn execute_config(arr: &[Value], server_info: &mut ServerInfo) -> Result<Value, CommandError> {
+    check_arity(arr, -2)?;
+    let subcommand = String::from_utf8_lossy(&arr[1].to_bytes()).to_uppercase();
+    match subcommand.as_ref() {
+        "GET" => {
+            if arr.len() < 3 {
+                return Err(CommandError::WrongArity("config|get".to_string()));
             }
-            _ => panic!("INVALID SUBCOMMAND {:?}", &arr[3]),
-        },
-        _ => panic!("INVALID COMMAND STRUCTURE {:?}", &arr[2]),
+            let patterns: Vec<Vec<u8>> =
+                arr[2..].iter().map(|pattern| pattern.to_bytes().to_ascii_lowercase()).collect();
+            let config = &server_info.config;
+            let pairs = CONFIG_PARAMS
+                .iter()
+                .filter(|name| patterns.iter().any(|pattern| glob::matches(pattern, name.as_bytes())))
+                .map(|name| {
+                    let value = match *name {
+                        "dir" => config.dir.clone(),
+                        "dbfilename" => config.dbfilename.clone(),
+                        "maxmemory" => config.maxmemory.to_string(),
+                        "maxmemory-policy" => config.maxmemory_policy.to_string(),
+                        "maxmemory-samples" => config.maxmemory_samples.to_string(),
+                        "notify-keyspace-events" => notify::format(config.notify_keyspace_events),
+                        "proto-max-bulk-len" => config.proto_max_bulk_len.to_string(),
+                        "proto-max-multibulk-len" => config.proto_max_multibulk_len.to_string(),
+                        "client-query-buffer-limit" => config.client_query_buffer_limit.to_string(),
+                        _ => config.databases.to_string(),
+                    };
+                    (bulk(name.as_bytes()), bulk(value.as_bytes()))
+                })
+                .collect();
+            Ok(Map(pairs))
+        }
+        "SET" => {
+            if arr.len() != 4 {
+                return Err(CommandError::WrongArity("config|set".to_string()));
+            }
+            let name = String::from_utf8_lossy(&arr[2].to_bytes()).into_owned();
+            let value = String::from_utf8_lossy(&arr[3].to_bytes()).into_owned();
+            set_config(&mut server_info.config, &name, &value).map_err(|reason| {
+                CommandError::Other(format!(
+                    "CONFIG SET failed (possibly related to argument '{}') - {}",
+                    name, reason
+                ))
+            })?;
+            Ok(SimpleString("OK".to_string()))
+        }
+        _ => Err(CommandError::Other(format!(
+            "unknown subcommand '{}'. Try CONFIG HELP.",
+            String::from_utf8_lossy(&arr[1].to_bytes())
+        ))),
     }
 }
 
-fn execute_keys(stream: &mut TcpStream, arr: &Vec<Value>, db: &mut Cache) -> Result<(), Error> {
+fn set_config(config: &mut Config, name: &str, value: &str) -> Result<(), String> {
+    match name.to_lowercase().as_ref() {
+        "maxmemory" => {
+            config.maxmemory = parse_memory(value)
+                .ok_or("argument must be a memory value")?;
+        }
+        "maxmemory-policy" => config.maxmemory_policy = value.parse()?,
+        "maxmemory-samples" => {
+            config.maxmemory_samples = value
+                .parse()
+                .ok()
+                .filter(|samples| (1..=64).contains(samples))
+                .ok_or("argument must be between 1 and 64")?;
+        }
+        "proto-max-bulk-len" => {
+            config.proto_max_bulk_len = parse_memory(value)
+                .ok_or("argument must be a memory value")?;
+        }
+        "proto-max-multibulk-len" => {
+            config.proto_max_multibulk_len = value
+                .parse()
+                .ok()
+                .filter(|len| *len > 0)
+                .ok_or("argument must be a positive integer")?;
+        }
+        "client-query-buffer-limit" => {
+            config.client_query_buffer_limit = parse_memory(value)
+                .ok_or("argument must be a memory value")?;
+        }
+        "notify-keyspace-events" => {
+            let flags = notify::parse(value)
+                .ok_or("Invalid event class character. Use 'Ag$lshzxeKEtmdn'.")?;
+            config.notify_keyspace_events = flags;
+        }
+        _ => return Err("Unsupported CONFIG parameter".to_string()),
+    }
+    Ok(())
+}
+
+fn execute_save(stream: &mut impl Write, dbs: &[Cache], server_info: &ServerInfo) -> Result<(), Error> {
+    let path = Path::new(&server_info.config.dir).join(&server_info.config.dbfilename);
+    let response = match fs::write(&path, rdb::save(dbs)) {
+        Ok(()) => SimpleString("OK".to_string()),
+        Err(e) => {
+            println!("Error saving {}: {}", path.display(), e);
+            SimpleError("ERR Background save failed".to_string())
+        }
+    };
+    write_buffer(stream, &response.to_resp())
+}
 
-    match &arr[1] {
-        BulkString(string) => match string.to_uppercase().as_ref() {
-            "*" => {
-                let mut keys = Vec::new();
-                for key in db.keys().iter_mut() {
-                    let string = String::from_utf8(key.to_vec()).unwrap();
-                    keys.push(BulkString(string))
-                };
-                let response =  Array(keys).to_resp();
-                println!("{:?}", String::from_utf8(response.to_vec()));
-                write_buffer(stream, &*response)
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::net::TcpListener;
 
+    /// A server driven without an event loop: what a client sends is handed
+    /// straight to `process_buffer`.
+    pub(crate) struct Server {
+        pub(crate) dbs: Vec<Cache>,
+        pub(crate) connections: HashMap<Token, TcpStream>,
+        pub(crate) server_info: ServerInfo,
+    }
+
+    impl Server {
+        pub(crate) fn new(role: &str) -> Self {
+            let config = Config::default();
+            Self {
+                dbs: (0..config.databases).map(|_| Cache::new()).collect(),
+                connections: HashMap::new(),
+                server_info: ServerInfo::new(role.to_string(), None, None, config),
             }
-            _ => panic!("INVALID SUBCOMMAND {:?}", &arr[3]),
-        },
-        _ => panic!("INVALID COMMAND STRUCTURE {:?}", &arr[2]),
+        }
+
+        /// Opens a connection registered as `token`, returning the client's
+        /// end of it.
+        pub(crate) fn connect(&mut self, token: Token) -> std::net::TcpStream {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let (server, _) = listener.accept().unwrap();
+            server.set_nonblocking(true).unwrap();
+            self.connections.insert(token, TcpStream::from_std(server));
+            client
+                .set_read_timeout(Some(Duration::from_millis(50)))
+                .unwrap();
+            client
+        }
+
+        /// Hands `input` to the server as `tcp_handler` would after reading
+        /// it, keeping what isn't run yet in the client's query buffer.
+        pub(crate) fn send(&mut self, token: Token, input: &[u8]) {
+            let state = self.server_info.clients.entry(token).or_default();
+            state.query_buffer.extend_from_slice(input);
+            resume(&mut self.dbs, &token, &mut self.connections, &mut self.server_info);
+        }
     }
-}
\ No newline at end of file
+
+    /// Everything sent to `client` so far.
+    pub(crate) fn received(client: &mut std::net::TcpStream) -> Vec<u8> {
+        let mut received = Vec::new();
+        let mut buffer = [0; 4096];
+        while let Ok(n @ 1..) = client.read(&mut buffer) {
+            received.extend_from_slice(&buffer[..n]);
+        }
+        received
+    }
+
+    #[test]
+    fn test_empty_stream_read() {
+        let mut server = Server::new("master");
+        let mut client = server.connect(Token(2));
+        server.send(Token(2), b"XREAD STREAMS s 0\r\n");
+        assert_eq!(received(&mut client), b"*-1\r\n");
+        server.send(Token(2), b"MULTI\r\nXREAD BLOCK 0 STREAMS s $\r\nEXEC\r\n");
+        assert_eq!(received(&mut client), b"+OK\r\n+QUEUED\r\n*1\r\n*-1\r\n");
+    }
+
+    /// `XREAD`'s RESP2 reply carrying entry `1-1` of `stream`, `f` set to `v`.
+    fn entry_reply(stream: &str) -> Vec<u8> {
+        format!(
+            "*1\r\n*2\r\n${}\r\n{}\r\n*1\r\n*2\r\n$3\r\n1-1\r\n*2\r\n$1\r\nf\r\n$1\r\nv\r\n",
+            stream.len(),
+            stream
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn test_blocked_client_pipeline_waits() {
+        let mut server = Server::new("master");
+        let mut client = server.connect(Token(2));
+        let mut writer = server.connect(Token(3));
+        server.send(
+            Token(2),
+            b"XREAD BLOCK 0 STREAMS s $\r\nPING\r\nXREAD BLOCK 0 STREAMS t $\r\nSELECT 1\r\n",
+        );
+        assert_eq!(received(&mut client), b"");
+        // More commands sent while blocked wait behind the others.
+        server.send(Token(2), b"ECHO hi\r\n");
+        assert_eq!(received(&mut client), b"");
+
+        server.send(Token(3), b"XADD s 1-1 f v\r\n");
+        let mut expected = entry_reply("s");
+        expected.extend_from_slice(b"+PONG\r\n");
+        assert_eq!(received(&mut client), expected);
+        assert!(server.server_info.blocked.contains_key(&Token(2)));
+
+        server.send(Token(3), b"XADD t 1-1 f v\r\n");
+        received(&mut writer);
+        let mut expected = entry_reply("t");
+        expected.extend_from_slice(b"+OK\r\n$2\r\nhi\r\n");
+        assert_eq!(received(&mut client), expected);
+        assert!(server.server_info.clients[&Token(2)].query_buffer.is_empty());
+    }
+
+    #[test]
+    fn test_blocked_read_keeps_its_database() {
+        let mut server = Server::new("master");
+        let mut client = server.connect(Token(2));
+        let _writer = server.connect(Token(3));
+        server.send(Token(2), b"XREAD BLOCK 0 STREAMS s $\r\nSELECT 1\r\n");
+        server.send(Token(3), b"SELECT 1\r\nXADD s 1-1 f v\r\n");
+        assert_eq!(received(&mut client), b"");
+
+        server.send(Token(3), b"SELECT 0\r\nXADD s 1-1 f v\r\n");
+        let mut expected = entry_reply("s");
+        expected.extend_from_slice(b"+OK\r\n");
+        assert_eq!(received(&mut client), expected);
+        assert_eq!(server.server_info.clients[&Token(2)].db, 1);
+    }
+
+    #[test]
+    fn test_multi_exec_discard() {
+        let mut server = Server::new("master");
+        let mut client = server.connect(Token(2));
+        server.send(Token(2), b"MULTI\r\nSET k v\r\nGET k\r\nEXEC\r\n");
+        assert_eq!(
+            received(&mut client),
+            b"+OK\r\n+QUEUED\r\n+QUEUED\r\n*2\r\n+OK\r\n$1\r\nv\r\n"
+        );
+
+        server.send(Token(2), b"MULTI\r\nSET k w\r\nDISCARD\r\nGET k\r\n");
+        assert_eq!(received(&mut client), b"+OK\r\n+QUEUED\r\n+OK\r\n$1\r\nv\r\n");
+        server.send(Token(2), b"EXEC\r\nDISCARD\r\n");
+        assert_eq!(
+            received(&mut client),
+            b"-ERR EXEC without MULTI\r\n-ERR DISCARD without MULTI\r\n"
+        );
+    }
+
+    #[test]
+    fn test_exec_after_queueing_error() {
+        let mut server = Server::new("master");
+        let mut client = server.connect(Token(2));
+        server.send(Token(2), b"MULTI\r\nSET k\r\nSET k v\r\nEXEC\r\n");
+        assert_eq!(
+            received(&mut client),
+            b"+OK\r\n-ERR wrong number of arguments for 'set' command\r\n+QUEUED\r\n\
+              -EXECABORT Transaction discarded because of previous errors.\r\n"
+        );
+        assert_eq!(server.dbs[0].get(b"k"), Ok(None));
+
+        // Commands whose replies can't be collected, or that can't wait for
+        // `EXEC`, are refused.
+        for command in [&b"SUBSCRIBE c\r\n"[..], b"SAVE\r\n", b"WAIT 0 0\r\n"] {
+            server.send(Token(2), b"MULTI\r\n");
+            server.send(Token(2), command);
+            server.send(Token(2), b"EXEC\r\n");
+            assert_eq!(
+                received(&mut client),
+                b"+OK\r\n-ERR Command not allowed inside a transaction\r\n\
+                  -EXECABORT Transaction discarded because of previous errors.\r\n"
+            );
+        }
+        assert!(!server.server_info.pubsub.is_subscribed(Token(2)));
+    }
+
+    #[test]
+    fn test_nested_multi() {
+        let mut server = Server::new("master");
+        let mut client = server.connect(Token(2));
+        server.send(Token(2), b"MULTI\r\nMULTI\r\nINCR n\r\nEXEC\r\n");
+        assert_eq!(
+            received(&mut client),
+            b"+OK\r\n-ERR MULTI calls can not be nested\r\n+QUEUED\r\n*1\r\n:1\r\n"
+        );
+    }
+
+    #[test]
+    fn test_exec_sends_writes_to_replicas_in_a_transaction() {
+        let mut server = Server::new("master");
+        let mut client = server.connect(Token(2));
+        let mut replica = server.connect(Token(3));
+        server.server_info.replicas.insert(Token(3), 0);
+        server.send(Token(2), b"MULTI\r\nSET k v\r\nGET k\r\nDEL k\r\nEXEC\r\n");
+        received(&mut client);
+        assert_eq!(
+            received(&mut replica),
+            b"*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n*1\r\n$5\r\nMULTI\r\n\
+              *3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n*2\r\n$3\r\nDEL\r\n$1\r\nk\r\n\
+              *1\r\n$4\r\nEXEC\r\n"
+        );
+
+        // A transaction that only reads sends nothing.
+        server.send(Token(2), b"MULTI\r\nGET k\r\nEXEC\r\n");
+        assert_eq!(received(&mut client), b"+OK\r\n+QUEUED\r\n*1\r\n$-1\r\n");
+        assert_eq!(received(&mut replica), b"");
+    }
+
+    #[test]
+    fn test_table_flags_decide_what_reaches_replicas() {
+        let mut server = Server::new("master");
+        let mut client = server.connect(Token(2));
+        let mut replica = server.connect(Token(3));
+        server.server_info.replicas.insert(Token(3), 0);
+        let publish = b"*3\r\n$7\r\nPUBLISH\r\n$1\r\nc\r\n$1\r\nm\r\n";
+        let setnx = b"*3\r\n$5\r\nSETNX\r\n$1\r\nk\r\n$1\r\nv\r\n";
+        let sadd = b"*3\r\n$4\r\nSADD\r\n$1\r\nk\r\n$1\r\nm\r\n";
+        server.send(Token(2), b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n*1\r\n$4\r\nPING\r\n");
+        for command in [&publish[..], setnx, setnx, sadd] {
+            server.send(Token(2), command);
+        }
+        assert_eq!(
+            received(&mut client),
+            b"$-1\r\n+PONG\r\n:0\r\n:1\r\n:0\r\n\
+              -WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+        let mut expected = b"*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n".to_vec();
+        expected.extend_from_slice(publish);
+        expected.extend_from_slice(setnx);
+        assert_eq!(received(&mut replica), expected);
+    }
+
+    #[test]
+    fn test_subscriber_mode() {
+        let mut server = Server::new("master");
+        let mut client = server.connect(Token(2));
+        server.send(Token(2), b"SUBSCRIBE c\r\n");
+        received(&mut client);
+        server.send(Token(2), b"PING\r\nPING hi\r\nGET k\r\n");
+        assert_eq!(
+            received(&mut client),
+            b"*2\r\n$4\r\npong\r\n$0\r\n\r\n*2\r\n$4\r\npong\r\n$2\r\nhi\r\n\
+              -ERR Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / \
+              RESET are allowed in this context\r\n"
+        );
+
+        server.send(Token(2), b"RESET\r\nGET k\r\nPING\r\n");
+        assert_eq!(received(&mut client), b"+RESET\r\n$-1\r\n+PONG\r\n");
+        assert!(!server.server_info.pubsub.is_subscribed(Token(2)));
+
+        server.send(Token(2), b"SUBSCRIBE c\r\n");
+        received(&mut client);
+        server.send(Token(2), b"QUIT\r\n");
+        assert_eq!(received(&mut client), b"+OK\r\n");
+        assert!(!server.connections.contains_key(&Token(2)));
+        assert!(!server.server_info.pubsub.is_subscribed(Token(2)));
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut server = Server::new("master");
+        let mut client = server.connect(Token(2));
+        server.send(Token(2), b"HELLO 3\r\nSELECT 1\r\nCLIENT SETNAME me\r\n");
+        server.send(Token(2), b"CLIENT TRACKING ON\r\nWATCH k\r\nMULTI\r\nSET k v\r\n");
+        received(&mut client);
+        server.send(Token(2), b"RESET\r\nEXEC\r\nCLIENT GETNAME\r\n");
+        assert_eq!(
+            received(&mut client),
+            b"+RESET\r\n-ERR EXEC without MULTI\r\n$-1\r\n"
+        );
+        let state = &server.server_info.clients[&Token(2)];
+        assert_eq!((state.protocol, state.db), (2, 0));
+        assert!(state.watched.is_empty());
+        assert!(!server.server_info.tracking.is_enabled());
+        assert_eq!(server.dbs[1].get(b"k"), Ok(None));
+    }
+
+    #[test]
+    fn test_quit_stops_the_pipeline() {
+        let mut server = Server::new("master");
+        let mut client = server.connect(Token(2));
+        server.send(Token(2), b"MULTI\r\nQUIT\r\nSET k v\r\n");
+        assert_eq!(received(&mut client), b"+OK\r\n+OK\r\n");
+        assert!(!server.connections.contains_key(&Token(2)));
+        assert!(!server.server_info.clients.contains_key(&Token(2)));
+        assert_eq!(server.dbs[0].get(b"k"), Ok(None));
+    }
+
+    #[test]
+    fn test_exec_on_a_replica() {
+        let mut server = Server::new("slave");
+        let mut client = server.connect(Token(2));
+        server.send(Token(2), b"MULTI\r\nPING\r\nECHO hi\r\nEXEC\r\n");
+        assert_eq!(
+            received(&mut client),
+            b"+OK\r\n+QUEUED\r\n+QUEUED\r\n*2\r\n+PONG\r\n$2\r\nhi\r\n"
+        );
+    }
+
+    #[test]
+    fn test_exec_tracks_only_reads() {
+        let mut server = Server::new("master");
+        let mut client = server.connect(Token(2));
+        let mut writer = server.connect(Token(3));
+        server.send(Token(2), b"HELLO 3\r\nCLIENT TRACKING ON\r\n");
+        received(&mut client);
+        server.send(Token(2), b"MULTI\r\nINCR written\r\nGET read\r\nEXEC\r\n");
+        assert_eq!(
+            received(&mut client),
+            b"+OK\r\n+QUEUED\r\n+QUEUED\r\n*2\r\n:1\r\n_\r\n"
+        );
+
+        server.send(Token(3), b"SET written 5\r\nSET read 5\r\n");
+        received(&mut writer);
+        assert_eq!(
+            received(&mut client),
+            b">2\r\n$10\r\ninvalidate\r\n*1\r\n$4\r\nread\r\n"
+        );
+    }
+
+    #[test]
+    fn test_failed_writes_leave_watched_keys_alone() {
+        let mut server = Server::new("master");
+        let mut client = server.connect(Token(2));
+        let mut other = server.connect(Token(3));
+        let mut tracker = server.connect(Token(4));
+        server.send(Token(3), b"SET str v\r\nSADD s a\r\n");
+        server.send(Token(3), b"XADD x 1-1 f v\r\nXGROUP CREATE x g $\r\n");
+        server.send(Token(4), b"HELLO 3\r\nCLIENT TRACKING ON\r\nGET str\r\nSCARD s\r\n");
+        received(&mut other);
+        received(&mut tracker);
+
+        server.send(Token(2), b"WATCH str s x\r\n");
+        server.send(Token(3), b"SADD str m\r\nSREM s missing\r\nXACK x g 0-1\r\n");
+        assert_eq!(
+            received(&mut other),
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n:0\r\n:0\r\n"
+        );
+        server.send(Token(2), b"MULTI\r\nGET str\r\nEXEC\r\n");
+        assert_eq!(received(&mut client), b"+OK\r\n+OK\r\n+QUEUED\r\n*1\r\n$1\r\nv\r\n");
+        assert_eq!(received(&mut tracker), b"");
+
+        server.send(Token(2), b"WATCH s\r\n");
+        server.send(Token(3), b"SREM s a\r\n");
+        server.send(Token(2), b"MULTI\r\nSCARD s\r\nEXEC\r\n");
+        assert_eq!(received(&mut client), b"+OK\r\n+OK\r\n+QUEUED\r\n*-1\r\n");
+        assert_eq!(received(&mut tracker), b">2\r\n$10\r\ninvalidate\r\n*1\r\n$1\r\ns\r\n");
+    }
+
+    #[test]
+    fn test_notify_keyspace_events_is_per_server() {
+        let mut server = Server::new("master");
+        let mut other = Server::new("master");
+        let mut client = server.connect(Token(2));
+        let mut subscriber = server.connect(Token(3));
+        let mut other_subscriber = other.connect(Token(3));
+        server.send(Token(2), b"CONFIG SET notify-keyspace-events Eg\r\n");
+        server.send(Token(2), b"CONFIG GET notify-keyspace-events\r\n");
+        assert_eq!(
+            received(&mut client),
+            b"+OK\r\n*2\r\n$22\r\nnotify-keyspace-events\r\n$2\r\ngE\r\n"
+        );
+        server.send(Token(3), b"SUBSCRIBE __keyevent@0__:del\r\n");
+        other.send(Token(3), b"SUBSCRIBE __keyevent@0__:del\r\n");
+        received(&mut subscriber);
+        received(&mut other_subscriber);
+
+        server.send(Token(2), b"SET k v\r\nDEL k\r\n");
+        other.send(Token(2), b"SET k v\r\nDEL k\r\n");
+        assert_eq!(
+            received(&mut subscriber),
+            b"*3\r\n$7\r\nmessage\r\n$18\r\n__keyevent@0__:del\r\n$1\r\nk\r\n"
+        );
+        assert_eq!(received(&mut other_subscriber), b"");
+    }
+}
@@ -0,0 +1,39 @@
+//! Frees large values on a background thread, for `UNLINK` and
+//! `FLUSHDB`/`FLUSHALL ASYNC`, so dropping them doesn't stall the event loop.
+
+use crate::cache::Data;
+use std::sync::mpsc::{self, Sender};
+use std::sync::OnceLock;
+use std::thread;
+
+/// Values that take more allocations than this to free are handed off,
+/// as with Redis's `LAZYFREE_THRESHOLD`.
+const LAZYFREE_THRESHOLD: usize = 64;
+
+/// Drops `value` on the background thread.
+pub fn drop_later<T: Send + 'static>(value: T) {
+    static QUEUE: OnceLock<Sender<Box<dyn Send>>> = OnceLock::new();
+    let queue = QUEUE.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<Box<dyn Send>>();
+        thread::spawn(move || receiver.into_iter().for_each(drop));
+        sender
+    });
+    // Only fails if the thread is gone, in which case dropping here is
+    // the best we can do.
+    let _ = queue.send(Box::new(value));
+}
+
+/// Frees `value` in the background if that's worth it, otherwise right away.
+pub fn free(value: Data) {
+    let effort = match &value {
+        Data::String(_) => 1,
+        Data::Set(set) => set.len(),
+        Data::Stream(stream) => {
+            let pending: usize = stream.groups.values().map(|g| g.pending.len()).sum();
+            stream.len() + pending
+        }
+    };
+    if effort > LAZYFREE_THRESHOLD {
+        drop_later(value);
+    }
+}
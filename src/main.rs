@@ -1,26 +1,85 @@
 use std::collections::HashMap;
 
 mod cache;
+mod commands;
 mod event_loop;
+mod glob;
 mod handler;
+mod lazyfree;
+mod notify;
 mod resp;
+mod random;
+mod stream;
 mod replication;
 mod persistence;
+mod pubsub;
+mod tracking;
 
-use cache::Cache;
+use cache::{Cache, EvictionPolicy};
 use clap::Parser;
 use event_loop::EventLoop;
 use handler::tcp_handler;
 use mio::Token;
+use std::fs;
 use std::net::TcpStream;
+use std::path::Path;
 use std::net::ToSocketAddrs;
 use std::time::{Duration, Instant};
+use crate::commands::stream::BlockedRead;
+use crate::commands::table::{self, Module};
+use crate::persistence::rdb;
+use crate::pubsub::PubSub;
+use crate::tracking::Tracking;
+use crate::resp::value::Value;
 use crate::replication::handshake::handshake;
 
+/// The connection a replica receives its master's commands on.
+const MASTER_TOKEN: Token = Token(1);
+
 #[derive(Debug)]
 struct Config {
     dir: String,
     dbfilename: String,
+    /// Memory limit in bytes; 0 means none.
+    maxmemory: usize,
+    maxmemory_policy: EvictionPolicy,
+    maxmemory_samples: usize,
+    databases: usize,
+    /// The longest bulk string a client may send.
+    proto_max_bulk_len: usize,
+    /// The most arguments a client's command may have.
+    proto_max_multibulk_len: usize,
+    /// How many bytes of incomplete commands a client may have buffered
+    /// before it is disconnected.
+    client_query_buffer_limit: usize,
+    /// The keyspace event classes published, as `notify::parse` gives them.
+    notify_keyspace_events: u16,
+}
+
+/// Parses a memory size as `redis.conf` writes them: bytes, optionally
+/// followed by a `k`/`kb`/`m`/`mb`/`g`/`gb` unit.
+fn parse_memory(value: &str) -> Option<usize> {
+    let value = value.to_lowercase();
+    let digits = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let multiplier = match &value[digits..] {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    value[..digits].parse::<usize>().ok()?.checked_mul(multiplier)
+}
+
+fn memory_arg(value: &str) -> Result<usize, String> {
+    parse_memory(value).ok_or_else(|| format!("invalid memory size '{}'", value))
+}
+
+fn notify_arg(value: &str) -> Result<u16, String> {
+    notify::parse(value).ok_or_else(|| format!("invalid event classes '{}'", value))
 }
 
 #[derive(Debug, Clone)]
@@ -31,6 +90,44 @@ struct WaitState {
     start_time: Instant,
     acks_received: usize,
 }
+#[derive(Debug)]
+struct ClientState {
+    /// The RESP version negotiated with `HELLO`.
+    protocol: u8,
+    /// The name set with `CLIENT SETNAME` or `HELLO ... SETNAME`.
+    name: Option<Vec<u8>>,
+    /// The database selected with `SELECT`.
+    db: usize,
+    /// The transaction opened with `MULTI`, until `EXEC` or `DISCARD`.
+    multi: Option<Transaction>,
+    /// Keys under `WATCH`, with their database and version at the time.
+    watched: Vec<(usize, Vec<u8>, u64)>,
+    /// Bytes read from the connection that don't yet make up a whole
+    /// command.
+    query_buffer: Vec<u8>,
+}
+
+impl Default for ClientState {
+    fn default() -> Self {
+        Self {
+            protocol: 2,
+            name: None,
+            db: 0,
+            multi: None,
+            watched: Vec::new(),
+            query_buffer: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Transaction {
+    /// The queued commands, each with the table entry it resolved to.
+    commands: Vec<(Value, Module, &'static table::Command)>,
+    /// Set when a command couldn't be queued, making `EXEC` fail.
+    aborted: bool,
+}
+
 #[derive(Debug)]
 struct ServerInfo {
     role: String,
@@ -40,8 +137,62 @@ struct ServerInfo {
     master_repl_offset: usize,
     replicas: HashMap<Token, usize>,
     waiting: Option<WaitState>,
+    clients: HashMap<Token, ClientState>,
+    blocked: HashMap<Token, BlockedRead>,
+    evicted_keys: usize,
+    /// The database replicas last saw selected in the replication stream;
+    /// `None` until a `SELECT` has been sent to every one of them.
+    repl_db: Option<usize>,
     config: Config,
+    pubsub: PubSub,
+    tracking: Tracking,
+}
+
+impl ServerInfo {
+    fn new(
+        role: String,
+        master_host: Option<String>,
+        master_port: Option<String>,
+        config: Config,
+    ) -> Self {
+        Self {
+            role,
+            master_host,
+            master_port,
+            master_replid: "8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb".to_string(),
+            master_repl_offset: 0,
+            replicas: HashMap::new(),
+            waiting: None,
+            clients: HashMap::new(),
+            blocked: HashMap::new(),
+            evicted_keys: 0,
+            repl_db: None,
+            config,
+            pubsub: PubSub::default(),
+            tracking: Tracking::default(),
+        }
+    }
 }
+
+/// The defaults `Cli` gives, for tests.
+#[cfg(test)]
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            dir: ".".to_string(),
+            dbfilename: "dump.rdb".to_string(),
+            maxmemory: 0,
+            maxmemory_policy: EvictionPolicy::NoEviction,
+            maxmemory_samples: 5,
+            databases: 16,
+            proto_max_bulk_len: 512 * 1024 * 1024,
+            proto_max_multibulk_len: 1024 * 1024,
+            client_query_buffer_limit: 1024 * 1024 * 1024,
+            notify_keyspace_events: 0,
+        }
+    }
+}
+
 #[derive(Parser)]
 struct Cli {
     #[arg(short = 'p', long = "port", default_value = "6379")]
@@ -55,9 +206,35 @@ struct Cli {
 
     #[arg(long = "dbfilename")]
     dbfilename: String,
+
+    #[arg(long = "maxmemory", default_value = "0", value_parser = memory_arg)]
+    maxmemory: usize,
+
+    #[arg(long = "maxmemory-policy", default_value = "noeviction")]
+    maxmemory_policy: EvictionPolicy,
+
+    #[arg(long = "maxmemory-samples", default_value = "5")]
+    maxmemory_samples: usize,
+
+    #[arg(long = "databases", default_value = "16", value_parser = clap::value_parser!(u16).range(1..))]
+    databases: u16,
+
+    #[arg(long = "notify-keyspace-events", default_value = "", value_parser = notify_arg)]
+    notify_keyspace_events: u16,
+
+    #[arg(long = "proto-max-bulk-len", default_value = "512mb", value_parser = memory_arg)]
+    proto_max_bulk_len: usize,
+
+    #[arg(long = "proto-max-multibulk-len", default_value = "1048576")]
+    proto_max_multibulk_len: usize,
+
+    #[arg(long = "client-query-buffer-limit", default_value = "1gb", value_parser = memory_arg)]
+    client_query_buffer_limit: usize,
 }
 
-fn connect_master(server_info: &ServerInfo) -> Result<TcpStream, std::io::Error> {
+fn connect_master(
+    server_info: &ServerInfo,
+) -> Result<(TcpStream, Vec<u8>, Vec<u8>), std::io::Error> {
     let host = match &server_info.master_host {
         Some(host) => host,
         None => "localhost",
@@ -68,13 +245,25 @@ fn connect_master(server_info: &ServerInfo) -> Result<TcpStream, std::io::Error>
     };
     let addr = format!("{}:{}", host, port);
     let address = addr.to_socket_addrs()?.next().unwrap();
-    let mut stream: TcpStream = TcpStream::connect(address)?;
+    let stream: TcpStream = TcpStream::connect(address)?;
 
     stream.set_nonblocking(false)?;
-    stream = handshake(stream)?;
+    let (stream, rdb, rest) = handshake(stream)?;
     stream.set_nonblocking(true)?;
 
-    Ok(stream)
+    Ok((stream, rdb, rest))
+}
+
+fn load_dump(config: &Config, dbs: &mut [Cache]) {
+    let path = Path::new(&config.dir).join(&config.dbfilename);
+    match fs::read(&path) {
+        Ok(bytes) => match rdb::load(&bytes, dbs) {
+            Ok(()) => println!("Loaded {}", path.display()),
+            Err(e) => println!("Error loading {}: {}", path.display(), e),
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => println!("Error reading {}: {}", path.display(), e),
+    }
 }
 
 fn main() {
@@ -91,41 +280,49 @@ fn main() {
         }
         None => "master".to_string(),
     };
-    let master_replid = "8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb".to_string();
-    let master_repl_offset = 0usize;
     let config = Config {
         dir: cli.dir,
         dbfilename: cli.dbfilename,
+        maxmemory: cli.maxmemory,
+        maxmemory_policy: cli.maxmemory_policy,
+        maxmemory_samples: cli.maxmemory_samples,
+        databases: cli.databases as usize,
+        proto_max_bulk_len: cli.proto_max_bulk_len,
+        proto_max_multibulk_len: cli.proto_max_multibulk_len,
+        client_query_buffer_limit: cli.client_query_buffer_limit,
+        notify_keyspace_events: cli.notify_keyspace_events,
     };
 
-    let server_info = ServerInfo {
-        role,
-        master_host,
-        master_port,
-        master_replid,
-        master_repl_offset,
-        replicas: HashMap::new(),
-        waiting: None,
-        config,
-    };
+    let mut server_info = ServerInfo::new(role, master_host, master_port, config);
 
     let master_connection = match server_info.role.as_str() {
-        "slave" => match connect_master(&server_info) {
-            Ok(conn) => Some(conn),
-            Err(_e) => None,
-        },
+        "slave" => connect_master(&server_info).ok(),
         _ => None,
     };
 
     let address = format!("127.0.0.1:{}", cli.port);
-    let db = Cache::new();
+    let mut dbs: Vec<Cache> = (0..server_info.config.databases)
+        .map(|_| Cache::new())
+        .collect();
     let connections = match master_connection {
-        Some(conn) => HashMap::from([(Token(1), mio::net::TcpStream::from_std(conn))]),
-        None => HashMap::new(),
+        Some((conn, snapshot, rest)) => {
+            if let Err(e) = rdb::load(&snapshot, &mut dbs) {
+                println!("Error loading snapshot from master: {}", e);
+            }
+            // Commands sent right behind the snapshot run with the first
+            // ones read from the connection.
+            server_info.clients.entry(MASTER_TOKEN).or_default().query_buffer = rest;
+            HashMap::from([(MASTER_TOKEN, mio::net::TcpStream::from_std(conn))])
+        }
+        None => {
+            load_dump(&server_info.config, &mut dbs);
+            HashMap::new()
+        }
     };
+    handler::configure_dbs(&mut dbs, &server_info);
     let mut event_loop = EventLoop::new(&address, server_info, connections);
 
-    match event_loop.run(db, tcp_handler) {
+    match event_loop.run(dbs, tcp_handler) {
         Ok(()) => println!("The event_loop ran successfully!"),
         Err(e) => println!("Error running event_loop: {}", e),
     }
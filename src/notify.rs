@@ -0,0 +1,97 @@
+//! Keyspace notifications, enabled with `notify-keyspace-events`. Writes
+//! record an event in their `Cache`, and the handler publishes each one to
+//! `__keyspace@<db>__:<key>` and `__keyevent@<db>__:<event>`.
+
+pub const KEYSPACE: u16 = 1 << 0;
+pub const KEYEVENT: u16 = 1 << 1;
+pub const GENERIC: u16 = 1 << 2;
+pub const STRING: u16 = 1 << 3;
+pub const SET: u16 = 1 << 5;
+pub const EXPIRED: u16 = 1 << 8;
+pub const EVICTED: u16 = 1 << 9;
+pub const STREAM: u16 = 1 << 10;
+pub const NEW: u16 = 1 << 13;
+
+// Classes nothing here raises: types and modules this server doesn't
+// have, and key misses. They are accepted so values written for Redis work.
+const LIST: u16 = 1 << 4;
+const HASH: u16 = 1 << 6;
+const ZSET: u16 = 1 << 7;
+const KEY_MISS: u16 = 1 << 11;
+const MODULE: u16 = 1 << 12;
+
+/// The classes `A` stands for, which leave out key misses and new keys.
+const ALL: u16 = GENERIC | STRING | LIST | SET | HASH | ZSET | EXPIRED | EVICTED | STREAM | MODULE;
+
+/// Each class with its character, in the order Redis writes them.
+const CLASSES: [(char, u16); 14] = [
+    ('g', GENERIC),
+    ('$', STRING),
+    ('l', LIST),
+    ('s', SET),
+    ('h', HASH),
+    ('z', ZSET),
+    ('x', EXPIRED),
+    ('e', EVICTED),
+    ('t', STREAM),
+    ('d', MODULE),
+    ('K', KEYSPACE),
+    ('E', KEYEVENT),
+    ('m', KEY_MISS),
+    ('n', NEW),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub event: &'static str,
+    pub key: Vec<u8>,
+}
+
+/// Whether `flags` publish events of `class`, which also needs at least
+/// one of the keyspace and keyevent channels.
+pub fn enabled(flags: u16, class: u16) -> bool {
+    flags & class != 0 && flags & (KEYSPACE | KEYEVENT) != 0
+}
+
+/// Parses a `notify-keyspace-events` value such as `KEA` or `Ex`.
+pub fn parse(value: &str) -> Option<u16> {
+    value.chars().try_fold(0, |flags, c| {
+        let class = match c {
+            'A' => ALL,
+            _ => CLASSES.iter().find(|(name, _)| *name == c)?.1,
+        };
+        Some(flags | class)
+    })
+}
+
+pub fn format(flags: u16) -> String {
+    let mut value = String::new();
+    let mut classes = &CLASSES[..];
+    if flags & ALL == ALL {
+        value.push('A');
+        classes = &CLASSES[10..];
+    }
+    for (name, class) in classes {
+        if flags & class != 0 {
+            value.push(*name);
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format() {
+        assert_eq!(parse(""), Some(0));
+        assert_eq!(parse("KEA"), Some(KEYSPACE | KEYEVENT | ALL));
+        assert_eq!(parse("Ex"), Some(KEYEVENT | EXPIRED));
+        assert_eq!(parse("Kq"), None);
+        assert_eq!(format(parse("AEK").unwrap()), "AKE");
+        assert_eq!(format(parse("xE$g").unwrap()), "g$xE");
+        assert_eq!(format(parse("Agsn").unwrap()), "An");
+        assert_eq!(format(0), "");
+    }
+}
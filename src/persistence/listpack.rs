@@ -0,0 +1,202 @@
+//! The listpack format Redis uses for small aggregates and stream nodes:
+//! a `u32` total size and `u16` element count, the entries, each followed by
+//! its length encoded backwards, and a `0xFF` terminator.
+
+const HEADER_SIZE: usize = 6;
+const EOF: u8 = 0xFF;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry {
+    Int(i64),
+    Str(Vec<u8>),
+}
+
+impl Entry {
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Entry::Int(n) => Some(*n),
+            Entry::Str(s) => std::str::from_utf8(s).ok()?.parse().ok(),
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Entry::Int(n) => n.to_string().into_bytes(),
+            Entry::Str(s) => s,
+        }
+    }
+}
+
+pub fn encode(entries: &[Entry]) -> Vec<u8> {
+    let mut out = vec![0; HEADER_SIZE];
+    for entry in entries {
+        let start = out.len();
+        match entry {
+            Entry::Int(n) => encode_int(*n, &mut out),
+            Entry::Str(s) => encode_str(s, &mut out),
+        }
+        let len = out.len() - start;
+        encode_backlen(len, &mut out);
+    }
+    out.push(EOF);
+
+    let total = out.len() as u32;
+    let count = u16::try_from(entries.len()).unwrap_or(u16::MAX);
+    out[..4].copy_from_slice(&total.to_le_bytes());
+    out[4..6].copy_from_slice(&count.to_le_bytes());
+    out
+}
+
+fn encode_int(n: i64, out: &mut Vec<u8>) {
+    if (0..=127).contains(&n) {
+        out.push(n as u8);
+    } else if (-4096..=4095).contains(&n) {
+        let v = (n as u64) & 0x1FFF;
+        out.push(0xC0 | (v >> 8) as u8);
+        out.push(v as u8);
+    } else if i16::try_from(n).is_ok() {
+        out.push(0xF1);
+        out.extend_from_slice(&(n as i16).to_le_bytes());
+    } else if (-(1 << 23)..(1 << 23)).contains(&n) {
+        out.push(0xF2);
+        out.extend_from_slice(&(n as i32).to_le_bytes()[..3]);
+    } else if i32::try_from(n).is_ok() {
+        out.push(0xF3);
+        out.extend_from_slice(&(n as i32).to_le_bytes());
+    } else {
+        out.push(0xF4);
+        out.extend_from_slice(&n.to_le_bytes());
+    }
+}
+
+fn encode_str(s: &[u8], out: &mut Vec<u8>) {
+    let len = s.len();
+    if len < 64 {
+        out.push(0x80 | len as u8);
+    } else if len < 4096 {
+        out.push(0xE0 | (len >> 8) as u8);
+        out.push(len as u8);
+    } else {
+        out.push(0xF0);
+        out.extend_from_slice(&(len as u32).to_le_bytes());
+    }
+    out.extend_from_slice(s);
+}
+
+/// Writes `len` seven bits at a time, most significant group first, so
+/// it can be read back from the end of the entry.
+fn encode_backlen(len: usize, out: &mut Vec<u8>) {
+    let size = backlen_size(len);
+    for i in (0..size).rev() {
+        let group = ((len >> (7 * i)) & 127) as u8;
+        match i == size - 1 {
+            true => out.push(group),
+            false => out.push(group | 128),
+        }
+    }
+}
+
+fn backlen_size(len: usize) -> usize {
+    match len {
+        0..=127 => 1,
+        128..=16383 => 2,
+        16384..=2097151 => 3,
+        2097152..=268435455 => 4,
+        _ => 5,
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> Option<Vec<Entry>> {
+    let total = u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?) as usize;
+    if total != bytes.len() || bytes.last() != Some(&EOF) {
+        return None;
+    }
+
+    let mut entries = Vec::new();
+    let mut pos = HEADER_SIZE;
+    while *bytes.get(pos)? != EOF {
+        let (entry, len) = decode_entry(&bytes[pos..])?;
+        entries.push(entry);
+        pos += len + backlen_size(len);
+    }
+    Some(entries)
+}
+
+/// Decodes the entry at the start of `bytes`, returning it with its encoded
+/// length (not counting the backlen).
+fn decode_entry(bytes: &[u8]) -> Option<(Entry, usize)> {
+    let first = *bytes.first()?;
+    let int = |size: usize| -> Option<i64> {
+        let raw = bytes.get(1..1 + size)?;
+        let mut buf = [0u8; 8];
+        buf[..size].copy_from_slice(raw);
+        // Sign-extend from `size` bytes.
+        let shift = 64 - 8 * size as u32;
+        Some(i64::from_le_bytes(buf) << shift >> shift)
+    };
+    let string = |offset: usize, len: usize| -> Option<Entry> {
+        Some(Entry::Str(bytes.get(offset..offset + len)?.to_vec()))
+    };
+
+    match first {
+        0x00..=0x7F => Some((Entry::Int(first as i64), 1)),
+        0x80..=0xBF => {
+            let len = (first & 0x3F) as usize;
+            Some((string(1, len)?, 1 + len))
+        }
+        0xC0..=0xDF => {
+            let v = (((first & 0x1F) as i64) << 8) | *bytes.get(1)? as i64;
+            let v = if v >= 1 << 12 { v - (1 << 13) } else { v };
+            Some((Entry::Int(v), 2))
+        }
+        0xE0..=0xEF => {
+            let len = (((first & 0x0F) as usize) << 8) | *bytes.get(1)? as usize;
+            Some((string(2, len)?, 2 + len))
+        }
+        0xF0 => {
+            let len = u32::from_le_bytes(bytes.get(1..5)?.try_into().ok()?) as usize;
+            Some((string(5, len)?, 5 + len))
+        }
+        0xF1 => Some((Entry::Int(int(2)?), 3)),
+        0xF2 => Some((Entry::Int(int(3)?), 4)),
+        0xF3 => Some((Entry::Int(int(4)?), 5)),
+        0xF4 => Some((Entry::Int(int(8)?), 9)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let entries = vec![
+            Entry::Int(0),
+            Entry::Int(127),
+            Entry::Int(-1),
+            Entry::Int(4095),
+            Entry::Int(-4096),
+            Entry::Int(30000),
+            Entry::Int(-(1 << 23)),
+            Entry::Int(i32::MAX as i64),
+            Entry::Int(i64::MIN),
+            Entry::Str(b"".to_vec()),
+            Entry::Str(b"field".to_vec()),
+            Entry::Str(vec![b'x'; 200]),
+            Entry::Str(vec![b'y'; 5000]),
+        ];
+        let encoded = encode(&entries);
+        assert_eq!(decode(&encoded), Some(entries));
+    }
+
+    #[test]
+    fn test_known_encoding() {
+        // lpNew() followed by lpAppend("a") and lpAppendInteger(1).
+        let encoded = encode(&[Entry::Str(b"a".to_vec()), Entry::Int(1)]);
+        assert_eq!(
+            encoded,
+            vec![12, 0, 0, 0, 2, 0, 0x81, b'a', 2, 0x01, 1, 0xFF]
+        );
+    }
+}
@@ -0,0 +1,62 @@
+//! LZF decompression, for strings Redis compressed when saving with
+//! `rdbcompression yes`. We never compress on save.
+
+/// The most output a byte of input can produce: a three-byte back
+/// reference copies up to 264 bytes.
+const MAX_RATIO: usize = 88;
+
+/// Decompresses `input`, which must expand to exactly `expected_len` bytes.
+/// The length comes from the payload, so it is only trusted as far as the
+/// input could actually produce.
+pub fn decompress(input: &[u8], expected_len: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(expected_len.min(input.len().saturating_mul(MAX_RATIO)));
+    let mut ip = 0;
+    while ip < input.len() {
+        let ctrl = input[ip] as usize;
+        ip += 1;
+        if ctrl < 32 {
+            // Literal run of ctrl + 1 bytes.
+            let run = input.get(ip..ip + ctrl + 1)?;
+            out.extend_from_slice(run);
+            ip += ctrl + 1;
+        } else {
+            // Back reference: copy `len + 2` bytes from earlier output.
+            let mut len = ctrl >> 5;
+            if len == 7 {
+                len += *input.get(ip)? as usize;
+                ip += 1;
+            }
+            let offset = ((ctrl & 0x1F) << 8) + *input.get(ip)? as usize + 1;
+            ip += 1;
+            let start = out.len().checked_sub(offset)?;
+            for i in 0..len + 2 {
+                out.push(out[start + i]);
+            }
+        }
+        if out.len() > expected_len {
+            return None;
+        }
+    }
+    (out.len() == expected_len).then_some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decompress() {
+        // "abcabcabcabc": a 3-byte literal, then a 9-byte back reference at
+        // distance 3.
+        let input = [0x02, b'a', b'b', b'c', 0xE0, 0x00, 0x02];
+        assert_eq!(decompress(&input, 12), Some(b"abcabcabcabc".to_vec()));
+        assert_eq!(decompress(&input, 11), None);
+    }
+
+    #[test]
+    fn test_huge_declared_length() {
+        let input = [0x02, b'a', b'b', b'c', 0xE0, 0x00, 0x02];
+        assert_eq!(decompress(&input, 1 << 40), None);
+        assert_eq!(decompress(&input, usize::MAX), None);
+    }
+}
@@ -1 +1,4 @@
-pub mod rdb;
\ No newline at end of file
+pub mod crc64;
+pub mod listpack;
+pub mod lzf;
+pub mod rdb;
@@ -1,19 +1,701 @@
-use hex;
+//! Reading and writing RDB snapshots (format version 11), used for the
+//! dump file, `SAVE` and full resynchronisation of replicas.
 
-const HEX_DATA: &str = "524544495330303131fa0972656469732d76657205372e322e30fa0a72656469732d62697473c040fa056374696d65c26d08bc65fa08757365642d6d656dc2b0c41000fa08616f662d62617365c000fff06e3bfec0ff5aa2";
+use super::crc64::crc64;
+use super::listpack::{self, Entry};
+use super::lzf;
+use crate::cache::{now_ms, Cache, Data, Members};
+use crate::stream::{
+    Consumer, ConsumerGroup, Fields, PendingEntry, Stream, StreamId, NODE_MAX_ENTRIES,
+};
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
 
-pub struct RDB {
-    data: String,
+const MAGIC: &[u8] = b"REDIS0011";
+const RDB_VERSION: u16 = 11;
+
+const OPCODE_AUX: u8 = 0xFA;
+const OPCODE_RESIZEDB: u8 = 0xFB;
+const OPCODE_EXPIRETIME_MS: u8 = 0xFC;
+const OPCODE_EXPIRETIME: u8 = 0xFD;
+const OPCODE_SELECTDB: u8 = 0xFE;
+const OPCODE_EOF: u8 = 0xFF;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_SET: u8 = 2;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_STREAM_LISTPACKS: u8 = 15;
+const TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const TYPE_SET_LISTPACK: u8 = 20;
+const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+const ENC_INT8: u8 = 0;
+const ENC_INT16: u8 = 1;
+const ENC_INT32: u8 = 2;
+const ENC_LZF: u8 = 3;
+
+const STREAM_ITEM_FLAG_DELETED: i64 = 1;
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 2;
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("Bad RDB: {}", msg))
+}
+
+/// Serialises every database, each introduced by `SELECTDB`; empty ones
+/// are left out.
+pub fn save(dbs: &[Cache]) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    write_aux(&mut out, b"redis-ver", b"7.2.0");
+    write_aux(&mut out, b"redis-bits", b"64");
+    write_aux(&mut out, b"ctime", (now_ms() / 1000).to_string().as_bytes());
+
+    let now = now_ms();
+    for (index, db) in dbs.iter().enumerate() {
+        let entries: Vec<_> = db
+            .iter()
+            .filter(|(_, _, expiry)| expiry.is_none_or(|expiry| expiry > now))
+            .collect();
+        if entries.is_empty() {
+            continue;
+        }
+        out.push(OPCODE_SELECTDB);
+        write_len(&mut out, index as u64);
+        out.push(OPCODE_RESIZEDB);
+        write_len(&mut out, entries.len() as u64);
+        write_len(
+            &mut out,
+            entries.iter().filter(|(_, _, e)| e.is_some()).count() as u64,
+        );
+
+        for (key, data, expiry) in entries {
+            if let Some(at) = expiry {
+                out.push(OPCODE_EXPIRETIME_MS);
+                out.extend_from_slice(&at.to_le_bytes());
+            }
+            out.push(value_type(data));
+            write_string(&mut out, key);
+            encode_value(data, &mut out);
+        }
+    }
+
+    out.push(OPCODE_EOF);
+    // A zero checksum tells readers that checksumming is disabled.
+    out.extend_from_slice(&[0; 8]);
+    out
+}
+
+/// Loads a snapshot into `dbs`, skipping keys that have already expired.
+pub fn load(bytes: &[u8], dbs: &mut [Cache]) -> Result<(), Error> {
+    let mut reader = Reader { bytes, pos: 0 };
+    let magic = reader.take(9)?;
+    if !magic.starts_with(b"REDIS") {
+        return Err(invalid("wrong signature"));
+    }
+
+    let mut selected = 0;
+    let mut expiry_ms = None;
+    loop {
+        match reader.byte()? {
+            OPCODE_AUX => {
+                reader.string()?;
+                reader.string()?;
+            }
+            OPCODE_SELECTDB => {
+                selected = reader.len()? as usize;
+                if selected >= dbs.len() {
+                    return Err(invalid("database index out of range"));
+                }
+            }
+            OPCODE_RESIZEDB => {
+                reader.len()?;
+                reader.len()?;
+            }
+            OPCODE_EXPIRETIME_MS => expiry_ms = Some(reader.u64_le()?),
+            OPCODE_EXPIRETIME => {
+                let secs = u32::from_le_bytes(reader.take(4)?.try_into().unwrap());
+                expiry_ms = Some(secs as u64 * 1000);
+            }
+            OPCODE_EOF => return Ok(()),
+            value_type => {
+                let key = reader.string()?;
+                let data = decode_value(value_type, &mut reader)?;
+                let expiry = expiry_ms.take();
+                if expiry.is_some_and(|at| at <= now_ms()) {
+                    continue;
+                }
+                dbs[selected].insert(key, data, expiry);
+            }
+        }
+    }
+}
+
+pub fn value_type(data: &Data) -> u8 {
+    match data {
+        Data::String(_) => TYPE_STRING,
+        Data::Set(_) => TYPE_SET,
+        Data::Stream(_) => TYPE_STREAM_LISTPACKS_3,
+    }
 }
 
-impl RDB {
-    pub fn new() -> Self {
-        Self {
-            data: String::from(HEX_DATA),
+/// Writes the payload of a value, without its type byte.
+pub fn encode_value(data: &Data, out: &mut Vec<u8>) {
+    match data {
+        Data::String(value) => write_string(out, value),
+        Data::Set(set) => {
+            write_len(out, set.len() as u64);
+            for member in set {
+                write_string(out, member);
+            }
         }
+        Data::Stream(stream) => encode_stream(stream, out),
     }
+}
+
+/// Reads the payload of a value of type `value_type`.
+pub fn decode_value(value_type: u8, reader: &mut Reader) -> Result<Data, Error> {
+    match value_type {
+        TYPE_STRING => Ok(Data::String(reader.string()?)),
+        TYPE_SET => {
+            let len = reader.len()?;
+            let mut set = Members::new();
+            for _ in 0..len {
+                set.insert(reader.string()?);
+            }
+            Ok(Data::Set(set))
+        }
+        TYPE_SET_INTSET => {
+            let blob = reader.string()?;
+            Ok(Data::Set(decode_intset(&blob)?))
+        }
+        TYPE_SET_LISTPACK => {
+            let blob = reader.string()?;
+            let entries = listpack::decode(&blob).ok_or_else(|| invalid("listpack"))?;
+            Ok(Data::Set(
+                entries.into_iter().map(Entry::into_bytes).collect(),
+            ))
+        }
+        TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => {
+            Ok(Data::Stream(decode_stream(value_type, reader)?))
+        }
+        other => Err(invalid(&format!("unsupported value type {}", other))),
+    }
+}
+
+/// Serialises one value for `DUMP`: its type byte and payload, then the
+/// RDB version and a CRC64 of everything before it, little-endian.
+pub fn dump(data: &Data) -> Vec<u8> {
+    let mut out = vec![value_type(data)];
+    encode_value(data, &mut out);
+    out.extend_from_slice(&RDB_VERSION.to_le_bytes());
+    let checksum = crc64(&out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    out
+}
+
+/// Whether a `DUMP` payload is intact and from an RDB version we can read.
+pub fn verify_dump(payload: &[u8]) -> bool {
+    let Some(body_len) = payload.len().checked_sub(10) else {
+        return false;
+    };
+    let (body, trailer) = payload.split_at(body_len + 2);
+    let version = u16::from_le_bytes([body[body_len], body[body_len + 1]]);
+    version <= RDB_VERSION && crc64(body).to_le_bytes() == trailer
+}
+
+/// Decodes a payload that passed `verify_dump`.
+pub fn load_dump(payload: &[u8]) -> Result<Data, Error> {
+    let body = &payload[..payload.len() - 10];
+    let mut reader = Reader {
+        bytes: body,
+        pos: 0,
+    };
+    let value_type = reader.byte()?;
+    let data = decode_value(value_type, &mut reader)?;
+    if reader.pos != body.len() {
+        return Err(invalid("trailing bytes after value"));
+    }
+    Ok(data)
+}
+
+fn write_aux(out: &mut Vec<u8>, key: &[u8], value: &[u8]) {
+    out.push(OPCODE_AUX);
+    write_string(out, key);
+    write_string(out, value);
+}
+
+fn write_len(out: &mut Vec<u8>, len: u64) {
+    if len < 1 << 6 {
+        out.push(len as u8);
+    } else if len < 1 << 14 {
+        out.extend_from_slice(&(0x4000 | len as u16).to_be_bytes());
+    } else if len <= u32::MAX as u64 {
+        out.push(0x80);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        out.push(0x81);
+        out.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+/// Writes a string, using the compact integer encodings for short
+/// canonical integers like Redis does.
+fn write_string(out: &mut Vec<u8>, s: &[u8]) {
+    let int = std::str::from_utf8(s)
+        .ok()
+        .and_then(|s| s.parse::<i32>().ok())
+        .filter(|n| n.to_string().as_bytes() == s);
+    match int {
+        Some(n) if i8::try_from(n).is_ok() => {
+            out.push(0xC0 | ENC_INT8);
+            out.push(n as i8 as u8);
+        }
+        Some(n) if i16::try_from(n).is_ok() => {
+            out.push(0xC0 | ENC_INT16);
+            out.extend_from_slice(&(n as i16).to_le_bytes());
+        }
+        Some(n) => {
+            out.push(0xC0 | ENC_INT32);
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        None => {
+            write_len(out, s.len() as u64);
+            out.extend_from_slice(s);
+        }
+    }
+}
+
+fn write_id(out: &mut Vec<u8>, id: StreamId) {
+    write_len(out, id.ms);
+    write_len(out, id.seq);
+}
+
+fn encode_stream(stream: &Stream, out: &mut Vec<u8>) {
+    let entries: Vec<(&StreamId, &Fields)> = stream.entries.iter().collect();
+    write_len(out, entries.chunks(NODE_MAX_ENTRIES).len() as u64);
+    for node in entries.chunks(NODE_MAX_ENTRIES) {
+        let (master_id, master_fields) = node[0];
+        write_string(out, &master_id.to_be_bytes());
+        write_string(out, &encode_node(*master_id, master_fields, node));
+    }
+
+    write_len(out, stream.len() as u64);
+    write_id(out, stream.last_id);
+    write_id(out, stream.first_id);
+    write_id(out, stream.max_deleted_entry_id);
+    write_len(out, stream.entries_added);
+
+    write_len(out, stream.groups.len() as u64);
+    for (name, group) in &stream.groups {
+        write_string(out, name);
+        write_id(out, group.last_id);
+        write_len(out, group.entries_read.unwrap_or(u64::MAX));
+
+        write_len(out, group.pending.len() as u64);
+        for (id, entry) in &group.pending {
+            out.extend_from_slice(&id.to_be_bytes());
+            out.extend_from_slice(&entry.delivery_time.to_le_bytes());
+            write_len(out, entry.delivery_count);
+        }
+
+        write_len(out, group.consumers.len() as u64);
+        for (name, consumer) in &group.consumers {
+            write_string(out, name);
+            out.extend_from_slice(&consumer.seen_time.to_le_bytes());
+            let active_time = consumer.active_time.unwrap_or(u64::MAX);
+            out.extend_from_slice(&active_time.to_le_bytes());
+            write_len(out, consumer.pending.len() as u64);
+            for id in &consumer.pending {
+                out.extend_from_slice(&id.to_be_bytes());
+            }
+        }
+    }
+}
+
+/// Lays out one radix tree node: a master entry holding the first entry's
+/// field names, then every entry delta-encoded against the master ID.
+fn encode_node(
+    master_id: StreamId,
+    master_fields: &Fields,
+    node: &[(&StreamId, &Fields)],
+) -> Vec<u8> {
+    let mut lp = vec![
+        Entry::Int(node.len() as i64),
+        Entry::Int(0),
+        Entry::Int(master_fields.len() as i64),
+    ];
+    lp.extend(
+        master_fields
+            .iter()
+            .map(|(field, _)| Entry::Str(field.clone())),
+    );
+    lp.push(Entry::Int(0));
+
+    for (id, fields) in node {
+        let same_fields = fields.len() == master_fields.len()
+            && fields
+                .iter()
+                .zip(master_fields)
+                .all(|((a, _), (b, _))| a == b);
+        let flags = match same_fields {
+            true => STREAM_ITEM_FLAG_SAMEFIELDS,
+            false => 0,
+        };
+        lp.push(Entry::Int(flags));
+        lp.push(Entry::Int(id.ms.wrapping_sub(master_id.ms) as i64));
+        lp.push(Entry::Int(id.seq.wrapping_sub(master_id.seq) as i64));
+        if same_fields {
+            lp.extend(fields.iter().map(|(_, value)| Entry::Str(value.clone())));
+            lp.push(Entry::Int(fields.len() as i64 + 3));
+        } else {
+            lp.push(Entry::Int(fields.len() as i64));
+            for (field, value) in fields.iter() {
+                lp.push(Entry::Str(field.clone()));
+                lp.push(Entry::Str(value.clone()));
+            }
+            lp.push(Entry::Int(fields.len() as i64 * 2 + 4));
+        }
+    }
+    listpack::encode(&lp)
+}
+
+fn int(lp: &mut std::vec::IntoIter<Entry>) -> Result<i64, Error> {
+    lp.next()
+        .and_then(|e| e.as_int())
+        .ok_or_else(|| invalid("stream listpack"))
+}
+
+fn bytes(lp: &mut std::vec::IntoIter<Entry>) -> Result<Vec<u8>, Error> {
+    lp.next()
+        .map(Entry::into_bytes)
+        .ok_or_else(|| invalid("stream listpack"))
+}
+
+fn decode_node(master_id: StreamId, lp: Vec<Entry>, stream: &mut Stream) -> Result<(), Error> {
+    let mut lp = lp.into_iter();
+    let count = int(&mut lp)?;
+    let deleted = int(&mut lp)?;
+    let num_master_fields = int(&mut lp)?;
+    let mut master_fields = Vec::new();
+    for _ in 0..num_master_fields {
+        master_fields.push(bytes(&mut lp)?);
+    }
+    int(&mut lp)?;
+
+    for _ in 0..count + deleted {
+        let flags = int(&mut lp)?;
+        let ms = master_id.ms.wrapping_add(int(&mut lp)? as u64);
+        let seq = master_id.seq.wrapping_add(int(&mut lp)? as u64);
+        let mut fields = Vec::new();
+        if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+            for field in &master_fields {
+                fields.push((field.clone(), bytes(&mut lp)?));
+            }
+        } else {
+            for _ in 0..int(&mut lp)? {
+                fields.push((bytes(&mut lp)?, bytes(&mut lp)?));
+            }
+        }
+        int(&mut lp)?;
+        if flags & STREAM_ITEM_FLAG_DELETED == 0 {
+            stream.entries.insert(StreamId::new(ms, seq), fields);
+        }
+    }
+    Ok(())
+}
+
+fn decode_stream(value_type: u8, reader: &mut Reader) -> Result<Stream, Error> {
+    let mut stream = Stream::new();
+    for _ in 0..reader.len()? {
+        let key = reader.string()?;
+        let master_id = StreamId::from_be_bytes(&key).ok_or_else(|| invalid("stream node key"))?;
+        let blob = reader.string()?;
+        let lp = listpack::decode(&blob).ok_or_else(|| invalid("listpack"))?;
+        decode_node(master_id, lp, &mut stream)?;
+    }
+
+    let length = reader.len()?;
+    stream.last_id = reader.id()?;
+    if value_type >= TYPE_STREAM_LISTPACKS_2 {
+        stream.first_id = reader.id()?;
+        stream.max_deleted_entry_id = reader.id()?;
+        stream.entries_added = reader.len()?;
+    } else {
+        stream.first_id = stream
+            .entries
+            .keys()
+            .next()
+            .copied()
+            .unwrap_or(StreamId::MIN);
+        stream.entries_added = length;
+    }
+
+    for _ in 0..reader.len()? {
+        let name = reader.string()?;
+        let last_id = reader.id()?;
+        let entries_read = match value_type >= TYPE_STREAM_LISTPACKS_2 {
+            true => Some(reader.len()?).filter(|n| *n != u64::MAX),
+            false => stream.estimate_entries_read(last_id),
+        };
+        let mut group = ConsumerGroup::new(last_id, entries_read);
+
+        let mut pending = BTreeMap::new();
+        for _ in 0..reader.len()? {
+            let id = reader.raw_id()?;
+            let delivery_time = reader.u64_le()?;
+            let delivery_count = reader.len()?;
+            pending.insert(id, (delivery_time, delivery_count));
+        }
+
+        for _ in 0..reader.len()? {
+            let consumer_name = reader.string()?;
+            let seen_time = reader.u64_le()?;
+            let active_time = match value_type >= TYPE_STREAM_LISTPACKS_3 {
+                true => Some(reader.u64_le()?).filter(|t| *t != u64::MAX),
+                false => Some(seen_time),
+            };
+            let mut consumer = Consumer::new(seen_time);
+            consumer.active_time = active_time;
+            for _ in 0..reader.len()? {
+                let id = reader.raw_id()?;
+                let (delivery_time, delivery_count) = pending
+                    .get(&id)
+                    .ok_or_else(|| invalid("consumer PEL entry"))?;
+                group.pending.insert(
+                    id,
+                    PendingEntry {
+                        consumer: consumer_name.clone(),
+                        delivery_time: *delivery_time,
+                        delivery_count: *delivery_count,
+                    },
+                );
+                consumer.pending.insert(id);
+            }
+            group.consumers.insert(consumer_name, consumer);
+        }
+        stream.groups.insert(name, group);
+    }
+    Ok(stream)
+}
+
+fn decode_intset(blob: &[u8]) -> Result<Members, Error> {
+    let header = blob.get(..8).ok_or_else(|| invalid("intset"))?;
+    let width = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let len = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+    if ![2, 4, 8].contains(&width) || blob.len() != 8 + width * len {
+        return Err(invalid("intset"));
+    }
+    Ok(blob[8..]
+        .chunks(width)
+        .map(|chunk| {
+            let n = match width {
+                2 => i16::from_le_bytes(chunk.try_into().unwrap()) as i64,
+                4 => i32::from_le_bytes(chunk.try_into().unwrap()) as i64,
+                _ => i64::from_le_bytes(chunk.try_into().unwrap()),
+            };
+            n.to_string().into_bytes()
+        })
+        .collect())
+}
+
+/// A cursor over RDB bytes.
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+enum Length {
+    Len(u64),
+    Encoded(u8),
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn take(&mut self, n: usize) -> Result<&[u8], Error> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|end| *end <= self.bytes.len());
+        let end = end.ok_or_else(|| invalid("unexpected end of file"))?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u64_le(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn length(&mut self) -> Result<Length, Error> {
+        let first = self.byte()?;
+        match first >> 6 {
+            0 => Ok(Length::Len((first & 0x3F) as u64)),
+            1 => Ok(Length::Len(
+                (((first & 0x3F) as u64) << 8) | self.byte()? as u64,
+            )),
+            2 => match first {
+                0x80 => Ok(Length::Len(
+                    u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as u64,
+                )),
+                0x81 => Ok(Length::Len(u64::from_be_bytes(
+                    self.take(8)?.try_into().unwrap(),
+                ))),
+                _ => Err(invalid("length encoding")),
+            },
+            _ => Ok(Length::Encoded(first & 0x3F)),
+        }
+    }
+
+    fn len(&mut self) -> Result<u64, Error> {
+        match self.length()? {
+            Length::Len(len) => Ok(len),
+            Length::Encoded(_) => Err(invalid("expected a length")),
+        }
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>, Error> {
+        match self.length()? {
+            Length::Len(len) => Ok(self.take(len as usize)?.to_vec()),
+            Length::Encoded(ENC_INT8) => Ok((self.byte()? as i8).to_string().into_bytes()),
+            Length::Encoded(ENC_INT16) => {
+                let n = i16::from_le_bytes(self.take(2)?.try_into().unwrap());
+                Ok(n.to_string().into_bytes())
+            }
+            Length::Encoded(ENC_INT32) => {
+                let n = i32::from_le_bytes(self.take(4)?.try_into().unwrap());
+                Ok(n.to_string().into_bytes())
+            }
+            Length::Encoded(ENC_LZF) => {
+                let compressed_len = self.len()? as usize;
+                let len = self.len()? as usize;
+                let compressed = self.take(compressed_len)?;
+                lzf::decompress(compressed, len).ok_or_else(|| invalid("LZF string"))
+            }
+            Length::Encoded(_) => Err(invalid("string encoding")),
+        }
+    }
+
+    fn id(&mut self) -> Result<StreamId, Error> {
+        Ok(StreamId::new(self.len()?, self.len()?))
+    }
+
+    fn raw_id(&mut self) -> Result<StreamId, Error> {
+        StreamId::from_be_bytes(self.take(16)?).ok_or_else(|| invalid("stream ID"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMPTY_RDB: &str = "524544495330303131fa0972656469732d76657205372e322e30fa0a72656469732d62697473c040fa056374696d65c26d08bc65fa08757365642d6d656dc2b0c41000fa08616f662d62617365c000fff06e3bfec0ff5aa2";
+
+    fn from_hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_load_redis_snapshot() {
+        let mut dbs = [Cache::new()];
+        load(&from_hex(EMPTY_RDB), &mut dbs).unwrap();
+        assert!(dbs[0].keys().is_empty());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut db = Cache::new();
+        db.insert(b"string".to_vec(), b"value".to_vec(), None);
+        db.insert(b"number".to_vec(), b"-1234".to_vec(), None);
+        db.insert(
+            b"expiring".to_vec(),
+            b"soon".to_vec(),
+            Some(now_ms() + 60_000),
+        );
+        let set = db.get_or_create_set(b"set").unwrap();
+        set.insert(b"a".to_vec());
+        set.insert(b"1".to_vec());
+
+        let mut stream = Stream::new();
+        for i in 1..=150u64 {
+            let field = match i % 2 {
+                0 => b"even".to_vec(),
+                _ => b"odd".to_vec(),
+            };
+            stream.add(
+                StreamId::new(i, i % 3),
+                vec![(field, i.to_string().into_bytes())],
+            );
+        }
+        stream.delete(&StreamId::new(10, 1));
+        let mut group = ConsumerGroup::new(StreamId::new(2, 2), Some(2));
+        group.consumer(b"alice", 1000);
+        group.assign(StreamId::new(1, 1), b"alice", 1000, 1);
+        group.consumer(b"bob", 2000);
+        stream.groups.insert(b"g".to_vec(), group);
+        let mut other = Cache::new();
+        other.insert(b"stream".to_vec(), Data::Stream(stream), None);
+        let mut dbs = [db, Cache::new(), other];
+
+        let mut loaded = [Cache::new(), Cache::new(), Cache::new()];
+        load(&save(&dbs), &mut loaded).unwrap();
+        for (db, loaded) in dbs.iter_mut().zip(loaded.iter_mut()) {
+            for key in db.keys() {
+                assert_eq!(loaded.get_data(&key), db.get_data(&key).cloned().as_ref());
+                assert_eq!(loaded.expiry(&key), db.expiry(&key));
+            }
+            assert_eq!(loaded.keys().len(), db.keys().len());
+        }
+        assert_eq!(loaded[0].keys().len(), 4);
+        assert_eq!(loaded[2].keys().len(), 1);
+
+        let mut too_few = [Cache::new(), Cache::new()];
+        assert!(load(&save(&dbs), &mut too_few).is_err());
+    }
+
+    #[test]
+    fn test_dump_payload() {
+        // The `DUMP` of "10" from the Redis documentation, RDB version 9.
+        let payload = from_hex("00c00a0900be6d06895a28000a");
+        assert!(verify_dump(&payload));
+        assert_eq!(load_dump(&payload).unwrap(), Data::String(b"10".to_vec()));
+        let ours = dump(&Data::String(b"10".to_vec()));
+        assert_eq!(ours[..3], payload[..3]);
+        assert!(verify_dump(&ours));
+
+        let set = Data::Set(Members::from([b"a".to_vec(), b"b".to_vec()]));
+        assert_eq!(load_dump(&dump(&set)).unwrap(), set);
+
+        let mut corrupt = payload.clone();
+        corrupt[2] ^= 1;
+        assert!(!verify_dump(&corrupt));
+        let mut corrupt = payload.clone();
+        corrupt[12] ^= 1;
+        assert!(!verify_dump(&corrupt));
+        let mut newer = ours.clone();
+        newer[3] = 12;
+        let checksum = crc64(&newer[..5]);
+        newer[5..].copy_from_slice(&checksum.to_le_bytes());
+        assert!(!verify_dump(&newer));
+        assert!(!verify_dump(b"short"));
+    }
+
+    #[test]
+    fn test_expired_keys_are_skipped() {
+        let mut out = MAGIC.to_vec();
+        out.push(OPCODE_EXPIRETIME_MS);
+        out.extend_from_slice(&1000u64.to_le_bytes());
+        out.push(TYPE_STRING);
+        write_string(&mut out, b"old");
+        write_string(&mut out, b"value");
+        out.push(OPCODE_EOF);
 
-    pub fn to_binary(&self) -> Result<Vec<u8>, hex::FromHexError> {
-        Ok(hex::decode(&self.data)?)
+        let mut dbs = [Cache::new()];
+        load(&out, &mut dbs).unwrap();
+        assert_eq!(dbs[0].get(b"old"), Ok(None));
     }
 }
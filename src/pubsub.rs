@@ -0,0 +1,285 @@
+//! The Pub/Sub registry: which connections are subscribed to which channels
+//! and patterns.
+
+use crate::commands::bulk;
+use crate::glob;
+use crate::resp::value::Value;
+use mio::Token;
+use std::collections::{HashMap, HashSet};
+
+/// The three namespaces a client can subscribe in. Shard channels are
+/// separate from ordinary ones even though, without a cluster, every
+/// channel lives on this one shard.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Channel,
+    Pattern,
+    Shard,
+}
+
+#[derive(Debug, Default)]
+pub struct PubSub {
+    channels: HashMap<Vec<u8>, HashSet<Token>>,
+    patterns: HashMap<Vec<u8>, HashSet<Token>>,
+    shard_channels: HashMap<Vec<u8>, HashSet<Token>>,
+    /// Each client's subscriptions, in the order it made them.
+    clients: HashMap<Token, Subscriptions>,
+}
+
+#[derive(Debug, Default)]
+struct Subscriptions {
+    channels: Vec<Vec<u8>>,
+    patterns: Vec<Vec<u8>>,
+    shard_channels: Vec<Vec<u8>>,
+}
+
+impl Subscriptions {
+    fn of(&self, kind: Kind) -> &Vec<Vec<u8>> {
+        match kind {
+            Kind::Channel => &self.channels,
+            Kind::Pattern => &self.patterns,
+            Kind::Shard => &self.shard_channels,
+        }
+    }
+
+    fn of_mut(&mut self, kind: Kind) -> &mut Vec<Vec<u8>> {
+        match kind {
+            Kind::Channel => &mut self.channels,
+            Kind::Pattern => &mut self.patterns,
+            Kind::Shard => &mut self.shard_channels,
+        }
+    }
+}
+
+impl PubSub {
+    fn registry(&self, kind: Kind) -> &HashMap<Vec<u8>, HashSet<Token>> {
+        match kind {
+            Kind::Channel => &self.channels,
+            Kind::Pattern => &self.patterns,
+            Kind::Shard => &self.shard_channels,
+        }
+    }
+
+    fn registry_mut(&mut self, kind: Kind) -> &mut HashMap<Vec<u8>, HashSet<Token>> {
+        match kind {
+            Kind::Channel => &mut self.channels,
+            Kind::Pattern => &mut self.patterns,
+            Kind::Shard => &mut self.shard_channels,
+        }
+    }
+
+    /// The count subscription replies carry: channels and patterns
+    /// together, or shard channels on their own.
+    pub fn count(&self, kind: Kind, client: Token) -> usize {
+        self.clients.get(&client).map_or(0, |subs| match kind {
+            Kind::Shard => subs.shard_channels.len(),
+            _ => subs.channels.len() + subs.patterns.len(),
+        })
+    }
+
+    pub fn is_subscribed(&self, client: Token) -> bool {
+        self.clients.contains_key(&client)
+    }
+
+    pub fn subscribe(&mut self, kind: Kind, client: Token, name: &[u8]) {
+        let subscribers = self.registry_mut(kind).entry(name.to_vec()).or_default();
+        if subscribers.insert(client) {
+            let subs = self.clients.entry(client).or_default();
+            subs.of_mut(kind).push(name.to_vec());
+        }
+    }
+
+    pub fn unsubscribe(&mut self, kind: Kind, client: Token, name: &[u8]) {
+        if !remove(self.registry_mut(kind), name, client) {
+            return;
+        }
+        if let Some(subs) = self.clients.get_mut(&client) {
+            subs.of_mut(kind).retain(|n| n != name);
+            let idle = subs.channels.is_empty()
+                && subs.patterns.is_empty()
+                && subs.shard_channels.is_empty();
+            if idle {
+                self.clients.remove(&client);
+            }
+        }
+    }
+
+    /// Drops every subscription `client` has, once it has disconnected.
+    pub fn remove_client(&mut self, client: Token) {
+        let Some(subs) = self.clients.remove(&client) else {
+            return;
+        };
+        for kind in [Kind::Channel, Kind::Pattern, Kind::Shard] {
+            for name in subs.of(kind) {
+                remove(self.registry_mut(kind), name, client);
+            }
+        }
+    }
+
+    pub fn subscriptions(&self, kind: Kind, client: Token) -> Vec<Vec<u8>> {
+        self.clients
+            .get(&client)
+            .map_or_else(Vec::new, |subs| subs.of(kind).clone())
+    }
+
+    /// The messages a `PUBLISH` delivers: a `message` to each subscriber of
+    /// `channel` and a `pmessage` per matching pattern.
+    pub fn messages(&self, channel: &[u8], message: &[u8]) -> Vec<(Token, Value)> {
+        let mut messages = Vec::new();
+        if let Some(clients) = self.channels.get(channel) {
+            let push = Value::Push(vec![bulk(b"message"), bulk(channel), bulk(message)]);
+            messages.extend(clients.iter().map(|client| (*client, push.clone())));
+        }
+        for (pattern, clients) in &self.patterns {
+            if !glob::matches(pattern, channel) {
+                continue;
+            }
+            let push = Value::Push(vec![
+                bulk(b"pmessage"),
+                bulk(pattern),
+                bulk(channel),
+                bulk(message),
+            ]);
+            messages.extend(clients.iter().map(|client| (*client, push.clone())));
+        }
+        messages
+    }
+
+    /// The `smessage`s an `SPUBLISH` delivers.
+    pub fn shard_messages(&self, channel: &[u8], message: &[u8]) -> Vec<(Token, Value)> {
+        let Some(clients) = self.shard_channels.get(channel) else {
+            return Vec::new();
+        };
+        let push = Value::Push(vec![bulk(b"smessage"), bulk(channel), bulk(message)]);
+        clients
+            .iter()
+            .map(|client| (*client, push.clone()))
+            .collect()
+    }
+
+    /// Channels of `kind` with at least one subscriber, optionally only
+    /// those matching `pattern`.
+    pub fn active_channels(&self, kind: Kind, pattern: Option<&[u8]>) -> Vec<Vec<u8>> {
+        self.registry(kind)
+            .keys()
+            .filter(|channel| pattern.is_none_or(|pattern| glob::matches(pattern, channel)))
+            .cloned()
+            .collect()
+    }
+
+    pub fn numsub(&self, kind: Kind, channel: &[u8]) -> usize {
+        self.registry(kind).get(channel).map_or(0, HashSet::len)
+    }
+
+    pub fn numpat(&self) -> usize {
+        self.patterns.len()
+    }
+}
+
+/// Removes `client` from the subscribers of `name`, dropping the entry once
+/// it has none. Returns whether it was subscribed.
+fn remove(registry: &mut HashMap<Vec<u8>, HashSet<Token>>, name: &[u8], client: Token) -> bool {
+    let Some(clients) = registry.get_mut(name) else {
+        return false;
+    };
+    let removed = clients.remove(&client);
+    if clients.is_empty() {
+        registry.remove(name);
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscriptions() {
+        let mut pubsub = PubSub::default();
+        let (a, b) = (Token(2), Token(3));
+        pubsub.subscribe(Kind::Channel, a, b"news");
+        pubsub.subscribe(Kind::Channel, a, b"news");
+        pubsub.subscribe(Kind::Channel, a, b"sport");
+        pubsub.subscribe(Kind::Pattern, a, b"n*");
+        pubsub.subscribe(Kind::Channel, b, b"news");
+        assert_eq!(pubsub.count(Kind::Channel, a), 3);
+        assert_eq!(pubsub.numsub(Kind::Channel, b"news"), 2);
+        assert_eq!(pubsub.numpat(), 1);
+        assert_eq!(
+            pubsub.subscriptions(Kind::Channel, a),
+            vec![b"news".to_vec(), b"sport".to_vec()]
+        );
+
+        let mut messages = pubsub.messages(b"news", b"hi");
+        messages.sort_by_key(|(client, value)| (*client, format!("{:?}", value)));
+        assert_eq!(
+            messages,
+            vec![
+                (
+                    a,
+                    Value::Push(vec![bulk(b"message"), bulk(b"news"), bulk(b"hi")])
+                ),
+                (
+                    a,
+                    Value::Push(vec![
+                        bulk(b"pmessage"),
+                        bulk(b"n*"),
+                        bulk(b"news"),
+                        bulk(b"hi")
+                    ])
+                ),
+                (
+                    b,
+                    Value::Push(vec![bulk(b"message"), bulk(b"news"), bulk(b"hi")])
+                ),
+            ]
+        );
+        assert!(pubsub.messages(b"weather", b"hi").is_empty());
+
+        pubsub.unsubscribe(Kind::Channel, a, b"news");
+        pubsub.unsubscribe(Kind::Channel, a, b"sport");
+        pubsub.unsubscribe(Kind::Channel, b, b"news");
+        assert!(pubsub.active_channels(Kind::Channel, None).is_empty());
+        assert!(pubsub.is_subscribed(a));
+        pubsub.unsubscribe(Kind::Pattern, a, b"n*");
+        assert!(!pubsub.is_subscribed(a));
+        assert_eq!(pubsub.numpat(), 0);
+    }
+
+    #[test]
+    fn test_shard_channels() {
+        let mut pubsub = PubSub::default();
+        let client = Token(2);
+        pubsub.subscribe(Kind::Shard, client, b"orders");
+        pubsub.subscribe(Kind::Channel, client, b"orders");
+        assert_eq!(pubsub.count(Kind::Shard, client), 1);
+        assert_eq!(pubsub.count(Kind::Channel, client), 1);
+        assert_eq!(
+            pubsub.shard_messages(b"orders", b"1"),
+            vec![(
+                client,
+                Value::Push(vec![bulk(b"smessage"), bulk(b"orders"), bulk(b"1")])
+            )]
+        );
+        pubsub.unsubscribe(Kind::Channel, client, b"orders");
+        assert!(pubsub.is_subscribed(client));
+        assert!(pubsub.messages(b"orders", b"1").is_empty());
+        pubsub.unsubscribe(Kind::Shard, client, b"orders");
+        assert!(!pubsub.is_subscribed(client));
+    }
+
+    #[test]
+    fn test_remove_client() {
+        let mut pubsub = PubSub::default();
+        let (a, b) = (Token(2), Token(3));
+        pubsub.subscribe(Kind::Channel, a, b"news");
+        pubsub.subscribe(Kind::Pattern, a, b"n*");
+        pubsub.subscribe(Kind::Shard, a, b"orders");
+        pubsub.subscribe(Kind::Channel, b, b"news");
+        pubsub.remove_client(a);
+        assert!(!pubsub.is_subscribed(a));
+        assert_eq!(pubsub.numsub(Kind::Channel, b"news"), 1);
+        assert_eq!(pubsub.numpat(), 0);
+        assert!(pubsub.active_channels(Kind::Shard, None).is_empty());
+    }
+}
@@ -0,0 +1,32 @@
+use std::cell::Cell;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// xorshift64* — plenty for picking random members and keys, and the server is
+// single-threaded so a thread-local state is all we need.
+thread_local! {
+    static STATE: Cell<u64> = Cell::new(seed());
+}
+
+fn seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    nanos | 1
+}
+
+pub fn next_u64() -> u64 {
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    })
+}
+
+/// Returns a random index in `0..n`. `n` must be non-zero.
+pub fn below(n: usize) -> usize {
+    (next_u64() % n as u64) as usize
+}
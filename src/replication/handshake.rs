@@ -1,52 +1,54 @@
-use crate::resp::parser;
-use crate::resp::value::CRLF_OFFSET;
+use crate::resp::parser::{self, RespError};
+use crate::resp::value::{Value, CRLF_OFFSET};
 use std::io::{Read, Write};
 use std::net::TcpStream;
 
-pub fn handshake(mut stream: TcpStream) -> Result<TcpStream, std::io::Error> {
+/// Connects to the master as a replica, returning the stream, the RDB
+/// snapshot to load and whatever the master sent after the snapshot.
+pub fn handshake(
+    mut stream: TcpStream,
+) -> Result<(TcpStream, Vec<u8>, Vec<u8>), std::io::Error> {
     stream.write_all(b"*1\r\n$4\r\nPING\r\n")?;
     stream.flush()?;
     let response = read_response(&mut stream)?;
 
-    if response != b"+PONG\r\n" {
-        Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "Expected PONG",
-        ))?;
+    if response != Value::SimpleString("PONG".to_string()) {
+        Err(std::io::Error::other("Expected PONG"))?;
     }
     stream.write_all(b"*3\r\n$8\r\nREPLCONF\r\n$14\r\nlistening-port\r\n$4\r\n6380\r\n")?;
     stream.flush()?;
     let response = read_response(&mut stream)?;
-    if response != b"+OK\r\n" {
-        Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "REPLCONF listening-port failed",
-        ))?;
+    if response != Value::SimpleString("OK".to_string()) {
+        Err(std::io::Error::other("REPLCONF listening-port failed"))?;
     }
     stream.write_all(b"*3\r\n$8\r\nREPLCONF\r\n$4\r\ncapa\r\n$6\r\npsync2\r\n")?;
     stream.flush()?;
     let response = read_response(&mut stream)?;
-    if response != b"+OK\r\n" {
-        Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "REPLCONF capa failed",
-        ))?;
+    if response != Value::SimpleString("OK".to_string()) {
+        Err(std::io::Error::other("REPLCONF capa failed"))?;
     }
 
     stream.write_all(b"*3\r\n$5\r\nPSYNC\r\n$1\r\n?\r\n$2\r\n-1\r\n")?;
     stream.flush()?;
-    execute_psync(&mut stream)?;
-    Ok(stream)
+    let (rdb, rest) = execute_psync(&mut stream)?;
+    Ok((stream, rdb, rest))
 }
 
-fn execute_psync(stream: &mut TcpStream) -> Result<(), std::io::Error> {
+/// Reads the `FULLRESYNC` reply and the RDB snapshot that follows it,
+/// returning the snapshot and any bytes read past it: the start of the
+/// command stream, which must not be lost.
+fn execute_psync(stream: &mut TcpStream) -> Result<(Vec<u8>, Vec<u8>), std::io::Error> {
     let mut buffer = [0; 512];
     let mut full_buffer = Vec::new();
     let mut offset = 0;
-    let mut _rdb_file = None;
-    while _rdb_file == None {
-        let n = stream.read(&mut buffer[0..])?;
-        full_buffer.extend_from_slice(&buffer[..n]);
+    loop {
+        if offset >= full_buffer.len() {
+            let n = stream.read(&mut buffer[0..])?;
+            if n == 0 {
+                Err(std::io::Error::other("Connection closed during PSYNC"))?;
+            }
+            full_buffer.extend_from_slice(&buffer[..n]);
+        }
 
         match full_buffer[offset] {
             b'+' => {
@@ -58,23 +60,61 @@ fn execute_psync(stream: &mut TcpStream) -> Result<(), std::io::Error> {
                 offset += bytes + CRLF_OFFSET;
             }
             b'$' => {
+                if parser::_find_crlf(&full_buffer[offset..]).is_none() {
+                    let n = stream.read(&mut buffer[0..])?;
+                    full_buffer.extend_from_slice(&buffer[..n]);
+                    continue;
+                }
                 let (bytes, count) =
-                    parser::_parse_element_length(&full_buffer[offset + 1..].to_vec());
-                if full_buffer[offset + bytes..].len() >= count {
-                    let response = full_buffer[offset..].to_vec();
-                    _rdb_file = Some(response);
-                    break;
+                    parser::_parse_element_length(&full_buffer[offset + 1..], "bulk")
+                        .map_err(|e| std::io::Error::other(e.to_string()))?;
+                let start = offset + 1 + bytes;
+                if full_buffer.len() >= start + count {
+                    let rest = full_buffer.split_off(start + count);
+                    return Ok((full_buffer.split_off(start), rest));
                 }
-                continue;
+                let n = stream.read(&mut buffer[0..])?;
+                full_buffer.extend_from_slice(&buffer[..n]);
             }
             _ => offset += 1,
         }
     }
-    Ok(())
 }
 
-fn read_response(stream: &mut TcpStream) -> Result<Vec<u8>, std::io::Error> {
+/// Reads one reply from the master, however many reads it arrives in.
+fn read_response(stream: &mut TcpStream) -> Result<Value, std::io::Error> {
     let mut buffer = [0; 512];
-    let n = stream.read(&mut buffer)?;
-    Ok(buffer[..n].to_vec())
+    let mut response = Vec::new();
+    loop {
+        let n = stream.read(&mut buffer)?;
+        if n == 0 {
+            Err(std::io::Error::other("Connection closed during handshake"))?;
+        }
+        response.extend_from_slice(&buffer[..n]);
+        match parser::parse(&response) {
+            Ok((_, reply)) => return Ok(reply),
+            Err(RespError::Incomplete) => continue,
+            Err(e) => Err(std::io::Error::other(e.to_string()))?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_psync_keeps_commands_after_snapshot() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut replica = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut master, _) = listener.accept().unwrap();
+        master
+            .write_all(b"+FULLRESYNC abc 0\r\n$5\r\nREDIS*1\r\n$4\r\nPING\r\n")
+            .unwrap();
+
+        let (snapshot, rest) = execute_psync(&mut replica).unwrap();
+        assert_eq!(snapshot, b"REDIS");
+        assert_eq!(rest, b"*1\r\n$4\r\nPING\r\n");
+    }
 }
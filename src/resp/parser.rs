@@ -1,224 +1,469 @@
 use super::value::*;
 use num_bigint::BigInt;
+use std::fmt;
+
+/// Why a buffer couldn't be parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RespError {
+    /// The buffer ends partway through a value; more input may complete it.
+    Incomplete,
+    /// The input isn't valid RESP, whatever follows.
+    Protocol(String),
+}
 
-pub fn parse(buffer: Vec<u8>) -> (usize, Value) {
-    if buffer.is_empty() {
-        return (0, Value::SimpleError("INVALID BUFFER".to_string()));
+impl fmt::Display for RespError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RespError::Incomplete => write!(f, "Protocol error: incomplete value"),
+            RespError::Protocol(reason) => write!(f, "Protocol error: {}", reason),
+        }
     }
+}
 
-    match buffer[0] {
-        b'+' => _parse_simple_string(1usize, buffer),
-        b'-' => _parse_simple_error(1usize, buffer),
-        b':' => _parse_integer(1usize, buffer),
-        b'$' => _parse_bulk_string(1usize, buffer),
-        b'*' => _parse_array(1usize, buffer),
-        b'_' => _parse_null(1usize, buffer),
-        b'#' => _parse_boolean(1usize, buffer),
-        b',' => _parse_double(1usize, buffer),
-        b'(' => _parse_big_number(1usize, buffer),
-        b'!' => _parse_bulk_error(1usize, buffer),
-        _ => (0, Value::SimpleString("INVALID".to_string())),
-    }
+fn protocol_error<T>(reason: &str) -> Result<T, RespError> {
+    Err(RespError::Protocol(reason.to_string()))
 }
 
-pub(crate) fn _find_crlf(buffer: &Vec<u8>) -> Option<usize> {
-    buffer.windows(2).position(|window| window == CRLF)
+/// Parses the value at the start of `buffer`, returning it with the number
+/// of bytes it took up.
+pub fn parse(buffer: &[u8]) -> Result<(usize, Value), RespError> {
+    _parse_value(0, buffer)
 }
 
-pub(crate) fn _parse_element_length(buffer: &Vec<u8>) -> (usize, usize) {
-    let mut bytes_consumed = match _find_crlf(&buffer) {
-        Some(pos) => pos,
-        None => buffer.len(),
+/// Parses the value starting `bytes_consumed` bytes into `buffer`,
+/// returning the offset just past it. Nested values are parsed in place
+/// rather than from copies of what is left, so each byte is looked at
+/// once however long a pipeline gets.
+fn _parse_value(bytes_consumed: usize, buffer: &[u8]) -> Result<(usize, Value), RespError> {
+    let Some(&kind) = buffer.get(bytes_consumed) else {
+        return Err(RespError::Incomplete);
     };
-    let count = std::str::from_utf8(&buffer[..bytes_consumed])
-        .unwrap()
-        .to_string();
-    match count.parse::<usize>() {
-        Ok(i) => {
-            bytes_consumed += CRLF_OFFSET;
-            (bytes_consumed, i)
-        }
-        Err(_) => (bytes_consumed, count.parse::<usize>().unwrap()),
+    let bytes_consumed = bytes_consumed + 1;
+
+    match kind {
+        b'+' => _parse_simple_string(bytes_consumed, buffer),
+        b'-' => _parse_simple_error(bytes_consumed, buffer),
+        b':' => _parse_integer(bytes_consumed, buffer),
+        b'$' => _parse_bulk_string(bytes_consumed, buffer),
+        b'*' => _parse_array(bytes_consumed, buffer),
+        b'_' => _parse_null(bytes_consumed, buffer),
+        b'#' => _parse_boolean(bytes_consumed, buffer),
+        b',' => _parse_double(bytes_consumed, buffer),
+        b'(' => _parse_big_number(bytes_consumed, buffer),
+        b'!' => _parse_bulk_error(bytes_consumed, buffer),
+        b'=' => _parse_verbatim_string(bytes_consumed, buffer),
+        b'%' => _parse_map(bytes_consumed, buffer),
+        b'|' => _parse_attribute(bytes_consumed, buffer),
+        b'~' => _parse_set(bytes_consumed, buffer),
+        b'>' => _parse_push(bytes_consumed, buffer),
+        other => Err(RespError::Protocol(format!(
+            "unknown type byte '{}'",
+            other.escape_ascii()
+        ))),
     }
 }
 
-fn _parse_simple_string(mut bytes_consumed: usize, buffer_: Vec<u8>) -> (usize, Value) {
-    let buffer = &buffer_[bytes_consumed..].to_vec();
+/// How long an inline command or a length line may get without its line
+/// ending before the client is taken to be misbehaving, as in Redis.
+pub const PROTO_INLINE_MAX_SIZE: usize = 64 * 1024;
+
+/// Bounds on the requests a client may send, so a claimed length can't make
+/// the server wait for, and buffer, more than it is willing to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// The longest bulk string argument (`proto-max-bulk-len`).
+    pub max_bulk_len: usize,
+    /// The most arguments a command may have (`proto-max-multibulk-len`).
+    pub max_multibulk_len: usize,
+}
 
-    let pos = match _find_crlf(&buffer) {
-        Some(pos) => pos,
-        None => buffer.len(),
-    };
-    bytes_consumed += pos;
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_bulk_len: 512 * 1024 * 1024,
+            max_multibulk_len: 1024 * 1024,
+        }
+    }
+}
 
-    let parsed = Value::SimpleString(std::str::from_utf8(&buffer[0..pos]).unwrap().to_string());
-    bytes_consumed += CRLF_OFFSET;
-    (bytes_consumed, parsed)
+/// Parses a client's request the way Redis reads one: an array of bulk
+/// strings, or anything not starting with `*` as an inline command. A
+/// blank line or an empty array parses as an empty array.
+pub fn parse_request(buffer: &[u8], limits: &Limits) -> Result<(usize, Value), RespError> {
+    match buffer.first() {
+        None => Err(RespError::Incomplete),
+        Some(b'*') => _parse_multibulk(buffer, limits),
+        Some(_) => _parse_inline(buffer),
+    }
 }
 
-fn _parse_simple_error(mut bytes_consumed: usize, buffer_: Vec<u8>) -> (usize, Value) {
-    let buffer = &buffer_[bytes_consumed..].to_vec();
+/// Parses a request array one length line at a time, so a length over
+/// `limits` is refused as soon as it arrives rather than once its data has.
+fn _parse_multibulk(buffer: &[u8], limits: &Limits) -> Result<(usize, Value), RespError> {
+    if buffer[1..].starts_with(b"-1\r\n") {
+        return Ok((1 + 2 + CRLF_OFFSET, Value::Array(vec![])));
+    }
+    let (header, count) =
+        _parse_request_length(&buffer[1..], "multibulk", limits.max_multibulk_len)?;
+    let mut bytes_consumed = 1 + header;
 
-    let pos = match _find_crlf(&buffer) {
-        Some(pos) => pos,
-        None => buffer.len(),
-    };
-    bytes_consumed += pos;
+    let mut arr = Vec::new();
+    while arr.len() < count {
+        match buffer.get(bytes_consumed) {
+            None => return Err(RespError::Incomplete),
+            Some(b'$') => {}
+            Some(got) => {
+                return Err(RespError::Protocol(format!(
+                    "expected '$', got '{}'",
+                    got.escape_ascii()
+                )))
+            }
+        }
+        let (header, length) =
+            _parse_request_length(&buffer[bytes_consumed + 1..], "bulk", limits.max_bulk_len)?;
+        let start = bytes_consumed + 1 + header;
+        let end = start + length;
+        if buffer.len() < end + CRLF_OFFSET {
+            return Err(RespError::Incomplete);
+        }
+        if &buffer[end..end + CRLF_OFFSET] != CRLF {
+            return protocol_error("expected CRLF after bulk data");
+        }
+        arr.push(Value::BulkString(buffer[start..end].to_vec()));
+        bytes_consumed = end + CRLF_OFFSET;
+    }
 
-    let parsed = Value::SimpleError(std::str::from_utf8(&buffer[0..pos]).unwrap().to_string());
-    bytes_consumed += CRLF_OFFSET;
-    (bytes_consumed, parsed)
+    Ok((bytes_consumed, Value::Array(arr)))
 }
 
-fn _parse_bulk_string(mut bytes_consumed: usize, buffer_: Vec<u8>) -> (usize, Value) {
-    let buffer = buffer_[bytes_consumed..].to_vec();
-
-    if buffer[0] == b'0' {
-        bytes_consumed += 3 + CRLF_OFFSET;
-        return (bytes_consumed, Value::BulkString("".to_string()));
+/// Parses a request's length line, refusing lengths over `max` and lines
+/// that go on too long to be one.
+fn _parse_request_length(
+    buffer: &[u8],
+    kind: &str,
+    max: usize,
+) -> Result<(usize, usize), RespError> {
+    if _find_crlf(buffer).is_none() && buffer.len() > PROTO_INLINE_MAX_SIZE {
+        return Err(RespError::Protocol(format!(
+            "too big {} count string",
+            kind
+        )));
     }
-
-    if &buffer[0..2] == *b"-1" {
-        bytes_consumed += 2 + CRLF_OFFSET;
-        return (bytes_consumed, Value::Null);
+    let (bytes_consumed, length) = _parse_element_length(buffer, kind)?;
+    if length > max {
+        return Err(RespError::Protocol(format!("invalid {} length", kind)));
     }
+    Ok((bytes_consumed, length))
+}
 
-    let (start, count) = _parse_element_length(&buffer);
+pub(crate) fn _find_crlf(buffer: &[u8]) -> Option<usize> {
+    buffer.windows(2).position(|window| window == CRLF)
+}
 
-    let end = start + count;
+/// The text up to the next CRLF, and how many bytes it takes up with it.
+fn _read_line(buffer: &[u8]) -> Result<(&[u8], usize), RespError> {
+    let pos = _find_crlf(buffer).ok_or(RespError::Incomplete)?;
+    Ok((&buffer[..pos], pos + CRLF_OFFSET))
+}
 
-    let parsed = Value::BulkString(
-        std::str::from_utf8(&buffer[start..end])
-            .unwrap()
-            .to_string(),
-    );
-    bytes_consumed += end + CRLF_OFFSET;
-    (bytes_consumed, parsed)
+/// Parses the length line of a `kind` (`bulk` or `multibulk`), returning
+/// the bytes it takes up and the length.
+pub(crate) fn _parse_element_length(
+    buffer: &[u8],
+    kind: &str,
+) -> Result<(usize, usize), RespError> {
+    let (line, bytes_consumed) = _read_line(buffer)?;
+    let count = std::str::from_utf8(line)
+        .ok()
+        .and_then(|count| count.parse::<usize>().ok())
+        .ok_or_else(|| RespError::Protocol(format!("invalid {} length", kind)))?;
+    Ok((bytes_consumed, count))
 }
 
-fn _parse_integer(mut bytes_consumed: usize, buffer_: Vec<u8>) -> (usize, Value) {
-    let buffer = buffer_[bytes_consumed..].to_vec();
+/// Reads a line-based value such as a simple string or a number.
+fn _parse_line(
+    bytes_consumed: usize,
+    buffer: &[u8],
+    value: impl FnOnce(&[u8]) -> Result<Value, RespError>,
+) -> Result<(usize, Value), RespError> {
+    let (line, length) = _read_line(&buffer[bytes_consumed..])?;
+    Ok((bytes_consumed + length, value(line)?))
+}
 
-    let pos = match _find_crlf(&buffer) {
-        Some(pos) => pos,
-        None => buffer.len(),
-    };
-    bytes_consumed += pos;
-    let int_buffer = &buffer[0..pos];
+/// Reads the length-prefixed content of a bulk string, bulk error or
+/// verbatim string.
+fn _parse_blob(bytes_consumed: usize, buffer: &[u8]) -> Result<(usize, &[u8]), RespError> {
+    let buffer = &buffer[bytes_consumed..];
+    let (start, count) = _parse_element_length(buffer, "bulk")?;
+    let end = start + count;
+    if buffer.len() < end + CRLF_OFFSET {
+        return Err(RespError::Incomplete);
+    }
+    if &buffer[end..end + CRLF_OFFSET] != CRLF {
+        return protocol_error("expected CRLF after bulk data");
+    }
+    Ok((bytes_consumed + end + CRLF_OFFSET, &buffer[start..end]))
+}
 
-    let s = std::str::from_utf8(int_buffer).unwrap().to_string();
-    match s.parse::<i64>() {
-        Ok(i) => {
-            bytes_consumed += CRLF_OFFSET;
-            (bytes_consumed, Value::Integer(i))
-        }
-        Err(_) => (
-            bytes_consumed,
-            Value::SimpleError("INVALID_INTEGER".to_string()),
-        ),
+fn _parse_simple_string(bytes_consumed: usize, buffer: &[u8]) -> Result<(usize, Value), RespError> {
+    _parse_line(bytes_consumed, buffer, |line| {
+        Ok(Value::SimpleString(
+            String::from_utf8_lossy(line).to_string(),
+        ))
+    })
+}
+
+fn _parse_simple_error(bytes_consumed: usize, buffer: &[u8]) -> Result<(usize, Value), RespError> {
+    _parse_line(bytes_consumed, buffer, |line| {
+        Ok(Value::SimpleError(
+            String::from_utf8_lossy(line).to_string(),
+        ))
+    })
+}
+
+fn _parse_bulk_string(bytes_consumed: usize, buffer: &[u8]) -> Result<(usize, Value), RespError> {
+    if buffer[bytes_consumed..].starts_with(b"-1\r\n") {
+        return Ok((bytes_consumed + 2 + CRLF_OFFSET, Value::Null));
     }
+    let (bytes_consumed, content) = _parse_blob(bytes_consumed, buffer)?;
+    Ok((bytes_consumed, Value::BulkString(content.to_vec())))
 }
 
-fn _parse_array(mut bytes_consumed: usize, buffer_: Vec<u8>) -> (usize, Value) {
-    let buffer = buffer_[bytes_consumed..].to_vec();
+fn _parse_integer(bytes_consumed: usize, buffer: &[u8]) -> Result<(usize, Value), RespError> {
+    _parse_line(bytes_consumed, buffer, |line| {
+        std::str::from_utf8(line)
+            .ok()
+            .and_then(|s| s.parse::<i64>().ok())
+            .map(Value::Integer)
+            .ok_or_else(|| RespError::Protocol("invalid integer".to_string()))
+    })
+}
 
-    if buffer[0..2] == *b"-1" {
+fn _parse_array(mut bytes_consumed: usize, buffer: &[u8]) -> Result<(usize, Value), RespError> {
+    if buffer[bytes_consumed..].starts_with(b"-1\r\n") {
         bytes_consumed += 2 + CRLF_OFFSET;
-        return (bytes_consumed, Value::Null);
+        return Ok((bytes_consumed, Value::Null));
     }
 
     let mut arr = Vec::new();
 
-    let (start, count) = _parse_element_length(&buffer);
+    let (start, count) = _parse_element_length(&buffer[bytes_consumed..], "multibulk")?;
     bytes_consumed += start;
 
-    let mut i = start;
     while arr.len() < count {
-        let (last_pos, parsed_data) = parse(buffer[i..].to_vec());
-        i += last_pos;
-        bytes_consumed += last_pos;
+        let (end, parsed_data) = _parse_value(bytes_consumed, buffer)?;
+        bytes_consumed = end;
 
         arr.push(parsed_data)
     }
 
-    (bytes_consumed, Value::Array(arr))
+    Ok((bytes_consumed, Value::Array(arr)))
 }
 
-fn _parse_null(mut bytes_consumed: usize, buffer_: Vec<u8>) -> (usize, Value) {
-    let buffer = buffer_[bytes_consumed..].to_vec();
+fn _parse_set(bytes_consumed: usize, buffer: &[u8]) -> Result<(usize, Value), RespError> {
+    match _parse_array(bytes_consumed, buffer)? {
+        (bytes_consumed, Value::Array(arr)) => Ok((bytes_consumed, Value::Set(arr))),
+        parsed => Ok(parsed),
+    }
+}
 
-    let pos = match _find_crlf(&buffer) {
-        Some(pos) => pos,
-        None => buffer.len(),
-    };
-    bytes_consumed += pos + CRLF_OFFSET;
-    (bytes_consumed, Value::Null)
+fn _parse_push(bytes_consumed: usize, buffer: &[u8]) -> Result<(usize, Value), RespError> {
+    match _parse_array(bytes_consumed, buffer)? {
+        (bytes_consumed, Value::Array(arr)) => Ok((bytes_consumed, Value::Push(arr))),
+        parsed => Ok(parsed),
+    }
 }
 
-fn _parse_boolean(mut bytes_consumed: usize, buffer_: Vec<u8>) -> (usize, Value) {
-    let buffer = buffer_[bytes_consumed..].to_vec();
+/// Parses the pair count and then the pairs of a map or attribute.
+fn _parse_pairs(
+    mut bytes_consumed: usize,
+    buffer: &[u8],
+) -> Result<(usize, Vec<(Value, Value)>), RespError> {
+    let (start, count) = _parse_element_length(&buffer[bytes_consumed..], "multibulk")?;
+    bytes_consumed += start;
 
-    let parsed = if buffer[0] == b't' { true } else { false };
-    let pos = match _find_crlf(&buffer) {
-        Some(pos) => pos,
-        None => buffer.len(),
-    };
-    bytes_consumed += pos + CRLF_OFFSET;
-    (bytes_consumed, Value::Boolean(parsed))
+    let mut pairs = Vec::new();
+    while pairs.len() < count {
+        let (end, key) = _parse_value(bytes_consumed, buffer)?;
+        let (end, value) = _parse_value(end, buffer)?;
+        bytes_consumed = end;
+
+        pairs.push((key, value));
+    }
+
+    Ok((bytes_consumed, pairs))
 }
 
-fn _parse_double(mut bytes_consumed: usize, buffer_: Vec<u8>) -> (usize, Value) {
-    let buffer = buffer_[bytes_consumed..].to_vec();
+fn _parse_map(bytes_consumed: usize, buffer: &[u8]) -> Result<(usize, Value), RespError> {
+    let (bytes_consumed, pairs) = _parse_pairs(bytes_consumed, buffer)?;
+    Ok((bytes_consumed, Value::Map(pairs)))
+}
 
-    let pos = match _find_crlf(&buffer) {
-        Some(pos) => pos,
-        None => buffer.len(),
-    };
+/// An attribute is followed by the reply it describes, parsed along with it.
+fn _parse_attribute(bytes_consumed: usize, buffer: &[u8]) -> Result<(usize, Value), RespError> {
+    let (bytes_consumed, pairs) = _parse_pairs(bytes_consumed, buffer)?;
+    let (bytes_consumed, reply) = _parse_value(bytes_consumed, buffer)?;
+    Ok((bytes_consumed, Value::Attribute(pairs, Box::new(reply))))
+}
 
-    let s = std::str::from_utf8(&buffer[0..pos]).unwrap().to_string();
-    match s.parse::<f64>() {
-        Ok(i) => {
-            bytes_consumed += pos + CRLF_OFFSET;
-            (bytes_consumed, Value::Double(i))
-        }
-        Err(_) => (bytes_consumed, Value::SimpleError("INVALID".to_string())),
-    }
+fn _parse_null(bytes_consumed: usize, buffer: &[u8]) -> Result<(usize, Value), RespError> {
+    _parse_line(bytes_consumed, buffer, |line| match line {
+        b"" => Ok(Value::Null),
+        _ => protocol_error("invalid null"),
+    })
 }
 
-fn _parse_big_number(mut bytes_consumed: usize, buffer_: Vec<u8>) -> (usize, Value) {
-    let buffer = buffer_[bytes_consumed..].to_vec();
+fn _parse_boolean(bytes_consumed: usize, buffer: &[u8]) -> Result<(usize, Value), RespError> {
+    _parse_line(bytes_consumed, buffer, |line| match line {
+        b"t" => Ok(Value::Boolean(true)),
+        b"f" => Ok(Value::Boolean(false)),
+        _ => protocol_error("invalid boolean"),
+    })
+}
 
-    let pos = match _find_crlf(&buffer) {
-        Some(pos) => pos,
-        None => buffer.len(),
-    };
-    let buffer = &buffer[0..pos];
+fn _parse_double(bytes_consumed: usize, buffer: &[u8]) -> Result<(usize, Value), RespError> {
+    _parse_line(bytes_consumed, buffer, |line| {
+        std::str::from_utf8(line)
+            .ok()
+            .and_then(|s| s.parse::<f64>().ok())
+            .map(Value::Double)
+            .ok_or_else(|| RespError::Protocol("invalid double".to_string()))
+    })
+}
 
-    let s = std::str::from_utf8(buffer).unwrap().to_string();
-    match s.parse::<BigInt>() {
-        Ok(i) => {
-            bytes_consumed += pos + CRLF_OFFSET;
-            (bytes_consumed, Value::BigNumber(i))
-        }
-        Err(_) => (bytes_consumed, Value::SimpleError("INVALID".to_string())),
-    }
+fn _parse_big_number(bytes_consumed: usize, buffer: &[u8]) -> Result<(usize, Value), RespError> {
+    _parse_line(bytes_consumed, buffer, |line| {
+        std::str::from_utf8(line)
+            .ok()
+            .and_then(|s| s.parse::<BigInt>().ok())
+            .map(Value::BigNumber)
+            .ok_or_else(|| RespError::Protocol("invalid big number".to_string()))
+    })
 }
 
-fn _parse_bulk_error(mut bytes_consumed: usize, buffer_: Vec<u8>) -> (usize, Value) {
-    let buffer = buffer_[bytes_consumed..].to_vec();
+fn _parse_bulk_error(bytes_consumed: usize, buffer: &[u8]) -> Result<(usize, Value), RespError> {
+    let (bytes_consumed, content) = _parse_blob(bytes_consumed, buffer)?;
+    let parsed = Value::BulkError(String::from_utf8_lossy(content).to_string());
+    Ok((bytes_consumed, parsed))
+}
 
-    let (start, count) = _parse_element_length(&buffer);
+fn _parse_verbatim_string(
+    bytes_consumed: usize,
+    buffer: &[u8],
+) -> Result<(usize, Value), RespError> {
+    let (bytes_consumed, content) = _parse_blob(bytes_consumed, buffer)?;
+    // The text is preceded by its format and a colon, e.g. `txt:`.
+    match content.get(3) {
+        Some(b':') => Ok((
+            bytes_consumed,
+            Value::VerbatimString(
+                String::from_utf8_lossy(&content[..3]).to_string(),
+                content[4..].to_vec(),
+            ),
+        )),
+        _ => protocol_error("invalid verbatim string format"),
+    }
+}
 
-    let end = start + count;
+/// Parses an inline command, as typed into telnet or sent by a health
+/// check: one line of space-separated arguments, which may be quoted.
+fn _parse_inline(buffer: &[u8]) -> Result<(usize, Value), RespError> {
+    let Some(pos) = buffer.iter().position(|&b| b == b'\n') else {
+        if buffer.len() > PROTO_INLINE_MAX_SIZE {
+            return protocol_error("too big inline request");
+        }
+        return Err(RespError::Incomplete);
+    };
+    let line = &buffer[..pos];
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    match split_args(line) {
+        Some(args) => Ok((
+            pos + 1,
+            Value::Array(args.into_iter().map(Value::BulkString).collect()),
+        )),
+        None => protocol_error("unbalanced quotes in request"),
+    }
+}
 
-    let parsed = Value::BulkError(
-        std::str::from_utf8(&buffer[start..end])
-            .unwrap()
-            .to_string(),
-    );
+/// Splits a line into arguments the way Redis does. Double-quoted
+/// arguments understand `\n`, `\r`, `\t`, `\b`, `\a` and `\xHH` escapes,
+/// single-quoted ones only `\'`. Returns `None` if a quote isn't closed or
+/// is followed by something other than a space.
+fn split_args(line: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut args = Vec::new();
+    let mut i = 0;
+    loop {
+        while i < line.len() && line[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if i == line.len() {
+            return Some(args);
+        }
 
-    bytes_consumed += end + CRLF_OFFSET;
-    (bytes_consumed, parsed)
+        let mut arg = Vec::new();
+        let (mut in_double, mut in_single) = (false, false);
+        loop {
+            let c = line.get(i).copied();
+            if in_double {
+                match (c?, line.get(i + 1).copied()) {
+                    (b'\\', Some(b'x'))
+                        if line.len() > i + 3
+                            && line[i + 2].is_ascii_hexdigit()
+                            && line[i + 3].is_ascii_hexdigit() =>
+                    {
+                        let hex = std::str::from_utf8(&line[i + 2..i + 4]).ok()?;
+                        arg.push(u8::from_str_radix(hex, 16).ok()?);
+                        i += 3;
+                    }
+                    (b'\\', Some(escaped)) => {
+                        arg.push(match escaped {
+                            b'n' => b'\n',
+                            b'r' => b'\r',
+                            b't' => b'\t',
+                            b'b' => 0x08,
+                            b'a' => 0x07,
+                            other => other,
+                        });
+                        i += 1;
+                    }
+                    (b'"', next) => {
+                        // The closing quote must end the argument.
+                        if next.is_some_and(|next| !next.is_ascii_whitespace()) {
+                            return None;
+                        }
+                        i += 1;
+                        break;
+                    }
+                    (c, _) => arg.push(c),
+                }
+            } else if in_single {
+                match (c?, line.get(i + 1).copied()) {
+                    (b'\\', Some(b'\'')) => {
+                        arg.push(b'\'');
+                        i += 1;
+                    }
+                    (b'\'', next) => {
+                        if next.is_some_and(|next| !next.is_ascii_whitespace()) {
+                            return None;
+                        }
+                        i += 1;
+                        break;
+                    }
+                    (c, _) => arg.push(c),
+                }
+            } else {
+                match c {
+                    None => break,
+                    Some(c) if c.is_ascii_whitespace() => break,
+                    Some(b'"') => in_double = true,
+                    Some(b'\'') => in_single = true,
+                    Some(c) => arg.push(c),
+                }
+            }
+            i += 1;
+        }
+        args.push(arg);
+    }
 }
 
 #[cfg(test)]
@@ -228,7 +473,7 @@ mod tests {
     #[test]
     fn test_simple_string() {
         let input = b"+OK\r\n";
-        let (bytes, result) = parse(input.to_vec());
+        let (bytes, result) = parse(input).unwrap();
         assert_eq!(bytes, input.len());
         match result {
             Value::SimpleString(s) => assert_eq!(s, "OK"),
@@ -239,7 +484,7 @@ mod tests {
     #[test]
     fn test_simple_error() {
         let input = b"-Error message\r\n";
-        let (bytes, result) = parse(input.to_vec());
+        let (bytes, result) = parse(input).unwrap();
         assert_eq!(bytes, input.len());
         match result {
             Value::SimpleError(s) => assert_eq!(s, "Error message"),
@@ -253,7 +498,7 @@ mod tests {
         #[test]
         fn test_unsigned_integer() {
             let input = b":134445553333\r\n";
-            let (bytes, result) = parse(input.to_vec());
+            let (bytes, result) = parse(input).unwrap();
             assert_eq!(bytes, input.len());
             match result {
                 Value::Integer(s) => assert_eq!(s, 134445553333),
@@ -264,7 +509,7 @@ mod tests {
         #[test]
         fn test_positive_sign_integer() {
             let input = b":+5\r\n";
-            let (bytes, result) = parse(input.to_vec());
+            let (bytes, result) = parse(input).unwrap();
             assert_eq!(bytes, input.len());
             match result {
                 Value::Integer(s) => assert_eq!(s, 5),
@@ -275,7 +520,7 @@ mod tests {
         #[test]
         fn test_negative_sign_integer() {
             let input = b":-2\r\n";
-            let (bytes, result) = parse(input.to_vec());
+            let (bytes, result) = parse(input).unwrap();
             assert_eq!(bytes, input.len());
             match result {
                 Value::Integer(s) => assert_eq!(s, -2),
@@ -290,10 +535,10 @@ mod tests {
         #[test]
         fn test_bulk_string() {
             let input = b"$4\r\nPING\r\n";
-            let (bytes, result) = parse(input.to_vec());
+            let (bytes, result) = parse(input).unwrap();
             assert_eq!(bytes, input.len());
             match result {
-                Value::BulkString(s) => assert_eq!(s, "PING"),
+                Value::BulkString(s) => assert_eq!(s, b"PING"),
                 _ => panic!("Wrong type"),
             }
         }
@@ -301,21 +546,28 @@ mod tests {
         #[test]
         fn test_empty_string() {
             let input = b"$0\r\n\r\n";
-            let (bytes, result) = parse(input.to_vec());
+            let (bytes, result) = parse(input).unwrap();
             assert_eq!(bytes, input.len());
             match result {
-                Value::BulkString(s) => assert_eq!(s, ""),
+                Value::BulkString(s) => assert!(s.is_empty()),
                 _ => panic!("Wrong type"),
             }
         }
+        #[test]
+        fn test_binary_string() {
+            let input = b"$4\r\n\x00\xff\r\n\r\n";
+            let (bytes, result) = parse(input).unwrap();
+            assert_eq!(bytes, input.len());
+            assert_eq!(result, Value::BulkString(b"\x00\xff\r\n".to_vec()));
+            assert_eq!(result.to_resp(), input);
+        }
+
         #[test]
         fn test_null_string() {
             let input = b"$-1\r\n";
-            let (bytes, result) = parse(input.to_vec());
+            let (bytes, result) = parse(input).unwrap();
             assert_eq!(bytes, input.len());
-            match result {
-                s => assert_eq!(s, Value::Null),
-            }
+            assert_eq!(result, Value::Null);
         }
     }
 
@@ -325,12 +577,12 @@ mod tests {
         #[test]
         fn test_simple_element_array() {
             let input = b"*2\r\n$12\r\nPINGPONGPING\r\n:42\r\n";
-            let (bytes, result) = parse(input.to_vec());
+            let (bytes, result) = parse(input).unwrap();
             assert_eq!(bytes, input.len());
             match result {
                 Value::Array(arr) => {
                     let expected = vec![
-                        Value::BulkString("PINGPONGPING".to_string()),
+                        Value::BulkString(b"PINGPONGPING".to_vec()),
                         Value::Integer(42),
                     ];
                     assert_eq!(arr, expected);
@@ -342,24 +594,22 @@ mod tests {
         #[test]
         fn test_null_array() {
             let input = b"*-1\r\n";
-            let (bytes, result) = parse(input.to_vec());
+            let (bytes, result) = parse(input).unwrap();
             assert_eq!(bytes, input.len());
-            match result {
-                arr => assert_eq!(arr, Value::Null),
-            }
+            assert_eq!(result, Value::Null);
         }
 
         #[test]
         fn test_null_elements_in_array() {
             let input = b"*3\r\n$5\r\nhello\r\n$-1\r\n$5\r\nworld\r\n";
-            let (bytes, result) = parse(input.to_vec());
+            let (bytes, result) = parse(input).unwrap();
             assert_eq!(bytes, input.len());
             match result {
                 Value::Array(arr) => {
                     let expected = vec![
-                        Value::BulkString("hello".to_string()),
+                        Value::BulkString(b"hello".to_vec()),
                         Value::Null,
-                        Value::BulkString("world".to_string()),
+                        Value::BulkString(b"world".to_vec()),
                     ];
                     assert_eq!(arr, expected);
                 }
@@ -370,7 +620,7 @@ mod tests {
         #[test]
         fn test_nested_array() {
             let input = b"*2\r\n*3\r\n:1\r\n:2\r\n:3\r\n*2\r\n+Hello\r\n-World\r\n";
-            let (bytes, result) = parse(input.to_vec());
+            let (bytes, result) = parse(input).unwrap();
             assert_eq!(bytes, input.len());
             match result {
                 Value::Array(arr) => {
@@ -395,11 +645,9 @@ mod tests {
     #[test]
     fn test_null() {
         let input = b"_\r\n";
-        let (bytes, result) = parse(input.to_vec());
+        let (bytes, result) = parse(input).unwrap();
         assert_eq!(bytes, input.len());
-        match result {
-            n => assert_eq!(n, Value::Null),
-        }
+        assert_eq!(result, Value::Null);
     }
 
     mod boolean {
@@ -408,10 +656,10 @@ mod tests {
         #[test]
         fn test_true_boolean() {
             let input = b"#t\r\n";
-            let (bytes, result) = parse(input.to_vec());
+            let (bytes, result) = parse(input).unwrap();
             assert_eq!(bytes, input.len());
             match result {
-                Value::Boolean(s) => assert_eq!(s, true),
+                Value::Boolean(s) => assert!(s),
                 _ => panic!("Wrong type"),
             }
         }
@@ -419,10 +667,10 @@ mod tests {
         #[test]
         fn test_false_boolean() {
             let input = b"#f\r\n";
-            let (bytes, result) = parse(input.to_vec());
+            let (bytes, result) = parse(input).unwrap();
             assert_eq!(bytes, input.len());
             match result {
-                Value::Boolean(s) => assert_eq!(s, false),
+                Value::Boolean(s) => assert!(!s),
                 _ => panic!("Wrong type"),
             }
         }
@@ -434,7 +682,7 @@ mod tests {
         #[test]
         fn test_double() {
             let input = b",1.23\r\n";
-            let (bytes, result) = parse(input.to_vec());
+            let (bytes, result) = parse(input).unwrap();
             assert_eq!(bytes, input.len());
             match result {
                 Value::Double(s) => assert_eq!(s, 1.23),
@@ -445,7 +693,7 @@ mod tests {
         #[test]
         fn test_positive_sign_double() {
             let input = b",+2.43\r\n";
-            let (bytes, result) = parse(input.to_vec());
+            let (bytes, result) = parse(input).unwrap();
             assert_eq!(bytes, input.len());
             match result {
                 Value::Double(s) => assert_eq!(s, 2.43),
@@ -456,7 +704,7 @@ mod tests {
         #[test]
         fn test_negative_sign_double() {
             let input = b",-5.24513\r\n";
-            let (bytes, result) = parse(input.to_vec());
+            let (bytes, result) = parse(input).unwrap();
             assert_eq!(bytes, input.len());
             match result {
                 Value::Double(s) => assert_eq!(s, -5.24513),
@@ -467,7 +715,7 @@ mod tests {
         #[test]
         fn test_infinity() {
             let input = b",inf\r\n";
-            let (bytes, result) = parse(input.to_vec());
+            let (bytes, result) = parse(input).unwrap();
             assert_eq!(bytes, input.len());
             match result {
                 Value::Double(s) => assert_eq!(s, f64::INFINITY),
@@ -478,7 +726,7 @@ mod tests {
         #[test]
         fn test_negative_infinity() {
             let input = b",-inf\r\n";
-            let (bytes, result) = parse(input.to_vec());
+            let (bytes, result) = parse(input).unwrap();
             assert_eq!(bytes, input.len());
             match result {
                 Value::Double(s) => assert_eq!(s, f64::NEG_INFINITY),
@@ -486,10 +734,24 @@ mod tests {
             }
         }
 
+        #[test]
+        fn test_double_format() {
+            let encode = |d: f64| Value::Double(d).to_resp();
+            assert_eq!(encode(1.5), b",1.5\r\n");
+            assert_eq!(encode(0.1), b",0.10000000000000001\r\n");
+            assert_eq!(encode(3.0), b",3\r\n");
+            assert_eq!(encode(-0.0), b",-0\r\n");
+            assert_eq!(encode(1e17), b",1e+17\r\n");
+            assert_eq!(encode(1.25e-5), b",1.2500000000000001e-05\r\n");
+            assert_eq!(encode(123456.789), b",123456.789\r\n");
+            assert_eq!(encode(f64::NEG_INFINITY), b",-inf\r\n");
+            assert_eq!(encode(f64::NAN), b",nan\r\n");
+        }
+
         #[test]
         fn test_not_a_number() {
             let input = b",nan\r\n";
-            let (bytes, result) = parse(input.to_vec());
+            let (bytes, result) = parse(input).unwrap();
             assert_eq!(bytes, input.len());
             match result {
                 Value::Double(s) => assert!(s.is_nan()),
@@ -505,7 +767,7 @@ mod tests {
         #[test]
         fn test_big_number() {
             let input = b"(3492890328409238509324850943850943825024385\r\n";
-            let (bytes, result) = parse(input.to_vec());
+            let (bytes, result) = parse(input).unwrap();
             assert_eq!(bytes, input.len());
             match result {
                 Value::BigNumber(s) => assert_eq!(s, BIG_INT_STRING.parse::<BigInt>().unwrap()),
@@ -516,7 +778,7 @@ mod tests {
         #[test]
         fn test_negative_big_number() {
             let input = b"(-3492890328409238509324850943850943825024385\r\n";
-            let (bytes, result) = parse(input.to_vec());
+            let (bytes, result) = parse(input).unwrap();
             assert_eq!(bytes, input.len());
             match result {
                 Value::BigNumber(s) => {
@@ -527,10 +789,17 @@ mod tests {
             }
         }
 
+        #[test]
+        fn test_encode_big_number() {
+            let n = format!("-{}", BIG_INT_STRING).parse::<BigInt>().unwrap();
+            let encoded = Value::BigNumber(n).to_resp();
+            assert_eq!(encoded, format!("(-{}\r\n", BIG_INT_STRING).into_bytes());
+        }
+
         #[test]
         fn test_positive_big_number() {
             let input = b"(+3492890328409238509324850943850943825024385\r\n";
-            let (bytes, result) = parse(input.to_vec());
+            let (bytes, result) = parse(input).unwrap();
             assert_eq!(bytes, input.len());
             match result {
                 Value::BigNumber(s) => {
@@ -542,14 +811,331 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_set() {
+        let input = b"~2\r\n$3\r\none\r\n$3\r\ntwo\r\n";
+        let (bytes, result) = parse(input).unwrap();
+        assert_eq!(bytes, input.len());
+        assert_eq!(
+            result,
+            Value::Set(vec![
+                Value::BulkString(b"one".to_vec()),
+                Value::BulkString(b"two".to_vec()),
+            ])
+        );
+        assert_eq!(result.to_resp(), input.to_vec());
+    }
+
+    #[test]
+    fn test_push() {
+        let input = b">3\r\n$7\r\nmessage\r\n$2\r\nch\r\n$2\r\nhi\r\n";
+        let (bytes, result) = parse(input).unwrap();
+        assert_eq!(bytes, input.len());
+        let elements = vec![
+            Value::BulkString(b"message".to_vec()),
+            Value::BulkString(b"ch".to_vec()),
+            Value::BulkString(b"hi".to_vec()),
+        ];
+        assert_eq!(result, Value::Push(elements.clone()));
+        assert_eq!(result.to_resp(), input.to_vec());
+        assert_eq!(result.to_resp2(), Value::Array(elements).to_resp());
+    }
+
     #[test]
     fn test_bulk_error() {
         let input = b"!21\r\nSYNTAX invalid syntax\r\n";
-        let (bytes, result) = parse(input.to_vec());
+        let (bytes, result) = parse(input).unwrap();
         assert_eq!(bytes, input.len());
         match result {
             Value::BulkError(s) => assert_eq!(s, "SYNTAX invalid syntax"),
             _ => panic!("Wrong type"),
         }
     }
+
+    #[test]
+    fn test_verbatim_string() {
+        let input = b"=15\r\ntxt:Some string\r\n";
+        let (bytes, result) = parse(input).unwrap();
+        assert_eq!(bytes, input.len());
+        assert_eq!(
+            result,
+            Value::VerbatimString("txt".to_string(), b"Some string".to_vec())
+        );
+        assert_eq!(result.to_resp(), input.to_vec());
+        assert_eq!(result.to_resp2(), b"$11\r\nSome string\r\n".to_vec());
+    }
+
+    #[test]
+    fn test_map() {
+        let input = b"%2\r\n+first\r\n:1\r\n+second\r\n:2\r\n";
+        let (bytes, result) = parse(input).unwrap();
+        assert_eq!(bytes, input.len());
+        let first = Value::SimpleString("first".to_string());
+        let second = Value::SimpleString("second".to_string());
+        assert_eq!(
+            result,
+            Value::Map(vec![
+                (first.clone(), Value::Integer(1)),
+                (second.clone(), Value::Integer(2)),
+            ])
+        );
+        assert_eq!(result.to_resp(), input.to_vec());
+        let flattened = Value::Array(vec![first, Value::Integer(1), second, Value::Integer(2)]);
+        assert_eq!(result.to_resp2(), flattened.to_resp());
+    }
+
+    #[test]
+    fn test_attribute() {
+        let input = b"|1\r\n+key-popularity\r\n%1\r\n$1\r\na\r\n,0.1923\r\n*1\r\n:2039123\r\n";
+        let (bytes, result) = parse(input).unwrap();
+        assert_eq!(bytes, input.len());
+        let popularity = Value::Map(vec![(
+            Value::BulkString(b"a".to_vec()),
+            Value::Double(0.1923),
+        )]);
+        let reply = Value::Array(vec![Value::Integer(2039123)]);
+        assert_eq!(
+            result,
+            Value::Attribute(
+                vec![(
+                    Value::SimpleString("key-popularity".to_string()),
+                    popularity
+                )],
+                Box::new(reply.clone())
+            )
+        );
+        assert_eq!(result.to_resp(), input.to_vec());
+        assert_eq!(result.to_resp2(), reply.to_resp());
+    }
+
+    #[test]
+    fn test_resp2_downgrade() {
+        assert_eq!(Value::Boolean(true).to_resp2(), b":1\r\n".to_vec());
+        assert_eq!(Value::Double(1.5).to_resp2(), b"$3\r\n1.5\r\n".to_vec());
+        assert_eq!(
+            Value::BulkError("SYNTAX bad\r\nthing".to_string()).to_resp2(),
+            b"-SYNTAX bad  thing\r\n".to_vec()
+        );
+        assert_eq!(Value::Null.to_resp2(), b"$-1\r\n".to_vec());
+    }
+
+    mod inline {
+        use super::*;
+
+        fn args(args: &[&[u8]]) -> Value {
+            Value::Array(args.iter().map(|a| Value::BulkString(a.to_vec())).collect())
+        }
+
+        fn request(input: &[u8]) -> Result<(usize, Value), RespError> {
+            parse_request(input, &Limits::default())
+        }
+
+        #[test]
+        fn test_inline_command() {
+            let input = b"PING\r\n";
+            assert_eq!(request(input), Ok((input.len(), args(&[b"PING"]))));
+            let input = b"  set  key value\n$4\r\n";
+            assert_eq!(request(input), Ok((17, args(&[b"set", b"key", b"value"]))));
+            assert_eq!(request(b"\r\n"), Ok((2, args(&[]))));
+            assert_eq!(request(b"PING"), Err(RespError::Incomplete));
+        }
+
+        #[test]
+        fn test_quoted_arguments() {
+            let input = b"SET \"hello world\" 'it\\'s' \"a\\x41\\n\\\"\" x\"y z\"\r\n";
+            assert_eq!(
+                request(input),
+                Ok((
+                    input.len(),
+                    args(&[b"SET", b"hello world", b"it's", b"aA\n\"", b"xy z"])
+                ))
+            );
+            assert_eq!(
+                request(b"SET '' \"\"\r\n"),
+                Ok((11, args(&[b"SET", b"", b""])))
+            );
+        }
+
+        #[test]
+        fn test_unbalanced_quotes() {
+            let error = Err(RespError::Protocol(
+                "unbalanced quotes in request".to_string(),
+            ));
+            assert_eq!(request(b"SET \"key\r\n"), error);
+            assert_eq!(request(b"SET \"key\"x\r\n"), error);
+            assert_eq!(request(b"SET 'key\r\n"), error);
+        }
+    }
+
+    mod errors {
+        use super::*;
+
+        fn protocol(reason: &str) -> Result<(usize, Value), RespError> {
+            Err(RespError::Protocol(reason.to_string()))
+        }
+
+        fn request(input: &[u8]) -> Result<(usize, Value), RespError> {
+            parse_request(input, &Limits::default())
+        }
+
+        #[test]
+        fn test_incomplete() {
+            for input in [
+                &b""[..],
+                b"+OK",
+                b"$5\r\nhel",
+                b"$5\r\nhello",
+                b"*2\r\n$1\r\na\r\n",
+            ] {
+                assert_eq!(parse(input), Err(RespError::Incomplete));
+            }
+        }
+
+        #[test]
+        fn test_invalid_lengths() {
+            assert_eq!(parse(b"$abc\r\n"), protocol("invalid bulk length"));
+            assert_eq!(parse(b"$-2\r\n"), protocol("invalid bulk length"));
+            assert_eq!(parse(b"*x\r\n"), protocol("invalid multibulk length"));
+            assert_eq!(
+                parse(b"$3\r\nabcde\r\n"),
+                protocol("expected CRLF after bulk data")
+            );
+        }
+
+        #[test]
+        fn test_invalid_values() {
+            assert_eq!(parse(b":12a\r\n"), protocol("invalid integer"));
+            assert_eq!(parse(b"#x\r\n"), protocol("invalid boolean"));
+            assert_eq!(parse(b",1.2.3\r\n"), protocol("invalid double"));
+            assert_eq!(parse(b"?\r\n"), protocol("unknown type byte '?'"));
+        }
+
+        #[test]
+        fn test_request_must_be_bulk_strings() {
+            assert_eq!(
+                request(b"*2\r\n$3\r\nGET\r\n:1\r\n"),
+                protocol("expected '$', got ':'")
+            );
+            assert_eq!(request(b"*-1\r\n"), Ok((5, Value::Array(vec![]))));
+        }
+
+        #[test]
+        fn test_limits() {
+            let limits = Limits {
+                max_bulk_len: 4,
+                max_multibulk_len: 2,
+            };
+            let limited = |input: &[u8]| parse_request(input, &limits);
+            assert_eq!(
+                limited(b"*2\r\n$4\r\nECHO\r\n$4\r\nabcd\r\n"),
+                Ok((
+                    24,
+                    Value::Array(vec![
+                        Value::BulkString(b"ECHO".to_vec()),
+                        Value::BulkString(b"abcd".to_vec())
+                    ])
+                ))
+            );
+            // Oversized lengths are refused before their data arrives.
+            assert_eq!(limited(b"*3\r\n"), protocol("invalid multibulk length"));
+            assert_eq!(
+                limited(b"*2\r\n$4\r\nECHO\r\n$5\r\n"),
+                protocol("invalid bulk length")
+            );
+
+            let long = vec![b'1'; PROTO_INLINE_MAX_SIZE + 1];
+            assert_eq!(
+                request(&[b"*", &long[..]].concat()),
+                protocol("too big multibulk count string")
+            );
+            assert_eq!(
+                request(&[b"*1\r\n$", &long[..]].concat()),
+                protocol("too big bulk count string")
+            );
+            assert_eq!(request(&long), protocol("too big inline request"));
+            assert_eq!(request(&long[1..]), Err(RespError::Incomplete));
+        }
+
+        #[test]
+        fn test_display() {
+            assert_eq!(
+                RespError::Protocol("invalid bulk length".to_string()).to_string(),
+                "Protocol error: invalid bulk length"
+            );
+        }
+    }
+
+    /// Every value `to_resp` writes is parsed back as the same value.
+    mod round_trip {
+        use super::*;
+        use proptest::prelude::*;
+
+        fn text() -> impl Strategy<Value = String> {
+            "[^\r\n]*"
+        }
+
+        fn pairs(inner: BoxedStrategy<Value>) -> impl Strategy<Value = Vec<(Value, Value)>> {
+            prop::collection::vec((inner.clone(), inner), 0..4)
+        }
+
+        fn value() -> impl Strategy<Value = Value> {
+            let leaf = prop_oneof![
+                text().prop_map(Value::SimpleString),
+                text().prop_map(Value::SimpleError),
+                any::<i64>().prop_map(Value::Integer),
+                any::<Vec<u8>>().prop_map(Value::BulkString),
+                Just(Value::Null),
+                any::<bool>().prop_map(Value::Boolean),
+                // NaN is left out, as it never equals itself.
+                prop_oneof![
+                    any::<f64>().prop_filter("NaN", |d| !d.is_nan()),
+                    Just(f64::INFINITY),
+                    Just(f64::NEG_INFINITY),
+                ]
+                .prop_map(Value::Double),
+                any::<i128>().prop_map(|n| Value::BigNumber(BigInt::from(n))),
+                any::<String>().prop_map(Value::BulkError),
+                ("[a-z]{3}", any::<Vec<u8>>())
+                    .prop_map(|(format, text)| Value::VerbatimString(format, text)),
+            ];
+            leaf.prop_recursive(3, 32, 4, |inner| {
+                let elements = prop::collection::vec(inner.clone(), 0..4);
+                prop_oneof![
+                    elements.clone().prop_map(Value::Array),
+                    elements.clone().prop_map(Value::Set),
+                    elements.prop_map(Value::Push),
+                    pairs(inner.clone()).prop_map(Value::Map),
+                    (pairs(inner.clone()), inner)
+                        .prop_map(|(pairs, reply)| Value::Attribute(pairs, Box::new(reply))),
+                ]
+            })
+        }
+
+        proptest! {
+            #[test]
+            fn test_round_trip(value in value()) {
+                let encoded = value.to_resp();
+                let (bytes, parsed) = parse(&encoded).unwrap();
+                prop_assert_eq!(bytes, encoded.len());
+                prop_assert_eq!(parsed, value);
+            }
+
+            #[test]
+            fn test_truncated(value in value()) {
+                let encoded = value.to_resp();
+                for end in 0..encoded.len() {
+                    prop_assert_eq!(parse(&encoded[..end]), Err(RespError::Incomplete));
+                }
+            }
+
+            #[test]
+            fn test_double_round_trip(d in any::<f64>()) {
+                let encoded = Value::Double(d).to_resp();
+                match parse(&encoded).unwrap() {
+                    (_, Value::Double(parsed)) if d.is_nan() => prop_assert!(parsed.is_nan()),
+                    (_, parsed) => prop_assert_eq!(parsed, Value::Double(d)),
+                }
+            }
+        }
+    }
 }
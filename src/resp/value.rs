@@ -1,25 +1,30 @@
-use num_bigint::{BigInt, Sign};
+use num_bigint::BigInt;
 
 pub const CRLF: &[u8] = b"\r\n";
 pub const CRLF_OFFSET: usize = CRLF.len();
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     SimpleString(String),
     SimpleError(String),
     Integer(i64),
-    BulkString(String),
+    BulkString(Vec<u8>),
     Array(Vec<Value>),
     Null,
     Boolean(bool),
     Double(f64),
     BigNumber(BigInt),
     BulkError(String),
-    // VerbatimString,
-    // Map,
-    // Attribute,
-    // Set,
-    // Push,
+    /// Text with its three-character format, such as `txt` or `mkd`.
+    VerbatimString(String, Vec<u8>),
+    /// Key-value pairs, in the order they are sent.
+    Map(Vec<(Value, Value)>),
+    /// Metadata about the reply that follows, which RESP2 clients never see.
+    Attribute(Vec<(Value, Value)>, Box<Value>),
+    Set(Vec<Value>),
+    /// Out-of-band data such as Pub/Sub messages, sent to RESP2 clients as
+    /// an array.
+    Push(Vec<Value>),
 }
 
 impl Value {
@@ -28,42 +33,33 @@ impl Value {
             Value::SimpleString(s) => {
                 let head = b"+".to_vec();
                 let value = s.as_bytes().to_vec();
-                let combined = [head, value, CRLF.to_vec()].concat();
-                combined
+                [head, value, CRLF.to_vec()].concat()
             }
             Value::SimpleError(s) => {
                 let head = b"-".to_vec();
                 let value = s.as_bytes().to_vec();
-                let combined = [head, value, CRLF.to_vec()].concat();
-                combined
+                [head, value, CRLF.to_vec()].concat()
             }
             Value::Integer(i) => {
                 let head = b":".to_vec();
                 let value = i.to_string().into_bytes();
-                let combined = [head, value, CRLF.to_vec()].concat();
-                combined
+                [head, value, CRLF.to_vec()].concat()
             }
             Value::BulkString(s) => {
                 let head = b"$".to_vec();
                 let length = s.len().to_string().into_bytes();
-                let value = s.to_string().into_bytes();
-
-                let combined = [head, length, CRLF.to_vec(), value, CRLF.to_vec()].concat();
-                combined
+                [head, length, CRLF.to_vec(), s.clone(), CRLF.to_vec()].concat()
             }
             Value::Array(a) => {
                 let head = b"*".to_vec();
                 let num_elements = a.len().to_string().into_bytes();
-                let elements =
-                    [a.iter().map(Value::to_resp).flatten().collect::<Vec<u8>>()].concat();
+                let elements = [a.iter().flat_map(Value::to_resp).collect::<Vec<u8>>()].concat();
 
-                let combined = [head, num_elements, CRLF.to_vec(), elements].concat();
-                combined
+                [head, num_elements, CRLF.to_vec(), elements].concat()
             }
             Value::Null => {
                 let head = b"_".to_vec();
-                let combined = [head, CRLF.to_vec()].concat();
-                combined
+                [head, CRLF.to_vec()].concat()
             }
             Value::Boolean(b) => {
                 let head = b"#".to_vec();
@@ -71,49 +67,140 @@ impl Value {
                     true => b"t".to_vec(),
                     false => b"f".to_vec(),
                 };
-                let combined = [head, value, CRLF.to_vec()].concat();
-                combined
+                [head, value, CRLF.to_vec()].concat()
             }
             Value::Double(d) => {
                 let head = b",".to_vec();
-
-                let value = match d {
-                    &f64::INFINITY => b"inf".to_vec(),
-                    &f64::NEG_INFINITY => b"-inf".to_vec(),
-                    _ => {
-                        if d.is_nan() {
-                            b"nan".to_vec()
-                        } else {
-                            d.to_string().into_bytes()
-                        }
-                    }
-                };
-                let combined = [head, value, CRLF.to_vec()].concat();
-                combined
+                [head, format_double(*d), CRLF.to_vec()].concat()
             }
             Value::BigNumber(n) => {
-                let head = b",".to_vec();
-                let (sign, value) = n.to_bytes_be();
-                let sign = match sign {
-                    Sign::Plus => b"+".to_vec(),
-                    Sign::Minus => b"-".to_vec(),
-                    _ => b"+".to_vec(),
-                };
-                let combined = [head, sign, value, CRLF.to_vec()].concat();
-                combined
+                let head = b"(".to_vec();
+                let value = n.to_string().into_bytes();
+                [head, value, CRLF.to_vec()].concat()
             }
             Value::BulkError(s) => {
                 let head = b"!".to_vec();
+                let length = s.len().to_string().into_bytes();
                 let value = s.as_bytes().to_vec();
-                let combined = [head, value, CRLF.to_vec()].concat();
-                combined
+                [head, length, CRLF.to_vec(), value, CRLF.to_vec()].concat()
+            }
+            Value::VerbatimString(format, text) => {
+                let head = b"=".to_vec();
+                let length = (format.len() + 1 + text.len()).to_string().into_bytes();
+                let value = [format.as_bytes(), b":", text].concat();
+                [head, length, CRLF.to_vec(), value, CRLF.to_vec()].concat()
+            }
+            Value::Map(pairs) => {
+                let head = b"%".to_vec();
+                [head, encode_pairs(pairs)].concat()
+            }
+            Value::Attribute(pairs, reply) => {
+                let head = b"|".to_vec();
+                [head, encode_pairs(pairs), reply.to_resp()].concat()
+            }
+            Value::Set(a) => {
+                let head = b"~".to_vec();
+                let num_elements = a.len().to_string().into_bytes();
+                let elements = a.iter().flat_map(Value::to_resp).collect::<Vec<u8>>();
+                [head, num_elements, CRLF.to_vec(), elements].concat()
+            }
+            Value::Push(a) => {
+                let head = b">".to_vec();
+                let num_elements = a.len().to_string().into_bytes();
+                let elements = a.iter().flat_map(Value::to_resp).collect::<Vec<u8>>();
+                [head, num_elements, CRLF.to_vec(), elements].concat()
+            }
+        }
+    }
+
+    /// Serializes for clients that only speak RESP2, replacing RESP3-only
+    /// types with their closest RESP2 equivalent.
+    pub fn to_resp2(&self) -> Vec<u8> {
+        match self {
+            Value::Array(a) | Value::Set(a) | Value::Push(a) => {
+                let head = b"*".to_vec();
+                let num_elements = a.len().to_string().into_bytes();
+                let elements = a.iter().flat_map(Value::to_resp2).collect::<Vec<u8>>();
+                [head, num_elements, CRLF.to_vec(), elements].concat()
+            }
+            Value::Map(pairs) => {
+                let head = b"*".to_vec();
+                let num_elements = (pairs.len() * 2).to_string().into_bytes();
+                let elements = pairs
+                    .iter()
+                    .flat_map(|(key, value)| [key.to_resp2(), value.to_resp2()].concat())
+                    .collect::<Vec<u8>>();
+                [head, num_elements, CRLF.to_vec(), elements].concat()
             }
+            Value::Attribute(_, reply) => reply.to_resp2(),
+            Value::Null => b"$-1\r\n".to_vec(),
+            Value::Boolean(b) => Value::Integer(*b as i64).to_resp(),
+            Value::Double(d) => Value::BulkString(format_double(*d)).to_resp(),
+            Value::BigNumber(n) => Value::BulkString(n.to_string().into_bytes()).to_resp(),
+            Value::BulkError(s) => Value::SimpleError(s.replace(['\r', '\n'], " ")).to_resp(),
+            Value::VerbatimString(_, text) => Value::BulkString(text.clone()).to_resp(),
+            _ => self.to_resp(),
         }
     }
     pub fn to_bytes(&self) -> Vec<u8> {
         match self {
-            Value::BulkString(s) => s.to_string().into_bytes(),
+            Value::BulkString(s) => s.clone(),
             _ => panic!("Not a BulkString"),
         }
     }
 }
+
+/// The reply for a missing array, like a stream read that found nothing:
+/// `*-1` in RESP2, where `Null` would be the null bulk string `$-1`.
+pub fn null_array(protocol: u8) -> Vec<u8> {
+    match protocol {
+        3 => Value::Null.to_resp(),
+        _ => b"*-1\r\n".to_vec(),
+    }
+}
+
+/// Formats a double as Redis does, like C's `%.17g`: enough significant
+/// digits to read back the exact same value, without trailing zeros, in
+/// exponent form when the exponent is below -4 or above 16.
+fn format_double(d: f64) -> Vec<u8> {
+    match d {
+        f64::INFINITY => b"inf".to_vec(),
+        f64::NEG_INFINITY => b"-inf".to_vec(),
+        _ if d.is_nan() => b"nan".to_vec(),
+        _ => {
+            // `{:e}` rounds to 17 significant digits first, which can carry
+            // into the exponent, just as `%g` decides on the rounded value.
+            let scientific = format!("{:.16e}", d);
+            let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+            let exponent: i32 = exponent.parse().unwrap_or(0);
+            let formatted = if (-4..17).contains(&exponent) {
+                let fixed = format!("{:.*}", (16 - exponent) as usize, d);
+                trim_fraction(&fixed).to_string()
+            } else {
+                let sign = if exponent < 0 { '-' } else { '+' };
+                format!("{}e{}{:02}", trim_fraction(mantissa), sign, exponent.abs())
+            };
+            formatted.into_bytes()
+        }
+    }
+}
+
+/// Drops trailing zeros after the decimal point, and the point if nothing
+/// is left after it.
+fn trim_fraction(number: &str) -> &str {
+    match number.contains('.') {
+        true => number.trim_end_matches('0').trim_end_matches('.'),
+        false => number,
+    }
+}
+
+/// The pair count and then each key followed by its value, as maps and
+/// attributes send them.
+fn encode_pairs(pairs: &[(Value, Value)]) -> Vec<u8> {
+    let num_pairs = pairs.len().to_string().into_bytes();
+    let elements = pairs
+        .iter()
+        .flat_map(|(key, value)| [key.to_resp(), value.to_resp()].concat())
+        .collect::<Vec<u8>>();
+    [num_pairs, CRLF.to_vec(), elements].concat()
+}
@@ -0,0 +1,460 @@
+use crate::cache::ELEMENT_OVERHEAD;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::Bound;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub fn new(ms: u64, seq: u64) -> Self {
+        StreamId { ms, seq }
+    }
+
+    pub fn next(self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => self.ms.checked_add(1).map(|ms| StreamId::new(ms, 0)),
+        }
+    }
+
+    pub fn prev(self) -> Option<StreamId> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => self.ms.checked_sub(1).map(|ms| StreamId::new(ms, u64::MAX)),
+        }
+    }
+
+    /// Parses `ms-seq`, or a bare `ms` whose sequence is filled in with
+    /// `missing_seq` (0 for range starts, `u64::MAX` for range ends).
+    pub fn parse(s: &str, missing_seq: u64) -> Option<StreamId> {
+        match s.split_once('-') {
+            Some((ms, seq)) => Some(StreamId::new(ms.parse().ok()?, seq.parse().ok()?)),
+            None => Some(StreamId::new(s.parse().ok()?, missing_seq)),
+        }
+    }
+
+    /// Big-endian encoding, used as radix tree keys in the RDB format.
+    pub fn to_be_bytes(self) -> [u8; 16] {
+        let mut bytes = [0u8; 16];
+        bytes[..8].copy_from_slice(&self.ms.to_be_bytes());
+        bytes[8..].copy_from_slice(&self.seq.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_be_bytes(bytes: &[u8]) -> Option<StreamId> {
+        if bytes.len() != 16 {
+            return None;
+        }
+        let ms = u64::from_be_bytes(bytes[..8].try_into().ok()?);
+        let seq = u64::from_be_bytes(bytes[8..].try_into().ok()?);
+        Some(StreamId::new(ms, seq))
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+pub type Fields = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Entries per listpack node when a stream is laid out as a radix tree of
+/// listpacks (RDB encoding, `XINFO`), matching `stream-node-max-entries`.
+pub const NODE_MAX_ENTRIES: usize = 100;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingEntry {
+    pub consumer: Vec<u8>,
+    pub delivery_time: u64,
+    pub delivery_count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Consumer {
+    pub seen_time: u64,
+    /// Last time the consumer actually read or claimed something; `None`
+    /// until it does.
+    pub active_time: Option<u64>,
+    pub pending: BTreeSet<StreamId>,
+}
+
+impl Consumer {
+    pub fn new(now: u64) -> Self {
+        Consumer {
+            seen_time: now,
+            active_time: None,
+            pending: BTreeSet::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsumerGroup {
+    pub last_id: StreamId,
+    /// Logical read counter used to compute the lag; `None` when it can't
+    /// be known (e.g. the group was created at an arbitrary ID).
+    pub entries_read: Option<u64>,
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+    pub consumers: BTreeMap<Vec<u8>, Consumer>,
+}
+
+impl ConsumerGroup {
+    pub fn new(last_id: StreamId, entries_read: Option<u64>) -> Self {
+        ConsumerGroup {
+            last_id,
+            entries_read,
+            pending: BTreeMap::new(),
+            consumers: BTreeMap::new(),
+        }
+    }
+
+    pub fn consumer(&mut self, name: &[u8], now: u64) -> &mut Consumer {
+        let consumer = self
+            .consumers
+            .entry(name.to_vec())
+            .or_insert_with(|| Consumer::new(now));
+        consumer.seen_time = now;
+        consumer
+    }
+
+    /// Assigns `id` to `consumer`, taking it away from its previous owner.
+    pub fn assign(&mut self, id: StreamId, consumer: &[u8], delivery_time: u64, count: u64) {
+        if let Some(previous) = self.pending.get(&id) {
+            if let Some(owner) = self.consumers.get_mut(&previous.consumer) {
+                owner.pending.remove(&id);
+            }
+        }
+        self.pending.insert(
+            id,
+            PendingEntry {
+                consumer: consumer.to_vec(),
+                delivery_time,
+                delivery_count: count,
+            },
+        );
+        if let Some(owner) = self.consumers.get_mut(consumer) {
+            owner.pending.insert(id);
+        }
+    }
+
+    pub fn ack(&mut self, id: &StreamId) -> bool {
+        match self.pending.remove(id) {
+            Some(entry) => {
+                if let Some(owner) = self.consumers.get_mut(&entry.consumer) {
+                    owner.pending.remove(id);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trim {
+    MaxLen(u64),
+    MinId(StreamId),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stream {
+    pub entries: BTreeMap<StreamId, Fields>,
+    pub last_id: StreamId,
+    pub first_id: StreamId,
+    pub max_deleted_entry_id: StreamId,
+    pub entries_added: u64,
+    pub groups: BTreeMap<Vec<u8>, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn new() -> Self {
+        Stream::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// An estimate of the memory the stream takes, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        let entries: usize = self
+            .entries
+            .values()
+            .map(|fields| {
+                let bytes: usize = fields
+                    .iter()
+                    .map(|(field, value)| field.len() + value.len())
+                    .sum();
+                ELEMENT_OVERHEAD * (1 + fields.len()) + bytes
+            })
+            .sum();
+        let groups: usize = self
+            .groups
+            .iter()
+            .map(|(name, group)| {
+                let consumers: usize = group
+                    .consumers
+                    .iter()
+                    .map(|(name, consumer)| {
+                        ELEMENT_OVERHEAD * (1 + consumer.pending.len()) + name.len()
+                    })
+                    .sum();
+                ELEMENT_OVERHEAD * (1 + group.pending.len()) + name.len() + consumers
+            })
+            .sum();
+        entries + groups
+    }
+
+    /// Number of listpack nodes the entries occupy.
+    pub fn nodes(&self) -> usize {
+        self.entries.len().div_ceil(NODE_MAX_ENTRIES)
+    }
+
+    /// The ID `XADD *` would generate at `now_ms`.
+    pub fn next_id(&self, now_ms: u64) -> Option<StreamId> {
+        if now_ms > self.last_id.ms {
+            Some(StreamId::new(now_ms, 0))
+        } else {
+            self.last_id.next()
+        }
+    }
+
+    /// The ID `XADD ms-*` would generate.
+    pub fn next_id_for(&self, ms: u64) -> Option<StreamId> {
+        match ms.cmp(&self.last_id.ms) {
+            std::cmp::Ordering::Greater => Some(StreamId::new(ms, 0)),
+            std::cmp::Ordering::Equal => self.last_id.next(),
+            std::cmp::Ordering::Less => None,
+        }
+    }
+
+    /// Appends an entry; the caller is responsible for `id` being greater
+    /// than `last_id`.
+    pub fn add(&mut self, id: StreamId, fields: Fields) {
+        if self.entries.is_empty() {
+            self.first_id = id;
+        }
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        self.entries_added += 1;
+    }
+
+    pub fn range(
+        &self,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: Option<usize>,
+        rev: bool,
+    ) -> Vec<(StreamId, &Fields)> {
+        let empty = match (start, end) {
+            (Bound::Included(s), Bound::Included(e)) => s > e,
+            (Bound::Included(s), Bound::Excluded(e))
+            | (Bound::Excluded(s), Bound::Included(e))
+            | (Bound::Excluded(s), Bound::Excluded(e)) => s >= e,
+            _ => false,
+        };
+        if empty {
+            return Vec::new();
+        }
+        let range = self.entries.range((start, end)).map(|(id, f)| (*id, f));
+        let count = count.unwrap_or(usize::MAX);
+        match rev {
+            true => range.rev().take(count).collect(),
+            false => range.take(count).collect(),
+        }
+    }
+
+    pub fn delete(&mut self, id: &StreamId) -> bool {
+        if self.entries.remove(id).is_none() {
+            return false;
+        }
+        if *id > self.max_deleted_entry_id {
+            self.max_deleted_entry_id = *id;
+        }
+        self.update_first_id();
+        true
+    }
+
+    /// Removes entries from the head of the stream. `limit` caps how many
+    /// entries a single call may evict (0 means unlimited).
+    pub fn trim(&mut self, strategy: Trim, limit: usize) -> usize {
+        let limit = if limit == 0 { usize::MAX } else { limit };
+        let mut removed = 0;
+        while removed < limit {
+            let first = match self.entries.keys().next() {
+                Some(first) => *first,
+                None => break,
+            };
+            let evict = match strategy {
+                Trim::MaxLen(max) => self.entries.len() as u64 > max,
+                Trim::MinId(min) => first < min,
+            };
+            if !evict {
+                break;
+            }
+            self.entries.remove(&first);
+            if first > self.max_deleted_entry_id {
+                self.max_deleted_entry_id = first;
+            }
+            removed += 1;
+        }
+        if removed > 0 {
+            self.update_first_id();
+        }
+        removed
+    }
+
+    fn update_first_id(&mut self) {
+        self.first_id = self.entries.keys().next().copied().unwrap_or(StreamId::MIN);
+    }
+
+    /// Whether an XDEL/XTRIM tombstone may lie in `[start, +]`.
+    pub fn has_tombstones_from(&self, start: StreamId) -> bool {
+        if self.entries.is_empty() || self.max_deleted_entry_id == StreamId::MIN {
+            return false;
+        }
+        start <= self.max_deleted_entry_id
+    }
+
+    /// Estimates how many entries were ever added up to and including `id`,
+    /// following Redis' `streamEstimateDistanceFromFirstEverEntry`.
+    pub fn estimate_entries_read(&self, id: StreamId) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        if self.entries.is_empty() && id <= self.last_id {
+            return Some(self.entries_added);
+        }
+        match id.cmp(&self.last_id) {
+            std::cmp::Ordering::Equal => return Some(self.entries_added),
+            std::cmp::Ordering::Greater => return None,
+            std::cmp::Ordering::Less => {}
+        }
+        if self.max_deleted_entry_id == StreamId::MIN || self.max_deleted_entry_id < self.first_id {
+            let len = self.entries.len() as u64;
+            match id.cmp(&self.first_id) {
+                std::cmp::Ordering::Less => return Some(self.entries_added - len),
+                std::cmp::Ordering::Equal => return Some(self.entries_added - len + 1),
+                std::cmp::Ordering::Greater => {}
+            }
+        }
+        None
+    }
+
+    /// Number of entries the group still has to read, if it can be known.
+    pub fn lag(&self, group: &ConsumerGroup) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        if let Some(read) = group.entries_read {
+            if !self.has_tombstones_from(group.last_id) {
+                return Some(self.entries_added.saturating_sub(read));
+            }
+        }
+        self.estimate_entries_read(group.last_id)
+            .map(|read| self.entries_added.saturating_sub(read))
+    }
+
+    /// Moves the group's read cursor past `id`, keeping the logical read
+    /// counter in step when possible.
+    pub fn advance_group(&self, group: &mut ConsumerGroup, id: StreamId) {
+        group.entries_read = match group.entries_read {
+            Some(read) if !self.has_tombstones_from(id) => Some(read + 1),
+            _ => self.estimate_entries_read(id),
+        };
+        group.last_id = id;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields() -> Fields {
+        vec![(b"field".to_vec(), b"value".to_vec())]
+    }
+
+    #[test]
+    fn test_parse_id() {
+        assert_eq!(StreamId::parse("5-3", 0), Some(StreamId::new(5, 3)));
+        assert_eq!(
+            StreamId::parse("5", u64::MAX),
+            Some(StreamId::new(5, u64::MAX))
+        );
+        assert_eq!(StreamId::parse("x-1", 0), None);
+    }
+
+    #[test]
+    fn test_next_id() {
+        let mut stream = Stream::new();
+        assert_eq!(stream.next_id(10), Some(StreamId::new(10, 0)));
+        stream.add(StreamId::new(10, 0), fields());
+        assert_eq!(stream.next_id(5), Some(StreamId::new(10, 1)));
+        assert_eq!(stream.next_id_for(10), Some(StreamId::new(10, 1)));
+        assert_eq!(stream.next_id_for(9), None);
+    }
+
+    #[test]
+    fn test_range() {
+        let mut stream = Stream::new();
+        for ms in 1..=5 {
+            stream.add(StreamId::new(ms, 0), fields());
+        }
+        let ids = |entries: Vec<(StreamId, &Fields)>| {
+            entries.iter().map(|(id, _)| id.ms).collect::<Vec<u64>>()
+        };
+        let all = stream.range(Bound::Unbounded, Bound::Unbounded, None, false);
+        assert_eq!(ids(all), vec![1, 2, 3, 4, 5]);
+        let rev = stream.range(
+            Bound::Excluded(StreamId::new(1, 0)),
+            Bound::Included(StreamId::new(4, 0)),
+            Some(2),
+            true,
+        );
+        assert_eq!(ids(rev), vec![4, 3]);
+    }
+
+    #[test]
+    fn test_trim_and_delete() {
+        let mut stream = Stream::new();
+        for ms in 1..=5 {
+            stream.add(StreamId::new(ms, 0), fields());
+        }
+        assert_eq!(stream.trim(Trim::MaxLen(3), 0), 2);
+        assert_eq!(stream.first_id, StreamId::new(3, 0));
+        assert_eq!(stream.trim(Trim::MinId(StreamId::new(5, 0)), 1), 1);
+        assert!(stream.delete(&StreamId::new(5, 0)));
+        assert_eq!(stream.max_deleted_entry_id, StreamId::new(5, 0));
+        assert_eq!(stream.len(), 1);
+        assert_eq!(stream.entries_added, 5);
+    }
+
+    #[test]
+    fn test_lag() {
+        let mut stream = Stream::new();
+        for ms in 1..=3 {
+            stream.add(StreamId::new(ms, 0), fields());
+        }
+        let mut group = ConsumerGroup::new(StreamId::MIN, Some(0));
+        assert_eq!(stream.lag(&group), Some(3));
+        stream.advance_group(&mut group, StreamId::new(1, 0));
+        assert_eq!(stream.lag(&group), Some(2));
+
+        // A group created at an arbitrary ID behind a deleted entry can't
+        // know its lag.
+        stream.delete(&StreamId::new(2, 0));
+        let group = ConsumerGroup::new(StreamId::new(1, 5), None);
+        assert_eq!(stream.lag(&group), None);
+    }
+}
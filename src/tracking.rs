@@ -0,0 +1,234 @@
+//! Server-assisted client-side caching (`CLIENT TRACKING`): which clients
+//! may have cached which keys, so they can be told when those change.
+//!
+//! Like Redis, the table ignores databases: a write to a key in any
+//! database invalidates it for everyone that read it in any other.
+
+use mio::Token;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Options {
+    /// The client invalidations are sent to instead of this one.
+    pub redirect: Option<Token>,
+    /// Broadcasting: invalidate every key under `prefixes` (or any key if
+    /// there are none), whether the client read it or not.
+    pub bcast: bool,
+    pub prefixes: Vec<Vec<u8>>,
+    /// Only track reads right after `CLIENT CACHING yes`.
+    pub optin: bool,
+    /// Track reads except right after `CLIENT CACHING no`.
+    pub optout: bool,
+    /// Don't invalidate keys for the client that wrote them.
+    pub noloop: bool,
+}
+
+#[derive(Debug, Default)]
+struct Client {
+    options: Options,
+    /// Set by `CLIENT CACHING` for the next command only.
+    caching: Option<bool>,
+}
+
+#[derive(Debug, Default)]
+pub struct Tracking {
+    clients: HashMap<Token, Client>,
+    /// Keys read by clients outside broadcasting mode, with who read them.
+    /// An entry goes once it has been invalidated; the client must read the
+    /// key again to hear about the next change.
+    keys: HashMap<Vec<u8>, HashSet<Token>>,
+}
+
+impl Tracking {
+    /// Whether any client has tracking on, so `Cache` only records the
+    /// keys commands touch when someone needs them.
+    pub fn is_enabled(&self) -> bool {
+        !self.clients.is_empty()
+    }
+
+    pub fn options(&self, client: Token) -> Option<&Options> {
+        self.clients.get(&client).map(|state| &state.options)
+    }
+
+    /// Turns tracking on for `client`, or changes its options.
+    pub fn enable(&mut self, client: Token, options: Options) {
+        let state = self.clients.entry(client).or_default();
+        state.options = options;
+        state.caching = None;
+    }
+
+    pub fn disable(&mut self, client: Token) {
+        self.clients.remove(&client);
+        for readers in self.keys.values_mut() {
+            readers.remove(&client);
+        }
+        self.keys.retain(|_, readers| !readers.is_empty());
+    }
+
+    pub fn set_caching(&mut self, client: Token, caching: bool) {
+        if let Some(state) = self.clients.get_mut(&client) {
+            state.caching = Some(caching);
+        }
+    }
+
+    /// Remembers that `client` read `keys`, if its mode tracks reads and
+    /// `CLIENT CACHING` allowed this one. Every command but `CLIENT
+    /// CACHING` itself uses up the `CLIENT CACHING` setting.
+    pub fn record_reads(&mut self, client: Token, keys: Vec<Vec<u8>>, caching_command: bool) {
+        let Some(state) = self.clients.get_mut(&client) else {
+            return;
+        };
+        let options = &state.options;
+        let tracked = match state.caching {
+            _ if options.bcast => false,
+            caching if options.optin => caching == Some(true),
+            caching if options.optout => caching != Some(false),
+            _ => true,
+        };
+        if !caching_command {
+            state.caching = None;
+        }
+        if tracked {
+            for key in keys {
+                self.keys.entry(key).or_default().insert(client);
+            }
+        }
+    }
+
+    /// The keys each client must drop now that `keys` changed, keyed by
+    /// the client the invalidation goes to, which is the redirect target
+    /// if there is one. `writer` is the client that made the change, if any.
+    pub fn invalidate(
+        &mut self,
+        keys: &[Vec<u8>],
+        writer: Option<Token>,
+    ) -> HashMap<Token, Vec<Vec<u8>>> {
+        let mut invalidations: HashMap<Token, Vec<Vec<u8>>> = HashMap::new();
+        for key in keys {
+            let mut receivers: HashSet<Token> = self.keys.remove(key).unwrap_or_default();
+            for (client, state) in &self.clients {
+                let options = &state.options;
+                let matches = options.prefixes.is_empty()
+                    || options
+                        .prefixes
+                        .iter()
+                        .any(|prefix| key.starts_with(prefix));
+                if options.bcast && matches {
+                    receivers.insert(*client);
+                }
+            }
+            for client in receivers {
+                let Some(state) = self.clients.get(&client) else {
+                    continue;
+                };
+                if state.options.noloop && Some(client) == writer {
+                    continue;
+                }
+                let target = state.options.redirect.unwrap_or(client);
+                let pending = invalidations.entry(target).or_default();
+                if !pending.contains(key) {
+                    pending.push(key.clone());
+                }
+            }
+        }
+        invalidations
+    }
+
+    /// The clients to tell to drop everything after a flush.
+    pub fn flush(&mut self) -> HashSet<Token> {
+        self.keys.clear();
+        self.clients
+            .iter()
+            .map(|(client, state)| state.options.redirect.unwrap_or(*client))
+            .collect()
+    }
+}
+
+/// Checks that no prefix is a prefix of another, which would make a key
+/// match twice.
+pub fn check_prefixes(prefixes: &[Vec<u8>]) -> Result<(), String> {
+    for (i, prefix) in prefixes.iter().enumerate() {
+        for other in &prefixes[i + 1..] {
+            if prefix.starts_with(other) || other.starts_with(prefix) {
+                return Err(format!(
+                    "Prefix '{}' overlaps with another provided prefix '{}'. Prefixes for a single client must not overlap.",
+                    String::from_utf8_lossy(other),
+                    String::from_utf8_lossy(prefix)
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(keys: &[&str]) -> Vec<Vec<u8>> {
+        keys.iter().map(|key| key.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_default_mode() {
+        let mut tracking = Tracking::default();
+        let (reader, writer) = (Token(2), Token(3));
+        tracking.enable(reader, Options::default());
+        tracking.record_reads(reader, keys(&["a", "b"]), false);
+        // Untracked clients' reads are ignored.
+        tracking.record_reads(writer, keys(&["c"]), false);
+
+        let invalidations = tracking.invalidate(&keys(&["a", "c"]), Some(writer));
+        assert_eq!(invalidations, HashMap::from([(reader, keys(&["a"]))]));
+        // Each read is invalidated once.
+        assert!(tracking.invalidate(&keys(&["a"]), None).is_empty());
+
+        tracking.disable(reader);
+        assert!(tracking.invalidate(&keys(&["b"]), None).is_empty());
+    }
+
+    #[test]
+    fn test_options() {
+        let mut tracking = Tracking::default();
+        let (client, other) = (Token(2), Token(3));
+        let bcast = Options {
+            bcast: true,
+            prefixes: keys(&["user:"]),
+            noloop: true,
+            redirect: Some(other),
+            ..Options::default()
+        };
+        tracking.enable(client, bcast);
+        tracking.record_reads(client, keys(&["order:1"]), false);
+        assert!(tracking.invalidate(&keys(&["order:1"]), None).is_empty());
+        assert_eq!(
+            tracking.invalidate(&keys(&["user:1", "user:2"]), None),
+            HashMap::from([(other, keys(&["user:1", "user:2"]))])
+        );
+        assert!(tracking
+            .invalidate(&keys(&["user:1"]), Some(client))
+            .is_empty());
+
+        let optin = Options {
+            optin: true,
+            ..Options::default()
+        };
+        tracking.enable(client, optin);
+        tracking.record_reads(client, keys(&["a"]), false);
+        tracking.set_caching(client, true);
+        tracking.record_reads(client, keys(&[]), true);
+        tracking.record_reads(client, keys(&["b"]), false);
+        tracking.record_reads(client, keys(&["c"]), false);
+        assert_eq!(
+            tracking.invalidate(&keys(&["a", "b", "c"]), None),
+            HashMap::from([(client, keys(&["b"]))])
+        );
+        assert_eq!(tracking.flush(), HashSet::from([client]));
+    }
+
+    #[test]
+    fn test_check_prefixes() {
+        assert_eq!(check_prefixes(&keys(&["a:", "b:"])), Ok(()));
+        assert!(check_prefixes(&keys(&["a:", "b:", "a:1"])).is_err());
+    }
+}